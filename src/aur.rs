@@ -0,0 +1,219 @@
+use crate::diagnosis::{self, Diagnosis};
+use crate::output;
+use std::process::Command;
+
+// Why a single target of a bulk AUR helper install failed
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FailureCategory {
+    // Not in the repos or the AUR (404, renamed, removed)
+    NotFound,
+    // Unknown or untrusted PGP key
+    Pgp,
+    // Conflicts with an installed package or files on disk
+    Conflict,
+    // makepkg failed somewhere in prepare/build/package
+    BuildError,
+}
+
+impl FailureCategory {
+    pub fn describe(self) -> &'static str {
+        match self {
+            FailureCategory::NotFound => "not found",
+            FailureCategory::Pgp => "PGP signature",
+            FailureCategory::Conflict => "conflict",
+            FailureCategory::BuildError => "build error",
+        }
+    }
+    
+    // Keyservers and upstream downloads flake, a missing or conflicting
+    // package won't fix itself on a second try
+    pub fn retryable(self) -> bool {
+        matches!(self, FailureCategory::Pgp | FailureCategory::BuildError)
+    }
+}
+
+#[derive(Debug)]
+pub struct PackageFailure {
+    pub package: String,
+    pub category: FailureCategory,
+    // The output line the failure was recognised from
+    pub detail: String,
+}
+
+pub struct InstallOutcome {
+    pub success: bool,
+    pub failures: Vec<PackageFailure>,
+    // Known causes found anywhere in the output
+    pub diagnoses: Vec<Diagnosis>,
+}
+
+// "foo-1.2.3-1" -> "foo" when foo is one of the targets
+fn match_target(name: &str, targets: &[String]) -> String {
+    let name = name.trim().trim_matches(|c| c == '\'' || c == '"' || c == ',' || c == ':');
+    targets
+        .iter()
+        .filter(|target| name == target.as_str() || name.starts_with(&format!("{}-", target)))
+        .max_by_key(|target| target.len())
+        .cloned()
+        .unwrap_or_else(|| name.to_string())
+}
+
+// Pick the per-target failures out of paru/yay/pikaur/pacman/makepkg output
+pub fn parse_failures(output: &str, targets: &[String]) -> Vec<PackageFailure> {
+    let mut failures: Vec<PackageFailure> = Vec::new();
+    let mut push = |package: String, category: FailureCategory, line: &str| {
+        if !failures.iter().any(|f| f.package == package && f.category == category) {
+            failures.push(PackageFailure { package, category, detail: line.trim().to_string() });
+        }
+    };
+    
+    // makepkg errors don't name the package, the "Making package" header does
+    let mut building: Option<String> = None;
+    let mut in_missing_list = false;
+    
+    for line in output.lines() {
+        let trimmed = line.trim();
+        let lower = trimmed.to_lowercase();
+        
+        // paru: "could not find all required packages:" followed by indented names
+        if in_missing_list {
+            if line.starts_with(' ') && !trimmed.is_empty() {
+                let name = trimmed.split_whitespace().next().unwrap_or(trimmed);
+                push(match_target(name, targets), FailureCategory::NotFound, line);
+                continue;
+            }
+            in_missing_list = false;
+        }
+        
+        if let Some(rest) = trimmed.strip_prefix("==> Making package: ") {
+            building = rest.split_whitespace().next().map(|name| match_target(name, targets));
+        } else if lower.contains("could not find all required packages") {
+            in_missing_list = true;
+        } else if let Some(name) = trimmed.strip_prefix("error: target not found: ") {
+            push(match_target(name, targets), FailureCategory::NotFound, line);
+        } else if let Some(rest) = trimmed.split("No AUR package found for ").nth(1) {
+            push(match_target(rest, targets), FailureCategory::NotFound, line);
+        } else if lower.contains("404") && lower.contains("http") {
+            if let Some(package) = &building {
+                push(package.clone(), FailureCategory::NotFound, line);
+            }
+        } else if lower.contains("pgp signature") || lower.contains("unknown public key") || lower.contains("signature from") {
+            // pacman: "error: foo: signature from ... is invalid"
+            let named = trimmed
+                .strip_prefix("error: ")
+                .and_then(|rest| rest.split_once(": "))
+                .map(|(name, _)| match_target(name, targets));
+            if let Some(package) = named.or_else(|| building.clone()) {
+                push(package, FailureCategory::Pgp, line);
+            }
+        } else if lower.contains("are in conflict") {
+            // ":: foo and bar are in conflict"
+            let rest = trimmed.trim_start_matches(':').trim();
+            if let Some((first, _)) = rest.split_once(" and ") {
+                push(match_target(first, targets), FailureCategory::Conflict, line);
+            }
+        } else if lower.contains("exists in filesystem") {
+            // "foo: /usr/bin/foo exists in filesystem"
+            if let Some((name, _)) = trimmed.split_once(": ") {
+                push(match_target(name, targets), FailureCategory::Conflict, line);
+            }
+        } else if lower.contains("a failure occurred in") {
+            if let Some(package) = &building {
+                push(package.clone(), FailureCategory::BuildError, line);
+            }
+        } else if let Some(rest) = trimmed.strip_prefix("error: failed to build '") {
+            // paru: "error: failed to build 'foo-1.0-1': ..."
+            let name = rest.split('\'').next().unwrap_or(rest);
+            push(match_target(name, targets), FailureCategory::BuildError, line);
+        } else if let Some(rest) = trimmed.strip_prefix("error: packages failed to build:") {
+            for name in rest.split_whitespace() {
+                push(match_target(name, targets), FailureCategory::BuildError, line);
+            }
+        } else if let Some(rest) = trimmed.split("error making: ").nth(1) {
+            // yay: " -> error making: foo-exit status 4"
+            let name = rest.split("-exit status").next().unwrap_or(rest);
+            push(match_target(name, targets), FailureCategory::BuildError, line);
+        }
+    }
+    
+    failures
+}
+
+// `<helper> -S --needed --noconfirm <flags> -` with the packages piped to
+// its stdin, one per line, output still shown live but also parsed for what
+// went wrong. A pipe has no path in /tmp for another user to read or an
+// overlapping run to overwrite, nor the length limit of the command line.
+pub fn install(helper: &str, flags: &[String], packages: &[String], cwd: &str) -> InstallOutcome {
+    let mut command = Command::new(helper);
+    command.args(["-S", "--needed", "--noconfirm"]).args(flags).arg("-").current_dir(cwd);
+    let list: String = packages.iter().map(|package| format!("{}\n", package)).collect();
+    // The helper calls sudo itself for pacman
+    let (status, mut output, stderr) =
+        output::watch_with_input(&mut command, true, list).unwrap_or_else(|_| panic!("Failed to execute {}", helper));
+    output.push_str(&stderr);
+    
+    if status.success() {
+        return InstallOutcome { success: true, failures: Vec::new(), diagnoses: Vec::new() };
+    }
+    InstallOutcome {
+        success: false,
+        failures: parse_failures(&output, packages),
+        diagnoses: diagnosis::classify(&output),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FailureCategory, parse_failures};
+    
+    fn targets(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+    
+    fn summary(output: &str, names: &[&str]) -> Vec<(String, FailureCategory)> {
+        parse_failures(output, &targets(names)).into_iter().map(|f| (f.package, f.category)).collect()
+    }
+    
+    #[test]
+    fn paru_missing_list_names_each_package() {
+        let output = "error: could not find all required packages:\n    nosuchpkg (target)\n    gone-too (target)\n:: Resolving dependencies...\n";
+        assert_eq!(
+            summary(output, &["nosuchpkg", "gone-too", "fine"]),
+            vec![("nosuchpkg".to_string(), FailureCategory::NotFound), ("gone-too".to_string(), FailureCategory::NotFound)]
+        );
+    }
+    
+    #[test]
+    fn makepkg_errors_go_to_the_package_being_built() {
+        let output = "==> Making package: foo-git 1.0-1 (Sat 01 Jan)\n==> ERROR: A failure occurred in build().\n    Aborting...\n";
+        assert_eq!(summary(output, &["foo-git", "foo"]), vec![("foo-git".to_string(), FailureCategory::BuildError)]);
+    }
+    
+    #[test]
+    fn versions_are_matched_back_to_targets() {
+        let output = "error: failed to build 'foo-1.2.3-1': \n error making: bar-exit status 4\n";
+        assert_eq!(
+            summary(output, &["foo", "bar"]),
+            vec![("foo".to_string(), FailureCategory::BuildError), ("bar".to_string(), FailureCategory::BuildError)]
+        );
+    }
+    
+    #[test]
+    fn pgp_and_conflicts_are_told_apart() {
+        let output = "error: baz: signature from \"Someone <someone@example.org>\" is unknown trust\n:: qux and qux-bin are in conflict\nquux: /usr/bin/quux exists in filesystem\n";
+        assert_eq!(
+            summary(output, &["baz", "qux", "quux"]),
+            vec![
+                ("baz".to_string(), FailureCategory::Pgp),
+                ("qux".to_string(), FailureCategory::Conflict),
+                ("quux".to_string(), FailureCategory::Conflict),
+            ]
+        );
+    }
+    
+    #[test]
+    fn a_failure_is_reported_once() {
+        let output = "error: target not found: nosuchpkg\nerror: target not found: nosuchpkg\n";
+        assert_eq!(summary(output, &["nosuchpkg"]), vec![("nosuchpkg".to_string(), FailureCategory::NotFound)]);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::config;
+use crate::usage::StepUsage;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+// How far the setup got, step by step. It survives reboots, and `ass
+// --resume` uses it to skip what already finished.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Checkpoint {
+    // Install state the setup is in: "start", or "post-nix" once Nix is in
+    pub phase: String,
+    // Finished steps, in the order they finished
+    pub completed: Vec<String>,
+    // Started but not finished: failed, interrupted or still going
+    pub running: Vec<String>,
+    // Seconds each finished step took, for the ETA of the ones left
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub durations: BTreeMap<String, u64>,
+    // What the finished steps cost, in the order they ran
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub usage: Vec<(String, StepUsage)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_login: Option<FirstLogin>,
+}
+
+// Actions left for the first graphical login (ass first-login)
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct FirstLogin {
+    // "pending" until the first login ran them, then "done"
+    pub status: String,
+    // Action -> "ok" or what went wrong
+    pub results: BTreeMap<String, String>,
+}
+
+// Parallel steps update the file at the same time
+static LOCK: Mutex<()> = Mutex::new(());
+
+pub fn path() -> String {
+    config::state_path("state.json")
+}
+
+pub fn load() -> Option<Checkpoint> {
+    let content = std::fs::read_to_string(path()).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn update(change: impl FnOnce(&mut Checkpoint)) {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut checkpoint = load().unwrap_or_default();
+    change(&mut checkpoint);
+    
+    let path = path();
+    if let Some(dir) = Path::new(&path).parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    if let Ok(content) = serde_json::to_string_pretty(&checkpoint) {
+        let _ = std::fs::write(&path, content + "\n");
+    }
+}
+
+// The phase a setup starts in, or the one it's waiting in
+pub fn phase() -> String {
+    load().map(|checkpoint| checkpoint.phase).filter(|phase| !phase.is_empty()).unwrap_or_else(|| "start".to_string())
+}
+
+pub fn set_phase(phase: &str) {
+    update(|checkpoint| checkpoint.phase = phase.to_string());
+}
+
+pub fn record_step(step: &str, secs: u64, usage: StepUsage) {
+    update(|checkpoint| {
+        checkpoint.durations.insert(step.to_string(), secs);
+        checkpoint.usage.retain(|(name, _)| name != step);
+        checkpoint.usage.push((step.to_string(), usage));
+    });
+}
+
+// An update's timings aren't part of any setup
+pub fn clear_steps() {
+    if load().is_some() {
+        update(|checkpoint| {
+            checkpoint.durations.clear();
+            checkpoint.usage.clear();
+        });
+    }
+}
+
+pub fn started(step: &str) {
+    update(|checkpoint| {
+        checkpoint.completed.retain(|done| done != step);
+        if !checkpoint.running.iter().any(|running| running == step) {
+            checkpoint.running.push(step.to_string());
+        }
+    });
+}
+
+pub fn completed(step: &str) {
+    update(|checkpoint| {
+        checkpoint.running.retain(|running| running != step);
+        checkpoint.completed.push(step.to_string());
+    });
+}
+
+pub fn set_first_login(first_login: FirstLogin) {
+    update(|checkpoint| checkpoint.first_login = Some(first_login));
+}
+
+pub fn is_completed(step: &str) -> bool {
+    load().is_some_and(|checkpoint| checkpoint.completed.iter().any(|done| done == step))
+}
+
+// The setup finished. First-login results outlive the run they belong to.
+pub fn clear() {
+    match load().and_then(|checkpoint| checkpoint.first_login) {
+        Some(first_login) => update(|checkpoint| {
+            *checkpoint = Checkpoint {
+                first_login: Some(first_login),
+                ..Checkpoint::default()
+            }
+        }),
+        None => {
+            let _ = std::fs::remove_file(path());
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::env;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Set in the re-executed child so it knows it's being supervised
+const CHILD_ENV: &str = "ASS_CI_CHILD";
+
+// Line the child prints when a step starts, swallowed by the parent
+const STEP_MARKER: &str = "\u{1e}ass-step ";
+
+// --ci, or CI=true as set by most pipeline runners
+pub fn requested(flag: bool) -> bool {
+    flag || env::var("CI").map(|v| v == "true" || v == "1").unwrap_or(false)
+}
+
+pub fn is_child() -> bool {
+    env::var_os(CHILD_ENV).is_some()
+}
+
+// Tell the supervising parent which step the following lines belong to
+pub fn mark_step(step: &str) {
+    if is_child() {
+        println!("{}{}", STEP_MARKER, step);
+        let _ = std::io::stdout().flush();
+    }
+}
+
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    utc(secs)
+}
+
+// UTC "YYYY-MM-DDTHH:MM:SSZ" without pulling in a date crate
+pub fn utc(secs: u64) -> String {
+    let (days, rest) = (secs / 86400, secs % 86400);
+    
+    // Civil-from-days (Howard Hinnant's algorithm)
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest / 60 % 60,
+        rest % 60
+    )
+}
+
+fn forward<R: Read + Send + 'static>(stream: R, to_stderr: bool, step: Arc<Mutex<String>>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            if let Some(name) = line.strip_prefix(STEP_MARKER) {
+                *step.lock().unwrap_or_else(|e| e.into_inner()) = name.to_string();
+                continue;
+            }
+            
+            // Progress output redraws with \r, only the final state is worth logging
+            let line = line.rsplit('\r').find(|part| !part.is_empty()).unwrap_or("");
+            let current = step.lock().unwrap_or_else(|e| e.into_inner()).clone();
+            let prefixed = format!("{} [{}] {}", timestamp(), current, line);
+            if to_stderr {
+                eprintln!("{}", prefixed);
+            } else {
+                println!("{}", prefixed);
+            }
+        }
+    })
+}
+
+// Run ourselves again with colors off and prefix every line the child (and
+// everything it spawns) prints with a timestamp and the current step
+pub fn supervise() -> ! {
+    let exe = env::current_exe().expect("Failed to find the ass executable");
+    let mut child = Command::new(exe)
+        .args(env::args_os().skip(1))
+        .env(CHILD_ENV, "1")
+        .env("NO_COLOR", "1")
+        .env("TERM", "dumb")
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to re-execute ass in CI mode");
+    
+    let step = Arc::new(Mutex::new("setup".to_string()));
+    let stdout = forward(child.stdout.take().expect("stdout is piped"), false, step.clone());
+    let stderr = forward(child.stderr.take().expect("stderr is piped"), true, step);
+    
+    let status = child.wait().expect("Failed to wait for ass");
+    let _ = stdout.join();
+    let _ = stderr.join();
+    std::process::exit(status.code().unwrap_or(1));
+}
@@ -0,0 +1,309 @@
+use crate::diagnosis::{self, Diagnosis};
+use crate::nixlog;
+use crate::output;
+use crate::plan;
+use crate::report::{self, Event};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+// A command to run, kept as data so the same spec drives dry-run output,
+// logging and the real execution
+pub struct Cmd {
+    program: OsString,
+    args: Vec<OsString>,
+    sudo: bool,
+    cwd: Option<PathBuf>,
+    envs: Vec<(OsString, OsString)>,
+    stdin_file: Option<PathBuf>,
+    quiet: bool,
+    network: bool,
+    diagnose: bool,
+    nix_build: bool,
+    live: bool,
+    escalates: bool,
+}
+
+impl Cmd {
+    pub fn new(program: impl AsRef<OsStr>) -> Self {
+        Cmd {
+            program: program.as_ref().to_os_string(),
+            args: Vec::new(),
+            sudo: false,
+            cwd: None,
+            envs: Vec::new(),
+            stdin_file: None,
+            quiet: false,
+            network: false,
+            diagnose: false,
+            nix_build: false,
+            live: false,
+            escalates: false,
+        }
+    }
+    
+    // Same as Cmd::new(program) but run through sudo
+    pub fn sudo(program: impl AsRef<OsStr>) -> Self {
+        Cmd { sudo: true, ..Cmd::new(program) }
+    }
+    
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.args.push(arg.as_ref().to_os_string());
+        self
+    }
+    
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args.extend(args.into_iter().map(|arg| arg.as_ref().to_os_string()));
+        self
+    }
+    
+    pub fn current_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.cwd = Some(dir.as_ref().to_path_buf());
+        self
+    }
+    
+    pub fn env(mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Self {
+        self.envs.push((key.as_ref().to_os_string(), value.as_ref().to_os_string()));
+        self
+    }
+    
+    // Feed a file to the command's stdin
+    pub fn stdin_file(mut self, path: impl AsRef<Path>) -> Self {
+        self.stdin_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+    
+    // Throw away stdout and stderr
+    pub fn quiet(mut self) -> Self {
+        self.quiet = true;
+        self
+    }
+    
+    // A network operation (clone, download, mirror sync): retried with
+    // backoff through run_with_retry before reporting failure
+    pub fn network(mut self) -> Self {
+        self.network = true;
+        self
+    }
+    
+    // Look through stderr for known failure causes (pacman and the AUR helpers)
+    pub fn diagnose(mut self) -> Self {
+        self.diagnose = true;
+        self
+    }
+    
+    // A Nix build (home-manager switch, nix-shell, ...): keep the full log of
+    // each derivation that fails to build
+    pub fn nix_build(mut self) -> Self {
+        self.nix_build = true;
+        self
+    }
+    
+    // Show the output as it comes even without --verbose, for commands that
+    // are a run of their own (ass as another user)
+    pub fn live(mut self) -> Self {
+        self.live = true;
+        self
+    }
+    
+    // Runs sudo itself partway through (makepkg -si, the AUR helpers), so the
+    // password is asked for before any spinner is drawn over the prompt
+    pub fn escalates(mut self) -> Self {
+        self.escalates = true;
+        self
+    }
+    
+    fn build(&self) -> std::io::Result<Command> {
+        let mut command = if self.sudo {
+            let mut command = Command::new("sudo");
+            command.arg(&self.program);
+            command
+        } else {
+            Command::new(&self.program)
+        };
+        command.args(&self.args);
+        
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+        for (key, value) in &self.envs {
+            command.env(key, value);
+        }
+        if let Some(path) = &self.stdin_file {
+            command.stdin(std::fs::File::open(path)?);
+        }
+        if self.quiet {
+            command.stdout(Stdio::null()).stderr(Stdio::null());
+        }
+        Ok(command)
+    }
+}
+
+// Shell-like rendering used for dry runs and logs
+impl fmt::Display for Cmd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let quote = |word: &OsStr| {
+            let word = word.to_string_lossy();
+            if !word.is_empty() && word.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=@+%,~".contains(c)) {
+                word.to_string()
+            } else {
+                format!("'{}'", word.replace('\'', r"'\''"))
+            }
+        };
+        
+        if let Some(cwd) = &self.cwd {
+            write!(f, "cd {} && ", quote(cwd.as_os_str()))?;
+        }
+        for (key, value) in &self.envs {
+            write!(f, "{}={} ", key.to_string_lossy(), quote(value))?;
+        }
+        if self.sudo {
+            write!(f, "sudo ")?;
+        }
+        write!(f, "{}", quote(&self.program))?;
+        for arg in &self.args {
+            write!(f, " {}", quote(arg))?;
+        }
+        if let Some(path) = &self.stdin_file {
+            write!(f, " < {}", quote(path.as_os_str()))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum CmdError {
+    // The program couldn't be started at all (not installed, bad cwd, ...)
+    Spawn(String),
+    // It ran and exited unsuccessfully; None when killed by a signal. With
+    // diagnose(), what its stderr said went wrong, with nix_build() where the
+    // logs of the derivations that failed were saved.
+    Failed(Option<i32>, Vec<Diagnosis>, Vec<String>),
+}
+
+impl CmdError {
+    pub fn hints(&self) -> Vec<&'static str> {
+        match self {
+            CmdError::Failed(_, diagnoses, _) => diagnoses.iter().map(|diagnosis| diagnosis.hint()).collect(),
+            CmdError::Spawn(_) => Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for CmdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CmdError::Spawn(e) => write!(f, "could not start: {}", e)?,
+            CmdError::Failed(Some(code), ..) => write!(f, "exit code {}", code)?,
+            CmdError::Failed(None, ..) => write!(f, "killed by a signal")?,
+        }
+        if let CmdError::Failed(_, diagnoses, _) = self
+            && !diagnoses.is_empty()
+        {
+            let causes: Vec<&str> = diagnoses.iter().map(|diagnosis| diagnosis.describe()).collect();
+            write!(f, ": {}", causes.join(", "))?;
+        }
+        if let CmdError::Failed(_, _, logs) = self
+            && !logs.is_empty()
+        {
+            write!(f, "; build log: {}", logs.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// How network operations are retried, from [network] retries and backoff
+#[derive(Clone, Copy)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub backoff: u64,
+}
+
+static RETRY_POLICY: OnceLock<RetryPolicy> = OnceLock::new();
+
+pub fn set_retry_policy(policy: RetryPolicy) {
+    let _ = RETRY_POLICY.set(policy);
+}
+
+// Run `operation` until it succeeds or the retries run out, waiting backoff
+// seconds before the first retry and twice as long before each one after it
+pub fn run_with_retry<T, E: fmt::Display>(what: &str, mut operation: impl FnMut() -> Result<T, E>) -> Result<T, E> {
+    let policy = *RETRY_POLICY.get_or_init(|| RetryPolicy { retries: 3, backoff: 2 });
+    let mut attempt = 0;
+    loop {
+        match operation() {
+            Err(e) if attempt < policy.retries => {
+                let wait = policy.backoff.saturating_mul(1 << attempt.min(16));
+                attempt += 1;
+                output::line(true, format!("↻ {} failed ({}), retrying in {}s ({}/{})", what, e, wait, attempt, policy.retries));
+                std::thread::sleep(Duration::from_secs(wait));
+            }
+            result => return result,
+        }
+    }
+}
+
+pub struct RunOptions {
+    pub dry_run: bool,
+    pub verbose: bool,
+}
+
+pub fn run(options: &RunOptions, cmd: &Cmd) -> Result<(), CmdError> {
+    if options.dry_run {
+        plan::add(plan::run(cmd));
+        return Ok(());
+    }
+    
+    if cmd.network {
+        run_with_retry(&cmd.to_string(), || run_once(options, cmd))
+    } else {
+        run_once(options, cmd)
+    }
+}
+
+fn run_once(options: &RunOptions, cmd: &Cmd) -> Result<(), CmdError> {
+    if options.verbose {
+        output::line(false, format!("$ {}", cmd));
+    }
+    
+    let mut command = cmd.build().map_err(|e| CmdError::Spawn(e.to_string()))?;
+    let keep_stderr = cmd.diagnose || cmd.nix_build;
+    let outcome = if cmd.live {
+        output::status_live(&mut command, keep_stderr)
+    } else if cmd.escalates {
+        output::status_escalating(&mut command, keep_stderr)
+    } else if keep_stderr {
+        output::status_with_stderr(&mut command)
+    } else {
+        output::status(&mut command).map(|status| (status, String::new()))
+    };
+    let result = match outcome {
+        Ok((status, _)) if status.success() => Ok(()),
+        Ok((status, stderr)) => Err(CmdError::Failed(
+            status.code(),
+            if cmd.diagnose { diagnosis::classify(&stderr) } else { Vec::new() },
+            if cmd.nix_build { nixlog::save_failed_logs(&stderr) } else { Vec::new() },
+        )),
+        Err(e) => Err(CmdError::Spawn(e.to_string())),
+    };
+    
+    let code = match &result {
+        Ok(()) => Some(0),
+        Err(CmdError::Failed(code, ..)) => *code,
+        Err(CmdError::Spawn(_)) => None,
+    };
+    report::emit(Event::Command {
+        command: &cmd.to_string(),
+        success: result.is_ok(),
+        code,
+        error: result.as_ref().err().map(|e| e.to_string()),
+    });
+    result
+}
@@ -0,0 +1,1332 @@
+use crate::facts;
+use crate::i18n::tr;
+use crate::paths;
+use crate::report;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
+// Settings read from ~/.config/ass/config.toml (or the file given with --config)
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    // desktop (default), minimal, server, gaming or one of [profiles]
+    pub profile: Option<String>,
+    // Built-in profiles adjusted, or new ones based on them
+    pub profiles: BTreeMap<String, ProfileConfig>,
+    // Steps not to run, by name (as shown in --ci output and timings)
+    pub skip: Vec<String>,
+    // How [users] are switched to: sudo (default) or machinectl, which gives
+    // them a full login session with their own user manager
+    pub switch_user: Option<String>,
+    // Other people to set up on this machine, by login name
+    pub users: BTreeMap<String, UserConfig>,
+    // wayland, x11 or both: which of the packages marked session= in the
+    // package list to install. Detected when unset.
+    pub session_type: Option<String>,
+    pub dotfiles: DotfilesConfig,
+    pub aur: AurConfig,
+    pub chaotic_aur: ChaoticAurConfig,
+    pub copr: CoprConfig,
+    pub keys: KeysConfig,
+    pub wallpapers: WallpapersConfig,
+    // Other repositories to keep on every machine, by name
+    pub repos: BTreeMap<String, RepoConfig>,
+    pub toolchains: ToolchainsConfig,
+    pub distrobox: DistroboxConfig,
+    pub mime: MimeConfig,
+    pub xdg_dirs: XdgDirsConfig,
+    pub keyboard: KeyboardConfig,
+    pub display_manager: DisplayManagerConfig,
+    pub autologin: AutologinConfig,
+    pub schedule: ScheduleConfig,
+    pub portal: PortalConfig,
+    pub polkit: PolkitConfig,
+    pub system_tweaks: SystemTweaksConfig,
+    pub grub: GrubConfig,
+    pub shared_drives: SharedDrivesConfig,
+    pub mounts: BTreeMap<String, MountConfig>,
+    pub automount: AutomountConfig,
+    pub smartd: SmartdConfig,
+    pub oom: OomConfig,
+    pub ssd: SsdConfig,
+    pub network: NetworkConfig,
+    pub nix: NixConfig,
+    pub home_manager: HomeManagerConfig,
+    pub reboot: RebootConfig,
+    pub first_login: FirstLoginConfig,
+    pub user_units: UserUnitsConfig,
+    pub git: GitConfig,
+    // Dotfiles packages copied outside $HOME with sudo: package = "/target/dir"
+    pub system_dotfiles: BTreeMap<String, String>,
+    pub files: BTreeMap<String, FileAssertion>,
+}
+
+pub const AUR_HELPERS: [&str; 3] = ["paru", "yay", "pikaur"];
+
+pub const EXISTING_HELPER_CHOICES: [&str; 3] = ["use", "replace", "coexist"];
+
+pub const RUST_SOURCES: [&str; 3] = ["auto", "rustup", "system"];
+
+pub const PACKAGE_SOURCES: [&str; 2] = ["binary", "aur"];
+
+pub const REBOOT_PAUSES: [&str; 3] = ["required", "recommended", "never"];
+
+pub const USER_SWITCHES: [&str; 2] = ["sudo", "machinectl"];
+
+pub const SESSION_TYPES: [&str; 3] = ["wayland", "x11", "both"];
+
+pub const POLKIT_AGENTS: [&str; 2] = ["hyprpolkitagent", "polkit-gnome"];
+
+pub const KEYBOARD_REMAPPERS: [&str; 2] = ["keyd", "kanata"];
+
+pub const OOM_METHODS: [&str; 2] = ["systemd-oomd", "earlyoom"];
+
+pub const VERSION_MANAGERS: [&str; 3] = ["fnm", "uv", "mise"];
+
+pub const REPO_UPDATES: [&str; 2] = ["clone", "pull"];
+
+pub const USER_UNIT_FALLBACKS: [&str; 3] = ["defer", "linger", "machinectl"];
+
+pub const REPORT_WHEN: [&str; 3] = ["always", "problems", "weekly"];
+
+pub const PROFILES: [&str; 4] = ["desktop", "minimal", "server", "gaming"];
+
+pub const DEFAULT_DOTFILES_REPO: &str = "https://github.com/jeebuscrossaint/dotfiles.git";
+
+pub const DEFAULT_WALLPAPER_REPOS: [&str; 16] = [
+    "https://github.com/rann01/IRIX-tiles",
+    "https://github.com/dharmx/walls",
+    "https://github.com/wallace-aph/tiles-and-such",
+    "https://github.com/tile-anon/tiles",
+    "https://github.com/whoisYoges/lwalpapers",
+    "https://github.com/D3Ext/aesthetic-wallpapers",
+    "https://github.com/peteroupc/classic-wallpaper",
+    "https://github.com/dixiedream/wallpapers",
+    "https://github.com/mylinuxforwork/wallpaper",
+    "https://github.com/makccr/wallpapers",
+    "https://github.com/Axenide/Wallpapers",
+    "https://github.com/l3ct3r/wallpapers",
+    "https://github.com/dmighty007/WallPapers",
+    "https://github.com/DenverCoder1/minimalistic-wallpaper-collection",
+    "https://github.com/BitterSweetcandyshop/wallpapers",
+    "https://github.com/linuxdotexe/nordic-wallpapers",
+];
+
+impl FileConfig {
+    pub fn profile(&self) -> &str {
+        self.profile.as_deref().unwrap_or("desktop")
+    }
+    
+    // The profile's settings, with [profiles] applied on top of the built-in
+    // one. None for a profile that doesn't exist.
+    pub fn profile_settings(&self) -> Option<Profile> {
+        let name = self.profile();
+        let custom = self.profiles.get(name);
+        let base = custom.and_then(|custom| custom.extends.as_deref()).unwrap_or(name);
+        let mut profile = match builtin_profile(base) {
+            Some(profile) => profile,
+            // A [profiles] entry of its own starts from desktop
+            None if custom.is_some() && base == name => builtin_profile("desktop")?,
+            None => return None,
+        };
+        if let Some(custom) = custom {
+            if let Some(desktop) = custom.desktop {
+                profile.desktop = desktop;
+            }
+            profile.skip.extend(custom.skip.iter().cloned());
+            profile.steps.extend(custom.steps.iter().cloned());
+            profile.exclude_groups.extend(custom.exclude_groups.iter().cloned());
+            profile.packages.extend(custom.packages.iter().cloned());
+        }
+        profile.name = name.to_string();
+        Some(profile)
+    }
+    
+    pub fn switch_user(&self) -> &str {
+        self.switch_user.as_deref().unwrap_or("sudo")
+    }
+}
+
+// [users.<name>] tables, unset keys fall back to the main user's settings
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct UserConfig {
+    pub dotfiles_repo: Option<String>,
+    pub dotfiles_branch: Option<String>,
+    // Dotfiles packages to stow
+    pub stow: Option<Vec<String>>,
+    // homeConfigurations.<name> to switch to
+    pub home_manager: Option<String>,
+}
+
+// [dotfiles] section
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DotfilesConfig {
+    pub repo: String,
+    // Branch to clone instead of the repo's default one
+    pub branch: Option<String>,
+    pub depth: Option<u32>,
+    pub filter: Option<String>,
+    // Packages to install, relative to the dotfiles repo unless absolute.
+    // archpkglist.txt, or aptpkglist.txt on Debian and Ubuntu, when unset.
+    pub package_list: Option<String>,
+    // Packages of the dotfiles repo to stow into the home
+    pub stow: Vec<String>,
+    // Where a package is stowed into instead of the home, e.g. "~/.config".
+    // A package can also name it in an .ass-target file of its own.
+    pub targets: BTreeMap<String, String>,
+}
+
+impl Default for DotfilesConfig {
+    fn default() -> Self {
+        DotfilesConfig {
+            repo: DEFAULT_DOTFILES_REPO.to_string(),
+            branch: None,
+            depth: None,
+            filter: None,
+            package_list: None,
+            stow: vec!["home-manager".to_string(), "nix".to_string()],
+            targets: BTreeMap::new(),
+        }
+    }
+}
+
+// Inside the home: "~", "~/dir" or relative to the home, without ".."
+pub fn valid_stow_target(target: &str) -> bool {
+    let relative = match target.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest.trim_start_matches('/'),
+        // ~user
+        Some(_) => return false,
+        None => target,
+    };
+    !relative.starts_with('/') && !relative.split('/').any(|part| part == "..")
+}
+
+impl DotfilesConfig {
+    pub fn package_list(&self) -> &str {
+        self.package_list.as_deref().unwrap_or_else(|| crate::pkg::backend().package_list())
+    }
+    
+    pub fn package_list_path(&self, dotfiles_path: &str) -> String {
+        let package_list = self.package_list();
+        match package_list.strip_prefix("~/") {
+            Some(rest) => paths::get().home(rest),
+            None if package_list.starts_with('/') => package_list.to_string(),
+            None => format!("{}/{}", dotfiles_path, package_list),
+        }
+    }
+    
+    pub fn clone_options(&self) -> CloneOptions {
+        CloneOptions {
+            depth: self.depth,
+            filter: self.filter.clone(),
+        }
+    }
+    
+    // git clone arguments: the clone options plus the branch, if any
+    pub fn clone_args(&self) -> Vec<String> {
+        let mut args = self.clone_options().git_args();
+        if let Some(branch) = &self.branch {
+            args.extend(["--branch".to_string(), branch.clone()]);
+        }
+        args
+    }
+}
+
+// How much of a repository to fetch. depth = 0 means full history; without
+// a depth the clone is shallow (1) unless a filter is set, since
+// "blob:none"/"tree:0" partial clones fetch history lazily anyway.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CloneOptions {
+    pub depth: Option<u32>,
+    pub filter: Option<String>,
+}
+
+impl CloneOptions {
+    pub fn git_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        match (self.depth, &self.filter) {
+            (Some(0), _) | (None, Some(_)) => {}
+            (Some(depth), _) => args.push(format!("--depth={}", depth)),
+            (None, None) => args.push("--depth=1".to_string()),
+        }
+        if let Some(filter) = &self.filter {
+            args.push(format!("--filter={}", filter));
+        }
+        args
+    }
+    
+    // Per-repo settings win over the section-wide ones
+    pub fn or(&self, fallback: &CloneOptions) -> CloneOptions {
+        CloneOptions {
+            depth: self.depth.or(fallback.depth),
+            filter: self.filter.clone().or_else(|| fallback.filter.clone()),
+        }
+    }
+}
+
+// [aur] section
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AurConfig {
+    // "paru", "yay" or "pikaur"
+    pub helper: String,
+    // What to do when a different helper is already installed: "use" it,
+    // "replace" it or "coexist" with it. Asked (and saved) when unset.
+    pub existing: Option<String>,
+    // Build AUR packages in a clean devtools chroot instead of on the host
+    // (paru only), so a broken or malicious PKGBUILD can't touch the system
+    pub chroot: bool,
+}
+
+impl Default for AurConfig {
+    fn default() -> Self {
+        AurConfig {
+            helper: "paru".to_string(),
+            existing: None,
+            chroot: false,
+        }
+    }
+}
+
+// [chaotic_aur] section
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChaoticAurConfig {
+    pub enable: bool,
+    // Base URLs serving chaotic-keyring/chaotic-mirrorlist, tried in order
+    pub mirrors: Vec<String>,
+    // For packages both chaotic-aur and the AUR provide: "binary" installs the
+    // prebuilt package (fast), "aur" builds it from the AUR (newest). Updates
+    // from chaotic-aur still apply to AUR-built packages on -Syu.
+    pub prefer: String,
+}
+
+impl Default for ChaoticAurConfig {
+    fn default() -> Self {
+        ChaoticAurConfig {
+            enable: true,
+            mirrors: vec!["https://cdn-mirror.chaotic.cx/chaotic-aur".to_string()],
+            prefer: "binary".to_string(),
+        }
+    }
+}
+
+// [copr] section, Fedora only
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct CoprConfig {
+    // "owner/project" (or "@group/project") repositories to enable before
+    // the package list is installed
+    pub repos: Vec<String>,
+}
+
+// [keys] section for pacman-key --recv-keys
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeysConfig {
+    // Tried in order until one hands out the key
+    pub keyservers: Vec<String>,
+    // Seconds before giving up on a keyserver
+    pub timeout: u64,
+    // Last resort per key ID: an HTTPS URL serving the armored key
+    pub urls: BTreeMap<String, String>,
+}
+
+impl Default for KeysConfig {
+    fn default() -> Self {
+        KeysConfig {
+            keyservers: vec![
+                "hkps://keyserver.ubuntu.com".to_string(),
+                "hkp://keyserver.ubuntu.com:80".to_string(),
+                "hkps://keys.openpgp.org".to_string(),
+            ],
+            timeout: 60,
+            urls: BTreeMap::new(),
+        }
+    }
+}
+
+// [wallpapers] section
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WallpapersConfig {
+    pub enable: bool,
+    // Repositories to clone, each into a directory of its name under dir
+    pub urls: Vec<String>,
+    // Where they go, absolute or starting with ~/ (e.g. "~/Pictures/wallpapers").
+    // Unset, the Pictures directory when the XDG dirs step manages it, else the home.
+    pub dir: Option<String>,
+    pub depth: Option<u32>,
+    pub filter: Option<String>,
+    // Overrides keyed by repository name, e.g. [wallpapers.repos.walls]
+    pub repos: BTreeMap<String, CloneOptions>,
+}
+
+impl Default for WallpapersConfig {
+    fn default() -> Self {
+        WallpapersConfig {
+            enable: true,
+            urls: DEFAULT_WALLPAPER_REPOS.iter().map(|url| url.to_string()).collect(),
+            dir: None,
+            depth: None,
+            filter: None,
+            repos: BTreeMap::new(),
+        }
+    }
+}
+
+impl WallpapersConfig {
+    pub fn dir_path(&self) -> Option<String> {
+        let dir = self.dir.as_ref()?;
+        Some(match dir.strip_prefix("~/") {
+            Some(rest) => paths::get().home(rest.trim_end_matches('/')),
+            None => dir.trim_end_matches('/').to_string(),
+        })
+    }
+    
+    pub fn clone_options(&self, repo_name: &str) -> CloneOptions {
+        let section = CloneOptions {
+            depth: self.depth,
+            filter: self.filter.clone(),
+        };
+        match self.repos.get(repo_name) {
+            Some(options) => options.or(&section),
+            None => section,
+        }
+    }
+}
+
+// [repos.<name>] entries: repositories wanted next to the dotfiles on every
+// machine (notes, scripts, fonts, ...). The [wallpapers] repositories are
+// added to these.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RepoConfig {
+    pub url: String,
+    // Absolute, or starting with ~/
+    pub dest: String,
+    #[serde(default)]
+    pub depth: Option<u32>,
+    #[serde(default)]
+    pub filter: Option<String>,
+    // "clone" once and leave it alone afterwards, or "pull" (fast-forward)
+    // it on every run and on ass update
+    #[serde(default = "default_repo_update")]
+    pub update: String,
+    // Only cloned on machines whose facts meet it, e.g. "chassis=desktop"
+    #[serde(default)]
+    pub when: Option<String>,
+}
+
+fn default_repo_update() -> String {
+    "clone".to_string()
+}
+
+impl RepoConfig {
+    pub fn dest_path(&self) -> String {
+        match self.dest.strip_prefix("~/") {
+            Some(rest) => paths::get().home(rest),
+            None => self.dest.clone(),
+        }
+    }
+    
+    pub fn clone_options(&self) -> CloneOptions {
+        CloneOptions {
+            depth: self.depth,
+            filter: self.filter.clone(),
+        }
+    }
+}
+
+// [profiles.<name>] entries
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProfileConfig {
+    // Built-in profile to start from, desktop by default
+    pub extends: Option<String>,
+    // Desktop integration: portal, polkit agent, display manager, wallpapers...
+    pub desktop: Option<bool>,
+    // Steps to leave out
+    pub skip: Vec<String>,
+    // Opt-in steps to run (setup_gaming)
+    pub steps: Vec<String>,
+    // Groups of the package list ("[name]" lines) not to install
+    pub exclude_groups: Vec<String>,
+    // Installed on top of the package list
+    pub packages: Vec<String>,
+}
+
+// What a profile selects, built-in settings and [profiles] combined
+#[derive(Default)]
+pub struct Profile {
+    pub name: String,
+    // The built-in profile it is based on
+    pub base: String,
+    pub desktop: bool,
+    pub skip: Vec<String>,
+    pub steps: Vec<String>,
+    pub exclude_groups: Vec<String>,
+    pub packages: Vec<String>,
+}
+
+fn builtin_profile(name: &str) -> Option<Profile> {
+    let strings = |items: &[&str]| items.iter().map(|item| item.to_string()).collect::<Vec<String>>();
+    let (desktop, steps, exclude_groups): (bool, &[&str], &[&str]) = match name {
+        "desktop" => (true, &[], &["gaming"]),
+        "minimal" => (false, &[], &["gaming"]),
+        // No wallpapers or graphical packages at all
+        "server" => (false, &[], &["gui", "gaming"]),
+        "gaming" => (true, &["setup_gaming"], &[]),
+        _ => return None,
+    };
+    Some(Profile {
+        name: name.to_string(),
+        base: name.to_string(),
+        desktop,
+        skip: Vec::new(),
+        steps: strings(steps),
+        exclude_groups: strings(exclude_groups),
+        packages: Vec::new(),
+    })
+}
+
+// [toolchains] section, one optional table per language
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToolchainsConfig {
+    pub node: Option<Toolchain>,
+    pub python: Option<Toolchain>,
+    pub go: Option<Toolchain>,
+    pub rust: RustToolchain,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Toolchain {
+    // Version manager to use (fnm, uv or mise), defaults per language
+    pub manager: Option<String>,
+    pub versions: Vec<String>,
+    // Version to set as the global default, defaults to the first one listed
+    pub default: Option<String>,
+}
+
+impl Toolchain {
+    pub fn manager_for(&self, language: &str) -> &str {
+        self.manager.as_deref().unwrap_or(match language {
+            "node" => "fnm",
+            "python" => "uv",
+            _ => "mise",
+        })
+    }
+    
+    pub fn default_version(&self) -> Option<&str> {
+        self.default
+            .as_deref()
+            .or_else(|| self.versions.first().map(|s| s.as_str()))
+    }
+}
+
+// [toolchains.rust]
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RustToolchain {
+    // Where cargo and rustc come from: "auto" (keep whatever is installed,
+    // rustup otherwise), "rustup" or "system" (the rust package)
+    pub source: String,
+    // Made the rustup default, only when no default is set yet
+    pub default_toolchain: String,
+    // rustup components, e.g. clippy, rustfmt, rust-analyzer
+    pub components: Vec<String>,
+    // Crates installed with cargo-binstall
+    pub cargo_tools: Vec<String>,
+}
+
+impl Default for RustToolchain {
+    fn default() -> Self {
+        RustToolchain {
+            source: "auto".to_string(),
+            default_toolchain: "stable".to_string(),
+            components: Vec::new(),
+            cargo_tools: Vec::new(),
+        }
+    }
+}
+
+// [distrobox] section, containers are only created when some are listed
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DistroboxConfig {
+    // Container engine to install alongside distrobox (podman or docker)
+    pub engine: String,
+    pub containers: Vec<DistroboxContainer>,
+}
+
+impl Default for DistroboxConfig {
+    fn default() -> Self {
+        DistroboxConfig {
+            engine: "podman".to_string(),
+            containers: Vec::new(),
+        }
+    }
+}
+
+// [[distrobox.containers]] entries
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DistroboxContainer {
+    pub name: String,
+    pub image: String,
+    #[serde(default)]
+    pub packages: Vec<String>,
+    // Desktop apps exported to the host menu
+    #[serde(default)]
+    pub export_apps: Vec<String>,
+    // Binaries exported to ~/.local/bin
+    #[serde(default)]
+    pub export_bins: Vec<String>,
+}
+
+// [mime] section, desktop file ids for common roles plus raw MIME overrides
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MimeConfig {
+    pub browser: Option<String>,
+    pub pdf: Option<String>,
+    pub image: Option<String>,
+    // Used through xdg-terminal-exec rather than a MIME type
+    pub terminal: Option<String>,
+    // Extra "mime/type" = "app.desktop" entries
+    pub types: BTreeMap<String, String>,
+}
+
+// [xdg_dirs] section, names are relative to $HOME
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct XdgDirsConfig {
+    pub enable: bool,
+    // Use desktop, downloads, ... instead of Desktop, Downloads, ...
+    pub lowercase: bool,
+    // Per-directory overrides keyed by desktop, download, templates,
+    // publicshare, documents, music, pictures or videos
+    pub names: BTreeMap<String, String>,
+}
+
+// (config key, XDG name, default directory)
+pub const XDG_USER_DIRS: [(&str, &str, &str); 8] = [
+    ("desktop", "DESKTOP", "Desktop"),
+    ("download", "DOWNLOAD", "Downloads"),
+    ("templates", "TEMPLATES", "Templates"),
+    ("publicshare", "PUBLICSHARE", "Public"),
+    ("documents", "DOCUMENTS", "Documents"),
+    ("music", "MUSIC", "Music"),
+    ("pictures", "PICTURES", "Pictures"),
+    ("videos", "VIDEOS", "Videos"),
+];
+
+impl XdgDirsConfig {
+    // Directory name (relative to $HOME) for a config key like "pictures"
+    pub fn dir_name(&self, key: &str) -> Option<String> {
+        if let Some(name) = self.names.get(key) {
+            return Some(name.clone());
+        }
+        
+        let (_, _, default) = XDG_USER_DIRS.iter().find(|(k, _, _)| *k == key)?;
+        if self.lowercase {
+            Some(default.to_lowercase())
+        } else {
+            Some(default.to_string())
+        }
+    }
+}
+
+// [keyboard] section for keyd/kanata remapping
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyboardConfig {
+    // "keyd" or "kanata", nothing is installed when unset
+    pub remapper: Option<String>,
+    // Directory inside the dotfiles repo holding the remapper config,
+    // defaults to the remapper's name
+    pub config_dir: Option<String>,
+}
+
+// [display_manager] section, nothing is enabled when name is unset
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayManagerConfig {
+    // "greetd" or "sddm"
+    pub name: Option<String>,
+    // Session command for greetd, detected from installed compositors when unset
+    pub session: Option<String>,
+    // sddm theme name
+    pub theme: Option<String>,
+    // Run sddm's greeter on Wayland instead of X11
+    pub wayland: bool,
+}
+
+// [autologin] section: getty logs the user in and the session starts on
+// login, for kiosk/HTPC machines without a display manager
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutologinConfig {
+    pub enable: bool,
+    // Defaults to the user running ass
+    pub user: Option<String>,
+    pub tty: String,
+    // Session command, detected from installed compositors when unset
+    pub session: Option<String>,
+    // "profile" (exec from the login shell's profile) or "systemd" (a user
+    // unit, for sessions such as cage that don't need the tty)
+    pub start: String,
+}
+
+impl Default for AutologinConfig {
+    fn default() -> Self {
+        AutologinConfig {
+            enable: false,
+            user: None,
+            tty: "tty1".to_string(),
+            session: None,
+            start: "profile".to_string(),
+        }
+    }
+}
+
+// [schedule] section, used by `ass schedule`
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScheduleConfig {
+    // systemd OnCalendar expression, empty for no timer (start it by hand)
+    pub at: String,
+    // systemd time span after which a run is stopped
+    pub max_runtime: String,
+    // Where scheduled runs send their report, through sendmail and as a
+    // JSON POST; nothing is sent when neither is set
+    pub report_email: Option<String>,
+    pub report_webhook: Option<String>,
+    // always, problems (failures and warnings) or weekly (problems right
+    // away, otherwise once a week)
+    pub report: String,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        ScheduleConfig {
+            at: "*-*-* 03:00:00".to_string(),
+            max_runtime: "2h".to_string(),
+            report_email: None,
+            report_webhook: None,
+            report: "weekly".to_string(),
+        }
+    }
+}
+
+// [portal] section
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct PortalConfig {
+    // hyprland, wlr, gnome, kde or gtk, detected from installed compositors when unset
+    pub desktop: Option<String>,
+}
+
+// [polkit] section
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolkitConfig {
+    // hyprpolkitagent or polkit-gnome, picked from the session type when unset
+    pub agent: Option<String>,
+}
+
+// [system_tweaks] section for small per-machine settings
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SystemTweaksConfig {
+    // Turn numlock on at boot (TTYs and sddm)
+    pub numlock: bool,
+    // Console font for /etc/vconsole.conf, e.g. "ter-v16n"
+    pub console_font: Option<String>,
+    // Console key repeat delay (ms) and rate (characters per second)
+    pub repeat_delay: Option<u32>,
+    pub repeat_rate: Option<u32>,
+}
+
+// [grub] section
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct GrubConfig {
+    // Theme directory relative to the dotfiles repo, an absolute path or a git URL
+    pub theme: Option<String>,
+    // For GitHub theme URLs: release tag to check out, or "latest" (pinned)
+    pub theme_version: Option<String>,
+    // Enable os-prober; when unset it's enabled if Windows is detected
+    pub os_prober: Option<bool>,
+}
+
+// [shared_drives] section for NTFS/exFAT partitions from other systems
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SharedDrivesConfig {
+    pub enable: bool,
+    // "fstab" (mounted under mount_root at boot) or "udisks" (mounted on demand under /media)
+    pub mount_method: String,
+    pub mount_root: String,
+}
+
+impl Default for SharedDrivesConfig {
+    fn default() -> Self {
+        SharedDrivesConfig {
+            enable: false,
+            mount_method: "fstab".to_string(),
+            mount_root: "/mnt".to_string(),
+        }
+    }
+}
+
+// [mounts.<name>] entries for network shares
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MountConfig {
+    // "nfs" or "cifs"
+    #[serde(rename = "type")]
+    pub fs_type: String,
+    // "server:/export" for NFS, "//server/share" for CIFS
+    pub source: String,
+    pub target: String,
+    #[serde(default)]
+    pub options: Option<String>,
+    // Mount on first access instead of at boot
+    #[serde(default = "default_true")]
+    pub automount: bool,
+    // CIFS credentials, the password is prompted for when not set
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub domain: Option<String>,
+}
+
+// [files.<name>] entries: a whole file to drop in place, or one line that
+// must be present (replacing the line starting with `matches` if there is one).
+// {{ hostname }} and the other facts in content or line are filled in.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileAssertion {
+    // Absolute, or starting with ~/
+    pub path: String,
+    #[serde(default)]
+    pub content: Option<String>,
+    #[serde(default)]
+    pub line: Option<String>,
+    #[serde(default)]
+    pub matches: Option<String>,
+    // "user:group", applied with chown
+    #[serde(default)]
+    pub owner: Option<String>,
+    // Octal, e.g. "644"
+    #[serde(default)]
+    pub mode: Option<String>,
+    // Write as root; defaults to true outside $HOME
+    #[serde(default)]
+    pub sudo: Option<bool>,
+    // Only asserted on machines whose facts meet it, e.g. "gpu=nvidia"
+    #[serde(default)]
+    pub when: Option<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+// [automount] section for removable media
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutomountConfig {
+    pub enable: bool,
+    // "udiskie" or "gvfs", picked from the desktop environment when unset
+    pub helper: Option<String>,
+}
+
+// [smartd] section for disk health monitoring
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmartdConfig {
+    pub enable: bool,
+    // Where failure mails go (needs a working MTA)
+    pub email: Option<String>,
+    // Desktop notification for logged in users
+    pub notify: bool,
+}
+
+// [oom] section for out-of-memory protection
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OomConfig {
+    // "systemd-oomd" or "earlyoom", nothing is set up when unset
+    pub method: Option<String>,
+    // systemd-oomd: kill a user's cgroup above this memory pressure
+    pub memory_pressure_limit: String,
+    // earlyoom: act when available memory drops below this percentage
+    pub min_free_percent: u32,
+}
+
+impl Default for OomConfig {
+    fn default() -> Self {
+        OomConfig {
+            method: None,
+            memory_pressure_limit: "50%".to_string(),
+            min_free_percent: 5,
+        }
+    }
+}
+
+// [ssd] section for TRIM and I/O scheduler settings
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SsdConfig {
+    pub enable: bool,
+    // When set, a udev rule applies these schedulers (e.g. "none", "mq-deadline", "bfq")
+    pub ssd_scheduler: Option<String>,
+    pub hdd_scheduler: Option<String>,
+}
+
+// [nix] section
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NixConfig {
+    // Seconds to wait for nix-daemon to accept connections after starting it
+    pub daemon_timeout: u64,
+    // "nix" (the official install script) or "determinate"
+    pub installer: String,
+    // Determinate installer release tag, or "latest" (resolved once and pinned)
+    pub installer_version: String,
+    // Installed into the user profile with `nix profile install`, for what
+    // home-manager doesn't manage: nixpkgs attributes or flake#attr
+    pub packages: Vec<String>,
+}
+
+impl Default for NixConfig {
+    fn default() -> Self {
+        NixConfig {
+            daemon_timeout: 30,
+            installer: "nix".to_string(),
+            installer_version: "latest".to_string(),
+            packages: Vec::new(),
+        }
+    }
+}
+
+// [home_manager] section
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct HomeManagerConfig {
+    // homeConfigurations.<name> to switch to; uses home-manager's own default when unset
+    pub configuration: Option<String>,
+    // Flake directory, defaults to ~/.config/home-manager
+    pub flake: Option<String>,
+}
+
+// [network] section, the connectivity check before anything is downloaded
+// and how downloads, clones and key fetches are retried
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    pub check: bool,
+    // Seconds each probe (DNS, ping, HTTPS) may take
+    pub timeout: u64,
+    // Probed instead of the distribution's mirror host and github.com
+    pub hosts: Vec<String>,
+    // Times a failed network operation is tried again before giving up
+    pub retries: u32,
+    // Seconds before the first retry, doubled for each one after it
+    pub backoff: u64,
+    // Repositories ([repos] and wallpapers) cloned at the same time
+    pub jobs: usize,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            check: true,
+            timeout: 5,
+            hosts: Vec::new(),
+            retries: 3,
+            backoff: 2,
+            jobs: 4,
+        }
+    }
+}
+
+// [reboot] section
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RebootConfig {
+    // Stop the run when a step needs a reboot "required" (default), also when
+    // one is only "recommended", or "never" and just warn
+    pub pause: String,
+    // Carry on by itself after the reboot through ass-resume.service
+    pub resume: bool,
+}
+
+impl Default for RebootConfig {
+    fn default() -> Self {
+        RebootConfig {
+            pause: "required".to_string(),
+            resume: true,
+        }
+    }
+}
+
+// [first_login] section, actions that need the graphical session and run
+// from a user unit at the first login after the setup
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FirstLoginConfig {
+    pub enable: bool,
+    // "schema.key" = "value" for gsettings set, values in GVariant syntax
+    // ("'prefer-dark'", "true", "24")
+    pub gsettings: BTreeMap<String, String>,
+}
+
+impl Default for FirstLoginConfig {
+    fn default() -> Self {
+        FirstLoginConfig {
+            enable: true,
+            gsettings: BTreeMap::new(),
+        }
+    }
+}
+
+// [user_units] section, how user units are enabled when there's no session
+// bus yet (a fresh install run from a TTY, before anyone logged in)
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UserUnitsConfig {
+    // "defer" links them into their target by hand to start at the first
+    // login, "linger" starts the user manager with loginctl enable-linger,
+    // "machinectl" enables them through a machinectl shell session
+    pub fallback: String,
+    // Seconds to wait for the user manager's bus to come up
+    pub wait: u64,
+}
+
+impl Default for UserUnitsConfig {
+    fn default() -> Self {
+        UserUnitsConfig {
+            fallback: "defer".to_string(),
+            wait: 10,
+        }
+    }
+}
+
+// [git] section, who commits made by ass are from (the repo from ass
+// dotfiles init), for machines where git's user.name and user.email aren't
+// set yet. Git's own identity is used for what's unset here.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitConfig {
+    pub name: Option<String>,
+    pub email: Option<String>,
+}
+
+static CONFIG_PATH: OnceLock<String> = OnceLock::new();
+
+// Use --config instead of the default location
+pub fn set_config_path(path: Option<String>) {
+    if let Some(path) = path {
+        let _ = CONFIG_PATH.set(path);
+    }
+}
+
+pub fn config_path() -> String {
+    CONFIG_PATH.get().cloned().unwrap_or_else(|| paths::get().config("ass/config.toml"))
+}
+
+// Files ass keeps between runs ($XDG_STATE_HOME/ass/<name>)
+pub fn state_path(name: &str) -> String {
+    format!("{}/ass/{}", paths::get().state_home, name)
+}
+
+// Missing config file is fine (everything is optional), a broken one is not
+pub fn load_file_config(path: &str) -> FileConfig {
+    if !Path::new(path).exists() {
+        return FileConfig::default();
+    }
+    
+    let content = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        report::fatal(tr!("Failed to read config file {}: {}", path, e));
+    });
+    
+    let file_config: FileConfig = toml::from_str(&content).unwrap_or_else(|e| {
+        report::fatal(tr!("Invalid config file {}: {}", path, e));
+    });
+    
+    for (name, profile) in &file_config.profiles {
+        if let Some(extends) = &profile.extends
+            && !PROFILES.contains(&extends.as_str())
+        {
+            report::fatal(format!("profiles.{}.extends in {} is '{}' (expected {})", name, path, extends, PROFILES.join(", ")));
+        }
+    }
+    if file_config.profile_settings().is_none() {
+        report::fatal(format!("Unknown profile '{}' in {} (expected {} or one of [profiles])", file_config.profile(), path, PROFILES.join(", ")));
+    }
+    if !AUR_HELPERS.contains(&file_config.aur.helper.as_str()) {
+        report::fatal(format!("Unknown AUR helper '{}' in {} (expected {})", file_config.aur.helper, path, AUR_HELPERS.join(", ")));
+    }
+    if file_config.aur.chroot && file_config.aur.helper != "paru" {
+        report::fatal(format!("aur.chroot in {} needs aur.helper = \"paru\" ({} can't build in a chroot)", path, file_config.aur.helper));
+    }
+    if let Some(existing) = &file_config.aur.existing
+        && !EXISTING_HELPER_CHOICES.contains(&existing.as_str())
+    {
+        report::fatal(format!("Unknown aur.existing '{}' in {} (expected {})", existing, path, EXISTING_HELPER_CHOICES.join(", ")));
+    }
+    let filters = file_config
+        .wallpapers
+        .repos
+        .values()
+        .filter_map(|options| options.filter.as_ref())
+        .chain(file_config.wallpapers.filter.as_ref())
+        .chain(file_config.repos.values().filter_map(|repo| repo.filter.as_ref()))
+        .chain(file_config.dotfiles.filter.as_ref());
+    for filter in filters {
+        if !filter.starts_with("blob:") && !filter.starts_with("tree:") {
+            report::fatal(format!("Unsupported clone filter '{}' in {} (expected blob:none, blob:limit=<n> or tree:0)", filter, path));
+        }
+    }
+    let rust = &file_config.toolchains.rust;
+    if !RUST_SOURCES.contains(&rust.source.as_str()) {
+        report::fatal(format!("Unknown toolchains.rust.source '{}' in {} (expected {})", rust.source, path, RUST_SOURCES.join(", ")));
+    }
+    if file_config.chaotic_aur.enable && file_config.chaotic_aur.mirrors.is_empty() {
+        report::fatal(format!("chaotic_aur.mirrors in {} is empty", path));
+    }
+    if !PACKAGE_SOURCES.contains(&file_config.chaotic_aur.prefer.as_str()) {
+        report::fatal(format!(
+            "Unknown chaotic_aur.prefer '{}' in {} (expected {})",
+            file_config.chaotic_aur.prefer,
+            path,
+            PACKAGE_SOURCES.join(", ")
+        ));
+    }
+    for repo in &file_config.copr.repos {
+        if !repo.split_once('/').is_some_and(|(owner, project)| owner != "@" && !owner.is_empty() && !project.is_empty() && !project.contains('/')) {
+            report::fatal(format!("Invalid copr.repos entry '{}' in {} (expected owner/project or @group/project)", repo, path));
+        }
+    }
+    for (package, target) in &file_config.dotfiles.targets {
+        if !valid_stow_target(target) {
+            report::fatal(format!(
+                "dotfiles.targets.{} in {} is '{}' (expected a directory in the home, like ~/.config; see [system_dotfiles] for the rest)",
+                package, path, target
+            ));
+        }
+    }
+    for package in &file_config.nix.packages {
+        if package.is_empty() || package.ends_with('#') || (!package.contains('#') && package.contains(':')) {
+            report::fatal(format!("Invalid nix.packages entry '{}' in {} (expected a nixpkgs attribute or flake#attr)", package, path));
+        }
+    }
+    if let Some(session_type) = &file_config.session_type
+        && !SESSION_TYPES.contains(&session_type.as_str())
+    {
+        report::fatal(format!("Unknown session_type '{}' in {} (expected {})", session_type, path, SESSION_TYPES.join(", ")));
+    }
+    if let Some(agent) = &file_config.polkit.agent
+        && !POLKIT_AGENTS.contains(&agent.as_str())
+    {
+        report::fatal(format!("Unknown polkit.agent '{}' in {} (expected {})", agent, path, POLKIT_AGENTS.join(", ")));
+    }
+    if let Some(remapper) = &file_config.keyboard.remapper
+        && !KEYBOARD_REMAPPERS.contains(&remapper.as_str())
+    {
+        report::fatal(format!("Unknown keyboard.remapper '{}' in {} (expected {})", remapper, path, KEYBOARD_REMAPPERS.join(", ")));
+    }
+    if let Some(method) = &file_config.oom.method
+        && !OOM_METHODS.contains(&method.as_str())
+    {
+        report::fatal(format!("Unknown oom.method '{}' in {} (expected {})", method, path, OOM_METHODS.join(", ")));
+    }
+    let toolchains = &file_config.toolchains;
+    for (language, toolchain) in [("node", &toolchains.node), ("python", &toolchains.python), ("go", &toolchains.go)] {
+        if let Some(manager) = toolchain.as_ref().and_then(|toolchain| toolchain.manager.as_deref())
+            && !VERSION_MANAGERS.contains(&manager)
+        {
+            report::fatal(format!(
+                "Unknown toolchains.{}.manager '{}' in {} (expected {})",
+                language,
+                manager,
+                path,
+                VERSION_MANAGERS.join(", ")
+            ));
+        }
+    }
+    if !USER_SWITCHES.contains(&file_config.switch_user()) {
+        report::fatal(format!(
+            "Unknown switch_user '{}' in {} (expected {})",
+            file_config.switch_user(),
+            path,
+            USER_SWITCHES.join(", ")
+        ));
+    }
+    if !REBOOT_PAUSES.contains(&file_config.reboot.pause.as_str()) {
+        report::fatal(format!(
+            "Unknown reboot.pause '{}' in {} (expected {})",
+            file_config.reboot.pause,
+            path,
+            REBOOT_PAUSES.join(", ")
+        ));
+    }
+    if !USER_UNIT_FALLBACKS.contains(&file_config.user_units.fallback.as_str()) {
+        report::fatal(format!(
+            "Unknown user_units.fallback '{}' in {} (expected {})",
+            file_config.user_units.fallback,
+            path,
+            USER_UNIT_FALLBACKS.join(", ")
+        ));
+    }
+    if file_config.git.name.as_deref().is_some_and(|name| name.trim().is_empty()) {
+        report::fatal(format!("git.name in {} is empty", path));
+    }
+    if let Some(email) = &file_config.git.email
+        && (email.trim().is_empty() || email.contains(['<', '>', '\n']))
+    {
+        report::fatal(format!("Invalid git.email '{}' in {}", email, path));
+    }
+    if !REPORT_WHEN.contains(&file_config.schedule.report.as_str()) {
+        report::fatal(format!(
+            "Unknown schedule.report '{}' in {} (expected {})",
+            file_config.schedule.report,
+            path,
+            REPORT_WHEN.join(", ")
+        ));
+    }
+    if let Some(email) = &file_config.schedule.report_email
+        && (!email.contains('@') || email.contains(['<', '>', '\n', ' ']))
+    {
+        report::fatal(format!("Invalid schedule.report_email '{}' in {}", email, path));
+    }
+    if let Some(webhook) = &file_config.schedule.report_webhook
+        && !webhook.starts_with("https://")
+        && !webhook.starts_with("http://")
+    {
+        report::fatal(format!("schedule.report_webhook '{}' in {} must be an http(s) URL", webhook, path));
+    }
+    for key in file_config.first_login.gsettings.keys() {
+        if !key.contains('.') {
+            report::fatal(format!("first_login.gsettings key '{}' in {} must be schema.key", key, path));
+        }
+    }
+    if file_config.nix.installer != "nix" && file_config.nix.installer != "determinate" {
+        report::fatal(format!("Unknown nix.installer '{}' in {} (expected nix or determinate)", file_config.nix.installer, path));
+    }
+    for (name, file) in &file_config.files {
+        let template = file.content.as_deref().or(file.line.as_deref()).unwrap_or_default();
+        let problem = if file.content.is_some() == file.line.is_some() {
+            Some("needs exactly one of content or line".to_string())
+        } else if file.matches.is_some() && file.line.is_none() {
+            Some("matches only works together with line".to_string())
+        } else if !file.path.starts_with('/') && !file.path.starts_with("~/") {
+            Some("path must be absolute or start with ~/".to_string())
+        } else if file.mode.as_ref().is_some_and(|mode| u32::from_str_radix(mode, 8).is_err()) {
+            Some("mode must be octal, e.g. \"644\"".to_string())
+        } else if let Err(e) = facts::check_template(template) {
+            Some(e)
+        } else if let Some(condition) = &file.when
+            && let Err(e) = facts::check(condition)
+        {
+            Some(format!("has when = \"{}\": {}", condition, e))
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            report::fatal(format!("files.{} in {} {}", name, path, problem));
+        }
+    }
+    if let Some(dir) = &file_config.wallpapers.dir
+        && !dir.starts_with('/')
+        && !dir.starts_with("~/")
+    {
+        report::fatal(format!("wallpapers.dir in {} must be absolute or start with ~/", path));
+    }
+    if let Some(url) = file_config.wallpapers.urls.iter().find(|url| url.trim_end_matches('/').rsplit('/').next().unwrap_or("").is_empty()) {
+        report::fatal(format!("wallpapers.urls in {} has '{}', which doesn't end in a repository name", path, url));
+    }
+    for (name, repo) in &file_config.repos {
+        let problem = if repo.url.is_empty() {
+            Some("needs a url".to_string())
+        } else if !repo.dest.starts_with('/') && !repo.dest.starts_with("~/") {
+            Some("dest must be absolute or start with ~/".to_string())
+        } else if !REPO_UPDATES.contains(&repo.update.as_str()) {
+            Some(format!("has unknown update '{}' (expected {})", repo.update, REPO_UPDATES.join(", ")))
+        } else if let Some(condition) = &repo.when
+            && let Err(e) = facts::check(condition)
+        {
+            Some(format!("has when = \"{}\": {}", condition, e))
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            report::fatal(format!("repos.{} in {} {}", name, path, problem));
+        }
+    }
+    let autologin = &file_config.autologin;
+    if autologin.start != "profile" && autologin.start != "systemd" {
+        report::fatal(format!("Unknown autologin.start '{}' in {} (expected profile or systemd)", autologin.start, path));
+    }
+    if autologin.enable && file_config.display_manager.name.is_some() {
+        report::fatal(format!("[autologin] and [display_manager] in {} both start the session, enable only one", path));
+    }
+    if !autologin.tty.starts_with("tty") {
+        report::fatal(format!("autologin.tty '{}' in {} should look like tty1", autologin.tty, path));
+    }
+    for (package, target) in &file_config.system_dotfiles {
+        if !target.starts_with('/') {
+            report::fatal(format!("system_dotfiles.{} in {} must be an absolute path", package, path));
+        }
+    }
+    
+    file_config
+}
+
+// Set (section, key) pairs in the config file, "" meaning top level. The
+// existing document is edited so hand-written sections survive.
+pub fn write_config_values(path: &str, values: &[(&str, &str, toml::Value)]) {
+    let mut document: toml::Table = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| content.parse().ok())
+        .unwrap_or_default();
+    
+    for (section, key, value) in values {
+        let table = if section.is_empty() {
+            &mut document
+        } else {
+            match document
+                .entry(section.to_string())
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                .as_table_mut()
+            {
+                Some(table) => table,
+                None => continue,
+            }
+        };
+        table.insert(key.to_string(), value.clone());
+    }
+    
+    let content = toml::to_string(&document).expect("Failed to serialize config");
+    
+    if let Some(dir) = Path::new(path).parent() {
+        std::fs::create_dir_all(dir).unwrap_or_else(|e| {
+            report::fatal(format!("Failed to create {}: {}", dir.display(), e));
+        });
+    }
+    std::fs::write(path, content).unwrap_or_else(|e| {
+        report::fatal(format!("Failed to write {}: {}", path, e));
+    });
+}
@@ -0,0 +1,72 @@
+use std::path::Path;
+use std::process::Command;
+
+// Where the setup is running, as reported by systemd-detect-virt
+#[derive(Clone, PartialEq, Debug)]
+pub enum RunContext {
+    BareMetal,
+    // Hypervisor name, e.g. "kvm", "vmware", "oracle", "microsoft"
+    Vm(String),
+    // Container manager, e.g. "docker", "podman", "systemd-nspawn"
+    Container(String),
+}
+
+fn detect_virt(flag: &str) -> Option<String> {
+    let output = Command::new("systemd-detect-virt").arg(flag).output().ok()?;
+    let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if output.status.success() && !name.is_empty() && name != "none" {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+impl RunContext {
+    pub fn detect() -> Self {
+        if let Some(name) = detect_virt("--container") {
+            return RunContext::Container(name);
+        }
+        // Minimal container images often don't ship systemd at all
+        if Path::new("/.dockerenv").exists() {
+            return RunContext::Container("docker".to_string());
+        }
+        if Path::new("/run/.containerenv").exists() {
+            return RunContext::Container("podman".to_string());
+        }
+        if let Some(name) = detect_virt("--vm") {
+            return RunContext::Vm(name);
+        }
+        RunContext::BareMetal
+    }
+    
+    pub fn describe(&self) -> String {
+        match self {
+            RunContext::BareMetal => "bare metal".to_string(),
+            RunContext::Vm(name) => format!("a {} virtual machine", name),
+            RunContext::Container(name) => format!("a {} container", name),
+        }
+    }
+    
+    pub fn is_container(&self) -> bool {
+        matches!(self, RunContext::Container(_))
+    }
+    
+    // Real disks, sensors and power management to configure
+    pub fn has_hardware(&self) -> bool {
+        *self == RunContext::BareMetal
+    }
+    
+    // Guest integration packages and the services they need enabled
+    pub fn guest_tools(&self) -> (&'static [&'static str], &'static [&'static str]) {
+        match self {
+            RunContext::Vm(name) => match name.as_str() {
+                "kvm" | "qemu" => (&["qemu-guest-agent", "spice-vdagent"], &["qemu-guest-agent.service"]),
+                "vmware" => (&["open-vm-tools"], &["vmtoolsd.service", "vmware-vmblock-fuse.service"]),
+                "oracle" => (&["virtualbox-guest-utils"], &["vboxservice.service"]),
+                "microsoft" => (&["hyperv"], &["hv_kvp_daemon.service", "hv_vss_daemon.service"]),
+                _ => (&[], &[]),
+            },
+            _ => (&[], &[]),
+        }
+    }
+}
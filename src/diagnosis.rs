@@ -0,0 +1,143 @@
+use crate::pkg::{self, Backend};
+
+// Common reasons a pacman, AUR helper, apt or dnf transaction fails, recognised from
+// its output so the summary can say what to do instead of just "failed"
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Diagnosis {
+    // Unknown, untrusted or expired PGP keys, or a corrupted keyring
+    KeyError,
+    // The mirror doesn't have a file the (stale) database points to
+    MirrorOutdated,
+    // Files of a package already exist on disk
+    FileConflict,
+    DiskFull,
+    // Another pacman is running, or a crashed one left its lock behind
+    DatabaseLocked,
+}
+
+impl Diagnosis {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Diagnosis::KeyError => "PGP key error",
+            Diagnosis::MirrorOutdated => "mirror returned 404",
+            Diagnosis::FileConflict => "conflicting files",
+            Diagnosis::DiskFull => "not enough space",
+            Diagnosis::DatabaseLocked => "database locked",
+        }
+    }
+    
+    // What to do about it with this system's package manager
+    pub fn hint(self) -> &'static str {
+        match pkg::backend() {
+            Backend::Pacman => self.pacman_hint(),
+            Backend::Apt => self.apt_hint(),
+            Backend::Dnf => self.dnf_hint(),
+        }
+    }
+    
+    fn pacman_hint(self) -> &'static str {
+        match self {
+            Diagnosis::KeyError => {
+                "Refresh the keyring with sudo pacman -Sy archlinux-keyring && sudo pacman-key --populate archlinux, \
+                 for AUR sources import the key with gpg --recv-keys <key id>"
+            }
+            Diagnosis::MirrorOutdated => {
+                "The mirror is behind the package database, sync with sudo pacman -Syy or pick fresher mirrors with reflector"
+            }
+            Diagnosis::FileConflict => {
+                "See who owns the file with pacman -Qo <path>, delete it if nothing does or pass --overwrite <path> once"
+            }
+            Diagnosis::DiskFull => "Free some space (sudo pacman -Sc clears the package cache) and check df -h / /var",
+            Diagnosis::DatabaseLocked => {
+                "If no other pacman is running, remove the stale lock with sudo rm /var/lib/pacman/db.lck"
+            }
+        }
+    }
+    
+    fn apt_hint(self) -> &'static str {
+        match self {
+            Diagnosis::KeyError => "A repository's signing key is missing or expired, reinstall its keyring package or re-add the key it publishes",
+            Diagnosis::MirrorOutdated => "The package lists are stale, refresh them with sudo apt-get update",
+            Diagnosis::FileConflict => {
+                "See who owns the file with dpkg -S <path>, then sudo apt-get -f install or remove the conflicting package"
+            }
+            Diagnosis::DiskFull => "Free some space (sudo apt-get clean clears the package cache) and check df -h / /var",
+            Diagnosis::DatabaseLocked => "Another apt or dpkg is running (often unattended-upgrades), wait for it to finish",
+        }
+    }
+    
+    fn dnf_hint(self) -> &'static str {
+        match self {
+            Diagnosis::KeyError => "The repository's GPG key isn't imported, install its release package or rpm --import the key it publishes",
+            Diagnosis::MirrorOutdated => "The metadata is stale, retry with sudo dnf upgrade --refresh or sudo dnf clean metadata",
+            Diagnosis::FileConflict => {
+                "See who owns the file with rpm -qf <path>, then remove the conflicting package or sudo dnf distro-sync"
+            }
+            Diagnosis::DiskFull => "Free some space (sudo dnf clean packages clears the package cache) and check df -h / /var",
+            Diagnosis::DatabaseLocked => "Another dnf is running (often PackageKit or dnf-makecache), wait for it to finish",
+        }
+    }
+}
+
+fn matches(diagnosis: Diagnosis, line: &str) -> bool {
+    match diagnosis {
+        Diagnosis::KeyError => {
+            line.contains("unknown public key")
+                || line.contains("invalid or corrupted package (pgp signature)")
+                || (line.contains("signature from") && (line.contains("unknown trust") || line.contains("invalid")))
+                || line.contains("could not be looked up remotely")
+                || (line.contains("key") && line.contains("expired"))
+                || line.contains("keyring is not writable")
+                || line.contains("no_pubkey")
+                || line.contains("the following signatures couldn't be verified")
+                || (line.contains("public key for") && line.contains("is not installed"))
+                || line.contains("gpg check failed")
+        }
+        Diagnosis::MirrorOutdated => {
+            line.contains("404")
+                && (line.contains("failed retrieving file")
+                    || line.contains("returned error")
+                    || line.contains("failed to fetch")
+                    || line.contains("status code: 404"))
+        }
+        Diagnosis::FileConflict => {
+            line.contains("exists in filesystem")
+                || line.contains("conflicting files")
+                || line.contains("trying to overwrite")
+                || line.contains("conflicts with file from package")
+        }
+        Diagnosis::DiskFull => {
+            line.contains("not enough free disk space")
+                || line.contains("no space left on device")
+                || line.contains("don't have enough free space")
+                || line.contains("more space needed on the")
+        }
+        Diagnosis::DatabaseLocked => {
+            line.contains("unable to lock database")
+                || line.contains("could not get lock")
+                || line.contains("waiting for process with pid")
+        }
+    }
+}
+
+const ALL: [Diagnosis; 5] = [
+    Diagnosis::KeyError,
+    Diagnosis::MirrorOutdated,
+    Diagnosis::FileConflict,
+    Diagnosis::DiskFull,
+    Diagnosis::DatabaseLocked,
+];
+
+// Every known failure signature in the output, in order of first appearance
+pub fn classify(output: &str) -> Vec<Diagnosis> {
+    let mut found = Vec::new();
+    for line in output.lines() {
+        let line = line.to_lowercase();
+        for diagnosis in ALL {
+            if !found.contains(&diagnosis) && matches(diagnosis, &line) {
+                found.push(diagnosis);
+            }
+        }
+    }
+    found
+}
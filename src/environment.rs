@@ -0,0 +1,155 @@
+use crate::paths;
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+// Where pacman, makepkg and the AUR helpers expect to find their tools
+const SYSTEM_PATH: [&str; 3] = ["/usr/local/sbin", "/usr/local/bin", "/usr/bin"];
+
+// Set inside a nix-shell or python/conda environment and meaningless (or
+// harmful) to the system tools we run
+const SHELL_VARS: [&str; 6] = ["IN_NIX_SHELL", "VIRTUAL_ENV", "PYTHONHOME", "PYTHONPATH", "CONDA_PREFIX", "CONDA_DEFAULT_ENV"];
+
+// Written by the multi-user installers (the official one and Determinate's)
+const NIX_DAEMON_SCRIPT: &str = "/nix/var/nix/profiles/default/etc/profile.d/nix-daemon.sh";
+
+// Shell bookkeeping that changes in any child shell, not something Nix set
+const SHELL_STATE: [&str; 4] = ["_", "SHLVL", "PWD", "OLDPWD"];
+
+fn is_nix_profile(dir: &str) -> bool {
+    dir.starts_with("/nix/var/nix/profiles/") || dir.contains("/.nix-profile") || dir.contains("/.local/state/nix/profiles/")
+}
+
+// Entries of PATH that belong to a shell environment rather than the system
+fn is_foreign(dir: &str, environments: &[String]) -> bool {
+    dir.is_empty()
+        || !dir.starts_with('/')
+        || dir.starts_with("/nix/store/")
+        || environments.iter().any(|prefix| dir.starts_with(prefix.as_str()))
+}
+
+// PATH with the system's directories first, then Nix's profiles, and the
+// directories of shell environments left out. Also returns what was dropped.
+fn system_first<'a>(path: &'a str, environments: &[String]) -> (String, Vec<&'a str>) {
+    let mut system: Vec<&str> = Vec::new();
+    let mut nix: Vec<&str> = Vec::new();
+    let mut dropped: Vec<&str> = Vec::new();
+    for dir in path.split(':') {
+        let dir = dir.trim_end_matches('/');
+        if system.contains(&dir) || nix.contains(&dir) {
+            continue;
+        }
+        if is_foreign(dir, environments) {
+            dropped.push(if dir.is_empty() { "(empty)" } else { dir });
+            continue;
+        }
+        if is_nix_profile(dir) {
+            nix.push(dir);
+        } else {
+            system.push(dir);
+        }
+    }
+    for dir in SYSTEM_PATH {
+        if !system.contains(&dir) && Path::new(dir).is_dir() {
+            system.push(dir);
+        }
+    }
+    (system.into_iter().chain(nix).collect::<Vec<&str>>().join(":"), dropped)
+}
+
+// What a nix-shell, a python venv or conda, or a half-sourced Nix profile
+// leave in our environment ends up in every child: nix-shell's compilers and
+// flags in makepkg, a venv's python in pacman hooks, Nix's coreutils ahead of
+// the system ones. Put the system first and drop the rest. Returns what was
+// changed. Call from main before any other thread is started.
+pub fn sanitize() -> Vec<String> {
+    let mut changes = Vec::new();
+    
+    let nix_shell = env::var_os("IN_NIX_SHELL").is_some();
+    if nix_shell {
+        changes.push("running inside a nix-shell".to_string());
+    }
+    let environments: Vec<String> = ["VIRTUAL_ENV", "CONDA_PREFIX"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .filter(|dir| dir.starts_with('/'))
+        .collect();
+    for dir in &environments {
+        changes.push(format!("running inside the python environment {}", dir));
+    }
+    
+    // Nix profiles stay usable (home-manager, nix) but can't shadow the
+    // system. That's the usual case after logging in with Nix, so it's silent.
+    let path = env::var("PATH").unwrap_or_default();
+    let (sanitized, dropped) = system_first(&path, &environments);
+    if !dropped.is_empty() {
+        changes.push(format!("removed {} from PATH", dropped.join(", ")));
+    }
+    
+    // nix-shell also points CC, NIX_CFLAGS_COMPILE and friends into the store.
+    // Outside of one, store paths (EDITOR, ...) are the user's own choice.
+    let leaked: Vec<String> = env::vars()
+        .filter(|(name, value)| {
+            name != "PATH"
+                && ((nix_shell && value.contains("/nix/store/"))
+                    || SHELL_VARS.contains(&name.as_str())
+                    || name.starts_with("CONDA_"))
+        })
+        .map(|(name, _)| name)
+        .collect();
+    if !leaked.is_empty() {
+        changes.push(format!("unset {}", leaked.join(" ")));
+    }
+    
+    // SAFETY: called from main before any other thread is started
+    unsafe {
+        env::set_var("PATH", &sanitized);
+        for name in &leaked {
+            env::remove_var(name);
+        }
+    }
+    changes
+}
+
+// What a login shell gets from Nix's profile script, applied to this process
+// so the steps after install_nix (and everything they run) find nix and
+// nix-channel without logging out and back in. Returns the variables that
+// changed, None when there's no script or sourcing it failed. Only call while
+// no other thread is running.
+pub fn load_nix_profile() -> Option<Vec<String>> {
+    let script = [NIX_DAEMON_SCRIPT.to_string(), paths::get().home(".nix-profile/etc/profile.d/nix.sh")]
+        .into_iter()
+        .find(|script| Path::new(script).is_file())?;
+    
+    // The script returns early when it thinks this shell already has it
+    let output = Command::new("sh")
+        .args(["-c", ". \"$1\" >/dev/null 2>&1; env -0", "sh", &script])
+        .env_remove("__ETC_PROFILE_NIX_SOURCED")
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    
+    let mut changed: Vec<(String, String)> = Vec::new();
+    for entry in output.stdout.split(|byte| *byte == 0) {
+        let entry = String::from_utf8_lossy(entry);
+        let Some((name, value)) = entry.split_once('=') else {
+            continue;
+        };
+        if name.is_empty() || SHELL_STATE.contains(&name) || env::var(name).is_ok_and(|current| current == value) {
+            continue;
+        }
+        // Same order sanitize() keeps, Nix can't shadow the system tools
+        let value = if name == "PATH" { system_first(value, &[]).0 } else { value.to_string() };
+        changed.push((name.to_string(), value));
+    }
+    
+    // SAFETY: the caller makes sure no other thread is running
+    unsafe {
+        for (name, value) in &changed {
+            env::set_var(name, value);
+        }
+    }
+    Some(changed.into_iter().map(|(name, _)| name).collect())
+}
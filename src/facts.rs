@@ -0,0 +1,243 @@
+use crate::context::RunContext;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+use std::sync::OnceLock;
+
+// What's known about the machine, for `when` conditions, {{ name }} in
+// [files] and when= in the package list. Detected once, on first use.
+pub const NAMES: [&str; 7] = ["hostname", "chassis", "gpu", "virtualization", "cpu", "ram_gb", "disk"];
+
+// Facts compared as numbers by <, <=, > and >=
+const NUMERIC: [&str; 1] = ["ram_gb"];
+
+// Comparisons a condition term can make, the two-character ones first
+const OPERATORS: [&str; 6] = ["!=", ">=", "<=", "=", ">", "<"];
+
+static FACTS: OnceLock<BTreeMap<&'static str, String>> = OnceLock::new();
+
+pub fn all() -> &'static BTreeMap<&'static str, String> {
+    FACTS.get_or_init(|| {
+        let context = RunContext::detect();
+        BTreeMap::from([
+            ("hostname", hostname()),
+            ("chassis", chassis(&context)),
+            ("gpu", gpu()),
+            ("virtualization", virtualization(&context)),
+            ("cpu", cpu()),
+            ("ram_gb", ram_gb()),
+            ("disk", disk()),
+        ])
+    })
+}
+
+pub fn get(name: &str) -> &'static str {
+    all().get(name).map(String::as_str).unwrap_or("unknown")
+}
+
+fn hostname() -> String {
+    let host = std::fs::read_to_string("/etc/hostname").or_else(|_| std::fs::read_to_string("/proc/sys/kernel/hostname")).unwrap_or_default();
+    host.trim().to_string()
+}
+
+// laptop, desktop, server, tablet, handheld, vm, container or unknown, from
+// the SMBIOS chassis type
+fn chassis(context: &RunContext) -> String {
+    match context {
+        RunContext::Container(_) => return "container".to_string(),
+        RunContext::Vm(_) => return "vm".to_string(),
+        RunContext::BareMetal => {}
+    }
+    let code = std::fs::read_to_string("/sys/class/dmi/id/chassis_type").ok().and_then(|code| code.trim().parse::<u32>().ok());
+    let kind = match code {
+        Some(3..=7 | 13 | 15 | 16 | 24 | 35 | 36) => "desktop",
+        Some(8..=10 | 14 | 31 | 32) => "laptop",
+        Some(11) => "handheld",
+        Some(17 | 23 | 25 | 28 | 29) => "server",
+        Some(30) => "tablet",
+        // "Other" and "Unknown" are common on laptops too, a battery gives them away
+        _ if has_battery() => "laptop",
+        _ => "unknown",
+    };
+    kind.to_string()
+}
+
+fn has_battery() -> bool {
+    std::fs::read_dir("/sys/class/power_supply")
+        .is_ok_and(|entries| entries.flatten().any(|entry| entry.file_name().to_string_lossy().starts_with("BAT")))
+}
+
+// Vendors of the display controllers on the PCI bus, e.g. "intel,nvidia"
+// for a hybrid laptop, or none
+fn gpu() -> String {
+    let mut vendors: Vec<&str> = std::fs::read_dir("/sys/bus/pci/devices")
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|entry| std::fs::read_to_string(entry.path().join("class")).is_ok_and(|class| class.starts_with("0x03")))
+                .map(|entry| match std::fs::read_to_string(entry.path().join("vendor")).unwrap_or_default().trim() {
+                    "0x10de" => "nvidia",
+                    "0x1002" => "amd",
+                    "0x8086" => "intel",
+                    "0x1af4" | "0x1234" | "0x15ad" | "0x80ee" | "0x1414" => "virtual",
+                    _ => "other",
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    vendors.sort();
+    vendors.dedup();
+    if vendors.is_empty() { "none".to_string() } else { vendors.join(",") }
+}
+
+// systemd-detect-virt's name for the hypervisor or container manager, or none
+fn virtualization(context: &RunContext) -> String {
+    match context {
+        RunContext::BareMetal => "none".to_string(),
+        RunContext::Vm(name) | RunContext::Container(name) => name.clone(),
+    }
+}
+
+// intel, amd, or the architecture for CPUs /proc/cpuinfo has no vendor for
+fn cpu() -> String {
+    let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+    let vendor = cpuinfo.lines().find_map(|line| line.strip_prefix("vendor_id").map(|rest| rest.trim_start_matches([' ', '\t', ':'])));
+    match vendor {
+        Some("GenuineIntel") => "intel".to_string(),
+        Some("AuthenticAMD") => "amd".to_string(),
+        _ if std::env::consts::ARCH.starts_with("aarch64") || std::env::consts::ARCH.starts_with("arm") => "arm".to_string(),
+        _ => std::env::consts::ARCH.to_string(),
+    }
+}
+
+// MemTotal rounded to whole GiB, a 16 GB machine reports a bit less
+fn ram_gb() -> String {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").unwrap_or_default();
+    let kib = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|rest| rest.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        .unwrap_or(0);
+    ((kib + 512 * 1024) / (1024 * 1024)).to_string()
+}
+
+// nvme, ssd or hdd for the disk holding /, through LUKS and LVM, or unknown
+fn disk() -> String {
+    let source = Command::new("findmnt").args(["-n", "-o", "SOURCE", "/"]).output().ok().filter(|output| output.status.success());
+    // btrfs subvolumes come as /dev/sda2[/@]
+    let Some(source) = source.map(|output| String::from_utf8_lossy(&output.stdout).trim().split('[').next().unwrap_or("").to_string()) else {
+        return "unknown".to_string();
+    };
+    if !Path::new(&source).exists() {
+        return "unknown".to_string();
+    }
+    let Ok(output) = Command::new("lsblk").args(["-s", "-n", "-P", "-o", "TYPE,ROTA,TRAN", &source]).output() else {
+        return "unknown".to_string();
+    };
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let kind = match listing.lines().find(|line| line.contains("TYPE=\"disk\"")) {
+        Some(line) if line.contains("TRAN=\"nvme\"") => "nvme",
+        Some(line) if line.contains("ROTA=\"0\"") => "ssd",
+        Some(_) => "hdd",
+        None => "unknown",
+    };
+    kind.to_string()
+}
+
+// A condition term, "gpu=nvidia" as ("gpu", "=", "nvidia")
+fn split(term: &str) -> Option<(&str, &str, &str)> {
+    let at = term.find(['!', '<', '>', '='])?;
+    let (name, rest) = term.split_at(at);
+    let operator = OPERATORS.into_iter().find(|operator| rest.starts_with(operator))?;
+    Some((name.trim(), operator, rest[operator.len()..].trim()))
+}
+
+// Conditions are terms joined by commas, all of which must hold:
+//
+//   chassis=laptop
+//   gpu=nvidia|amd, ram_gb>=16
+//   virtualization!=none
+//
+// a|b matches either. gpu can list several vendors and matches if any of
+// them does.
+pub fn check(condition: &str) -> Result<(), String> {
+    for term in condition.split(',').map(str::trim) {
+        let Some((name, operator, value)) = split(term) else {
+            return Err(format!("'{}' isn't a comparison like chassis=laptop", term));
+        };
+        if !NAMES.contains(&name) {
+            return Err(format!("'{}' isn't a fact (expected {})", name, NAMES.join(", ")));
+        }
+        if value.is_empty() {
+            return Err(format!("'{}' has nothing to compare {} with", term, name));
+        }
+        if matches!(operator, ">=" | "<=" | ">" | "<") {
+            if !NUMERIC.contains(&name) {
+                return Err(format!("'{}' compares {} as a number, which it isn't (numbers: {})", term, name, NUMERIC.join(", ")));
+            }
+            if value.parse::<f64>().is_err() {
+                return Err(format!("'{}' compares {} with something that isn't a number", term, name));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Whether this machine meets a condition check() accepted
+pub fn matches(condition: &str) -> bool {
+    condition.split(',').map(str::trim).all(|term| {
+        let Some((name, operator, value)) = split(term) else {
+            return false;
+        };
+        let fact = get(name);
+        let equal = value.split('|').any(|wanted| fact.split(',').any(|part| part == wanted.trim()));
+        let number = || fact.parse::<f64>().ok().zip(value.parse::<f64>().ok());
+        match operator {
+            "=" => equal,
+            "!=" => !equal,
+            ">=" => number().is_some_and(|(fact, value)| fact >= value),
+            "<=" => number().is_some_and(|(fact, value)| fact <= value),
+            ">" => number().is_some_and(|(fact, value)| fact > value),
+            _ => number().is_some_and(|(fact, value)| fact < value),
+        }
+    })
+}
+
+// What's between each {{ and }}, trimmed
+fn placeholders(text: &str) -> impl Iterator<Item = &str> {
+    text.split("{{").skip(1).filter_map(|rest| rest.split_once("}}").map(|(inside, _)| inside.trim()))
+}
+
+// {{ name }} that looks meant as a fact but isn't one. Anything else between
+// braces (Go or Jinja templates of other programs) is left alone.
+pub fn check_template(text: &str) -> Result<(), String> {
+    let unknown = placeholders(text).find(|inside| {
+        !inside.is_empty() && inside.chars().all(|c| c.is_ascii_lowercase() || c == '_') && !NAMES.contains(inside)
+    });
+    match unknown {
+        Some(name) => Err(format!("has {{{{ {} }}}}, which isn't a fact (expected {})", name, NAMES.join(", "))),
+        None => Ok(()),
+    }
+}
+
+// Replace {{ name }} with the fact's value
+pub fn render(text: &str) -> String {
+    let mut rendered = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        match after.find("}}").map(|end| (end, after[..end].trim())) {
+            Some((end, name)) if NAMES.contains(&name) => {
+                rendered.push_str(&rest[..start]);
+                rendered.push_str(get(name));
+                rest = &after[end + 2..];
+            }
+            _ => {
+                rendered.push_str(&rest[..start + 2]);
+                rest = after;
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
@@ -0,0 +1,341 @@
+use crate::ci;
+use crate::config;
+use crate::journal::{self, Entry};
+use crate::output::say;
+use crate::report::{self, Event};
+use crate::runlog;
+use crate::timings::format_duration;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Runs kept in the history, the oldest ones beyond this are dropped
+const KEPT_RUNS: usize = 100;
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct StepRecord {
+    name: String,
+    secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct WarningRecord {
+    kind: String,
+    message: String,
+}
+
+// What a run set out to do and how it went, written as it goes so a run
+// that dies halfway still leaves a record of where it got to
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct RunRecord {
+    id: String,
+    // "setup" or "update"
+    command: String,
+    args: Vec<String>,
+    profile: String,
+    started: u64,
+    finished: Option<u64>,
+    // Empty until the run gets to the end (or pauses)
+    outcome: String,
+    // Steps selected to run, before their own checks left any out
+    plan: Vec<String>,
+    // Steps started but not finished yet
+    running: Vec<String>,
+    steps: Vec<StepRecord>,
+    warnings: Vec<WarningRecord>,
+    // Run logs (see runlog), one per process the run took
+    logs: Vec<String>,
+}
+
+// None until begin(), so dry runs and the subcommands record nothing
+static CURRENT: Mutex<Option<RunRecord>> = Mutex::new(None);
+
+fn dir() -> String {
+    config::state_path("runs")
+}
+
+fn record_path(id: &str) -> String {
+    format!("{}/{}.json", dir(), id)
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// A ULID: 48 bits of milliseconds then 80 random bits in Crockford base32,
+// so IDs sort by when the run started
+fn ulid() -> String {
+    const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+    let mut random = [0u8; 10];
+    let read = std::fs::File::open("/dev/urandom").and_then(|mut urandom| urandom.read_exact(&mut random));
+    if read.is_err() {
+        // Unique enough for a run on one machine
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+        random[..4].copy_from_slice(&nanos.to_be_bytes());
+        random[4..8].copy_from_slice(&std::process::id().to_be_bytes());
+    }
+    let value = random.iter().fold(millis & ((1 << 48) - 1), |value, byte| (value << 8) | *byte as u128);
+    (0..26).map(|i| ALPHABET[((value >> (5 * (25 - i))) & 31) as usize] as char).collect()
+}
+
+fn save(record: &RunRecord) {
+    let _ = std::fs::create_dir_all(dir());
+    if let Ok(json) = serde_json::to_string_pretty(record) {
+        let _ = std::fs::write(record_path(&record.id), json + "\n");
+    }
+}
+
+fn load(id: &str) -> Option<RunRecord> {
+    let content = std::fs::read_to_string(record_path(id)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+// Run IDs, oldest first
+fn ids() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(dir()) else {
+        return Vec::new();
+    };
+    let mut ids: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_string_lossy().strip_suffix(".json").map(str::to_string))
+        .collect();
+    ids.sort();
+    ids
+}
+
+fn update(change: impl FnOnce(&mut RunRecord)) {
+    let mut current = CURRENT.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(record) = current.as_mut() {
+        change(record);
+        save(record);
+    }
+}
+
+// Start recording a run and return its ID. A continuing run (post-nix,
+// --resume) or one picking up after a pause adds to the last record.
+pub fn begin(command: &str, profile: &str, plan: Vec<String>, continuing: bool) -> String {
+    let ids = ids();
+    for old in ids.iter().take(ids.len().saturating_sub(KEPT_RUNS - 1)) {
+        let _ = std::fs::remove_file(record_path(old));
+    }
+    
+    let last = ids.last().and_then(|id| load(id));
+    let mut record = match last {
+        Some(mut last) if last.command == command && (continuing || last.outcome.starts_with("paused")) => {
+            last.outcome.clear();
+            last.finished = None;
+            last.running.clear();
+            last
+        }
+        _ => RunRecord {
+            id: ulid(),
+            command: command.to_string(),
+            args: std::env::args().skip(1).collect(),
+            profile: profile.to_string(),
+            started: now(),
+            plan,
+            ..RunRecord::default()
+        },
+    };
+    if let Some(log) = runlog::path() {
+        record.logs.push(log.to_string());
+    }
+    save(&record);
+    report::emit(Event::RunStarted { run: &record.id, command, profile, plan: &record.plan });
+    let id = record.id.clone();
+    *CURRENT.lock().unwrap_or_else(|e| e.into_inner()) = Some(record);
+    id
+}
+
+pub fn step_started(step: &str) {
+    update(|record| record.running.push(step.to_string()));
+}
+
+pub fn step_done(step: &str, secs: u64) {
+    update(|record| {
+        record.running.retain(|running| running != step);
+        record.steps.push(StepRecord { name: step.to_string(), secs });
+    });
+}
+
+pub fn warning(kind: &str, message: &str) {
+    update(|record| record.warnings.push(WarningRecord { kind: kind.to_string(), message: message.to_string() }));
+}
+
+// "success", "warnings", "paused for ..."; a run that never gets here shows
+// up as failed or interrupted
+pub fn finish(outcome: &str) {
+    update(|record| {
+        record.outcome = outcome.to_string();
+        record.finished = Some(now());
+        report::emit(Event::RunFinished { outcome });
+    });
+}
+
+fn describe_outcome(record: &RunRecord) -> String {
+    match record.outcome.as_str() {
+        "" if record.running.is_empty() => "didn't finish".to_string(),
+        "" => format!("stopped in {}", record.running.join(", ")),
+        "warnings" => format!("done, {} warning(s)", record.warnings.len()),
+        "success" => "done".to_string(),
+        outcome => outcome.to_string(),
+    }
+}
+
+// `ass history`: one line per recorded run, newest last
+pub fn print_history() {
+    let records: Vec<RunRecord> = ids().iter().filter_map(|id| load(id)).collect();
+    if records.is_empty() {
+        say!("No runs recorded yet in {}", dir());
+        return;
+    }
+    
+    say!("{:<26}  {:<20}  {:<7}  {:>8}  OUTCOME", "RUN", "STARTED (UTC)", "COMMAND", "TOOK");
+    for record in &records {
+        let took = match record.finished {
+            Some(finished) => format_duration(finished.saturating_sub(record.started)),
+            None => "-".to_string(),
+        };
+        say!(
+            "{:<26}  {:<20}  {:<7}  {:>8}  {}",
+            record.id,
+            ci::utc(record.started),
+            record.command,
+            took,
+            describe_outcome(record)
+        );
+    }
+    say!();
+    say!("ass show <run> for the details of one (a unique prefix of the ID will do)");
+}
+
+// `ass show <run>`: everything recorded about one run, the changes it made
+// included as far as the journal still has them
+pub fn show(prefix: &str) {
+    let matches: Vec<String> = ids().into_iter().filter(|id| id.starts_with(&prefix.to_uppercase())).collect();
+    let id = match matches.as_slice() {
+        [id] => id,
+        [] => {
+            report::fatal(format!("No run {} in {} (see ass history)", prefix, dir()));
+        }
+        _ => {
+            report::fatal(format!("{} matches {} runs, give more of the ID", prefix, matches.len()));
+        }
+    };
+    let Some(record) = load(id) else {
+        report::fatal(format!("Failed to read {}", record_path(id)));
+    };
+    
+    say!("Run {}", record.id);
+    say!("  Command:  ass {}", record.args.join(" "));
+    say!("  Profile:  {}", record.profile);
+    say!("  Started:  {}", ci::utc(record.started));
+    if let Some(finished) = record.finished {
+        say!("  Finished: {} (took {})", ci::utc(finished), format_duration(finished.saturating_sub(record.started)));
+    }
+    say!("  Outcome:  {}", describe_outcome(&record));
+    for log in &record.logs {
+        say!("  Log:      {}", log);
+    }
+    
+    if !record.plan.is_empty() {
+        say!();
+        say!("Planned steps ({}):", record.plan.len());
+        say!("  {}", record.plan.join(", "));
+    }
+    
+    if !record.steps.is_empty() {
+        say!();
+        say!("Steps run:");
+        for step in &record.steps {
+            say!("  {:<32} {}", step.name, format_duration(step.secs));
+        }
+    }
+    
+    if !record.warnings.is_empty() {
+        say!();
+        say!("Warnings:");
+        for warning in &record.warnings {
+            say!("  - [{}] {}", warning.kind, warning.message);
+        }
+    }
+    
+    let changes = changes(&record.id);
+    if changes.is_empty() {
+        say!();
+        say!("No changes left in {} (none recorded, or undone since)", journal::path());
+        return;
+    }
+    say!();
+    say!("Changes:");
+    for change in changes {
+        say!("  - {}", change);
+    }
+}
+
+// What the journal still has of a run's changes, one line each
+fn changes(id: &str) -> Vec<String> {
+    let Some(run) = journal::run(id) else {
+        return Vec::new();
+    };
+    let packages: Vec<&str> = run
+        .entries
+        .iter()
+        .filter_map(|entry| match entry {
+            Entry::Package(name) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    let mut changes = Vec::new();
+    if !packages.is_empty() {
+        changes.push(format!("Installed {}", packages.join(" ")));
+    }
+    for entry in &run.entries {
+        match entry {
+            Entry::Stow { package, target: Some(target), .. } => changes.push(format!("Stowed {} into {}", package, target)),
+            Entry::Stow { package, target: None, .. } => changes.push(format!("Stowed {}", package)),
+            Entry::Clone(path) => changes.push(format!("Cloned {}", path)),
+            Entry::File { path, backup: Some(_) } => changes.push(format!("Changed {}", path)),
+            Entry::File { path, backup: None } => changes.push(format!("Created {}", path)),
+            Entry::Package(_) => {}
+        }
+    }
+    changes
+}
+
+// One run as a scheduled report describes it
+#[derive(Serialize)]
+pub struct Summary {
+    pub id: String,
+    pub started: String,
+    pub outcome: String,
+    // Ended in warnings or didn't finish
+    pub problems: bool,
+    pub warnings: Vec<String>,
+    pub changes: Vec<String>,
+    pub logs: Vec<String>,
+}
+
+// The runs of a command started at or after `since` (seconds since the
+// epoch), oldest first
+pub fn summaries(command: &str, since: u64) -> Vec<Summary> {
+    ids()
+        .iter()
+        .filter_map(|id| load(id))
+        .filter(|record| record.command == command && record.started >= since)
+        .map(|record| Summary {
+            problems: record.outcome != "success",
+            outcome: describe_outcome(&record),
+            started: ci::utc(record.started),
+            warnings: record.warnings.iter().map(|warning| format!("[{}] {}", warning.kind, warning.message)).collect(),
+            changes: changes(&record.id),
+            logs: record.logs,
+            id: record.id,
+        })
+        .collect()
+}
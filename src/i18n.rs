@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+// Translations shipped in the binary, gettext-style .po files keyed by the
+// English text. Anything missing from a catalog is shown in English, so
+// strings can be wrapped in tr!() one at a time.
+const CATALOGS: [(&str, &str); 2] = [
+    ("de", include_str!("../locales/de.po")),
+    ("es", include_str!("../locales/es.po")),
+];
+
+static CATALOG: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+// First of LC_ALL, LC_MESSAGES and LANG that is set: "de_DE.UTF-8" -> "de"
+pub fn detect_language() -> Option<String> {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())?;
+    let language = locale.split(['_', '.', '@']).next()?.to_lowercase();
+    if language.is_empty() || language == "c" || language == "posix" { None } else { Some(language) }
+}
+
+// "text with \"escapes\"" -> text with "escapes"
+fn unquote(quoted: &str) -> String {
+    let inner = quoted.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')).unwrap_or(quoted);
+    let mut result = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some(other) => result.push(other),
+            None => {}
+        }
+    }
+    result
+}
+
+// msgid/msgstr pairs, continuation lines ("...") appended to the previous one
+fn parse_po(content: &str) -> HashMap<String, String> {
+    let mut catalog = HashMap::new();
+    let mut msgid: Option<String> = None;
+    let mut msgstr: Option<String> = None;
+    
+    let mut flush = |msgid: &mut Option<String>, msgstr: &mut Option<String>| {
+        if let (Some(id), Some(text)) = (msgid.take(), msgstr.take())
+            && !id.is_empty()
+            && !text.is_empty()
+        {
+            catalog.insert(id, text);
+        }
+    };
+    
+    for line in content.lines().map(str::trim) {
+        if let Some(rest) = line.strip_prefix("msgid ") {
+            flush(&mut msgid, &mut msgstr);
+            msgid = Some(unquote(rest));
+        } else if let Some(rest) = line.strip_prefix("msgstr ") {
+            msgstr = Some(unquote(rest));
+        } else if line.starts_with('"') {
+            let part = unquote(line);
+            match (&mut msgid, &mut msgstr) {
+                (_, Some(text)) => text.push_str(&part),
+                (Some(id), None) => id.push_str(&part),
+                _ => {}
+            }
+        }
+    }
+    flush(&mut msgid, &mut msgstr);
+    catalog
+}
+
+fn catalog() -> &'static HashMap<String, String> {
+    CATALOG.get_or_init(|| {
+        detect_language()
+            .and_then(|language| CATALOGS.iter().find(|(name, _)| *name == language))
+            .map(|(_, content)| parse_po(content))
+            .unwrap_or_default()
+    })
+}
+
+// The message in the user's language, or the English original
+pub fn translate(msgid: &'static str) -> &'static str {
+    catalog().get(msgid).map(String::as_str).unwrap_or(msgid)
+}
+
+// Fill "{}" placeholders in order, format!() needs a literal
+pub fn fill(template: &str, args: &[&dyn Display]) -> String {
+    let mut result = String::new();
+    let mut args = args.iter();
+    let mut rest = template;
+    while let Some(index) = rest.find("{}") {
+        result.push_str(&rest[..index]);
+        if let Some(arg) = args.next() {
+            result.push_str(&arg.to_string());
+        }
+        rest = &rest[index + 2..];
+    }
+    result.push_str(rest);
+    result
+}
+
+macro_rules! tr {
+    ($msgid:literal) => { $crate::i18n::translate($msgid) };
+    ($msgid:literal, $($arg:expr),+ $(,)?) => {
+        $crate::i18n::fill($crate::i18n::translate($msgid), &[$(&$arg as &dyn std::fmt::Display),+])
+    };
+}
+pub(crate) use tr;
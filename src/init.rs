@@ -0,0 +1,135 @@
+use crate::command::Cmd;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+// The init system services are managed with. Arch itself is systemd only,
+// Artix and friends offer the others.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Init {
+    Systemd,
+    OpenRc,
+    Runit,
+    Dinit,
+    Unknown,
+}
+
+static INIT: OnceLock<Init> = OnceLock::new();
+
+pub fn init() -> Init {
+    *INIT.get_or_init(Init::detect)
+}
+
+fn succeeds(program: &str, args: &[&str]) -> bool {
+    Command::new(program)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+impl Init {
+    fn detect() -> Init {
+        // The same check sd_booted() does
+        if Path::new("/run/systemd/system").exists() {
+            return Init::Systemd;
+        }
+        let pid1 = std::fs::read_to_string("/proc/1/comm").unwrap_or_default();
+        match pid1.trim() {
+            "runit" => return Init::Runit,
+            "dinit" => return Init::Dinit,
+            "openrc-init" => return Init::OpenRc,
+            _ => {}
+        }
+        // OpenRC also runs on top of sysvinit, runit inside containers
+        if Path::new("/run/openrc").exists() {
+            Init::OpenRc
+        } else if Path::new("/run/runit").exists() {
+            Init::Runit
+        } else if Path::new("/run/dinitctl").exists() {
+            Init::Dinit
+        } else if Path::new("/usr/bin/systemctl").exists() {
+            // A container or chroot of a systemd distribution
+            Init::Systemd
+        } else {
+            Init::Unknown
+        }
+    }
+    
+    pub fn name(self) -> &'static str {
+        match self {
+            Init::Systemd => "systemd",
+            Init::OpenRc => "OpenRC",
+            Init::Runit => "runit",
+            Init::Dinit => "dinit",
+            Init::Unknown => "an unknown init system",
+        }
+    }
+    
+    // (service definitions, linked into the running services to enable them).
+    // Artix keeps them under /etc/runit, Void in /etc/sv.
+    fn runit_dirs() -> (&'static str, &'static str) {
+        if Path::new("/etc/runit/sv").is_dir() { ("/etc/runit/sv", "/run/runit/service") } else { ("/etc/sv", "/var/service") }
+    }
+    
+    // Whether there's a service definition for `service`, packaged
+    // separately on the non-systemd distributions (nix-openrc, ...)
+    pub fn has_service(self, service: &str) -> bool {
+        match self {
+            Init::Systemd => ["/etc/systemd/system", "/usr/lib/systemd/system"]
+                .iter()
+                .any(|dir| Path::new(&format!("{}/{}.service", dir, service)).exists()),
+            Init::OpenRc => Path::new(&format!("/etc/init.d/{}", service)).exists(),
+            Init::Runit => Path::new(&format!("{}/{}", Init::runit_dirs().0, service)).is_dir(),
+            Init::Dinit => Path::new(&format!("/etc/dinit.d/{}", service)).exists(),
+            Init::Unknown => false,
+        }
+    }
+    
+    // Start the service now and at every boot
+    pub fn enable_now(self, service: &str) -> Vec<Cmd> {
+        match self {
+            Init::Systemd => vec![Cmd::sudo("systemctl").args(["enable", "--now", &format!("{}.service", service)])],
+            Init::OpenRc => vec![
+                Cmd::sudo("rc-update").args(["add", service, "default"]),
+                Cmd::sudo("rc-service").args([service, "start"]),
+            ],
+            // runsvdir picks the link up within a few seconds. On Void the
+            // enabled dir is itself a link (to /etc/runit/runsvdir/current),
+            // so the link is named in full, and left alone once it's there:
+            // ln would follow it into the service dir otherwise.
+            Init::Runit => {
+                let (available, enabled) = Init::runit_dirs();
+                let link = format!("{}/{}", enabled, service);
+                if std::fs::symlink_metadata(&link).is_ok() {
+                    return Vec::new();
+                }
+                vec![Cmd::sudo("ln").args(["-s", &format!("{}/{}", available, service), &link])]
+            }
+            Init::Dinit => vec![Cmd::sudo("dinitctl").args(["enable", service])],
+            Init::Unknown => Vec::new(),
+        }
+    }
+    
+    pub fn is_running(self, service: &str) -> bool {
+        match self {
+            Init::Systemd => succeeds("systemctl", &["is-active", "--quiet", &format!("{}.service", service)]),
+            Init::OpenRc => succeeds("rc-service", &[service, "status"]),
+            Init::Runit => succeeds("sv", &["check", service]),
+            Init::Dinit => succeeds("dinitctl", &["is-started", service]),
+            Init::Unknown => false,
+        }
+    }
+    
+    // What to run to see why a service isn't up
+    pub fn status_command(self, service: &str) -> String {
+        match self {
+            Init::Systemd => format!("systemctl status {}.service", service),
+            Init::OpenRc => format!("rc-service {} status", service),
+            Init::Runit => format!("sv status {}", service),
+            Init::Dinit => format!("dinitctl status {}", service),
+            Init::Unknown => format!("ps -C {}", service),
+        }
+    }
+}
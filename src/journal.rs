@@ -0,0 +1,240 @@
+use crate::config;
+use crate::pkg;
+use crate::workdir;
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+
+// Everything a run changed, so `ass undo` can take it back. One line per
+// change: "<run>\t<kind>\t<fields...>", oldest first. A run is one setup from
+// the start phase through post-nix (or one `ass update`).
+pub enum Entry {
+    // Installed by the package manager or the AUR helper, dependencies included
+    Package(String),
+    // A system file we replaced or appended to, with a copy of what was
+    // there before (None when it didn't exist)
+    File { path: String, backup: Option<String> },
+    // A repository we cloned
+    Clone(String),
+    // A stow package linked into the home, or the directory it was stowed into
+    Stow { dir: String, package: String, target: Option<String> },
+}
+
+impl Entry {
+    fn parse(fields: &[&str]) -> Option<Entry> {
+        match fields {
+            ["package", name] => Some(Entry::Package(name.to_string())),
+            ["file", path, "new"] => Some(Entry::File { path: path.to_string(), backup: None }),
+            ["file", path, backup] => Some(Entry::File { path: path.to_string(), backup: Some(backup.to_string()) }),
+            ["clone", path] => Some(Entry::Clone(path.to_string())),
+            ["stow", dir, package] => Some(Entry::Stow { dir: dir.to_string(), package: package.to_string(), target: None }),
+            ["stow", dir, package, target] => {
+                Some(Entry::Stow { dir: dir.to_string(), package: package.to_string(), target: Some(target.to_string()) })
+            }
+            _ => None,
+        }
+    }
+    
+    fn fields(&self) -> Vec<&str> {
+        match self {
+            Entry::Package(name) => vec!["package", name],
+            Entry::File { path, backup } => vec!["file", path, backup.as_deref().unwrap_or("new")],
+            Entry::Clone(path) => vec!["clone", path],
+            Entry::Stow { dir, package, target } => ["stow", dir, package].into_iter().chain(target.as_deref()).collect(),
+        }
+    }
+}
+
+pub struct Run {
+    pub id: String,
+    pub entries: Vec<Entry>,
+}
+
+struct Recording {
+    id: String,
+    // Installed packages as of the last look
+    packages: BTreeSet<String>,
+    // System files already backed up in this run, only the first copy counts
+    files: BTreeSet<String>,
+}
+
+// None until begin(), so dry runs and the subcommands record nothing
+static RECORDING: Mutex<Option<Recording>> = Mutex::new(None);
+
+pub fn path() -> String {
+    config::state_path("journal")
+}
+
+fn backup_dir(run: &str) -> String {
+    config::state_path(&format!("journal-files/{}", run))
+}
+
+fn read() -> Vec<(String, Entry)> {
+    let content = std::fs::read_to_string(path()).unwrap_or_default();
+    content
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let (run, rest) = fields.split_first()?;
+            Some((run.to_string(), Entry::parse(rest)?))
+        })
+        .collect()
+}
+
+fn append(run: &str, entries: &[Entry]) {
+    if entries.is_empty() {
+        return;
+    }
+    let path = path();
+    if let Some(dir) = Path::new(&path).parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    let Ok(mut file) = std::fs::OpenOptions::new().create(true).append(true).open(&path) else {
+        return;
+    };
+    for entry in entries {
+        let _ = writeln!(file, "{}\t{}", run, entry.fields().join("\t"));
+    }
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Option<Recording>> {
+    RECORDING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Start recording under the run's ID from history::begin. A continuing run
+// (post-nix, --resume, after a reboot pause) gets the ID it had before and
+// adds to the changes already recorded for it.
+pub fn begin(id: &str) {
+    let id = id.to_string();
+    let files = read()
+        .iter()
+        .filter(|(run, _)| *run == id)
+        .filter_map(|(_, entry)| match entry {
+            Entry::File { path, .. } => Some(path.clone()),
+            _ => None,
+        })
+        .collect();
+    *lock() = Some(Recording { id, packages: pkg::backend().installed_packages(), files });
+}
+
+// Record the packages installed since the last look. Called after every
+// step, whatever installed them.
+pub fn packages_changed() {
+    let mut recording = lock();
+    let Some(recording) = recording.as_mut() else {
+        return;
+    };
+    let now = pkg::backend().installed_packages();
+    let added: Vec<Entry> = now.difference(&recording.packages).map(|name| Entry::Package(name.clone())).collect();
+    append(&recording.id, &added);
+    recording.packages = now;
+}
+
+// Call before a system file is replaced or appended to. Keeps a copy of the
+// original the first time the run touches it.
+pub fn file_changing(target: &str) {
+    let mut recording = lock();
+    let Some(recording) = recording.as_mut() else {
+        return;
+    };
+    if !recording.files.insert(target.to_string()) {
+        return;
+    }
+    
+    let backup = if Path::new(target).exists() {
+        let dir = backup_dir(&recording.id);
+        let copy = format!("{}/{}", dir, target.trim_start_matches('/').replace('/', "%"));
+        // Some system files are only readable by root, so the copies are kept
+        // to us
+        let content = std::fs::read(target).ok().or_else(|| {
+            let output = Command::new("sudo").args(["cat", target]).output().ok()?;
+            output.status.success().then_some(output.stdout)
+        });
+        match content {
+            Some(content) if workdir::create_private_dir(&dir).is_ok() && workdir::write_private(&copy, &content).is_ok() => Some(copy),
+            // Without a copy undo can't restore it, so it's not recorded at all
+            _ => return,
+        }
+    } else {
+        None
+    };
+    append(&recording.id, &[Entry::File { path: target.to_string(), backup }]);
+}
+
+pub fn cloned(path: &str) {
+    if let Some(recording) = lock().as_ref() {
+        append(&recording.id, &[Entry::Clone(path.to_string())]);
+    }
+}
+
+pub fn stowed(dir: &str, package: &str, target: Option<&str>) {
+    if let Some(recording) = lock().as_ref() {
+        append(&recording.id, &[Entry::Stow { dir: dir.to_string(), package: package.to_string(), target: target.map(str::to_string) }]);
+    }
+}
+
+pub fn last_run() -> Option<Run> {
+    let id = read().last()?.0.clone();
+    run(&id)
+}
+
+pub fn run(id: &str) -> Option<Run> {
+    let entries: Vec<Entry> = read().into_iter().filter(|(run, _)| run == id).map(|(_, entry)| entry).collect();
+    if entries.is_empty() {
+        return None;
+    }
+    Some(Run { id: id.to_string(), entries })
+}
+
+// Every system file changed by any run, oldest change first, with the copy
+// of what was there before the first run touched it (None if it didn't exist)
+pub fn files() -> Vec<(String, Option<String>)> {
+    let mut seen = BTreeSet::new();
+    read()
+        .into_iter()
+        .filter_map(|(_, entry)| match entry {
+            Entry::File { path, backup } if seen.insert(path.clone()) => Some((path, backup)),
+            _ => None,
+        })
+        .collect()
+}
+
+// These files were put back the way they were before any run: drop their
+// entries from every run, with the copies
+pub fn forget_files(paths: &BTreeSet<String>) {
+    let journal = path();
+    let content = std::fs::read_to_string(&journal).unwrap_or_default();
+    let mut kept = Vec::new();
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match Entry::parse(&fields[1..]) {
+            Some(Entry::File { path, backup }) if paths.contains(&path) => {
+                if let Some(backup) = backup {
+                    let _ = std::fs::remove_file(backup);
+                }
+            }
+            _ => kept.push(line),
+        }
+    }
+    if kept.is_empty() {
+        let _ = std::fs::remove_file(&journal);
+    } else {
+        let _ = std::fs::write(&journal, kept.join("\n") + "\n");
+    }
+}
+
+// The run was undone: drop its entries and the copies of its files
+pub fn forget(run: &str) {
+    let path = path();
+    let content = std::fs::read_to_string(&path).unwrap_or_default();
+    let prefix = format!("{}\t", run);
+    let kept: Vec<&str> = content.lines().filter(|line| !line.starts_with(&prefix)).collect();
+    if kept.is_empty() {
+        let _ = std::fs::remove_file(&path);
+    } else {
+        let _ = std::fs::write(&path, kept.join("\n") + "\n");
+    }
+    let _ = std::fs::remove_dir_all(backup_dir(run));
+}
@@ -0,0 +1,99 @@
+use crate::output;
+use crate::report::Event;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+// journald's native protocol: one datagram per entry, see systemd.journal-fields(7)
+const SOCKET: &str = "/run/systemd/journal/socket";
+
+// Unset until start(), and on machines without journald
+static JOURNAL: OnceLock<UnixDatagram> = OnceLock::new();
+
+// The run's ID once history has one, put on every entry after it
+static RUN: Mutex<Option<String>> = Mutex::new(None);
+
+// Send the run's events to the journal as well, tagged ass, for
+// `journalctl -t ass` after an unattended run
+pub fn start() {
+    if !Path::new(SOCKET).exists() {
+        return;
+    }
+    if let Ok(socket) = UnixDatagram::unbound()
+        && socket.connect(SOCKET).is_ok()
+    {
+        let _ = JOURNAL.set(socket);
+    }
+}
+
+// Values with a newline are sent length-prefixed, the rest as KEY=value
+fn field(entry: &mut Vec<u8>, key: &str, value: &str) {
+    if value.contains('\n') {
+        entry.extend_from_slice(key.as_bytes());
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        entry.extend_from_slice(value.as_bytes());
+    } else {
+        entry.extend_from_slice(format!("{}={}", key, value).as_bytes());
+    }
+    entry.push(b'\n');
+}
+
+// (syslog priority, message) of an event
+fn describe(event: &Event<'_>) -> Option<(u8, String)> {
+    Some(match event {
+        Event::RunStarted { run, command, profile, .. } => (6, format!("Run {} started: ass {} (profile {})", run, command, profile)),
+        Event::StepStarted { step } => (6, format!("Step {} started", step)),
+        Event::StepFinished { step, secs } => (6, format!("Step {} finished after {}s", step, secs)),
+        Event::Command { command, success: true, .. } => (7, format!("Ran {}", command)),
+        Event::Command { command, error, .. } => (4, format!("{} failed: {}", command, error.as_deref().unwrap_or("unknown error"))),
+        Event::Warning { message, .. } => (4, format!("Warning: {}", message)),
+        Event::Error { message } => (3, format!("ERROR: {}", message)),
+        Event::RunFinished { outcome } => (5, format!("Run finished: {}", outcome)),
+        // Dry runs only, which change nothing worth a journal entry
+        Event::Plan { .. } => return None,
+    })
+}
+
+pub fn send(event: &Event<'_>) {
+    let Some(socket) = JOURNAL.get() else {
+        return;
+    };
+    let Some((priority, message)) = describe(event) else {
+        return;
+    };
+    let mut run = RUN.lock().unwrap_or_else(|e| e.into_inner());
+    if let Event::RunStarted { run: id, .. } = event {
+        *run = Some(id.to_string());
+    }
+    
+    let mut entry = Vec::new();
+    field(&mut entry, "MESSAGE", &message);
+    field(&mut entry, "PRIORITY", &priority.to_string());
+    field(&mut entry, "SYSLOG_IDENTIFIER", "ass");
+    
+    // The event's own fields as ASS_<NAME>, the same ones --output json has
+    let fields = match serde_json::to_value(event) {
+        Ok(serde_json::Value::Object(fields)) => fields,
+        _ => serde_json::Map::new(),
+    };
+    for (key, value) in &fields {
+        let value = match value {
+            serde_json::Value::Null => continue,
+            serde_json::Value::String(text) => text.clone(),
+            serde_json::Value::Array(items) => items.iter().filter_map(|item| item.as_str()).collect::<Vec<_>>().join("\n"),
+            other => other.to_string(),
+        };
+        field(&mut entry, &format!("ASS_{}", key.to_uppercase()), &value);
+    }
+    let step = output::step();
+    if !fields.contains_key("step") && !step.is_empty() {
+        field(&mut entry, "ASS_STEP", &step);
+    }
+    if let Some(run) = run.as_deref()
+        && !fields.contains_key("run")
+    {
+        field(&mut entry, "ASS_RUN", run);
+    }
+    let _ = socket.send(&entry);
+}
@@ -1,59 +1,487 @@
 use std::process::Command;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
 use std::env;
 use std::fs::OpenOptions;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+use std::time::Instant;
+
+mod aur;
+mod checkpoint;
+mod ci;
+mod command;
+mod config;
+mod context;
+mod environment;
+mod diagnosis;
+mod facts;
+mod history;
+mod i18n;
+mod init;
+mod journal;
+mod journald;
+mod nixlog;
+mod nixprofile;
+mod notify;
+mod network;
+mod output;
+mod paths;
+mod plan;
+mod pkg;
+mod pkglist;
+mod progress;
+mod reboot;
+mod releases;
+mod report;
+mod runlog;
+mod scaffold;
+mod state;
+mod timings;
+mod usage;
+mod userbus;
+mod warnings;
+mod wifi;
+mod wizard;
+mod workdir;
+
+use command::{Cmd, CmdError, RetryPolicy, RunOptions, run_with_retry};
+use context::RunContext;
+use i18n::tr;
+use init::Init;
+use pkg::{AurHelper, Backend, PackageManager};
+use config::{FileConfig, MountConfig, RepoConfig, SmartdConfig, Toolchain, XDG_USER_DIRS};
+use output::{esay, say};
+use paths::Paths;
+use timings::Timings;
+use usage::StepUsage;
+use warnings::{WarningKind, Warnings};
+use workdir::WorkDir;
 
 struct Config {
     dry_run: bool,
     verbose: bool,
     skip_wallpapers: bool,
+    skip_nvim_bootstrap: bool,
+    skip_tmux_bootstrap: bool,
+    skip_report: bool,
+    // homeConfigurations.<name> from --hm-config or [home_manager]
+    hm_configuration: Option<String>,
+    // Subcommand such as "wizard", None for a normal run
+    subcommand: Option<String>,
+    // Archive for `state export` / `state import`
+    state_archive: Option<String>,
+    // Run ID (or a prefix of it) for `ass show`
+    shown_run: Option<String>,
+    // Keep the work directory after a successful run
+    keep_work_dir: bool,
+    // Plain timestamped output for pipeline logs (--ci or CI=true)
+    ci: bool,
+    // Look up "latest" releases again instead of using the recorded pins
+    refresh_pins: bool,
+    // Download cap from --limit-rate, in curl's notation (500K, 2M)
+    limit_rate: Option<String>,
+    // Height of the pane commands' output is kept to with --verbose, 0 for none
+    tail: usize,
+    // --plan-format: how a dry run's plan is printed
+    plan_format: String,
+    work_dir: WorkDir,
+    warnings: Warnings,
+    // Bare metal, VM or container, detected at startup
+    context: RunContext,
+    // Timing profile from --timings, the local one otherwise
+    timings_profile: Option<String>,
+    timings: Timings,
+    // Packages the up-front pacman transaction already installed
+    batched: Mutex<BTreeSet<String>>,
+    // What rebuild_home_manager changed, for the summary at the end
+    hm_changes: Mutex<Option<String>>,
+    // Unread `home-manager news` after the switch, shown at the end too
+    hm_news: Mutex<Vec<String>>,
+    // Steps picked with --only, --from and --until
+    selection: StepSelection,
+    // From the config's profile or --profile
+    profile: config::Profile,
+    // Reboots steps asked for, checked after every step
+    reboots: reboot::Requests,
+    // Only the per-user steps, for one of the [users] (--user-steps). The
+    // main run's checkpoint is left alone.
+    other_user: bool,
+    // Picking a run back up (--resume or after a reboot): steps it already
+    // finished are skipped
+    resuming: bool,
+    file: FileConfig,
+}
+
+impl Config {
+    fn aur_helper(&self) -> &str {
+        &self.file.aur.helper
+    }
+    
+    // Desktop integration steps only make sense on desktop profiles
+    fn is_desktop(&self) -> bool {
+        self.profile.desktop
+    }
+    
+    // Scratch path for a step's downloads, only named (not created) in dry runs
+    fn work_path(&self, name: &str) -> String {
+        if self.dry_run { self.work_dir.planned_path(name) } else { self.work_dir.path(name) }
+    }
+}
+
+// Heaviest steps first, so the ones worth optimizing stand out
+fn print_usage_summary(steps: &[(String, StepUsage)]) {
+    let mut steps: Vec<&(String, StepUsage)> = steps.iter().filter(|(_, usage)| !usage.is_negligible()).collect();
+    if steps.is_empty() {
+        return;
+    }
+    steps.sort_by_key(|(_, usage)| std::cmp::Reverse(usage.cpu_ms));
+    
+    say!("\nResource usage per step:");
+    for (name, usage) in steps {
+        say!("  {}: {}", name, usage);
+    }
+}
+
+// Run one step, showing its ETA from earlier runs and recording how long it took
+fn run_step<T: Default>(config: &Config, name: &str, step: impl FnOnce() -> T) -> T {
+    ci::mark_step(name);
+    if config.file.skip.iter().any(|skipped| skipped == name) {
+        say!("⏭ Skipping {} (skip in config or --skip)", name);
+        return T::default();
+    }
+    
+    output::set_step(name);
+    history::step_started(name);
+    report::emit(report::Event::StepStarted { step: name });
+    if let Some((number, total)) = progress::position(name) {
+        say!("▶ Step {}/{}: {}", number, total, name);
+    }
+    config.timings.announce(name);
+    let started = Instant::now();
+    let before = usage::snapshot();
+    let result = step();
+    
+    if !config.dry_run {
+        let secs = started.elapsed().as_secs();
+        let usage = usage::snapshot().usage_since(&before);
+        if !config.other_user {
+            checkpoint::record_step(name, secs, usage);
+        }
+        history::step_done(name, secs);
+        report::emit(report::Event::StepFinished { step: name, secs });
+        config.timings.record(name, secs);
+        journal::packages_changed();
+        if config.verbose {
+            say!("  {} took {} ({})", name, timings::format_duration(secs), usage);
+        }
+    }
+    
+    result
 }
 
-// State file to track installation progress
-const STATE_FILE: &str = "/tmp/ass-install-state";
+// Stop after a step that wants a reboot before the rest, as far as [reboot]
+// pause goes. Below that the reasons become warnings.
+fn pause_if_reboot_needed(config: &Config, step: &str) {
+    // A container shares the host's kernel, nothing of ours to boot
+    if !config.context.is_container() {
+        config.reboots.check_boot_changes();
+    }
+    let reasons = config.reboots.take();
+    let Some(need) = reasons.iter().map(|(need, _)| *need).reduce(|a, b| if b > a { b } else { a }) else {
+        return;
+    };
+    
+    let pause_at = match config.file.reboot.pause.as_str() {
+        "recommended" => Some(reboot::Need::Recommended),
+        "required" => Some(reboot::Need::Required),
+        _ => None,
+    };
+    if pause_at.is_none_or(|pause_at| need < pause_at) {
+        for (need, reason) in &reasons {
+            config.warnings.add(WarningKind::Skipped, format!("Reboot {} after {}: {}", need.name(), step, reason));
+        }
+        return;
+    }
+    
+    say!();
+    say!("⚠ A reboot is {} after {}:", need.name(), step);
+    for (_, reason) in &reasons {
+        say!("  - {}", reason);
+    }
+    
+    if let Err(e) = reboot::write_paused(need) {
+        esay!("Failed to save the paused run to {}: {}", reboot::paused_path(), e);
+        std::process::exit(1);
+    }
+    
+    let resumes = config.file.reboot.resume && install_resume_unit(config);
+    say!();
+    if resumes {
+        print_box(&[
+            &format!("⚠ {}", tr!("REBOOT NEEDED")),
+            "",
+            tr!("The setup is paused until the next boot. Reboot now:"),
+            "$ sudo systemctl reboot",
+            "",
+            tr!("It resumes by itself after the reboot, follow it with:"),
+            "$ journalctl -fu ass-resume",
+        ]);
+    } else {
+        print_box(&[
+            &format!("⚠ {}", tr!("REBOOT NEEDED")),
+            "",
+            tr!("The setup is paused until the next boot. Reboot now:"),
+            "$ sudo systemctl reboot",
+            "",
+            tr!("After the reboot, run this script again:"),
+            "$ ./ass",
+        ]);
+    }
+    
+    history::finish(&format!("paused for a reboot after {}", step));
+    config.work_dir.finish(config.keep_work_dir);
+    config.warnings.print_recap();
+    std::process::exit(0);
+}
 
-fn get_install_state() -> String {
-    std::fs::read_to_string(STATE_FILE).unwrap_or_else(|_| "start".to_string())
+// Whether sudo works without anyone there to type a password. -k leaves
+// out the credentials cached by this run's earlier sudo calls.
+fn passwordless_sudo() -> bool {
+    Command::new("sudo")
+        .args(["-n", "-k", "true"])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
 }
 
-fn set_install_state(state: &str) {
-    std::fs::write(STATE_FILE, state).expect("Failed to write state file");
+// Run ass again with the same arguments on the next boot, as this user. The
+// unit has no terminal, so it needs sudo without a password.
+fn install_resume_unit(config: &Config) -> bool {
+    if !passwordless_sudo() {
+        if config.verbose {
+            say!("⏭ Not resuming by itself after the reboot, sudo asks for a password");
+        }
+        return false;
+    }
+    let exe = env::current_exe().expect("Failed to find the ass executable").to_string_lossy().to_string();
+    let user = env::var("USER").expect("USER environment variable not set");
+    // The wizard already wrote its config, don't ask again at boot
+    let args: Vec<String> = env::args().skip(1).filter(|arg| arg != "wizard").collect();
+    
+    install_system_content(config, &reboot::resume_unit(&exe, &args, &user, &paths::get().home), reboot::RESUME_UNIT_PATH, "644")
+        && run_or_warn(config, Cmd::sudo("systemctl").arg("daemon-reload"), "Failed to reload systemd")
+        && run_or_warn(
+            config,
+            Cmd::sudo("systemctl").args(["enable", "ass-resume.service"]),
+            "Failed to enable ass-resume.service, resume by hand after the reboot",
+        )
 }
 
-fn clear_install_state() {
-    let _ = std::fs::remove_file(STATE_FILE);
+// Pick up a run paused by pause_if_reboot_needed
+fn resume_paused_run(config: &mut Config) {
+    let Some(paused) = reboot::read_paused() else {
+        return;
+    };
+    
+    if !paused.rebooted() {
+        if paused.need == reboot::Need::Required {
+            report::error("The setup is paused until a reboot, reboot first");
+            esay!("To carry on without rebooting anyway, run: rm {}", reboot::paused_path());
+            std::process::exit(1);
+        }
+        say!("⚠ Carrying on without the recommended reboot");
+    }
+    
+    if config.dry_run {
+        plan::add(plan::other("Resume the run paused for a reboot").target(reboot::paused_path()));
+        return;
+    }
+    
+    say!("↻ Resuming the setup paused for a reboot...\n");
+    reboot::clear_paused();
+    config.resuming = true;
+    
+    if Path::new(reboot::RESUME_UNIT_PATH).exists() {
+        run_or_warn(config, Cmd::sudo("systemctl").args(["disable", "ass-resume.service"]), "Failed to disable ass-resume.service");
+        run_or_warn(config, Cmd::sudo("rm").args(["-f", reboot::RESUME_UNIT_PATH]), "Failed to remove ass-resume.service");
+        run_or_warn(config, Cmd::sudo("systemctl").arg("daemon-reload"), "Failed to reload systemd");
+    }
 }
 
 fn print_help() {
-    println!("A.S.S. - Automated System Setup");
-    println!();
-    println!("USAGE:");
-    println!("    ass [OPTIONS]");
-    println!();
-    println!("OPTIONS:");
-    println!("    --help, -h           Show this help message");
-    println!("    --dry-run            Show what would be done without executing");
-    println!("    --verbose, -v        Show detailed output");
-    println!("    --skip-wallpapers    Skip cloning wallpaper repositories");
-    println!();
-    println!("EXAMPLES:");
-    println!("    ass                       # Run the setup");
-    println!("    ass --dry-run             # Test without making changes");
-    println!("    ass --verbose             # Run with detailed output");
-    println!("    ass --skip-wallpapers     # Skip wallpaper downloads");
+    say!("A.S.S. - Automated System Setup");
+    say!();
+    say!("Sets up Arch Linux (pacman and an AUR helper), Debian and Ubuntu (apt) or");
+    say!("Fedora (dnf and [copr] repos), whichever /etc/os-release names. The pacman,");
+    say!("AUR and chaotic-aur steps are skipped elsewhere.");
+    say!();
+    say!("USAGE:");
+    say!("    ass [OPTIONS]");
+    say!("    ass wizard           Answer a few questions to generate the config file");
+    say!("    ass undo             Reverse the last run: packages, system files, clones, stow links");
+    say!("    ass history          List past runs with when they started and how they went");
+    say!("    ass show <RUN>       Plan, step timings, warnings and changes of one past run");
+    say!("    ass undo-system-dotfiles");
+    say!("                         Restore the files replaced by [system_dotfiles]");
+    say!("    ass dotfiles init    Start a dotfiles repo in ~/dotfiles with the expected layout,");
+    say!("                         create it on GitHub when GITHUB_TOKEN is set, then run with it");
+    say!("    ass hm migrate-flakes");
+    say!("                         Move a channel-based Home Manager setup to a flake");
+    say!("    ass pacdiff          Review and merge .pacnew/.pacsave files");
+    say!("    ass packages         List the package list with what each package is for and");
+    say!("                         whether it's installed or left out on this machine");
+    say!("    ass facts            Show the facts about this machine that when conditions,");
+    say!("                         {{{{ fact }}}} in [files] and when= in the package list use");
+    say!("    ass doctor           Check pacman, keyrings, Nix, Home Manager and the dotfiles");
+    say!("                         for problems and print how to fix them (changes nothing)");
+    say!("    ass first-login      Run the actions left for the first graphical login");
+    say!("                         (started by ass-first-login.service)");
+    say!("    ass update           Upgrade, pull the dotfiles and redeploy without prompting");
+    say!("    ass state export [FILE]");
+    say!("                         Pack config, release pins, timings and installed packages");
+    say!("    ass state import <FILE>");
+    say!("                         Use an exported state for the next run on this machine");
+    say!("    ass schedule [remove]");
+    say!("                         Run ass update from a systemd timer ([schedule] in the config)");
+    say!();
+    say!("OPTIONS:");
+    say!("    --help, -h           Show this help message");
+    say!("    --dry-run            Show what would be done without executing");
+    say!("    --verbose, -v        Show detailed output, with the output of every command");
+    say!("                         (otherwise only the last lines of one that fails)");
+    say!("    --tail <LINES>       With --verbose, show commands' output in a pane of their");
+    say!("                         last LINES lines under the step (default 10, 0 to scroll)");
+    say!("    --plan-format <FORMAT>");
+    say!("                         With --dry-run, text (default), or json or yaml for the plan");
+    say!("                         on stdout (each action's step, command, target and effect)");
+    say!("    --output <FORMAT>    text (default), or json for one event per line on stdout");
+    say!("                         (steps, commands, warnings, errors) with the rest on stderr");
+    say!("    --skip-wallpapers    Skip cloning wallpaper repositories");
+    say!("    --skip-nvim-bootstrap");
+    say!("                         Skip headless Neovim plugin installation");
+    say!("    --skip-tmux-bootstrap");
+    say!("                         Skip installing TPM and tmux plugins");
+    say!("    --skip-report        Skip the leftover cruft report at the end");
+    say!("    --hm-config <NAME>   Home Manager flake configuration to switch to");
+    say!("    --profile <NAME>     desktop, minimal, server, gaming or one of [profiles],");
+    say!("                         instead of the profile in the config file");
+    say!("    --keep-workdir       Keep downloads and generated files in");
+    say!("                         ~/.cache/ass/<run-id> after a successful run");
+    say!("    --keep-env           Run with PATH and the environment as they are, instead of");
+    say!("                         leaving out nix-shell, venv and conda additions");
+    say!("    --ci                 No colors, every line prefixed with a UTC timestamp");
+    say!("                         and the step name (default when CI=true)");
+    say!("    --resume             Continue a failed or interrupted run, skipping the steps");
+    say!("                         it finished (progress is kept in ~/.local/state/ass/state.json)");
+    say!("    --refresh-pins       Re-resolve \"latest\" release versions instead of");
+    say!("                         reusing the ones pinned by an earlier run");
+    say!("    --timings <FILE>     Estimate step times from a timing profile exported");
+    say!("                         from another machine (~/.local/state/ass/timings.toml)");
+    say!("    --config <FILE>      Read settings from FILE instead of ~/.config/ass/config.toml");
+    say!("    --skip <STEP>        Don't run a step (repeatable, adds to skip in the config)");
+    say!("    --only <STEP,...>    Run just these steps (repeatable)");
+    say!("    --from <STEP>        Start at this step, leaving out the ones before it");
+    say!("    --until <STEP>       Stop after this step");
+    say!("    --list-steps         List the step names, in the order they run");
+    say!("    --limit-rate <RATE>  Cap curl, pacman and apt downloads, e.g. 500K or 2M per second");
+    say!("                         (git clones and AUR source downloads aren't limited)");
+    say!("    --dotfiles-repo <URL>");
+    say!("                         Clone these dotfiles instead of the default ones; the");
+    say!("                         repo must contain the package list (archpkglist.txt,");
+    say!("                         or aptpkglist.txt on Debian and Ubuntu, unless");
+    say!("                         dotfiles.package_list says otherwise)");
+    say!("    --dotfiles-branch <NAME>");
+    say!("                         Branch of the dotfiles repo to clone");
+    say!("    --stow <PKG,...>     Dotfiles packages to stow instead of dotfiles.stow");
+    say!("    --user-steps         Only run the steps that set up a home (how [users]");
+    say!("                         are provisioned, together with --home)");
+    say!("    --home <DIR>         Set up this home instead of $HOME, ignoring the");
+    say!("                         XDG_*_HOME variables (e.g. with sudo -u <user>)");
+    say!();
+    say!("CONFIG:");
+    say!("    Optional settings are read from ~/.config/ass/config.toml");
+    say!();
+    say!("EXAMPLES:");
+    say!("    ass                       # Run the setup");
+    say!("    ass --dry-run             # Test without making changes");
+    say!("    ass --verbose             # Run with detailed output");
+    say!("    ass --skip-wallpapers     # Skip wallpaper downloads");
+    say!("    ass --hm-config laptop    # Switch to homeConfigurations.laptop");
+}
+
+// --home and --config decide where the config file, state and work dir
+// are, so they're picked out before anything else is read
+fn early_option(name: &str) -> Option<String> {
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == name {
+            return args.next();
+        }
+        if let Some(value) = arg.strip_prefix(name).and_then(|rest| rest.strip_prefix('=')) {
+            return Some(value.to_string());
+        }
+    }
+    None
 }
 
 fn parse_args() -> Config {
-    let args: Vec<String> = env::args().collect();
+    paths::init(early_option("--home"));
+    if !env::args().any(|arg| arg == "--keep-env") {
+        for change in environment::sanitize() {
+            esay!("⚠ Environment: {}", change);
+        }
+    }
+    config::set_config_path(early_option("--config"));
+    
     let mut config = Config {
         dry_run: false,
         verbose: false,
         skip_wallpapers: false,
+        skip_nvim_bootstrap: false,
+        skip_tmux_bootstrap: false,
+        skip_report: false,
+        hm_configuration: None,
+        subcommand: None,
+        state_archive: None,
+        shown_run: None,
+        keep_work_dir: false,
+        ci: false,
+        refresh_pins: false,
+        limit_rate: None,
+        tail: 10,
+        plan_format: "text".to_string(),
+        work_dir: WorkDir::new(),
+        warnings: Warnings::default(),
+        context: RunContext::detect(),
+        timings_profile: None,
+        timings: Timings::load(None, BTreeMap::new()),
+        batched: Mutex::new(BTreeSet::new()),
+        hm_changes: Mutex::new(None),
+        hm_news: Mutex::new(Vec::new()),
+        selection: StepSelection::default(),
+        profile: config::Profile::default(),
+        reboots: reboot::Requests::new(),
+        resuming: false,
+        other_user: false,
+        file: config::load_file_config(&config::config_path()),
     };
     
-    for arg in args.iter().skip(1) {
-        match arg.as_str() {
+    let mut args = env::args().skip(1).peekable();
+    while let Some(arg) = args.next() {
+        // Accept both "--flag value" and "--flag=value"
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline_value.clone().or_else(|| args.next()).unwrap_or_else(|| {
+                esay!("{}", tr!("Option {} requires a value", flag));
+                std::process::exit(1);
+            })
+        };
+        
+        match flag.as_str() {
             "--help" | "-h" => {
                 print_help();
                 std::process::exit(0);
@@ -61,983 +489,7185 @@ fn parse_args() -> Config {
             "--dry-run" => config.dry_run = true,
             "--verbose" | "-v" => config.verbose = true,
             "--skip-wallpapers" => config.skip_wallpapers = true,
+            "--skip-nvim-bootstrap" => config.skip_nvim_bootstrap = true,
+            "--skip-tmux-bootstrap" => config.skip_tmux_bootstrap = true,
+            "--skip-report" => config.skip_report = true,
+            "--hm-config" => config.hm_configuration = Some(value()),
+            "--profile" => config.file.profile = Some(value()),
+            "--keep-workdir" => config.keep_work_dir = true,
+            // Handled before the config is read
+            "--keep-env" => {}
+            "--refresh-pins" => config.refresh_pins = true,
+            "--resume" => config.resuming = true,
+            "--user-steps" => config.other_user = true,
+            "--stow" => config.file.dotfiles.stow = value().split(',').map(str::to_string).collect(),
+            "--ci" => config.ci = true,
+            "--timings" => config.timings_profile = Some(value()),
+            // Already handled by early_option
+            "--home" | "--config" => {
+                value();
+            }
+            "--skip" => config.file.skip.push(value()),
+            "--only" => config.selection.only.extend(value().split(',').map(str::to_string)),
+            "--from" => config.selection.from = Some(value()),
+            "--until" => config.selection.until = Some(value()),
+            "--list-steps" => {
+                list_steps();
+                std::process::exit(0);
+            }
+            "--limit-rate" => config.limit_rate = Some(value()),
+            "--tail" => {
+                let lines = value();
+                config.tail = lines.parse().unwrap_or_else(|_| {
+                    report::fatal(format!("Invalid --tail '{}' (expected a number of lines)", lines));
+                });
+            }
+            "--output" => match value().as_str() {
+                "text" => {}
+                "json" => report::enable_json(),
+                other => report::fatal(format!("Unknown --output '{}' (expected text or json)", other)),
+            },
+            "--plan-format" => {
+                let format = value();
+                if !plan::PLAN_FORMATS.contains(&format.as_str()) {
+                    report::fatal(format!("Unknown --plan-format '{}' (expected {})", format, plan::PLAN_FORMATS.join(", ")));
+                }
+                config.plan_format = format;
+            }
+            "--dotfiles-repo" => config.file.dotfiles.repo = value(),
+            "--dotfiles-branch" => config.file.dotfiles.branch = Some(value()),
+            "wizard" | "undo" | "undo-system-dotfiles" | "pacdiff" | "update" | "first-login" | "doctor" | "history" | "packages" | "facts" if config.subcommand.is_none() => config.subcommand = Some(flag),
+            "show" if config.subcommand.is_none() => {
+                let Some(run) = args.next_if(|next| !next.starts_with("--")) else {
+                    report::fatal("ass show needs a run ID (ass history lists them)");
+                };
+                config.subcommand = Some(flag);
+                config.shown_run = Some(run);
+            }
+            "schedule" if config.subcommand.is_none() => {
+                if args.next_if(|next| next == "remove").is_some() {
+                    config.subcommand = Some("schedule remove".to_string());
+                } else {
+                    config.subcommand = Some(flag);
+                }
+            }
+            "state" if config.subcommand.is_none() => match args.next().as_deref() {
+                Some(action @ ("export" | "import")) => {
+                    config.subcommand = Some(format!("state {}", action));
+                    config.state_archive = args.next_if(|next| !next.starts_with("--"));
+                }
+                other => {
+                    esay!("{}", tr!("Unknown state command: {}", other.unwrap_or("(none)")));
+                    esay!("{}", tr!("Use --help for usage information"));
+                    std::process::exit(1);
+                }
+            },
+            "dotfiles" if config.subcommand.is_none() => match args.next().as_deref() {
+                Some("init") => config.subcommand = Some("dotfiles init".to_string()),
+                other => {
+                    esay!("{}", tr!("Unknown dotfiles command: {}", other.unwrap_or("(none)")));
+                    esay!("{}", tr!("Use --help for usage information"));
+                    std::process::exit(1);
+                }
+            },
+            "hm" if config.subcommand.is_none() => match args.next().as_deref() {
+                Some("migrate-flakes") => config.subcommand = Some("hm migrate-flakes".to_string()),
+                other => {
+                    esay!("{}", tr!("Unknown hm command: {}", other.unwrap_or("(none)")));
+                    esay!("{}", tr!("Use --help for usage information"));
+                    std::process::exit(1);
+                }
+            },
             _ => {
-                eprintln!("Unknown option: {}", arg);
-                eprintln!("Use --help for usage information");
+                esay!("{}", tr!("Unknown option: {}", arg));
+                esay!("{}", tr!("Use --help for usage information"));
                 std::process::exit(1);
             }
         }
     }
     
-    config
-}
-
-
-// For now will simply check for git installation
-fn check_deps(config: &Config) {
+    config.ci = ci::requested(config.ci);
+    if config.plan_format != "text" {
+        if !config.dry_run {
+            report::fatal("--plan-format needs --dry-run");
+        }
+        if config.subcommand.as_deref().is_some_and(|subcommand| subcommand != "update") {
+            report::fatal("--plan-format only works for the setup and ass update");
+        }
+        plan::set_format(&config.plan_format);
+    }
+    config.selection.validate();
+    command::set_retry_policy(RetryPolicy { retries: config.file.network.retries, backoff: config.file.network.backoff });
     if config.verbose {
-        println!("Checking for required dependencies...");
+        output::enable_verbose();
     }
-    
-    if config.dry_run {
-        println!("[DRY RUN] Would check for: git, curl, sudo, systemctl");
-        return;
+    // The pane and the spinners redraw in place, which only works on a
+    // terminal, and would end up among the messages with --output json
+    if !config.ci && !report::is_json() && std::io::stdout().is_terminal() {
+        if !config.verbose {
+            progress::enable();
+        } else if config.tail > 0 {
+            output::enable_tail(config.tail);
+        }
     }
+    resolve_profile(&mut config);
     
-    let mut missing_deps = Vec::new();
-    
-    // Check for git
-    let output = Command::new("which")
-        .arg("git")
-        .output()
-        .expect("Failed to execute which command");
-    
-    if output.stdout.is_empty() {
-        missing_deps.push("git");
-    } else if config.verbose {
-        println!("✓ Found git: {}", String::from_utf8_lossy(&output.stdout).trim());
+    if let Some(rate) = &config.limit_rate
+        && !valid_rate(rate)
+    {
+        report::fatal(format!("Invalid --limit-rate '{}' (expected a number of bytes per second, optionally with K, M or G)", rate));
     }
     
-    // Check for curl (needed for Nix installer)
-    let output = Command::new("which")
-        .arg("curl")
-        .output()
-        .expect("Failed to execute which command");
+    // The wizard creates it, everything else expects an explicit --config to exist
+    if early_option("--config").is_some()
+        && config.subcommand.as_deref() != Some("wizard")
+        && !Path::new(&config::config_path()).exists()
+    {
+        report::fatal(format!("Config file {} doesn't exist", config::config_path()));
+    }
     
-    if output.stdout.is_empty() {
-        missing_deps.push("curl");
-    } else if config.verbose {
-        println!("✓ Found curl: {}", String::from_utf8_lossy(&output.stdout).trim());
+    // CLI flags win over the config file
+    if config.hm_configuration.is_none() {
+        config.hm_configuration = config.file.home_manager.configuration.clone();
     }
     
-    // Check for sudo
-    let output = Command::new("which")
-        .arg("sudo")
-        .output()
-        .expect("Failed to execute which command");
+    // Steps left over from an aborted first phase don't count as done
+    let mut done = match checkpoint::load() {
+        Some(checkpoint) if checkpoint.phase == "post-nix" => checkpoint.durations,
+        _ => BTreeMap::new(),
+    };
+    // Nor do the ones left out with --only/--from/--until
+    for step in STEPS.iter().filter(|step| !config.selection.includes(step.name)) {
+        done.entry(step.name.to_string()).or_insert(0);
+    }
+    config.timings = Timings::load(config.timings_profile.as_deref(), done);
     
-    if output.stdout.is_empty() {
-        eprintln!("ERROR: sudo is required but not found");
-        std::process::exit(1);
-    } else if config.verbose {
-        println!("✓ Found sudo: {}", String::from_utf8_lossy(&output.stdout).trim());
+    config
+}
+
+// curl's --limit-rate notation: a number with an optional K, M or G suffix
+fn valid_rate(rate: &str) -> bool {
+    let digits = rate.strip_suffix(['k', 'K', 'm', 'M', 'g', 'G']).unwrap_or(rate);
+    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+fn curl_rate_args(config: &Config) -> Vec<String> {
+    match &config.limit_rate {
+        Some(rate) => vec!["--limit-rate".to_string(), rate.clone()],
+        None => Vec::new(),
+    }
+}
+
+// pacman has no rate option, so with --limit-rate it gets a copy of
+// pacman.conf that downloads through a throttled curl. Written fresh for
+// every command since other steps edit /etc/pacman.conf.
+fn pacman_config_args(config: &Config) -> Vec<String> {
+    let Some(rate) = &config.limit_rate else {
+        return Vec::new();
+    };
+    if config.dry_run {
+        return vec!["--config".to_string(), config.work_dir.planned_path("pacman.conf")];
     }
+    let Ok(current) = std::fs::read_to_string("/etc/pacman.conf") else {
+        return Vec::new();
+    };
     
-    // Check for systemctl (needed for Nix daemon)
-    let output = Command::new("which")
-        .arg("systemctl")
-        .output()
-        .expect("Failed to execute which command");
+    let xfer = format!("XferCommand = /usr/bin/curl -L -C - -f --retry 3 --retry-delay 3 --limit-rate {} -o %o %u", rate);
+    let mut limited = Vec::new();
+    for line in current.lines() {
+        if line.trim_start().starts_with("XferCommand") {
+            continue;
+        }
+        limited.push(line.to_string());
+        if line.trim() == "[options]" {
+            limited.push(xfer.clone());
+        }
+    }
     
-    if output.stdout.is_empty() {
-        eprintln!("ERROR: systemctl is required but not found (are you on systemd?)");
+    let path = config.work_dir.path("pacman.conf");
+    std::fs::write(&path, limited.join("\n") + "\n").expect("Failed to write the rate limited pacman.conf");
+    vec!["--config".to_string(), path]
+}
+
+// sudo pacman, rate limited when asked to
+fn pacman_cmd(config: &Config) -> Cmd {
+    Cmd::sudo("pacman").args(pacman_config_args(config)).diagnose()
+}
+
+// apt-get takes its rate limit in KiB per second as an option
+fn apt_option_args(config: &Config) -> Vec<String> {
+    let Some(rate) = &config.limit_rate else {
+        return Vec::new();
+    };
+    let (digits, scale) = match rate.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&rate[..i], 1),
+        Some((i, 'm' | 'M')) => (&rate[..i], 1024),
+        Some((i, 'g' | 'G')) => (&rate[..i], 1024 * 1024),
+        _ => (rate.as_str(), 0),
+    };
+    let number: u64 = digits.parse().unwrap_or(0);
+    let kib = if scale == 0 { number / 1024 } else { number * scale };
+    vec!["-o".to_string(), format!("Acquire::http::Dl-Limit={}", kib.max(1))]
+}
+
+// dnf throttles downloads itself, with a lowercase k for KiB
+fn dnf_option_args(config: &Config) -> Vec<String> {
+    match &config.limit_rate {
+        Some(rate) => vec![format!("--setopt=throttle={}", rate.replace('K', "k"))],
+        None => Vec::new(),
+    }
+}
+
+// The distribution's package manager, rate limited when asked to
+fn package_manager(config: &Config) -> Box<dyn PackageManager> {
+    let backend = pkg::backend();
+    let options = match backend {
+        Backend::Pacman => pacman_config_args(config),
+        Backend::Apt => apt_option_args(config),
+        Backend::Dnf => dnf_option_args(config),
+    };
+    backend.manager(options)
+}
+
+// The configured AUR helper, for what has to come from the AUR
+fn aur_helper(config: &Config) -> AurHelper {
+    AurHelper { helper: config.aur_helper().to_string(), flags: aur_install_flags(config) }
+}
+
+// What package_manager installs with, for the dry run descriptions
+fn install_command(config: &Config) -> String {
+    package_manager(config).install(&[]).to_string()
+}
+
+// Installing packages through the package manager, for the dry-run plan
+fn planned_install(config: &Config, packages: &[&str]) -> plan::Action {
+    plan::install(format!("{} {}", install_command(config), packages.join(" ")), packages)
+}
+
+// What install_aur_packages runs, for the dry-run plan
+fn planned_aur_install(config: &Config, packages: &[&str]) -> plan::Action {
+    if pkg::backend() != Backend::Pacman {
+        return planned_install(config, packages);
+    }
+    let names: Vec<String> = packages.iter().map(|package| package.to_string()).collect();
+    plan::install(aur_helper(config).install(&names).to_string(), packages)
+}
+
+// Every step's commands go through here so dry runs, verbose logging and
+// sudo are handled the same way everywhere
+fn run_command(config: &Config, cmd: Cmd) -> Result<(), CmdError> {
+    command::run(&RunOptions { dry_run: config.dry_run, verbose: config.verbose }, &cmd)
+}
+
+fn run_or_exit(config: &Config, cmd: Cmd, failure: &str) {
+    if let Err(e) = run_command(config, cmd) {
+        esay!("{} ({})", failure, e);
+        for hint in e.hints() {
+            esay!("  → {}", hint);
+        }
         std::process::exit(1);
-    } else if config.verbose {
-        println!("✓ Found systemctl: {}", String::from_utf8_lossy(&output.stdout).trim());
     }
-    
-    // Install missing dependencies
-    if !missing_deps.is_empty() {
-        println!("Installing missing dependencies: {}", missing_deps.join(", "));
-        let mut args = vec!["-S", "--noconfirm"];
-        args.extend(missing_deps.iter().map(|s| *s));
-        
-        let status = Command::new("sudo")
-            .arg("pacman")
-            .args(&args)
-            .status()
-            .expect("Failed to install dependencies");
-        
-        if !status.success() {
-            eprintln!("Failed to install dependencies");
-            std::process::exit(1);
+}
+
+// Returns false (and records a warning) when the command failed
+fn run_or_warn(config: &Config, cmd: Cmd, failure: &str) -> bool {
+    match run_command(config, cmd) {
+        Ok(()) => true,
+        Err(e) => {
+            config.warnings.add_with_hints(WarningKind::Failure, format!("{} ({})", failure, e), e.hints());
+            false
         }
-        println!("✓ Dependencies installed successfully");
-    } else if config.verbose {
-        println!("✓ All required dependencies are installed");
     }
 }
 
-// proceed to install and setup paru (the greatest aur helper ever made)
-fn install_paru(config: &Config) {
-    println!("Installing paru...");
+// Installed with the other packages when missing
+const BOOTSTRAP_TOOLS: [&str; 2] = ["git", "curl"];
+
+// Nothing works without these
+const REQUIRED_TOOLS: [&str; 1] = ["sudo"];
+
+// Check for the bootstrap and required tools. A missing git or curl is
+// installed with the other packages, a missing sudo ends the run.
+fn check_deps(config: &Config) {
+    if config.verbose {
+        say!("Checking for required dependencies...");
+    }
     
     if config.dry_run {
-        println!("[DRY RUN] Would check if paru is installed, if not:");
-        println!("  1. git clone https://aur.archlinux.org/paru.git");
-        println!("  2. sudo pacman -Syyu --noconfirm rustup bat devtools");
-        println!("  3. rustup default stable");
-        println!("  4. cd paru && makepkg -si --noconfirm");
+        plan::add(plan::check(format!("Check for git, curl and sudo (init system: {})", init::init().name())));
+        if config.file.network.check {
+            plan::add(plan::check(format!("Check DNS, ping and HTTPS for {}", network_hosts(config).join(", "))));
+        }
         return;
     }
     
-    // Check if paru is already installed
-    let output = Command::new("which")
-        .arg("paru")
-        .output()
-        .expect("Failed to execute which command");
+    let mut missing_deps = Vec::new();
     
-    if !output.stdout.is_empty() {
-        if config.verbose {
-            println!("✓ Paru is already installed: {}", String::from_utf8_lossy(&output.stdout).trim());
-        } else {
-            println!("✓ Paru already installed, skipping installation");
+    for tool in BOOTSTRAP_TOOLS {
+        match which(tool) {
+            None => missing_deps.push(tool),
+            Some(path) if config.verbose => say!("✓ Found {}: {}", tool, path),
+            Some(_) => {}
         }
-        return;
     }
     
-    // Clone paru repo
-    if config.verbose {
-        println!("Cloning paru AUR repository...");
+    for tool in REQUIRED_TOOLS {
+        match which(tool) {
+            None => {
+                report::fatal(format!("{} is required but not found (install it as root and give your user sudo rights)", tool));
+            }
+            Some(path) if config.verbose => say!("✓ Found {}: {}", tool, path),
+            Some(_) => {}
+        }
     }
-    let status = Command::new("git")
-        .args(&["clone", "https://aur.archlinux.org/paru.git"])
-        .status()
-        .expect("Failed to execute git clone");
     
-    if !status.success() {
-        eprintln!("Failed to clone paru repository");
-        std::process::exit(1);
+    if config.file.network.check {
+        check_network(config);
     }
     
-    // Install dependencies
-    if config.verbose {
-        println!("Installing dependencies (rustup, bat, devtools)...");
+    if init::init() != Init::Systemd {
+        say!("⚠ Running on {}, steps that set up systemd units are skipped", init::init().name());
     }
-    let status = Command::new("sudo")
-        .args(&["pacman", "-Syyu", "--noconfirm", "rustup", "bat", "devtools"])
-        .status()
-        .expect("Failed to execute pacman");
     
-    if !status.success() {
-        eprintln!("Failed to install dependencies");
-        std::process::exit(1);
+    // Installed with everything else once pacman is configured
+    if !missing_deps.is_empty() {
+        say!("Missing dependencies: {} (installed with the other packages)", missing_deps.join(", "));
+    } else if config.verbose {
+        say!("✓ All required dependencies are installed");
     }
-    
-    // Setup rust stable
+}
+
+fn network_hosts(config: &Config) -> Vec<String> {
+    if config.file.network.hosts.is_empty() { network::default_hosts() } else { config.file.network.hosts.clone() }
+}
+
+// Nothing gets far without downloads, so stop right away when no host can
+// be reached over HTTPS, unless connecting to Wi-Fi fixes it (a fresh
+// install usually has no network yet). Other failures are reported and the
+// run goes on.
+fn check_network(config: &Config) {
     if config.verbose {
-        println!("Setting up Rust stable toolchain...");
-    }
-    let status = Command::new("rustup")
-        .args(&["default", "stable"])
-        .status()
-        .expect("Failed to execute rustup");
-    
-    if !status.success() {
-        eprintln!("Failed to setup rust stable");
-        std::process::exit(1);
+        say!("Checking network connectivity...");
     }
+    let timeout = std::time::Duration::from_secs(config.file.network.timeout);
+    let mut reports = network::check(&network_hosts(config), timeout);
     
-    // Build and install paru
-    if config.verbose {
-        println!("Building and installing paru...");
+    while !reports.iter().any(|report| report.reachable()) {
+        esay!("ERROR: No network connection");
+        for report in &reports {
+            esay!("  {}:", report.host);
+            for failure in report.failures() {
+                esay!("    ✗ {}", failure);
+            }
+        }
+        let interactive = !config.ci && std::io::stdin().is_terminal();
+        if !interactive || !wifi::available() || !ask_yes_no(tr!("Connect to a Wi-Fi network now?"), true) {
+            esay!("Check the connection (or [network] hosts in {}) and run ass again", config::config_path());
+            report::emit(report::Event::Error { message: "No network connection" });
+            std::process::exit(1);
+        }
+        if wifi::onboard() {
+            reports = network::check(&network_hosts(config), timeout);
+        }
     }
-    let status = Command::new("makepkg")
-        .args(&["-si", "--noconfirm"])
-        .current_dir("./paru")
-        .status()
-        .expect("Failed to execute makepkg");
     
-    if !status.success() {
-        eprintln!("Failed to build/install paru");
-        std::process::exit(1);
+    for report in &reports {
+        if !report.reachable() {
+            config.warnings.add(WarningKind::Failure, format!("{} is unreachable: {}", report.host, report.failures().join("; ")));
+        } else if config.verbose {
+            match report.failures().as_slice() {
+                [] => say!("✓ {}: DNS, ping and HTTPS", report.host),
+                failures => say!("✓ {} reachable over HTTPS ({})", report.host, failures.join("; ")),
+            }
+        }
     }
-    
-    println!("✓ Paru installed successfully!");
 }
 
-// Clone dotfiles and install packages
-fn setup_dotfiles(config: &Config) {
-    println!("Setting up dotfiles...");
+// Official repo packages the enabled first-phase steps need, known from the
+// config alone. Anything that depends on what gets installed later (detected
+// compositor, selected partitions, ...) is still installed by the step itself.
+fn declared_packages(config: &Config) -> Vec<(&'static str, Vec<String>)> {
+    let file = &config.file;
+    let mut declared: Vec<(&'static str, Vec<&str>)> = Vec::new();
     
-    if config.dry_run {
-        println!("[DRY RUN] Would execute:");
-        println!("  1. Check if ~/dotfiles exists");
-        println!("  2. cd ~");
-        println!("  3. git clone --depth=1 https://github.com/jeebuscrossaint/dotfiles.git");
-        println!("  4. cd dotfiles");
-        println!("  5. Filter out invalid packages and run paru -S --needed --noconfirm --skipreview --batchinstall");
-        return;
+    declared.push(("check_deps", BOOTSTRAP_TOOLS.into_iter().filter(|tool| which(tool).is_none()).collect()));
+    if pkg::backend() == Backend::Pacman && which(config.aur_helper()).is_none() {
+        let mut deps = aur_helper_build_deps(config.aur_helper()).to_vec();
+        if config.aur_helper() == "paru" {
+            deps.extend(rust_package(config));
+        }
+        declared.push(("install_aur_helper", deps));
     }
     
-    // Get home directory
-    let home = env::var("HOME").expect("HOME environment variable not set");
-    let dotfiles_path = format!("{}/dotfiles", home);
-    
-    // Check if dotfiles already exists
-    if Path::new(&dotfiles_path).exists() {
-        if config.verbose {
-            println!("✓ Dotfiles directory already exists at {}", dotfiles_path);
-        } else {
-            println!("✓ Dotfiles already cloned, skipping clone");
+    if config.is_desktop() {
+        if let Some((packages, _)) = file.portal.desktop.as_deref().and_then(portal_backend) {
+            declared.push(("setup_desktop_portal", [&["xdg-desktop-portal"], packages].concat()));
         }
-    } else {
-        // Clone dotfiles repo with --depth=1
-        if config.verbose {
-            println!("Cloning dotfiles repository to {} (shallow clone)...", home);
+        if let Some(agent) = file.polkit.agent.as_deref()
+            && !package_installed("plasma-workspace")
+            && !package_installed("gnome-shell")
+        {
+            declared.push(("setup_polkit_agent", vec![agent]));
         }
-        let status = Command::new("git")
-            .args(&["clone", "--depth=1", "https://github.com/jeebuscrossaint/dotfiles.git"])
-            .current_dir(&home)
-            .status()
-            .expect("Failed to execute git clone");
-        
-        if !status.success() {
-            eprintln!("Failed to clone dotfiles repository");
-            std::process::exit(1);
+        if file.automount.enable && let Some(helper) = file.automount.helper.as_deref() {
+            declared.push(("setup_removable_media", vec!["udisks2", helper]));
+        }
+        if let Some(packages) = file.display_manager.name.as_deref().and_then(display_manager_packages) {
+            declared.push(("setup_display_manager", packages.to_vec()));
         }
     }
     
-    // Install packages from archpkglist.txt
-    if config.verbose {
-        println!("Installing packages from archpkglist.txt...");
+    // paru builds the chroot with devtools' mkarchroot
+    if file.aur.chroot {
+        declared.push(("setup_dotfiles", vec!["devtools"]));
     }
-    
-    let pkglist_path = format!("{}/archpkglist.txt", dotfiles_path);
-    
-    // Read the package list and filter out problematic packages
-    let pkglist_content = std::fs::read_to_string(&pkglist_path)
-        .expect("Failed to read archpkglist.txt");
-    
-    let filtered_packages: Vec<&str> = pkglist_content
-        .lines()
-        .map(|line| line.trim())
-        .filter(|line| !line.is_empty() && !line.starts_with('#'))
-        .filter(|line| *line != "paru-debug") // Filter out paru-debug
-        .collect();
-    
-    if config.verbose {
-        println!("Installing {} packages (filtered out invalid packages)", filtered_packages.len());
+    if pkg::backend() == Backend::Dnf && !file.copr.repos.is_empty() {
+        declared.push(("setup_copr", vec![copr_plugin()]));
     }
+    declared.push(("install_guest_tools", config.context.guest_tools().0.to_vec()));
     
-    // Create a temporary filtered package list
-    let temp_pkglist = "/tmp/ass-filtered-pkglist.txt";
-    std::fs::write(temp_pkglist, filtered_packages.join("\n"))
-        .expect("Failed to write temporary package list");
-    
-    let status = Command::new("paru")
-        .args(&["-S", "--needed", "--noconfirm", "--skipreview", "--batchinstall", "-"])
-        .current_dir(&dotfiles_path)
-        .stdin(std::fs::File::open(temp_pkglist).expect("Failed to open temp package list"))
-        .status()
-        .expect("Failed to execute paru");
+    if !config.context.is_container() {
+        if file.system_tweaks.console_font.as_deref().is_some_and(|font| font.starts_with("ter-")) {
+            declared.push(("apply_system_tweaks", vec!["terminus-font"]));
+        }
+        if file.grub.os_prober == Some(true) {
+            declared.push(("configure_grub", vec!["os-prober", "ntfs-3g"]));
+        }
+    }
     
-    // Clean up temp file
-    let _ = std::fs::remove_file(temp_pkglist);
+    declared.push(("setup_network_mounts", mount_packages(&file.mounts)));
+    if config.context.has_hardware() && file.smartd.enable {
+        declared.push(("setup_smartd", smartd_packages(&file.smartd)));
+    }
+    if file.oom.method.as_deref() == Some("earlyoom") {
+        declared.push(("setup_oom_protection", vec!["earlyoom", "libnotify"]));
+    }
+    declared.push(("deploy_dotfiles", vec!["stow"]));
     
-    if !status.success() {
-        eprintln!("Failed to install packages from archpkglist.txt");
-        std::process::exit(1);
+    let toolchains = &file.toolchains;
+    declared.push((
+        "install_toolchains",
+        [("node", &toolchains.node), ("python", &toolchains.python), ("go", &toolchains.go)]
+            .into_iter()
+            .filter_map(|(language, toolchain)| Some(toolchain.as_ref()?.manager_for(language)))
+            .collect(),
+    ));
+    if !file.toolchains.rust.components.is_empty() || !file.toolchains.rust.cargo_tools.is_empty() {
+        declared.push(("install_rust_tools", rust_package(config).into_iter().collect()));
+    }
+    if !file.distrobox.containers.is_empty() {
+        declared.push(("setup_distrobox", vec!["distrobox", &file.distrobox.engine]));
     }
     
-    println!("✓ Dotfiles setup complete!");
+    declared
+        .into_iter()
+        .filter(|(step, packages)| !packages.is_empty() && will_run(config, step))
+        .map(|(step, packages)| (step, packages.into_iter().map(str::to_string).collect()))
+        .collect()
 }
 
-// Install stow and deploy dotfiles
-fn deploy_dotfiles(config: &Config) {
-    println!("Deploying dotfiles with GNU Stow...");
+// One package manager transaction (and one sudo prompt) for everything the
+// steps declared, instead of one per step. Returns what it installed.
+fn install_declared_packages(config: &Config) -> BTreeSet<String> {
+    let declared = declared_packages(config);
+    let packages: BTreeSet<String> = declared.iter().flat_map(|(_, packages)| packages.iter().cloned()).collect();
     
-    if config.dry_run {
-        println!("[DRY RUN] Would execute:");
-        println!("  1. sudo pacman -S --noconfirm stow");
-        println!("  2. mkdir -p ~/.config");
-        return;
-    }
-    
-    // Install GNU Stow
-    if config.verbose {
-        println!("Installing GNU Stow...");
+    if packages.is_empty() {
+        if config.verbose {
+            say!("⏭ No packages declared by the enabled steps");
+        }
+        return packages;
     }
-    let status = Command::new("sudo")
-        .args(&["pacman", "-S", "--noconfirm", "stow"])
-        .status()
-        .expect("Failed to execute pacman");
     
-    if !status.success() {
-        eprintln!("Failed to install stow");
-        std::process::exit(1);
+    say!("Installing {} packages for the enabled steps...", packages.len());
+    if config.verbose || config.dry_run {
+        for (step, step_packages) in &declared {
+            say!("  {}: {}", step, step_packages.join(" "));
+        }
     }
     
-    let home = env::var("HOME").expect("HOME environment variable not set");
-    let config_path = format!("{}/.config", home);
-    
-    // Create .config directory
-    if config.verbose {
-        println!("Creating ~/.config directory...");
+    let manager = package_manager(config);
+    let list = pkg::backend().native_names(&packages.iter().collect::<Vec<_>>());
+    let cmds = if list.is_empty() { vec![manager.refresh()] } else { manager.install_refreshed(&list) };
+    for cmd in cmds {
+        run_or_exit(config, cmd, "Failed to install packages");
     }
-    let status = Command::new("mkdir")
-        .args(&["-p", &config_path])
-        .status()
-        .expect("Failed to create .config directory");
     
-    if !status.success() {
-        eprintln!("Failed to create .config directory");
-        std::process::exit(1);
+    if !config.dry_run {
+        say!("✓ Packages installed!");
     }
-    
-    println!("✓ Stow installed and directories prepared!");
+    packages
 }
 
-// Stow custom configs after initial home-manager generation
-fn stow_custom_configs(config: &Config) {
-    println!("Deploying custom dotfiles with GNU Stow...");
-    
-    if config.dry_run {
-        println!("[DRY RUN] Would execute:");
-        println!("  1. Remove default ~/.config/home-manager");
-        println!("  2. Remove default ~/.config/nix");
-        println!("  3. cd ~/dotfiles && stow home-manager");
-        println!("  4. cd ~/dotfiles && stow nix");
+// `pacman -S --needed` (or apt-get install) for whatever the up-front
+// transaction didn't cover
+fn install_packages<S: AsRef<str>>(config: &Config, packages: &[S], failure: &str) {
+    let pending = pending_packages(config, packages);
+    if pending.is_empty() {
         return;
     }
+    let pending = pkg::backend().native_names(&pending);
+    if !pending.is_empty() {
+        run_or_exit(config, package_manager(config).install(&pending), failure);
+    }
+}
+
+// Packages from the AUR on Arch, from the distribution's repos elsewhere
+fn install_aur_packages(config: &Config, packages: &[&str], failure: &str) {
+    if pkg::backend() == Backend::Pacman {
+        let packages: Vec<String> = packages.iter().map(|package| package.to_string()).collect();
+        run_or_exit(config, aur_helper(config).install(&packages), failure);
+    } else {
+        install_packages(config, packages, failure);
+    }
+}
+
+fn pending_packages<S: AsRef<str>>(config: &Config, packages: &[S]) -> Vec<String> {
+    packages
+        .iter()
+        .map(|package| package.as_ref())
+        .filter(|package| !config.batched.lock().unwrap_or_else(|e| e.into_inner()).contains(*package))
+        .map(str::to_string)
+        .collect()
+}
+
+// Packages needed to build each supported AUR helper
+fn aur_helper_build_deps(helper: &str) -> &'static [&'static str] {
+    match helper {
+        "yay" => &["go"],
+        // makepkg -s pulls pikaur's python deps from the repos itself
+        "pikaur" => &[],
+        // Rust comes from ensure_rust so an existing install is kept
+        _ => &["bat", "devtools"],
+    }
+}
+
+// What currently provides cargo and rustc
+#[derive(PartialEq)]
+enum RustInstall {
+    Missing,
+    Rustup { has_default: bool },
+    // The rust package, or a toolchain installed some other way
+    System,
+}
+
+fn detect_rust() -> RustInstall {
+    if which("rustup").is_some() {
+        // Without a default toolchain `rustup default` fails or says so
+        let has_default = Command::new("rustup")
+            .arg("default")
+            .output()
+            .map(|output| output.status.success() && !String::from_utf8_lossy(&output.stdout).contains("no default"))
+            .unwrap_or(false);
+        return RustInstall::Rustup { has_default };
+    }
+    if which("cargo").is_some() || which("rustc").is_some() || package_installed("rust") {
+        return RustInstall::System;
+    }
+    RustInstall::Missing
+}
+
+// Package to install when there is no Rust at all yet
+fn rust_package(config: &Config) -> Option<&'static str> {
+    if detect_rust() != RustInstall::Missing {
+        return None;
+    }
+    Some(if config.file.toolchains.rust.source == "system" { "rust" } else { "rustup" })
+}
+
+// Make cargo usable without clobbering an existing toolchain setup. Returns
+// whether rustup manages it, components can only be added then.
+fn ensure_rust(config: &Config) -> bool {
+    let rust = &config.file.toolchains.rust;
     
-    let home = env::var("HOME").expect("HOME environment variable not set");
-    let dotfiles_path = format!("{}/dotfiles", home);
-    let hm_config_path = format!("{}/.config/home-manager", home);
-    let nix_config_path = format!("{}/.config/nix", home);
-    
-    // Remove default home-manager config
-    if Path::new(&hm_config_path).exists() {
-        if config.verbose {
-            println!("Removing default home-manager config...");
+    match detect_rust() {
+        RustInstall::Rustup { has_default: true } => {
+            if config.verbose {
+                say!("✓ Using the existing rustup default toolchain");
+            }
+            true
         }
-        let status = Command::new("rm")
-            .args(&["-rf", &hm_config_path])
-            .status()
-            .expect("Failed to remove home-manager config");
-        
-        if !status.success() {
-            eprintln!("Failed to remove default home-manager config");
-            std::process::exit(1);
+        RustInstall::Rustup { has_default: false } => {
+            if config.verbose {
+                say!("Setting {} as the default Rust toolchain...", rust.default_toolchain);
+            }
+            run_or_exit(
+                config,
+                Cmd::new("rustup").args(["default", &rust.default_toolchain]),
+                &format!("Failed to set up rust {}", rust.default_toolchain),
+            );
+            true
         }
-    }
-    
-    // Remove default nix config
-    if Path::new(&nix_config_path).exists() {
-        if config.verbose {
-            println!("Removing default nix config...");
+        RustInstall::System => {
+            if rust.source == "rustup" {
+                config.warnings.add(
+                    WarningKind::Skipped,
+                    "A system Rust is installed, not replacing it with rustup (remove the rust package to switch)",
+                );
+            } else if config.verbose {
+                say!("✓ Using the system Rust installation");
+            }
+            false
         }
-        let status = Command::new("rm")
-            .args(&["-rf", &nix_config_path])
-            .status()
-            .expect("Failed to remove nix config");
-        
-        if !status.success() {
-            eprintln!("Failed to remove default nix config");
-            std::process::exit(1);
+        RustInstall::Missing if rust.source == "system" => {
+            install_packages(config, &["rust"], "Failed to install rust");
+            false
+        }
+        RustInstall::Missing => {
+            install_packages(config, &["rustup"], "Failed to install rustup");
+            run_or_exit(
+                config,
+                Cmd::new("rustup").args(["default", &rust.default_toolchain]),
+                &format!("Failed to set up rust {}", rust.default_toolchain),
+            );
+            true
         }
     }
-    
-    // Stow home-manager
-    if config.verbose {
-        println!("Stowing home-manager...");
+}
+
+// Extra flags so the bulk install runs unattended
+fn aur_helper_batch_flags(helper: &str) -> &'static [&'static str] {
+    match helper {
+        "yay" => &["--answerdiff", "None", "--answerclean", "None"],
+        "pikaur" => &["--noedit", "--nodiff"],
+        _ => &["--skipreview", "--batchinstall"],
     }
-    let status = Command::new("stow")
-        .arg("home-manager")
-        .current_dir(&dotfiles_path)
-        .status()
-        .expect("Failed to stow home-manager");
-    
-    if !status.success() {
-        eprintln!("Failed to stow home-manager");
-        std::process::exit(1);
+}
+
+// Batch flags, plus a clean chroot for the builds when [aur] chroot is set
+// and the rate limited pacman.conf with --limit-rate
+fn aur_install_flags(config: &Config) -> Vec<String> {
+    let mut flags: Vec<String> = aur_helper_batch_flags(config.aur_helper()).iter().map(|flag| flag.to_string()).collect();
+    if config.file.aur.chroot {
+        flags.push("--chroot".to_string());
     }
-    
-    // Stow nix
-    if config.verbose {
-        println!("Stowing nix...");
+    flags.extend(pacman_config_args(config));
+    flags
+}
+
+fn which(binary: &str) -> Option<String> {
+    let output = Command::new("which").arg(binary).output().ok()?;
+    let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if output.status.success() && !path.is_empty() { Some(path) } else { None }
+}
+
+// Derivative distros often ship yay or pikaur already. Decide whether to use
+// that one, replace it or install the configured helper next to it, and save
+// the answer in the config so the next run (and the post-nix phase) agrees.
+fn resolve_aur_helper(config: &mut Config) {
+    let configured = config.aur_helper().to_string();
+    if pkg::backend() != Backend::Pacman || which(&configured).is_some() {
+        return;
     }
-    let status = Command::new("stow")
-        .arg("nix")
-        .current_dir(&dotfiles_path)
-        .status()
-        .expect("Failed to stow nix");
     
-    if !status.success() {
-        eprintln!("Failed to stow nix");
-        std::process::exit(1);
-    }
+    let others: Vec<&str> = config::AUR_HELPERS
+        .iter()
+        .copied()
+        .filter(|helper| *helper != configured && which(helper).is_some())
+        .collect();
+    let Some(other) = others.first().map(|helper| helper.to_string()) else {
+        return;
+    };
+    
+    let choice = match &config.file.aur.existing {
+        Some(choice) => choice.clone(),
+        None if config.dry_run => {
+            plan::add(plan::ask(format!("Ask whether to use, replace or keep the installed {}", others.join(", "))));
+            return;
+        }
+        None => {
+            say!("Found {} already installed, but the config asks for {}.", others.join(", "), configured);
+            say!("  use      - keep using {} and skip building {}", other, configured);
+            say!("  replace  - install {} and remove {}", configured, others.join(", "));
+            say!("  coexist  - install {} and leave {} alone", configured, others.join(", "));
+            let choice = loop {
+                let answer = prompt("How should the existing helper be handled? (use/replace/coexist)", "use");
+                if config::EXISTING_HELPER_CHOICES.contains(&answer.as_str()) {
+                    break answer;
+                }
+            };
+            
+            let mut values = vec![("aur", "existing", toml::Value::String(choice.clone()))];
+            if choice == "use" {
+                values.push(("aur", "helper", toml::Value::String(other.clone())));
+            }
+            let path = config::config_path();
+            config::write_config_values(&path, &values);
+            say!("✓ Saved choice to {}", path);
+            choice
+        }
+    };
     
-    println!("✓ Custom dotfiles deployed successfully!");
+    if choice == "use" {
+        say!("✓ Using the installed {} instead of {}", other, configured);
+        config.file.aur.helper = other;
+    }
+    config.file.aur.existing = Some(choice);
 }
 
-// Install Nix package manager
-fn install_nix(config: &Config) {
-    println!("Installing Nix package manager...");
+// With aur.existing = "replace", uninstall every other helper once ours works
+fn remove_other_aur_helpers(config: &Config) {
+    if config.file.aur.existing.as_deref() != Some("replace") {
+        return;
+    }
     
-    if config.dry_run {
-        println!("[DRY RUN] Would execute:");
-        println!("  1. Check if nix is already installed");
-        println!("  2. cd ~");
-        println!("  3. curl --proto '=https' --tlsv1.2 -sSfL https://nixos.org/nix/install -o nix-install.sh");
-        println!("  4. chmod +x nix-install.sh");
-        println!("  5. sh ./nix-install.sh --daemon");
-        println!("  6. Prompt user to log out and log back in");
+    // Ask pacman who owns the binary, it may be yay-bin, pikaur-git, ...
+    let packages: Vec<String> = config::AUR_HELPERS
+        .iter()
+        .filter(|helper| **helper != config.aur_helper())
+        .filter_map(|helper| which(helper))
+        .filter_map(|path| {
+            let output = Command::new("pacman").args(["-Qqo", &path]).output().ok()?;
+            let package = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if output.status.success() && !package.is_empty() { Some(package) } else { None }
+        })
+        .collect();
+    
+    if packages.is_empty() {
         return;
     }
     
-    // Check if nix is already installed
-    let output = Command::new("which")
-        .arg("nix")
-        .output()
-        .expect("Failed to execute which command");
+    say!("Removing {}...", packages.join(", "));
+    if run_or_warn(
+        config,
+        package_manager(config).remove(&packages, true),
+        &format!("Failed to remove {}", packages.join(", ")),
+    ) && !config.dry_run
+    {
+        say!("✓ Removed {}", packages.join(", "));
+    }
+}
+
+// proceed to install and setup paru (the greatest aur helper ever made),
+// or yay if that's what the config asks for
+fn install_aur_helper(config: &Config) {
+    let helper = config.aur_helper();
+    let deps = aur_helper_build_deps(helper);
+    say!("Installing {}...", helper);
     
-    if !output.stdout.is_empty() {
+    // Check if the helper is already installed
+    if let Some(path) = which(helper) {
         if config.verbose {
-            println!("✓ Nix is already installed: {}", String::from_utf8_lossy(&output.stdout).trim());
+            say!("✓ {} is already installed: {}", helper, path);
         } else {
-            println!("✓ Nix already installed, skipping installation");
+            say!("✓ {} already installed, skipping installation", helper);
         }
+        remove_other_aur_helpers(config);
         return;
     }
     
-    let home = env::var("HOME").expect("HOME environment variable not set");
-    
-    // Download Nix installer
+    // Clone the helper's AUR repo
     if config.verbose {
-        println!("Downloading Nix installer to {}...", home);
-    }
-    let status = Command::new("curl")
-        .args(&[
-            "--proto", "=https",
-            "--tlsv1.2",
-            "-sSfL",
-            "https://nixos.org/nix/install",
-            "-o", "nix-install.sh"
-        ])
-        .current_dir(&home)
-        .status()
-        .expect("Failed to execute curl");
-    
-    if !status.success() {
-        eprintln!("Failed to download Nix installer");
-        std::process::exit(1);
+        say!("Cloning {} AUR repository...", helper);
     }
+    let helper_path = config.work_path(helper);
+    run_or_exit(
+        config,
+        Cmd::new("git")
+            .args(["clone", &format!("https://aur.archlinux.org/{}.git", helper), &helper_path])
+            .network(),
+        &format!("Failed to clone {} repository", helper),
+    );
     
-    // Make installer executable
+    // Install dependencies
     if config.verbose {
-        println!("Making installer executable...");
+        say!("Installing dependencies ({})...", deps.join(", "));
     }
-    let nix_installer_path = format!("{}/nix-install.sh", home);
-    let status = Command::new("chmod")
-        .args(&["+x", &nix_installer_path])
-        .status()
-        .expect("Failed to execute chmod");
+    install_packages(config, deps, "Failed to install dependencies");
     
-    if !status.success() {
-        eprintln!("Failed to make Nix installer executable");
-        std::process::exit(1);
+    if helper == "paru" {
+        ensure_rust(config);
     }
     
-    // Run Nix installer with daemon mode
+    // Build and install the helper
     if config.verbose {
-        println!("Running Nix installer (daemon mode)...");
+        say!("Building and installing {}...", helper);
     }
-    let status = Command::new("sh")
-        .args(&["./nix-install.sh", "--daemon"])
-        .current_dir(&home)
-        .status()
-        .expect("Failed to execute Nix installer");
+    run_or_exit(
+        config,
+        Cmd::new("makepkg")
+            .args(["-si", "--noconfirm"])
+            .current_dir(&helper_path)
+            .escalates(),
+        &format!("Failed to build/install {}", helper),
+    );
     
-    if !status.success() {
-        eprintln!("Failed to install Nix");
-        std::process::exit(1);
+    if !config.dry_run {
+        say!("✓ {} installed successfully!", helper);
     }
-    
-    println!("✓ Nix installed successfully!");
-    println!();
-    println!("╔════════════════════════════════════════════════════════════╗");
-    println!("║  ⚠️  ACTION REQUIRED                                        ║");
-    println!("║                                                            ║");
-    println!("║  Nix has been installed successfully!                      ║");
-    println!("║                                                            ║");
-    println!("║  You MUST log out and log back in for the changes to      ║");
-    println!("║  take effect before continuing the installation.          ║");
-    println!("║                                                            ║");
-    println!("║  After logging back in, run this script again:            ║");
-    println!("║  $ ./ass                                                   ║");
-    println!("║                                                            ║");
-    println!("║  The installation will automatically resume from where    ║");
-    println!("║  it left off.                                              ║");
-    println!("╔════════════════════════════════════════════════════════════╗");
-    println!();
-    
-    // Set state to resume after nix installation
-    set_install_state("post-nix");
-    std::process::exit(0);
+    remove_other_aur_helpers(config);
 }
 
-// Enable Nix daemon and setup home-manager
-fn setup_home_manager(config: &Config) {
-    println!("Setting up Home Manager...");
-    
-    if config.dry_run {
-        println!("[DRY RUN] Would execute:");
-        println!("  1. sudo systemctl enable --now nix-daemon.service");
-        println!("  2. nix-channel --add https://github.com/nix-community/home-manager/archive/master.tar.gz home-manager");
-        println!("  3. nix-channel --update");
-        println!("  4. nix-shell '<home-manager>' -A install");
-        return;
+enum CloneState {
+    Missing,
+    Valid,
+    // A working clone of some other repository
+    WrongRemote(String),
+    // Not a work tree, or one without a checked-out commit (interrupted clone)
+    Broken,
+}
+
+fn git_output(path: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new("git").arg("-C").arg(path).args(args).output().ok()?;
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        None
     }
-    
-    // Enable and start Nix daemon service
-    if config.verbose {
-        println!("Enabling Nix daemon service...");
+}
+
+fn same_remote(a: &str, b: &str) -> bool {
+    let normalize = |url: &str| url.trim_end_matches('/').trim_end_matches(".git").to_lowercase();
+    normalize(a) == normalize(b)
+}
+
+fn check_clone(path: &str, url: &str) -> CloneState {
+    if !Path::new(path).exists() {
+        return CloneState::Missing;
     }
-    let status = Command::new("sudo")
-        .args(&["systemctl", "enable", "--now", "nix-daemon.service"])
-        .status()
-        .expect("Failed to execute systemctl");
-    
-    if !status.success() {
-        eprintln!("Failed to enable Nix daemon service");
-        std::process::exit(1);
+    // A repo made in place by `ass dotfiles init` that was never pushed
+    if normalize_path(Path::new(url)) == normalize_path(Path::new(path)) {
+        return if git_output(path, &["rev-parse", "--verify", "HEAD"]).is_some() { CloneState::Valid } else { CloneState::Broken };
     }
     
-    // Add home-manager channel
-    if config.verbose {
-        println!("Adding home-manager channel...");
+    // rev-parse from a subdirectory would find an enclosing repo, compare the top level
+    let toplevel = git_output(path, &["rev-parse", "--show-toplevel"]);
+    let inside = git_output(path, &["rev-parse", "--is-inside-work-tree"]).as_deref() == Some("true");
+    let is_root = toplevel.map(|top| normalize_path(Path::new(&top)) == normalize_path(Path::new(path))).unwrap_or(false);
+    if !inside || !is_root || git_output(path, &["rev-parse", "--verify", "HEAD"]).is_none() {
+        return CloneState::Broken;
     }
-    let status = Command::new("nix-channel")
-        .args(&[
-            "--add",
-            "https://github.com/nix-community/home-manager/archive/master.tar.gz",
-            "home-manager"
-        ])
-        .status()
-        .expect("Failed to execute nix-channel add");
     
-    if !status.success() {
-        eprintln!("Failed to add home-manager channel");
-        std::process::exit(1);
+    match git_output(path, &["remote", "get-url", "origin"]) {
+        Some(remote) if same_remote(&remote, url) => CloneState::Valid,
+        Some(remote) => CloneState::WrongRemote(remote),
+        None => CloneState::Broken,
     }
-    
-    // Update channels
-    if config.verbose {
-        println!("Updating nix channels...");
+}
+
+// What clone_repo would do, for the dry-run plan
+fn planned_clone(url: &str, path: &str, clone_args: &[String]) -> plan::Action {
+    let command = format!("git clone {} {} {}", clone_args.join(" "), url, path).replace("  ", " ");
+    let action = plan::clone(command, path);
+    match check_clone(path, url) {
+        CloneState::Valid => action.effect("nothing, already cloned"),
+        CloneState::WrongRemote(remote) => action.effect(format!("nothing, {} is a clone of {}", path, remote)),
+        CloneState::Broken => action.effect(format!("moves the incomplete clone at {} aside and clones again", path)),
+        CloneState::Missing => action,
     }
-    let status = Command::new("nix-channel")
-        .arg("--update")
-        .status()
-        .expect("Failed to execute nix-channel update");
+}
+
+// Clone url into path unless a good clone is already there. Leftovers of an
+// interrupted clone are moved aside to <path>.broken-<n> and cloned again,
+// so nothing the user may have put there gets deleted.
+fn clone_repo(config: &Config, url: &str, path: &str, clone_args: &[String]) -> bool {
+    let name = Path::new(path).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
     
-    if !status.success() {
-        eprintln!("Failed to update nix channels");
-        std::process::exit(1);
+    match check_clone(path, url) {
+        CloneState::Valid => {
+            if config.verbose {
+                say!("✓ {} already cloned at {}", name, path);
+            }
+            return true;
+        }
+        CloneState::WrongRemote(remote) => {
+            config.warnings.add(
+                WarningKind::Skipped,
+                format!("{} is a clone of {}, not {}; leaving it alone", path, remote, url),
+            );
+            return true;
+        }
+        CloneState::Broken => {
+            let mut n = 1;
+            while Path::new(&format!("{}.broken-{}", path, n)).exists() {
+                n += 1;
+            }
+            let aside = format!("{}.broken-{}", path, n);
+            say!("⚠ {} looks like an incomplete clone, moving it to {} and cloning again", path, aside);
+            if let Err(e) = std::fs::rename(path, &aside) {
+                esay!("Failed to move {} aside: {}", path, e);
+                return false;
+            }
+        }
+        CloneState::Missing => {}
     }
     
-    // Install home-manager
     if config.verbose {
-        println!("Installing home-manager...");
-    }
-    let status = Command::new("nix-shell")
-        .args(&["<home-manager>", "-A", "install"])
-        .status()
-        .expect("Failed to execute nix-shell");
-    
-    if !status.success() {
-        eprintln!("Failed to install home-manager");
-        std::process::exit(1);
+        say!("Cloning {} to {}...", url, path);
     }
     
-    println!("✓ Home Manager setup complete!");
-}
-
-// Clone wallpaper repositories
-fn clone_wallpapers(config: &Config) {
-    println!("Cloning wallpaper repositories...");
-    
-    let wallpaper_repos = vec![
-        "https://github.com/rann01/IRIX-tiles",
-        "https://github.com/dharmx/walls",
-        "https://github.com/wallace-aph/tiles-and-such",
-        "https://github.com/tile-anon/tiles",
-        "https://github.com/whoisYoges/lwalpapers",
-        "https://github.com/D3Ext/aesthetic-wallpapers",
-        "https://github.com/peteroupc/classic-wallpaper",
-        "https://github.com/dixiedream/wallpapers",
-        "https://github.com/mylinuxforwork/wallpaper",
-        "https://github.com/makccr/wallpapers",
-        "https://github.com/Axenide/Wallpapers",
-        "https://github.com/l3ct3r/wallpapers",
-        "https://github.com/dmighty007/WallPapers",
-        "https://github.com/DenverCoder1/minimalistic-wallpaper-collection",
-        "https://github.com/BitterSweetcandyshop/wallpapers",
-        "https://github.com/linuxdotexe/nordic-wallpapers",
-    ];
+    let spinner = progress::spinner(format!("Cloning {}", name));
+    // git's own progress would only land above the spinners once it's done
+    let quiet: &[&str] = if progress::is_active() { &["--quiet"] } else { &[] };
+    // A failed clone removes what it created, so each attempt starts clean
+    let cloned = run_command(config, Cmd::new("git").arg("clone").args(quiet).args(clone_args).args([url, path]).network()).is_ok();
+    drop(spinner);
     
-    if config.dry_run {
-        println!("[DRY RUN] Would clone {} wallpaper repositories to ~/ with --depth=1", wallpaper_repos.len());
-        for repo in &wallpaper_repos {
-            println!("  - {}", repo);
+    if cloned {
+        journal::cloned(path);
+        if config.verbose {
+            say!("✓ Cloned {}", url);
         }
-        return;
     }
+    cloned
+}
+
+// Bulk install through the AUR helper. One bad target aborts the whole
+// transaction, so failed targets are dropped (or retried once if the failure
+// looks transient) and the rest is installed again.
+fn install_package_list(config: &Config, mut packages: Vec<String>, cwd: &str, extra_flags: &[&str]) {
+    let mut flags = match pkg::backend() {
+        Backend::Pacman => aur_install_flags(config),
+        Backend::Apt => apt_option_args(config),
+        Backend::Dnf => dnf_option_args(config),
+    };
+    flags.extend(extra_flags.iter().map(|flag| flag.to_string()));
+    let mut retried: Vec<String> = Vec::new();
     
-    let home = env::var("HOME").expect("HOME environment variable not set");
-    
-    for repo in &wallpaper_repos {
-        // Extract repo name from URL
-        let repo_name = repo.split('/').last().unwrap_or("");
-        let repo_path = format!("{}/{}", home, repo_name);
+    while !packages.is_empty() {
+        let outcome = pkg::install(pkg::backend(), config.aur_helper(), &flags, &packages, cwd);
+        if outcome.success {
+            return;
+        }
         
-        // Check if repo already exists
-        if Path::new(&repo_path).exists() {
-            if config.verbose {
-                println!("✓ {} already exists, skipping", repo_name);
+        if outcome.failures.is_empty() {
+            esay!("Failed to install packages from {} (no failing package could be identified)", config.file.dotfiles.package_list());
+            for diagnosis in &outcome.diagnoses {
+                esay!("  → {}: {}", diagnosis.describe(), diagnosis.hint());
             }
-            continue;
+            std::process::exit(1);
         }
         
-        if config.verbose {
-            println!("Cloning {}...", repo);
+        let mut progress = false;
+        for failure in &outcome.failures {
+            if !packages.contains(&failure.package) {
+                // A dependency of one of the targets, nothing to drop
+                if config.verbose {
+                    say!("  {} failed ({}): {}", failure.package, failure.category.describe(), failure.detail);
+                }
+                continue;
+            }
+            
+            progress = true;
+            if failure.category.retryable() && !retried.contains(&failure.package) {
+                say!("↻ {} failed ({}), retrying", failure.package, failure.category.describe());
+                retried.push(failure.package.clone());
+            } else {
+                let hints = diagnosis::classify(&failure.detail).iter().map(|diagnosis| diagnosis.hint()).collect();
+                let (kind, what) = match pkglist::find(&failure.package) {
+                    Some(package) if package.optional => (WarningKind::Skipped, "Optional package"),
+                    _ => (WarningKind::Failure, "Package"),
+                };
+                config.warnings.add_with_hints(
+                    kind,
+                    format!("{} {} not installed ({}): {}", what, failure.package, failure.category.describe(), failure.detail),
+                    hints,
+                );
+                packages.retain(|package| *package != failure.package);
+            }
         }
         
-        let status = Command::new("git")
-            .args(&["clone", "--depth=1", repo])
-            .current_dir(&home)
-            .status()
-            .expect("Failed to execute git clone");
-        
-        if !status.success() {
-            eprintln!("⚠ Warning: Failed to clone {}", repo);
-            // Continue with other repos instead of exiting
-        } else if config.verbose {
-            println!("✓ Cloned {}", repo);
+        if !progress {
+            let culprits: Vec<String> = outcome
+                .failures
+                .iter()
+                .map(|f| format!("{} ({})", f.package, f.category.describe()))
+                .collect();
+            config.warnings.add_with_hints(
+                WarningKind::Failure,
+                format!("Bulk package install failed because of dependencies: {}", culprits.join(", ")),
+                outcome.diagnoses.iter().map(|diagnosis| diagnosis.hint()).collect(),
+            );
+            return;
         }
     }
-    
-    println!("✓ Wallpaper repositories cloned!");
 }
 
-// Rebuild home-manager configuration
-fn rebuild_home_manager(config: &Config) {
-    println!("Rebuilding Home Manager configuration...");
-    
-    if config.dry_run {
-        println!("[DRY RUN] Would execute:");
-        println!("  home-manager switch -b backup");
-        return;
-    }
-    
-    if config.verbose {
-        println!("Running home-manager switch...");
+// Packages in chaotic-aur that would otherwise be built from the AUR. It
+// also rebuilds a few official packages, those aren't a choice.
+fn chaotic_overlap(packages: &[String]) -> Vec<String> {
+    let chaotic: BTreeSet<String> = output_lines("pacman", &["-Slq", "chaotic-aur"]).into_iter().collect();
+    if chaotic.is_empty() {
+        return Vec::new();
     }
     
-    let status = Command::new("home-manager")
-        .args(&["switch", "-b", "backup"])
-        .status()
-        .expect("Failed to execute home-manager");
-    
-    if !status.success() {
-        eprintln!("Failed to rebuild home-manager configuration");
-        std::process::exit(1);
+    let official: BTreeSet<String> = OFFICIAL_REPOS
+        .iter()
+        .flat_map(|repo| output_lines("pacman", &["-Slq", repo]))
+        .collect();
+    packages
+        .iter()
+        .filter(|package| chaotic.contains(*package) && !official.contains(*package))
+        .cloned()
+        .collect()
+}
+
+// Names of the packages in a package list this machine gets. A "[group]"
+// line starts a group (gui, gaming, ...) that profiles can leave out,
+// packages above the first one are always installed unless their comment
+// puts them in a group or on other hosts (see pkglist).
+fn parse_package_list(config: &Config, content: &str) -> Result<Vec<String>, String> {
+    let packages = pkglist::parse(content)?;
+    pkglist::remember(&packages);
+    let host = facts::get("hostname");
+    let session = session_type(config);
+    let mut selected = Vec::new();
+    for package in packages {
+        match package.left_out(&config.profile.exclude_groups, host, &session) {
+            None => selected.push(package.name),
+            Some(pkglist::LeftOut::Group(group)) if config.verbose => {
+                say!("  ⏭ {} (group {} is left out by the {} profile)", package.name, group, config.profile.name);
+            }
+            Some(pkglist::LeftOut::Host) if config.verbose => say!("  ⏭ {} (not for {})", package.name, host),
+            Some(pkglist::LeftOut::Session(wanted)) if config.verbose => {
+                say!("  ⏭ {} ({} only, this machine's sessions are {})", package.name, wanted, session);
+            }
+            Some(pkglist::LeftOut::Facts(condition)) if config.verbose => say!("  ⏭ {} (when={} doesn't hold here)", package.name, condition),
+            Some(_) => {}
+        }
     }
-    
-    println!("✓ Home Manager configuration rebuilt successfully!");
+    Ok(selected)
 }
 
-// Setup Chaotic AUR repository
-fn setup_chaotic_aur(config: &Config) {
-    println!("Setting up Chaotic AUR...");
+// Clone dotfiles and install packages
+fn setup_dotfiles(config: &Config) {
+    say!("Setting up dotfiles...");
     
     if config.dry_run {
-        println!("[DRY RUN] Would execute:");
-        println!("  1. Check if Chaotic AUR is already configured");
-        println!("  2. sudo pacman-key --recv-key 3056513887B78AEB --keyserver keyserver.ubuntu.com");
-        println!("  3. sudo pacman-key --lsign-key 3056513887B78AEB");
-        println!("  4. sudo pacman -U --noconfirm 'https://cdn-mirror.chaotic.cx/chaotic-aur/chaotic-keyring.pkg.tar.zst'");
-        println!("  5. sudo pacman -U --noconfirm 'https://cdn-mirror.chaotic.cx/chaotic-aur/chaotic-mirrorlist.pkg.tar.zst'");
-        println!("  6. Append chaotic-aur config to /etc/pacman.conf");
-        println!("  7. sudo pacman -Syu --noconfirm");
-        return;
-    }
-    
-    // Check if Chaotic AUR is already configured
-    let pacman_conf = std::fs::read_to_string("/etc/pacman.conf")
-        .unwrap_or_default();
-    
-    if pacman_conf.contains("[chaotic-aur]") {
-        if config.verbose {
-            println!("✓ Chaotic AUR already configured");
-        } else {
-            println!("✓ Chaotic AUR already configured, skipping setup");
+        let dotfiles_path = paths::get().dotfiles();
+        plan::add(planned_clone(&config.file.dotfiles.repo, &dotfiles_path, &config.file.dotfiles.clone_args()));
+        
+        let command = match pkg::backend() {
+            Backend::Pacman => aur_helper(config).install(&[]).to_string(),
+            _ => install_command(config),
+        };
+        let mut install = plan::Action::new(
+            plan::Kind::Install,
+            format!("Read {}, filter out invalid packages and run {}", config.file.dotfiles.package_list(), command),
+        )
+        .command(format!("{} <packages>", command))
+        .target(config.file.dotfiles.package_list_path(&dotfiles_path))
+        .effect(format!("installs the packages in {} unless already installed", config.file.dotfiles.package_list()));
+        let session = session_type(config);
+        if session != "both" {
+            install = install.detail(format!("(leaving out packages marked for the other kind of session, this machine's are {})", session));
+        }
+        let imported = state::imported_packages().len();
+        if imported > 0 {
+            install = install.detail(format!("(plus {} packages from the imported state)", imported));
         }
+        if config.file.chaotic_aur.enable && pkg::backend() == Backend::Pacman {
+            install = install.detail(format!(
+                "Packages chaotic-aur also ships: {} (chaotic_aur.prefer = {})",
+                if config.file.chaotic_aur.prefer == "aur" { "built from the AUR with --aur" } else { "prebuilt binaries" },
+                config.file.chaotic_aur.prefer
+            ));
+        }
+        plan::add(install);
         return;
     }
     
-    // Receive GPG key
-    if config.verbose {
-        println!("Receiving Chaotic AUR GPG key...");
-    }
-    let status = Command::new("sudo")
-        .args(&["pacman-key", "--recv-key", "3056513887B78AEB", "--keyserver", "keyserver.ubuntu.com"])
-        .status()
-        .expect("Failed to execute pacman-key recv");
+    let dotfiles_path = paths::get().dotfiles();
     
-    if !status.success() {
-        eprintln!("Failed to receive Chaotic AUR GPG key");
+    // Clone dotfiles repo with --depth=1, or fix up a clone that got interrupted
+    let clone_args = config.file.dotfiles.clone_args();
+    if !clone_repo(config, &config.file.dotfiles.repo, &dotfiles_path, &clone_args) {
+        esay!("Failed to clone dotfiles repository");
         std::process::exit(1);
     }
     
-    // Locally sign the key
-    if config.verbose {
-        println!("Signing Chaotic AUR GPG key...");
-    }
-    let status = Command::new("sudo")
-        .args(&["pacman-key", "--lsign-key", "3056513887B78AEB"])
-        .status()
-        .expect("Failed to execute pacman-key lsign");
+    let pkglist_path = config.file.dotfiles.package_list_path(&dotfiles_path);
     
-    if !status.success() {
-        eprintln!("Failed to sign Chaotic AUR GPG key");
+    // Other people's dotfiles won't necessarily follow our layout
+    if !Path::new(&pkglist_path).is_file() {
+        report::error(format!("{} has no package list at {}", config.file.dotfiles.repo, pkglist_path));
+        esay!("Add one to the repo or point dotfiles.package_list at it in {}", config::config_path());
         std::process::exit(1);
     }
     
-    // Install chaotic-keyring
+    // Other users usually can't sudo, provision_users installs their list
+    if config.other_user {
+        say!("✓ Dotfiles cloned, {} is installed by the main run", config.file.dotfiles.package_list());
+        return;
+    }
+    
+    // Install packages from the package list (archpkglist.txt, or
+    // aptpkglist.txt on Debian and Ubuntu, by default)
     if config.verbose {
-        println!("Installing chaotic-keyring...");
+        say!("Installing packages from {}...", config.file.dotfiles.package_list());
     }
-    let status = Command::new("sudo")
-        .args(&["pacman", "-U", "--noconfirm", "https://cdn-mirror.chaotic.cx/chaotic-aur/chaotic-keyring.pkg.tar.zst"])
-        .status()
-        .expect("Failed to execute pacman");
     
-    if !status.success() {
-        eprintln!("Failed to install chaotic-keyring");
+    // Read the package list and filter out problematic packages
+    let pkglist_content = std::fs::read_to_string(&pkglist_path).unwrap_or_else(|e| {
+        esay!("Failed to read package list {}: {}", pkglist_path, e);
         std::process::exit(1);
-    }
+    });
     
-    // Install chaotic-mirrorlist
-    if config.verbose {
-        println!("Installing chaotic-mirrorlist...");
+    let mut filtered_packages = parse_package_list(config, &pkglist_content)
+        .unwrap_or_else(|e| report::fatal(format!("Invalid package list {}: {}", pkglist_path, e)));
+    for package in &config.profile.packages {
+        if !filtered_packages.contains(package) {
+            filtered_packages.push(package.clone());
+        }
     }
-    let status = Command::new("sudo")
-        .args(&["pacman", "-U", "--noconfirm", "https://cdn-mirror.chaotic.cx/chaotic-aur/chaotic-mirrorlist.pkg.tar.zst"])
-        .status()
-        .expect("Failed to execute pacman");
     
-    if !status.success() {
-        eprintln!("Failed to install chaotic-mirrorlist");
-        std::process::exit(1);
+    // Mirror what the machine an imported state came from had installed
+    let imported: Vec<String> = state::imported_packages()
+        .into_iter()
+        .filter(|package| !filtered_packages.contains(package) && package != "paru-debug")
+        .collect();
+    if !imported.is_empty() {
+        say!("Adding {} packages from the imported state", imported.len());
+        filtered_packages.extend(imported);
     }
     
-    // Append to /etc/pacman.conf
     if config.verbose {
-        println!("Adding Chaotic AUR to pacman.conf...");
+        say!("Installing {} packages (filtered out invalid packages)", filtered_packages.len());
     }
     
-    // Remove temp file if it exists
-    let _ = std::fs::remove_file("/tmp/chaotic-aur.conf");
-    
-    let mut file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open("/tmp/chaotic-aur.conf")
-        .expect("Failed to create temp file");
+    // Packages chaotic-aur also ships go where chaotic_aur.prefer says
+    let prefer = &config.file.chaotic_aur.prefer;
+    let overlap = if config.file.chaotic_aur.enable && pkg::backend() == Backend::Pacman {
+        chaotic_overlap(&filtered_packages)
+    } else {
+        Vec::new()
+    };
+    if !overlap.is_empty() {
+        say!("Package sources (chaotic_aur.prefer = {}):", prefer);
+        for package in &overlap {
+            say!("  {}: {}", package, if prefer == "aur" { "AUR build" } else { "chaotic-aur binary" });
+        }
+    }
     
-    writeln!(file, "\n[chaotic-aur]").expect("Failed to write");
-    writeln!(file, "Include = /etc/pacman.d/chaotic-mirrorlist").expect("Failed to write");
+    if prefer == "aur" && !overlap.is_empty() {
+        filtered_packages.retain(|package| !overlap.contains(package));
+        install_package_list(config, filtered_packages, &dotfiles_path, &[]);
+        install_package_list(config, overlap, &dotfiles_path, &["--aur"]);
+    } else {
+        install_package_list(config, filtered_packages, &dotfiles_path, &[]);
+    }
     
-    let status = Command::new("sudo")
-        .args(&["tee", "-a", "/etc/pacman.conf"])
-        .stdin(std::fs::File::open("/tmp/chaotic-aur.conf").expect("Failed to open temp file"))
-        .stdout(std::process::Stdio::null())
-        .status()
-        .expect("Failed to append to pacman.conf");
+    say!("✓ Dotfiles setup complete!");
+}
+
+// Install stow and deploy dotfiles
+fn deploy_dotfiles(config: &Config) {
+    say!("Deploying dotfiles with GNU Stow...");
     
-    if !status.success() {
-        eprintln!("Failed to update pacman.conf");
-        std::process::exit(1);
+    // Install GNU Stow
+    if config.verbose {
+        say!("Installing GNU Stow...");
     }
+    install_packages(config, &["stow"], "Failed to install stow");
     
-    // Clean up temp file
-    let _ = std::fs::remove_file("/tmp/chaotic-aur.conf");
+    // Stow targets the home, not $XDG_CONFIG_HOME
+    let config_path = paths::get().home(".config");
     
-    // Update system
+    // Create .config directory
     if config.verbose {
-        println!("Updating system with Chaotic AUR...");
+        say!("Creating ~/.config directory...");
     }
-    let status = Command::new("sudo")
-        .args(&["pacman", "-Syu", "--noconfirm"])
-        .status()
-        .expect("Failed to execute pacman");
+    run_or_exit(config, Cmd::new("mkdir").args(["-p", &config_path]), "Failed to create .config directory");
     
-    if !status.success() {
-        eprintln!("Failed to update system");
-        std::process::exit(1);
+    if !config.dry_run {
+        say!("✓ Stow installed and directories prepared!");
     }
-    
-    println!("✓ Chaotic AUR setup complete!");
 }
 
-// Configure pacman.conf with performance optimizations
-fn configure_pacman(config: &Config) {
-    println!("Configuring pacman.conf...");
-    
-    if config.dry_run {
-        println!("[DRY RUN] Would execute:");
-        println!("  1. Uncomment 'Color' in /etc/pacman.conf");
-        println!("  2. Comment out 'NoProgressBar' in /etc/pacman.conf");
-        println!("  3. Set 'ParallelDownloads = 25' in /etc/pacman.conf");
-        println!("  4. Add 'ILoveCandy' to /etc/pacman.conf");
-        return;
+// Resolve ".." and "." in a path without touching the filesystem (the target may not exist)
+fn normalize_path(path: &Path) -> std::path::PathBuf {
+    let mut normalized = std::path::PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            std::path::Component::CurDir => {}
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+// Collect symlinks pointing into the dotfiles repo whose target no longer exists
+fn find_broken_dotfile_links(dir: &Path, dotfiles: &Path, recursive: bool, broken: &mut Vec<std::path::PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        
+        if file_type.is_symlink() {
+            let Ok(target) = std::fs::read_link(&path) else {
+                continue;
+            };
+            let target = normalize_path(&dir.join(target));
+            if target.starts_with(dotfiles) && !path.exists() {
+                broken.push(path);
+            }
+        } else if file_type.is_dir() && recursive {
+            find_broken_dotfile_links(&path, dotfiles, recursive, broken);
+        }
+    }
+}
+
+// Report dangling links left behind by renamed/removed stow packages and offer to remove them
+fn clean_broken_stow_links(config: &Config) {
+    if config.verbose {
+        say!("Checking for broken dotfile symlinks...");
+    }
+    
+    if config.dry_run {
+        plan::add(plan::ask("Scan ~ and ~/.config for dangling symlinks into ~/dotfiles and offer to remove them"));
+        return;
+    }
+    
+    let paths = paths::get();
+    let dotfiles = Path::new(&paths.dotfiles()).to_path_buf();
+    
+    let mut broken = Vec::new();
+    find_broken_dotfile_links(Path::new(&paths.home), &dotfiles, false, &mut broken);
+    find_broken_dotfile_links(Path::new(&paths.home(".config")), &dotfiles, true, &mut broken);
+    
+    if broken.is_empty() {
+        if config.verbose {
+            say!("✓ No broken dotfile symlinks");
+        }
+        return;
+    }
+    
+    say!("Found {} broken symlink(s) into ~/dotfiles:", broken.len());
+    for link in &broken {
+        let target = std::fs::read_link(link).unwrap_or_default();
+        say!("  {} -> {}", link.display(), target.display());
+    }
+    
+    if !confirm(tr!("Remove them before restowing?")) {
+        config.warnings.add(WarningKind::Skipped, format!("Left {} broken symlink(s) into ~/dotfiles in place", broken.len()));
+        return;
+    }
+    
+    for link in &broken {
+        if let Err(e) = std::fs::remove_file(link) {
+            config.warnings.add(WarningKind::Failure, format!("Failed to remove {}: {}", link.display(), e));
+        }
+    }
+    say!("✓ Removed {} broken symlink(s)", broken.len());
+}
+
+// A package's own choice of where it's stowed, one line like "~/.config"
+const STOW_TARGET_FILE: &str = ".ass-target";
+
+// Where a package is stowed into, None for the home: dotfiles.targets, else
+// the package's .ass-target
+fn stow_target(config: &Config, dotfiles_path: &str, package: &str) -> Result<Option<String>, String> {
+    let file = format!("{}/{}/{}", dotfiles_path, package, STOW_TARGET_FILE);
+    let Some(target) = config
+        .file
+        .dotfiles
+        .targets
+        .get(package)
+        .cloned()
+        .or_else(|| std::fs::read_to_string(&file).ok().map(|content| content.trim().to_string()))
+    else {
+        return Ok(None);
+    };
+    if !config::valid_stow_target(&target) {
+        return Err(format!("target '{}' isn't a directory in the home", target));
+    }
+    let relative = target.strip_prefix('~').unwrap_or(&target).trim_start_matches('/');
+    Ok((!relative.is_empty()).then(|| paths::get().home(relative)))
+}
+
+// --target for a package stowed elsewhere, and the .ass-target file left out
+fn stow_args(dotfiles_path: &str, package: &str, target: Option<&str>) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(target) = target {
+        args.extend(["--target".to_string(), target.to_string()]);
+    }
+    if Path::new(&format!("{}/{}/{}", dotfiles_path, package, STOW_TARGET_FILE)).exists() {
+        args.push(format!("--ignore={}", STOW_TARGET_FILE.replace('.', r"\.")));
+    }
+    args
+}
+
+// Stow custom configs after initial home-manager generation
+fn stow_custom_configs(config: &Config) {
+    say!("Deploying custom dotfiles with GNU Stow...");
+    
+    let paths = paths::get();
+    let dotfiles_path = paths.dotfiles();
+    let hm_config_path = paths.config("home-manager");
+    let nix_config_path = paths.config("nix");
+    
+    let stow = &config.file.dotfiles.stow;
+    
+    // Remove default home-manager config
+    if stow.iter().any(|package| package == "home-manager") && Path::new(&hm_config_path).exists() {
+        if config.verbose {
+            say!("Removing default home-manager config...");
+        }
+        run_or_exit(
+            config,
+            Cmd::new("rm")
+                .args(["-rf", &hm_config_path]),
+            "Failed to remove default home-manager config",
+        );
+    }
+    
+    // Remove default nix config
+    if stow.iter().any(|package| package == "nix") && Path::new(&nix_config_path).exists() {
+        if config.verbose {
+            say!("Removing default nix config...");
+        }
+        run_or_exit(config, Cmd::new("rm").args(["-rf", &nix_config_path]), "Failed to remove default nix config");
+    }
+    
+    for package in stow {
+        let target = match stow_target(config, &dotfiles_path, package) {
+            Ok(target) => target,
+            Err(e) => {
+                config.warnings.add(WarningKind::Skipped, format!("Not stowing {}: {}", package, e));
+                continue;
+            }
+        };
+        if config.verbose {
+            say!("Stowing {} into {}...", package, target.as_deref().unwrap_or(&paths.home));
+        }
+        if let Some(target) = &target {
+            run_or_exit(config, Cmd::new("mkdir").args(["-p", target]), &format!("Failed to create {}", target));
+        }
+        let args = stow_args(&dotfiles_path, package, target.as_deref());
+        // Only a package that links something new is ours to unstow on undo
+        let links_new = Command::new("stow")
+            .args(&args)
+            .args(["--no", "--verbose", package])
+            .current_dir(&dotfiles_path)
+            .output()
+            .is_ok_and(|output| String::from_utf8_lossy(&output.stderr).contains("LINK:"));
+        run_or_exit(
+            config,
+            Cmd::new("stow")
+                .args(&args)
+                .arg(package)
+                .current_dir(&dotfiles_path),
+            &format!("Failed to stow {}", package),
+        );
+        if links_new {
+            journal::stowed(&dotfiles_path, package, target.as_deref());
+        }
+    }
+    
+    if !config.dry_run {
+        say!("✓ Custom dotfiles deployed successfully!");
+    }
+}
+
+// Install a language's versions through its version manager and set the global default
+fn install_toolchain(config: &Config, language: &str, toolchain: &Toolchain) {
+    let manager = toolchain.manager_for(language);
+    
+    let mut commands: Vec<Cmd> = Vec::new();
+    
+    match manager {
+        "fnm" => {
+            for version in &toolchain.versions {
+                commands.push(Cmd::new("fnm").args(["install", version]));
+            }
+            if let Some(version) = toolchain.default_version() {
+                commands.push(Cmd::new("fnm").args(["default", version]));
+            }
+        }
+        "uv" => {
+            if !toolchain.versions.is_empty() {
+                commands.push(Cmd::new("uv").args(["python", "install"]).args(&toolchain.versions));
+            }
+            // --default installs python/python3 shims into ~/.local/bin
+            if let Some(version) = toolchain.default_version() {
+                commands.push(Cmd::new("uv").args(["python", "install", "--preview", "--default", version]));
+            }
+        }
+        "mise" => {
+            for version in &toolchain.versions {
+                commands.push(Cmd::new("mise").arg("install").arg(format!("{}@{}", language, version)));
+            }
+            if let Some(version) = toolchain.default_version() {
+                commands.push(Cmd::new("mise").args(["use", "--global"]).arg(format!("{}@{}", language, version)));
+            }
+        }
+        _ => unreachable!("validated when loading the config"),
+    }
+    
+    if config.verbose {
+        say!("Installing {} toolchain with {}...", language, manager);
+    }
+    
+    for cmd in commands {
+        let failure = format!("Failed to run: {}", cmd);
+        run_or_exit(config, cmd, &failure);
+    }
+    
+    if config.verbose && !config.dry_run {
+        say!("✓ {} toolchain ready", language);
+    }
+}
+
+// Install version managers and language toolchains from the [toolchains] config
+fn install_toolchains(config: &Config) {
+    let toolchains = &config.file.toolchains;
+    let languages: Vec<(&str, &Toolchain)> = [
+        ("node", toolchains.node.as_ref()),
+        ("python", toolchains.python.as_ref()),
+        ("go", toolchains.go.as_ref()),
+    ]
+    .into_iter()
+    .filter_map(|(language, toolchain)| toolchain.map(|t| (language, t)))
+    .collect();
+    
+    if languages.is_empty() {
+        if config.verbose {
+            say!("⏭ No [toolchains] configured, skipping language toolchains");
+        }
+        return;
+    }
+    
+    say!("Installing language toolchains...");
+    
+    // Version managers needed by the configured languages
+    let mut managers: Vec<&str> = languages
+        .iter()
+        .map(|(language, toolchain)| toolchain.manager_for(language))
+        .collect();
+    managers.sort();
+    managers.dedup();
+    
+    if config.verbose {
+        say!("Installing version managers: {}", managers.join(", "));
+    }
+    install_packages(config, &managers, "Failed to install version managers");
+    
+    for (language, toolchain) in &languages {
+        install_toolchain(config, language, toolchain);
+    }
+    
+    if !config.dry_run {
+        say!("✓ Language toolchains installed!");
+    }
+}
+
+// Add rustup components and binstall cargo tools from [toolchains.rust]
+fn install_rust_tools(config: &Config) {
+    let rust = &config.file.toolchains.rust;
+    
+    if rust.components.is_empty() && rust.cargo_tools.is_empty() {
+        if config.verbose {
+            say!("⏭ No rust components or cargo tools configured, skipping");
+        }
+        return;
+    }
+    
+    say!("Installing Rust components and cargo tools...");
+    
+    // cargo-binstall is bootstrapped separately, don't install it twice
+    let tools: Vec<&str> = rust
+        .cargo_tools
+        .iter()
+        .map(|s| s.as_str())
+        .filter(|tool| *tool != "cargo-binstall")
+        .collect();
+    
+    // The AUR helper may have been installed already (or be yay), in which case Rust wasn't set up
+    let rustup = ensure_rust(config);
+    
+    if !rust.components.is_empty() && !rustup {
+        config.warnings.add(
+            WarningKind::Skipped,
+            format!("Rust components ({}) need rustup, the system Rust can't add them", rust.components.join(", ")),
+        );
+    } else if !rust.components.is_empty() {
+        if config.verbose {
+            say!("Adding rustup components: {}", rust.components.join(", "));
+        }
+        run_or_exit(
+            config,
+            Cmd::new("rustup")
+                .args(["component", "add"])
+                .args(&rust.components),
+            "Failed to add rustup components",
+        );
+    }
+    
+    if rust.cargo_tools.is_empty() {
+        if !config.dry_run {
+            say!("✓ Rust components installed!");
+        }
+        return;
+    }
+    
+    // cargo-binstall first so everything else can use prebuilt binaries
+    let status = Command::new("cargo")
+        .args(["binstall", "-V"])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status();
+    
+    if status.map(|s| s.success()).unwrap_or(false) {
+        if config.verbose {
+            say!("✓ cargo-binstall already installed");
+        }
+    } else {
+        if config.verbose {
+            say!("Installing cargo-binstall...");
+        }
+        run_or_exit(config, Cmd::new("cargo").args(["install", "cargo-binstall"]), "Failed to install cargo-binstall");
+    }
+    
+    if !tools.is_empty() {
+        if config.verbose {
+            say!("Installing cargo tools: {}", tools.join(", "));
+        }
+        run_or_exit(
+            config,
+            Cmd::new("cargo")
+                .args(["binstall", "--no-confirm"])
+                .args(&tools),
+            "Failed to install cargo tools",
+        );
+    }
+    
+    if !config.dry_run {
+        say!("✓ Rust components and cargo tools installed!");
+    }
+}
+
+// Create distrobox containers from the [distrobox] config and export their apps
+fn setup_distrobox(config: &Config) {
+    let distrobox = &config.file.distrobox;
+    
+    if distrobox.containers.is_empty() {
+        if config.verbose {
+            say!("⏭ No distrobox containers configured, skipping");
+        }
+        return;
+    }
+    
+    say!("Setting up distrobox containers...");
+    
+    // Install distrobox and the container engine
+    if config.verbose {
+        say!("Installing distrobox and {}...", distrobox.engine);
+    }
+    install_packages(config, &["distrobox", &distrobox.engine], "Failed to install distrobox");
+    
+    // Nothing to list yet when distrobox is only being previewed
+    let existing = output_lines("distrobox", &["list", "--no-color"]);
+    
+    for container in &distrobox.containers {
+        // distrobox list prints a table: ID | NAME | STATUS | IMAGE
+        let exists = existing
+            .iter()
+            .any(|line| line.split('|').nth(1).map(|n| n.trim()) == Some(container.name.as_str()));
+        
+        if exists {
+            if config.verbose {
+                say!("✓ Container {} already exists, skipping creation", container.name);
+            }
+        } else {
+            if config.verbose {
+                say!("Creating container {} from {}...", container.name, container.image);
+            }
+            let mut create = Cmd::new("distrobox")
+                .args(["create", "--yes", "--name", &container.name, "--image", &container.image]);
+            if !container.packages.is_empty() {
+                create = create.args(["--additional-packages", &container.packages.join(" ")]);
+            }
+            run_or_exit(config, create, &format!("Failed to create distrobox container {}", container.name));
+        }
+        
+        // First enter runs the container init (and installs the extra packages)
+        if config.verbose {
+            say!("Initializing container {}...", container.name);
+        }
+        run_or_exit(
+            config,
+            Cmd::new("distrobox")
+                .args(["enter", &container.name, "--", "true"]),
+            &format!("Failed to initialize distrobox container {}", container.name),
+        );
+        
+        let exports = container
+            .export_apps
+            .iter()
+            .map(|app| ("--app", app))
+            .chain(container.export_bins.iter().map(|bin| ("--bin", bin)));
+        
+        for (kind, target) in exports {
+            if config.verbose {
+                say!("Exporting {} from {}...", target, container.name);
+            }
+            run_or_warn(
+                config,
+                Cmd::new("distrobox").args(["enter", &container.name, "--", "distrobox-export", kind, target]),
+                &format!("Failed to export {} from {}", target, container.name),
+            );
+        }
+    }
+    
+    if !config.dry_run {
+        say!("✓ Distrobox containers ready!");
+    }
+}
+
+const DETERMINATE_INSTALLER_REPO: &str = "DeterminateSystems/nix-installer";
+
+// Install Nix package manager
+fn install_nix(config: &Config) {
+    say!("Installing Nix package manager...");
+    
+    // The installers only set the daemon up under systemd, elsewhere it needs
+    // a service of the distribution's (nix-openrc, nix-runit, ...)
+    let init = init::init();
+    let daemon = init == Init::Systemd || init.has_service("nix-daemon");
+    let determinate = config.file.nix.installer == "determinate" && init == Init::Systemd;
+    
+    if config.dry_run {
+        plan::add(plan::check("Check if nix is already installed"));
+        let work_dir = config.work_dir.root().display().to_string();
+        if determinate {
+            let version = match releases::pinned(DETERMINATE_INSTALLER_REPO) {
+                Some(tag) if config.file.nix.installer_version == "latest" && !config.refresh_pins => format!("{} (pinned)", tag),
+                _ => config.file.nix.installer_version.clone(),
+            };
+            plan::add(
+                plan::other(format!("Download {} nix-installer-{}-linux {}", DETERMINATE_INSTALLER_REPO, std::env::consts::ARCH, version))
+                    .target(format!("{}/nix-installer", work_dir)),
+            );
+            plan::add(plan::run("chmod +x nix-installer"));
+            plan::add(plan::run("./nix-installer install --no-confirm").target("/nix").effect("installs Nix"));
+        } else {
+            plan::add(
+                plan::run("curl --proto '=https' --tlsv1.2 -sSfL https://nixos.org/nix/install -o nix-install.sh")
+                    .target(format!("{}/nix-install.sh", work_dir)),
+            );
+            plan::add(plan::run("chmod +x nix-install.sh"));
+            plan::add(
+                plan::run(format!("sh ./nix-install.sh {}", if daemon { "--daemon" } else { "--no-daemon" }))
+                    .target("/nix")
+                    .effect("installs Nix"),
+            );
+        }
+        plan::add(
+            plan::other("Load Nix's profile script into this run and continue with the Nix setup")
+                .detail("or prompt user to log out and log back in if nix still isn't usable"),
+        );
+        return;
+    }
+    
+    // Check if nix is already installed
+    if let Some(path) = which("nix") {
+        if config.verbose {
+            say!("✓ Nix is already installed: {}", path);
+        } else {
+            say!("✓ Nix already installed, skipping installation");
+        }
+        return;
+    }
+    
+    if config.file.nix.installer == "determinate" && !determinate {
+        config.warnings.add(WarningKind::Skipped, format!("The Determinate installer needs systemd, using the official one on {}", init.name()));
+    }
+    
+    let (nix_installer_path, installer_url) = if determinate {
+        let tag = releases::resolve(DETERMINATE_INSTALLER_REPO, &config.file.nix.installer_version, config.refresh_pins)
+            .unwrap_or_else(|e| {
+                esay!("Failed to resolve the Determinate Nix installer release: {}", e);
+                std::process::exit(1);
+            });
+        say!("Using {} {} (pins in {})", DETERMINATE_INSTALLER_REPO, tag, releases::pins_path());
+        let url = format!(
+            "https://github.com/{}/releases/download/{}/nix-installer-{}-linux",
+            DETERMINATE_INSTALLER_REPO,
+            tag,
+            std::env::consts::ARCH
+        );
+        (config.work_dir.path("nix-installer"), url)
+    } else {
+        (config.work_dir.path("nix-install.sh"), "https://nixos.org/nix/install".to_string())
+    };
+    
+    // Download Nix installer
+    if config.verbose {
+        say!("Downloading Nix installer to {}...", nix_installer_path);
+    }
+    let download = Cmd::new("curl")
+        .args([
+            "--proto", "=https",
+            "--tlsv1.2",
+            "-sSfL",
+            &installer_url,
+            "-o", &nix_installer_path
+        ])
+        .args(curl_rate_args(config))
+        .network();
+    run_or_exit(config, download, "Failed to download Nix installer");
+    
+    // Make installer executable
+    if config.verbose {
+        say!("Making installer executable...");
+    }
+    run_or_exit(config, Cmd::new("chmod").args(["+x", &nix_installer_path]), "Failed to make Nix installer executable");
+    
+    // Run Nix installer with daemon mode (the Determinate one always sets up
+    // the daemon), single-user when there's no service to run the daemon with
+    if !daemon {
+        say!("⚠ No nix-daemon service for {}, installing Nix for this user only", init.name());
+    } else if config.verbose {
+        say!("Running Nix installer (daemon mode)...");
+    }
+    // live: the installer asks its own questions and for the sudo password
+    let installer = if determinate {
+        Cmd::new(&nix_installer_path).args(["install", "--no-confirm"])
+    } else {
+        Cmd::new("sh").args([nix_installer_path.as_str(), if daemon { "--daemon" } else { "--no-daemon" }])
+    };
+    run_or_exit(config, installer.live(), "Failed to install Nix");
+    
+    say!("✓ {}", tr!("Nix installed successfully!"));
+    
+    // Set state to resume after nix installation
+    journal::packages_changed();
+    checkpoint::completed("install_nix");
+    checkpoint::set_phase("post-nix");
+    
+    // install_nix never runs next to other steps, so this is the only thread
+    if let Some(loaded) = environment::load_nix_profile()
+        && which("nix").is_some()
+    {
+        if config.verbose {
+            say!("  Set {}", loaded.join(" "));
+        }
+        say!("✓ {}", tr!("Loaded the Nix environment, continuing without logging out"));
+        say!("  {}", tr!("Shells that are already open only see Nix after logging back in."));
+        return;
+    }
+    
+    say!();
+    print_box(&[
+        &format!("⚠ {}", tr!("ACTION REQUIRED")),
+        "",
+        tr!("Nix has been installed successfully!"),
+        "",
+        tr!("You MUST log out and log back in for the changes to take effect before continuing the installation."),
+        "",
+        tr!("After logging back in, run this script again:"),
+        "$ ./ass",
+        "",
+        tr!("The installation will automatically resume from where it left off."),
+    ]);
+    say!();
+    
+    // We exit before run_phase can mark the step done
+    config.work_dir.finish(config.keep_work_dir);
+    config.warnings.print_recap();
+    std::process::exit(0);
+}
+
+// Paragraphs in a double-lined box, word-wrapped since translations differ in length
+fn print_box(paragraphs: &[&str]) {
+    const WIDTH: usize = 56;
+    let print_line = |line: &str| say!("║  {}{}  ║", line, " ".repeat(WIDTH.saturating_sub(line.chars().count())));
+    
+    say!("╔{}╗", "═".repeat(WIDTH + 4));
+    for paragraph in paragraphs {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > WIDTH {
+                print_line(&line);
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        print_line(&line);
+    }
+    say!("╚{}╝", "═".repeat(WIDTH + 4));
+}
+
+// The socket can lag behind enabling the service, so wait until the daemon answers
+fn wait_for_nix_daemon(config: &Config) {
+    let socket = "/nix/var/nix/daemon-socket/socket";
+    let timeout = std::time::Duration::from_secs(config.file.nix.daemon_timeout);
+    let start = std::time::Instant::now();
+    
+    if config.verbose {
+        say!("Waiting for nix-daemon to become ready...");
+    }
+    
+    loop {
+        if Path::new(socket).exists() {
+            let status = Command::new("nix")
+                .args(["--extra-experimental-features", "nix-command", "store", "ping"])
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .status();
+            
+            if status.map(|s| s.success()).unwrap_or(false) {
+                if config.verbose {
+                    say!("✓ nix-daemon ready after {:.1}s", start.elapsed().as_secs_f32());
+                }
+                return;
+            }
+        }
+        
+        if start.elapsed() >= timeout {
+            esay!("nix-daemon did not become ready within {}s", timeout.as_secs());
+            esay!("Check: {}", init::init().status_command("nix-daemon"));
+            std::process::exit(1);
+        }
+        
+        std::thread::sleep(std::time::Duration::from_millis(500));
+    }
+}
+
+// A single-user install leaves the store to the user instead of root
+fn nix_single_user() -> bool {
+    std::fs::metadata("/nix/store").is_ok_and(|store| std::os::unix::fs::MetadataExt::uid(&store) != 0)
+}
+
+// How to start a service and keep it enabled, for messages
+fn enable_command(init: Init, service: &str) -> String {
+    let cmds: Vec<String> = init.enable_now(service).iter().map(|cmd| cmd.to_string()).collect();
+    if cmds.is_empty() { format!("start {} with your init system", service) } else { cmds.join(" && ") }
+}
+
+// Enable Nix daemon and setup home-manager
+fn setup_home_manager(config: &Config) {
+    say!("Setting up Home Manager...");
+    
+    if config.dry_run {
+        plan::add(plan::enable(enable_command(init::init(), "nix-daemon"), "nix-daemon").detail(format!(
+            "(wait up to {}s for the daemon socket and `nix store ping`, unless Nix is single-user)",
+            config.file.nix.daemon_timeout
+        )));
+        plan::add(plan::run("nix-channel --add https://github.com/nix-community/home-manager/archive/master.tar.gz home-manager"));
+        plan::add(plan::run(Cmd::new("nix-channel").arg("--update")));
+        plan::add(plan::run("nix-shell '<home-manager>' -A install").effect("installs home-manager"));
+        return;
+    }
+    
+    // Enable and start Nix daemon service (the main run already did for other users)
+    if nix_single_user() {
+        if config.verbose {
+            say!("⏭ Nix is single-user, no daemon to start");
+        }
+    } else {
+        if config.verbose {
+            say!("Enabling Nix daemon service...");
+        }
+        if !config.other_user {
+            for cmd in init::init().enable_now("nix-daemon") {
+                run_or_exit(config, cmd, "Failed to enable Nix daemon service");
+            }
+        }
+        wait_for_nix_daemon(config);
+    }
+    
+    // Add home-manager channel
+    if config.verbose {
+        say!("Adding home-manager channel...");
+    }
+    let add = Cmd::new("nix-channel").args([
+        "--add",
+        "https://github.com/nix-community/home-manager/archive/master.tar.gz",
+        "home-manager"
+    ]);
+    run_or_exit(config, add, "Failed to add home-manager channel");
+    
+    // Update channels
+    if config.verbose {
+        say!("Updating nix channels...");
+    }
+    run_or_exit(config, Cmd::new("nix-channel").arg("--update"), "Failed to update nix channels");
+    
+    // Install home-manager
+    if config.verbose {
+        say!("Installing home-manager...");
+    }
+    run_or_exit(
+        config,
+        Cmd::new("nix-shell")
+            .args(["<home-manager>", "-A", "install"])
+            .nix_build(),
+        "Failed to install home-manager",
+    );
+    
+    say!("✓ Home Manager setup complete!");
+}
+
+// Create XDG user directories with the names from the [xdg_dirs] config
+fn setup_xdg_user_dirs(config: &Config) {
+    let xdg_dirs = &config.file.xdg_dirs;
+    
+    if !xdg_dirs.enable {
+        if config.verbose {
+            say!("⏭ [xdg_dirs] not enabled, skipping XDG user directories");
+        }
+        return;
+    }
+    
+    for key in xdg_dirs.names.keys() {
+        if !XDG_USER_DIRS.iter().any(|(k, _, _)| k == key) {
+            report::fatal(format!("Unknown XDG user directory '{}' in [xdg_dirs.names]", key));
+        }
+    }
+    
+    say!("Setting up XDG user directories...");
+    
+    let paths = paths::get();
+    let dirs: Vec<(&str, String)> = XDG_USER_DIRS
+        .iter()
+        .filter_map(|(key, name, _)| Some((*name, paths.home(&xdg_dirs.dir_name(key)?))))
+        .collect();
+    
+    if config.dry_run {
+        plan::add(plan::install(package_manager(config).install(&["xdg-user-dirs".to_string()]), &["xdg-user-dirs"]));
+        for (name, path) in &dirs {
+            plan::add(plan::run(format!("mkdir -p {} && xdg-user-dirs-update --set {} {}", path, name, path)).target(path.to_string()));
+        }
+        return;
+    }
+    
+    if config.verbose {
+        say!("Installing xdg-user-dirs...");
+    }
+    install_packages(config, &["xdg-user-dirs"], "Failed to install xdg-user-dirs");
+    
+    for (name, path) in &dirs {
+        std::fs::create_dir_all(path).unwrap_or_else(|e| {
+            esay!("Failed to create {}: {}", path, e);
+            std::process::exit(1);
+        });
+        
+        run_or_exit(
+            config,
+            Cmd::new("xdg-user-dirs-update")
+                .args(["--set", name, path]),
+            &format!("Failed to set XDG_{}_DIR", name),
+        );
+        
+        if config.verbose {
+            say!("  ✓ XDG_{}_DIR = {}", name, path);
+        }
+    }
+    
+    say!("✓ XDG user directories created!");
+}
+
+// What clone_repos looks after: the [repos] entries plus, on desktops, the
+// wallpaper repositories in the Pictures directory. A [repos] entry with the
+// same name as a wallpaper repository replaces it.
+fn extra_repos(config: &Config) -> BTreeMap<String, RepoConfig> {
+    let mut repos = BTreeMap::new();
+    let wallpapers = &config.file.wallpapers;
+    
+    if !config.skip_wallpapers && wallpapers.enable && config.is_desktop() {
+        let paths = paths::get();
+        // Follow the configured Pictures directory when the XDG dirs step owns it
+        let wallpaper_dir = match (wallpapers.dir_path(), config.file.xdg_dirs.dir_name("pictures")) {
+            (Some(dir), _) => dir,
+            (None, Some(pictures)) if config.file.xdg_dirs.enable => paths.home(&pictures),
+            _ => paths.home.clone(),
+        };
+        for url in &wallpapers.urls {
+            let name = url.trim_end_matches('/').split('/').next_back().unwrap_or("");
+            let options = wallpapers.clone_options(name);
+            // Several collections share a name; the later ones are only kept
+            // to be reported by clone_repo as a clone of something else
+            let key = if repos.contains_key(name) {
+                url.trim_start_matches("https://github.com/").to_string()
+            } else {
+                name.to_string()
+            };
+            repos.insert(
+                key,
+                RepoConfig {
+                    url: url.clone(),
+                    dest: format!("{}/{}", wallpaper_dir, name),
+                    depth: options.depth,
+                    filter: options.filter,
+                    update: "clone".to_string(),
+                    when: None,
+                },
+            );
+        }
+    }
+    
+    for (name, repo) in &config.file.repos {
+        if repo.when.as_deref().is_some_and(|condition| !facts::matches(condition)) {
+            if config.verbose {
+                say!("⏭ Repository {} left out, when = \"{}\" doesn't hold here", name, repo.when.as_deref().unwrap_or_default());
+            }
+            continue;
+        }
+        repos.insert(name.clone(), repo.clone());
+    }
+    repos
+}
+
+// Clone the extra repositories, and fast-forward the ones set to update = "pull".
+// [network] jobs of them go at once, each one's output shown when it's done.
+fn clone_repos(config: &Config) {
+    let repos = extra_repos(config);
+    say!("Cloning {} repositories...", repos.len());
+    
+    if config.dry_run {
+        for repo in repos.values() {
+            let mut action = planned_clone(&repo.url, &repo.dest_path(), &repo.clone_options().git_args());
+            if repo.update == "pull" {
+                action = action.detail("pulled on every run");
+            }
+            plan::add(action);
+        }
+        return;
+    }
+    
+    let failed: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+    let bars = progress::bars(repos.len(), "Cloning repositories");
+    let jobs: Vec<output::Step> = repos
+        .iter()
+        .map(|(name, repo)| -> output::Step {
+            let (failed, bars) = (&failed, &bars);
+            (name.as_str(), Box::new(move || {
+                let bar = bars.as_ref().map(|bars| bars.start(name));
+                if !update_repo(config, name, repo) {
+                    failed.lock().unwrap_or_else(|e| e.into_inner()).push(name);
+                }
+                if let (Some(bars), Some(bar)) = (bars, bar) {
+                    bars.done(bar);
+                }
+            }))
+        })
+        .collect();
+    output::pool(config.file.network.jobs, jobs);
+    drop(bars);
+    
+    // Failures are already warnings, this is just the tally
+    let failed = failed.into_inner().unwrap_or_else(|e| e.into_inner());
+    if failed.is_empty() {
+        say!("✓ Repositories cloned!");
+    } else {
+        say!("⚠ {} of {} repositories cloned, failed: {}", repos.len() - failed.len(), repos.len(), failed.join(", "));
+    }
+}
+
+// Clone one of the extra repositories, or pull it. Returns whether it's there.
+fn update_repo(config: &Config, name: &str, repo: &RepoConfig) -> bool {
+    let dest = repo.dest_path();
+    
+    if repo.update == "pull" && matches!(check_clone(&dest, &repo.url), CloneState::Valid) {
+        return run_or_warn(
+            config,
+            Cmd::new("git").args(["-C", &dest, "pull", "--ff-only"]).network(),
+            &format!("Failed to pull {}", name),
+        );
+    }
+    
+    if let Some(parent) = Path::new(&dest).parent()
+        && let Err(e) = std::fs::create_dir_all(parent)
+    {
+        config.warnings.add(WarningKind::Failure, format!("Failed to create {}: {}", parent.display(), e));
+        return false;
+    }
+    if !clone_repo(config, &repo.url, &dest, &repo.clone_options().git_args()) {
+        config.warnings.add(WarningKind::Failure, format!("Failed to clone {}", repo.url));
+        return false;
+    }
+    true
+}
+
+// Store path of the newest home-manager generation, if any
+fn current_hm_generation() -> Option<String> {
+    // Lines look like "2024-05-01 12:00 : id 12 -> /nix/store/...-home-manager-generation"
+    let output = Command::new("home-manager")
+        .arg("generations")
+        .output()
+        .ok()?;
+    
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .and_then(|line| line.split(" -> ").nth(1))
+        .map(|path| path.trim().to_string())
+}
+
+const HM_FLAKE_TEMPLATE: &str = r#"{
+  description = "Home Manager configuration";
+  
+  inputs = {
+    nixpkgs.url = "github:nixos/nixpkgs/nixos-unstable";
+    home-manager = {
+      url = "github:nix-community/home-manager";
+      inputs.nixpkgs.follows = "nixpkgs";
+    };
+  };
+  
+  outputs = { nixpkgs, home-manager, ... }:
+    let
+      system = "@SYSTEM@";
+      pkgs = nixpkgs.legacyPackages.${system};
+    in {
+      homeConfigurations."@NAME@" = home-manager.lib.homeManagerConfiguration {
+        inherit pkgs;
+        modules = [ ./home.nix ];
+      };
+    };
+}
+"#;
+
+fn hm_channel_present() -> bool {
+    Command::new("nix-channel")
+        .arg("--list")
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).lines().any(|line| line.starts_with("home-manager ")))
+        .unwrap_or(false)
+}
+
+// `ass hm migrate-flakes`: wrap the home.nix that the channel flow uses in a
+// flake, switch to it once, and only then drop the home-manager channel
+fn migrate_hm_to_flakes(config: &Config) {
+    let flake = hm_flake_path(config);
+    let flake_file = format!("{}/flake.nix", flake);
+    let home_nix = format!("{}/home.nix", flake);
+    
+    if Path::new(&flake_file).exists() {
+        say!("✓ {} already exists, Home Manager is flake-based", flake_file);
+        return;
+    }
+    if !Path::new(&home_nix).exists() {
+        report::fatal(format!("No {} to migrate", home_nix));
+    }
+    if !config.dry_run && !hm_channel_present() {
+        report::fatal("No home-manager channel found, this doesn't look like a channel-based setup");
+    }
+    
+    let user = env::var("USER").expect("USER environment variable not set");
+    let name = config.hm_configuration.clone().unwrap_or(user);
+    let system = format!("{}-linux", std::env::consts::ARCH);
+    
+    say!("Migrating Home Manager to flakes...");
+    
+    if config.dry_run {
+        plan::add(plan::write(&flake_file, format!("Write {} with homeConfigurations.\"{}\" ({}) importing ./home.nix", flake_file, name, system)));
+        plan::add(plan::run("git add flake.nix").detail(format!("(if {} is in a git repo)", flake)));
+        plan::add(plan::run(format!("home-manager switch -b backup --flake {}#{}", flake, name)));
+        plan::add(plan::run("nix-channel --remove home-manager && nix-channel --update"));
+        plan::add(plan::write(
+            &config::config_path(),
+            format!("Set home_manager.configuration = \"{}\" in {}", name, config::config_path()),
+        ));
+        return;
+    }
+    
+    let content = HM_FLAKE_TEMPLATE.replace("@SYSTEM@", &system).replace("@NAME@", &name);
+    std::fs::write(&flake_file, content).unwrap_or_else(|e| {
+        esay!("Failed to write {}: {}", flake_file, e);
+        std::process::exit(1);
+    });
+    say!("  ✓ Wrote {}", flake_file);
+    
+    // Flakes only see files git knows about
+    if git_output(&flake, &["rev-parse", "--is-inside-work-tree"]).as_deref() == Some("true") {
+        run_or_warn(config, Cmd::new("git").args(["-C", &flake, "add", "flake.nix"]), "Failed to add flake.nix to git");
+    }
+    
+    // Verify with one switch before touching the channels
+    let switch = Cmd::new("home-manager")
+        .args(["switch", "-b", "backup", "--flake", &format!("{}#{}", flake, name)])
+        .env("NIX_CONFIG", "experimental-features = nix-command flakes")
+        .nix_build();
+    
+    if let Err(e) = run_command(config, switch) {
+        esay!("Switching to the flake failed ({}), removing {} and keeping the channel setup", e, flake_file);
+        if git_output(&flake, &["rev-parse", "--is-inside-work-tree"]).as_deref() == Some("true") {
+            run_or_warn(config, Cmd::new("git").args(["-C", &flake, "rm", "--cached", "--quiet", "flake.nix"]), "Failed to drop flake.nix from git");
+        }
+        let _ = std::fs::remove_file(&flake_file);
+        std::process::exit(1);
+    }
+    say!("  ✓ Switched to {}#{}", flake, name);
+    
+    if run_or_warn(config, Cmd::new("nix-channel").args(["--remove", "home-manager"]), "Failed to remove the home-manager channel") {
+        run_or_warn(config, Cmd::new("nix-channel").arg("--update"), "Failed to update nix channels");
+        say!("  ✓ Removed the home-manager channel");
+    }
+    
+    let path = config::config_path();
+    config::write_config_values(&path, &[("home_manager", "configuration", toml::Value::String(name.clone()))]);
+    say!("  ✓ Set home_manager.configuration = \"{}\" in {}", name, path);
+    
+    say!("✓ Home Manager now uses {}#{}", flake, name);
+    say!("  Commit flake.nix and the generated flake.lock to your dotfiles");
+    config.warnings.print_recap();
+}
+
+// Directory holding the home-manager flake
+fn hm_flake_path(config: &Config) -> String {
+    config.file.home_manager.flake.clone().unwrap_or_else(|| paths::get().config("home-manager"))
+}
+
+// Exit with the list of available outputs when the requested configuration doesn't exist
+fn check_hm_configuration(flake: &str, name: &str) {
+    if !Path::new(flake).join("flake.nix").exists() {
+        report::fatal(format!("--hm-config requires a flake, but {}/flake.nix doesn't exist", flake));
+    }
+    
+    let output = Command::new("nix")
+        .args([
+            "--extra-experimental-features", "nix-command flakes",
+            "eval", "--json",
+            &format!("{}#homeConfigurations", flake),
+            "--apply", "builtins.attrNames",
+        ])
+        .output()
+        .expect("Failed to execute nix eval");
+    
+    if !output.status.success() {
+        report::error(format!("Failed to read homeConfigurations from {}", flake));
+        esay!("{}", String::from_utf8_lossy(&output.stderr).trim());
+        std::process::exit(1);
+    }
+    
+    // Output is a JSON list of strings, e.g. ["desktop","laptop"]
+    let available: Vec<String> = serde_json::from_slice(&output.stdout).unwrap_or_else(|e| {
+        report::fatal(format!("Unexpected output from nix eval for {}#homeConfigurations: {}", flake, e));
+    });
+    
+    if !available.iter().any(|a| a == name) {
+        report::error(format!("homeConfigurations.{} not found in {}", name, flake));
+        if available.is_empty() {
+            esay!("The flake doesn't define any homeConfigurations");
+        } else {
+            esay!("Available configurations:");
+            for a in &available {
+                esay!("  - {}", a);
+            }
+        }
+        std::process::exit(1);
+    }
+}
+
+// Rebuild home-manager configuration, returning the package changes it made
+fn rebuild_home_manager(config: &Config) -> Option<String> {
+    say!("Rebuilding Home Manager configuration...");
+    
+    if config.dry_run {
+        match &config.hm_configuration {
+            Some(name) => plan::add(plan::run(format!("home-manager switch -b backup --flake {}#{}", hm_flake_path(config), name))),
+            None => plan::add(plan::run("home-manager switch -b backup")),
+        }
+        plan::add(plan::check("nix store diff-closures <previous generation> <new generation>"));
+        plan::add(plan::check("home-manager news").detail("(unread entries are shown at the end)"));
+        return None;
+    }
+    
+    let previous_generation = current_hm_generation();
+    
+    let mut switch = Cmd::new("home-manager").args(["switch", "-b", "backup"]).nix_build();
+    
+    if let Some(name) = &config.hm_configuration {
+        let flake = hm_flake_path(config);
+        check_hm_configuration(&flake, name);
+        switch = switch.args(["--flake", &format!("{}#{}", flake, name)]);
+        
+        if config.verbose {
+            say!("Using home configuration {}#{}", flake, name);
+        }
+    }
+    
+    if config.verbose {
+        say!("Running home-manager switch...");
+    }
+    
+    run_or_exit(config, switch, "Failed to rebuild home-manager configuration");
+    
+    say!("✓ Home Manager configuration rebuilt successfully!");
+    
+    // Modules change under a moving channel or flake input, news is where
+    // Home Manager says so
+    let news = unread_hm_news(config);
+    if !news.is_empty() {
+        say!("⚠ {} unread Home Manager news items, shown at the end", news.len());
+    }
+    *config.hm_news.lock().unwrap_or_else(|e| e.into_inner()) = news;
+    
+    // First switch has nothing to compare against
+    let previous_generation = previous_generation?;
+    let new_generation = current_hm_generation()?;
+    if previous_generation == new_generation {
+        return Some(String::new());
+    }
+    
+    if config.verbose {
+        say!("Comparing generations...");
+    }
+    let output = Command::new("nix")
+        .args([
+            "--extra-experimental-features", "nix-command",
+            "store", "diff-closures", &previous_generation, &new_generation,
+        ])
+        .output()
+        .ok()?;
+    
+    if !output.status.success() {
+        config.warnings.add(WarningKind::Failure, "Failed to diff home-manager generations");
+        return None;
+    }
+    
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// Unread `home-manager news` entries. Reading them marks them read, the
+// same as the pager would.
+fn unread_hm_news(config: &Config) -> Vec<String> {
+    let mut news = Command::new("home-manager");
+    news.arg("news").env("PAGER", "cat").stderr(std::process::Stdio::null());
+    if let Some(name) = &config.hm_configuration {
+        news.args(["--flake", &format!("{}#{}", hm_flake_path(config), name)]);
+    }
+    match news.output() {
+        Ok(output) if output.status.success() => parse_hm_news(&String::from_utf8_lossy(&output.stdout)),
+        _ => {
+            config.warnings.add(WarningKind::Failure, "Failed to read home-manager news");
+            Vec::new()
+        }
+    }
+}
+
+// Entries start with "* <date>", unread ones say "[unread]" there. Each is
+// returned as the date line and the text below it, unindented.
+fn parse_hm_news(text: &str) -> Vec<String> {
+    let mut entries: Vec<Vec<&str>> = Vec::new();
+    for line in text.lines() {
+        if line.starts_with("* ") {
+            entries.push(vec![line]);
+        } else if let Some(entry) = entries.last_mut() {
+            entry.push(line);
+        }
+    }
+    entries
+        .into_iter()
+        .filter(|entry| entry[0].contains("[unread]"))
+        .map(|entry| {
+            let date = entry[0].trim_start_matches("* ").replace("[unread]", "").trim().to_string();
+            let text: Vec<&str> = entry[1..].iter().map(|line| line.trim()).collect();
+            format!("{}\n{}", date, text.join("\n").trim())
+        })
+        .collect()
+}
+
+fn print_hm_news(config: &Config) {
+    let news = config.hm_news.lock().unwrap_or_else(|e| e.into_inner());
+    if news.is_empty() {
+        return;
+    }
+    say!("\n{}", tr!("Unread Home Manager news:"));
+    for entry in news.iter() {
+        for line in entry.lines() {
+            say!("  {}", line);
+        }
+        say!();
+    }
+}
+
+// Reconcile the user's Nix profile with [nix] packages: install what's
+// missing, remove what an earlier run installed that isn't listed anymore
+fn install_nix_packages(config: &Config) {
+    let declared = &config.file.nix.packages;
+    let recorded = nixprofile::recorded();
+    
+    let Some(installed) = nixprofile::installed().or_else(|| config.dry_run.then(BTreeMap::new)) else {
+        config.warnings.add_with_hints(
+            WarningKind::Failure,
+            "Failed to read the Nix profile, not installing [nix] packages",
+            vec!["A profile made by nix-env can't be used with `nix profile`"],
+        );
+        return;
+    };
+    
+    let missing: Vec<String> = declared
+        .iter()
+        .filter(|package| !installed.contains_key(nixprofile::element_name(package)))
+        .map(|package| nixprofile::installable(package))
+        .collect();
+    let dropped: Vec<&String> = recorded
+        .iter()
+        .filter(|package| !declared.contains(package))
+        .filter_map(|package| installed.get(nixprofile::element_name(package)))
+        .collect();
+    
+    if missing.is_empty() && dropped.is_empty() {
+        if config.verbose {
+            say!("✓ Nix profile packages up to date");
+        }
+    } else {
+        say!("Updating Nix profile packages...");
+    }
+    
+    if !missing.is_empty() {
+        run_or_warn(
+            config,
+            Cmd::new("nix").args(nixprofile::NIX_FEATURES).args(["profile", "install"]).args(&missing).nix_build(),
+            "Failed to install [nix] packages",
+        );
+    }
+    if !dropped.is_empty() {
+        run_or_warn(
+            config,
+            Cmd::new("nix").args(nixprofile::NIX_FEATURES).args(["profile", "remove"]).args(&dropped),
+            "Failed to remove packages dropped from [nix] packages",
+        );
+    }
+    if config.dry_run {
+        return;
+    }
+    
+    // Whatever failed to install or remove is tried again next time
+    let now = nixprofile::installed().unwrap_or_default();
+    let mut record: Vec<String> = recorded.iter().filter(|package| !declared.contains(package)).cloned().collect();
+    record.extend(declared.iter().cloned());
+    record.retain(|package| now.contains_key(nixprofile::element_name(package)));
+    if let Err(e) = nixprofile::record(&record) {
+        config.warnings.add(WarningKind::Failure, format!("Failed to record the installed [nix] packages: {}", e));
+    }
+    
+    if !missing.is_empty() || !dropped.is_empty() {
+        say!("✓ Nix profile packages updated!");
+    }
+}
+
+// Collect config files below a directory (follows the stow symlink into ~/dotfiles)
+fn collect_config_files(dir: &Path, files: &mut Vec<std::path::PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_config_files(&path, files);
+        } else if matches!(path.extension().and_then(|e| e.to_str()), Some("lua" | "vim")) {
+            files.push(path);
+        }
+    }
+}
+
+// Figure out which plugin manager the neovim config uses
+fn detect_nvim_plugin_manager(nvim_config_path: &str) -> Option<&'static str> {
+    if Path::new(nvim_config_path).join("lazy-lock.json").exists() {
+        return Some("lazy.nvim");
+    }
+    
+    let mut files = Vec::new();
+    collect_config_files(Path::new(nvim_config_path), &mut files);
+    
+    for file in &files {
+        let content = std::fs::read_to_string(file).unwrap_or_default();
+        if content.contains("folke/lazy.nvim") {
+            return Some("lazy.nvim");
+        }
+        if content.contains("wbthomason/packer.nvim") {
+            return Some("packer.nvim");
+        }
+        if content.contains("plug#begin") {
+            return Some("vim-plug");
+        }
+        if content.contains("savq/paq-nvim") {
+            return Some("paq-nvim");
+        }
+    }
+    
+    None
+}
+
+// Headless nvim arguments that install/sync plugins and then quit
+fn nvim_bootstrap_args(plugin_manager: &str) -> Vec<&'static str> {
+    match plugin_manager {
+        "lazy.nvim" => vec!["--headless", "+Lazy! sync", "+qa"],
+        "packer.nvim" => vec!["--headless", "-c", "autocmd User PackerComplete quitall", "-c", "PackerSync"],
+        "vim-plug" => vec!["--headless", "+PlugInstall --sync", "+qa"],
+        "paq-nvim" => vec!["--headless", "-c", "autocmd User PaqDoneSync quitall", "-c", "PaqSync"],
+        _ => Vec::new(),
+    }
+}
+
+// Install neovim plugins headlessly so the first launch isn't a wall of errors
+fn bootstrap_nvim_plugins(config: &Config) {
+    say!("Bootstrapping Neovim plugins...");
+    
+    if config.dry_run {
+        plan::add(plan::check("Check that nvim is installed and ~/.config/nvim exists"));
+        plan::add(plan::check("Detect plugin manager (lazy.nvim, packer.nvim, vim-plug, paq-nvim)"));
+        plan::add(
+            plan::run("nvim --headless \"+Lazy! sync\" +qa")
+                .detail("(or the equivalent for the detected manager)")
+                .effect("installs the Neovim plugins"),
+        );
+        return;
+    }
+    
+    // Check if nvim is installed
+    if which("nvim").is_none() {
+        say!("⏭ Neovim not installed, skipping plugin bootstrap");
+        return;
+    }
+    
+    let paths = paths::get();
+    let nvim_config_path = paths.config("nvim");
+    
+    if !Path::new(&nvim_config_path).exists() {
+        say!("⏭ No Neovim config found at {}, skipping plugin bootstrap", nvim_config_path);
+        return;
+    }
+    
+    let Some(plugin_manager) = detect_nvim_plugin_manager(&nvim_config_path) else {
+        say!("⏭ No supported Neovim plugin manager detected, skipping plugin bootstrap");
+        return;
+    };
+    
+    if config.verbose {
+        say!("Detected plugin manager: {}", plugin_manager);
+        say!("Running headless plugin sync...");
+    }
+    
+    let sync = Cmd::new("nvim").args(nvim_bootstrap_args(plugin_manager)).current_dir(&paths.home);
+    // Not fatal, plugins will be installed on first launch instead
+    if !run_or_warn(config, sync, &format!("Headless {} sync failed", plugin_manager)) {
+        return;
+    }
+    
+    say!("✓ Neovim plugins installed with {}!", plugin_manager);
+}
+
+// Find the tmux config, preferring the XDG location like tmux itself does
+fn find_tmux_config(paths: &Paths) -> Option<String> {
+    let candidates = [paths.config("tmux/tmux.conf"), paths.home(".tmux.conf")];
+    
+    candidates.into_iter().find(|path| Path::new(path).exists())
+}
+
+// Work out where TPM should live from the `run '.../tpm/tpm'` line in tmux.conf
+fn tpm_install_path(tmux_conf: &str, tmux_conf_path: &str, paths: &Paths) -> String {
+    for line in tmux_conf.lines() {
+        let line = line.trim();
+        if !line.starts_with("run") {
+            continue;
+        }
+        
+        if let Some(token) = line.split_whitespace().find(|t| t.contains("tpm/tpm")) {
+            let token = token.trim_matches(|c| c == '\'' || c == '"');
+            // Only the script's own segment, the directory is tpm too
+            let path = token
+                .strip_suffix("/tpm")
+                .unwrap_or(token)
+                .replacen('~', &paths.home, 1)
+                .replace("$HOME", &paths.home);
+            return path;
+        }
+    }
+    
+    // TPM defaults to a plugins dir next to an XDG config
+    if tmux_conf_path == paths.config("tmux/tmux.conf") {
+        paths.config("tmux/plugins/tpm")
+    } else {
+        paths.home(".tmux/plugins/tpm")
+    }
+}
+
+// Clone TPM and install tmux plugins so the config works on first attach
+fn bootstrap_tmux_plugins(config: &Config) {
+    say!("Bootstrapping tmux plugins...");
+    
+    if config.dry_run {
+        plan::add(plan::check("Check that tmux is installed and a tmux.conf with @plugin entries exists"));
+        plan::add(plan::clone("git clone --depth=1 https://github.com/tmux-plugins/tpm ~/.tmux/plugins/tpm", "~/.tmux/plugins/tpm"));
+        plan::add(plan::run("tmux new-session -d -s ass-tpm-bootstrap"));
+        plan::add(plan::run("~/.tmux/plugins/tpm/bin/install_plugins").effect("installs the tmux plugins"));
+        plan::add(plan::run("tmux kill-session -t ass-tpm-bootstrap"));
+        return;
+    }
+    
+    // Check if tmux is installed
+    if which("tmux").is_none() {
+        say!("⏭ tmux not installed, skipping plugin bootstrap");
+        return;
+    }
+    
+    let paths = paths::get();
+    
+    let Some(tmux_conf_path) = find_tmux_config(paths) else {
+        say!("⏭ No tmux config found, skipping plugin bootstrap");
+        return;
+    };
+    
+    let tmux_conf = std::fs::read_to_string(&tmux_conf_path).unwrap_or_default();
+    if !tmux_conf.contains("@plugin") {
+        say!("⏭ tmux config doesn't use any plugins, skipping plugin bootstrap");
+        return;
+    }
+    
+    let tpm_path = tpm_install_path(&tmux_conf, &tmux_conf_path, paths);
+    
+    // Clone TPM if it isn't there yet
+    if Path::new(&tpm_path).exists() {
+        if config.verbose {
+            say!("✓ TPM already present at {}", tpm_path);
+        }
+    } else {
+        if config.verbose {
+            say!("Cloning TPM to {}...", tpm_path);
+        }
+        let clone = Cmd::new("git").args(["clone", "--depth=1", "https://github.com/tmux-plugins/tpm", &tpm_path]).network();
+        if !run_or_warn(config, clone, "Failed to clone TPM") {
+            return;
+        }
+    }
+    
+    // install_plugins talks to a running server, so start a throwaway session
+    if config.verbose {
+        say!("Starting temporary tmux session...");
+    }
+    let session = Cmd::new("tmux").args(["new-session", "-d", "-s", "ass-tpm-bootstrap"]);
+    if !run_or_warn(config, session, "Failed to start a tmux session for plugin installation") {
+        return;
+    }
+    
+    if config.verbose {
+        say!("Installing tmux plugins...");
+    }
+    let install_script = format!("{}/bin/install_plugins", tpm_path);
+    let installed = run_or_warn(config, Cmd::new(&install_script), "TPM failed to install some tmux plugins");
+    
+    run_or_warn(config, Cmd::new("tmux").args(["kill-session", "-t", "ass-tpm-bootstrap"]), "Failed to stop the temporary tmux session");
+    
+    if !installed {
+        return;
+    }
+    
+    say!("✓ tmux plugins installed!");
+}
+
+// Set key=value entries inside an ini section, returning the new file content
+fn set_ini_entries(content: &str, section: &str, entries: &[(String, String)]) -> String {
+    let header = format!("[{}]", section);
+    let mut result = Vec::new();
+    let mut remaining: Vec<&(String, String)> = entries.iter().collect();
+    let mut in_section = false;
+    let mut found_section = false;
+    
+    for line in content.lines() {
+        let trimmed = line.trim();
+        
+        if trimmed.starts_with('[') {
+            // Leaving our section, add whatever keys weren't already present
+            if in_section {
+                for (key, value) in remaining.drain(..) {
+                    result.push(format!("{}={}", key, value));
+                }
+            }
+            in_section = trimmed == header;
+            found_section |= in_section;
+        } else if in_section
+            && let Some((key, _)) = trimmed.split_once('=')
+            && let Some(pos) = remaining.iter().position(|(k, _)| k == key.trim())
+        {
+            let (key, value) = remaining.remove(pos);
+            result.push(format!("{}={}", key, value));
+            continue;
+        }
+        
+        result.push(line.to_string());
+    }
+    
+    if !found_section {
+        if !result.is_empty() {
+            result.push(String::new());
+        }
+        result.push(header);
+    }
+    for (key, value) in remaining {
+        result.push(format!("{}={}", key, value));
+    }
+    
+    result.join("\n") + "\n"
+}
+
+// MIME types covered by each role in the [mime] config
+fn mime_types_for_role(role: &str) -> &'static [&'static str] {
+    match role {
+        "browser" => &[
+            "x-scheme-handler/http",
+            "x-scheme-handler/https",
+            "text/html",
+            "application/xhtml+xml",
+        ],
+        "pdf" => &["application/pdf"],
+        "image" => &[
+            "image/png",
+            "image/jpeg",
+            "image/gif",
+            "image/webp",
+            "image/bmp",
+            "image/svg+xml",
+        ],
+        _ => &[],
+    }
+}
+
+// Write default applications to mimeapps.list from the [mime] config
+fn configure_default_apps(config: &Config) {
+    let mime = &config.file.mime;
+    
+    let mut entries: Vec<(String, String)> = Vec::new();
+    for (role, desktop) in [("browser", &mime.browser), ("pdf", &mime.pdf), ("image", &mime.image)] {
+        if let Some(desktop) = desktop {
+            for mime_type in mime_types_for_role(role) {
+                entries.push((mime_type.to_string(), desktop.clone()));
+            }
+        }
+    }
+    for (mime_type, desktop) in &mime.types {
+        // Explicit types win over the role defaults
+        entries.retain(|(t, _)| t != mime_type);
+        entries.push((mime_type.clone(), desktop.clone()));
+    }
+    
+    if entries.is_empty() && mime.terminal.is_none() {
+        if config.verbose {
+            say!("⏭ No [mime] defaults configured, skipping");
+        }
+        return;
+    }
+    
+    say!("Configuring default applications...");
+    
+    let paths = paths::get();
+    let mimeapps_path = paths.config("mimeapps.list");
+    
+    if config.dry_run {
+        if !entries.is_empty() {
+            let current = std::fs::read_to_string(&mimeapps_path).unwrap_or_default();
+            let mut action = plan::write(&mimeapps_path, format!("Set in {} [Default Applications]:", mimeapps_path))
+                .details(entries.iter().map(|(mime_type, desktop)| format!("{}={}", mime_type, desktop)));
+            if set_ini_entries(&current, "Default Applications", &entries) == current {
+                action = action.effect("nothing, already set");
+            }
+            plan::add(action);
+        }
+        if let Some(terminal) = &mime.terminal {
+            plan::add(planned_aur_install(config, &["xdg-terminal-exec"]));
+            plan::add(plan::write(&paths.config("xdg-terminals.list"), format!("Write {} to ~/.config/xdg-terminals.list", terminal)));
+        }
+        return;
+    }
+    
+    if !entries.is_empty() {
+        // home-manager can own mimeapps.list as a read-only store symlink
+        if std::fs::symlink_metadata(&mimeapps_path).map(|m| m.file_type().is_symlink()).unwrap_or(false) {
+            config.warnings.add(WarningKind::Skipped, format!("{} is a symlink (managed elsewhere?), not touching it", mimeapps_path));
+        } else {
+            let current = std::fs::read_to_string(&mimeapps_path).unwrap_or_default();
+            let updated = set_ini_entries(&current, "Default Applications", &entries);
+            
+            if updated == current {
+                if config.verbose {
+                    say!("✓ mimeapps.list already up to date");
+                }
+            } else {
+                let _ = std::fs::create_dir_all(&paths.config_home);
+                std::fs::write(&mimeapps_path, updated).expect("Failed to write mimeapps.list");
+                if config.verbose {
+                    say!("✓ Updated {} ({} entries)", mimeapps_path, entries.len());
+                }
+            }
+        }
+    }
+    
+    if let Some(terminal) = &mime.terminal {
+        if config.verbose {
+            say!("Installing xdg-terminal-exec...");
+        }
+        install_aur_packages(config, &["xdg-terminal-exec"], "Failed to install xdg-terminal-exec");
+        
+        let terminals_path = paths.config("xdg-terminals.list");
+        let current = std::fs::read_to_string(&terminals_path).unwrap_or_default();
+        
+        // Preferred terminal goes first, keep any others as fallbacks
+        let mut lines = vec![terminal.clone()];
+        lines.extend(current.lines().filter(|l| l.trim() != terminal).map(|l| l.to_string()));
+        let updated = lines.join("\n") + "\n";
+        
+        if updated != current {
+            std::fs::write(&terminals_path, updated).expect("Failed to write xdg-terminals.list");
+        }
+        if config.verbose {
+            say!("✓ Default terminal set to {}", terminal);
+        }
+    }
+    
+    say!("✓ Default applications configured!");
+}
+
+const FIRST_LOGIN_UNIT: &str = "ass-first-login.service";
+
+fn first_login_service(exe: &str) -> String {
+    format!(
+        "[Unit]
+Description=Finish the ass setup inside the first graphical session
+PartOf=graphical-session.target
+After=graphical-session.target
+
+[Service]
+Type=oneshot
+ExecStart={} first-login
+
+[Install]
+WantedBy=graphical-session.target
+",
+        exe
+    )
+}
+
+// Leave the actions that need a running session (session bus, portals,
+// keyring) to a user unit started with the first graphical login
+fn setup_first_login(config: &Config) {
+    say!("Setting up first-login actions...");
+    
+    let user_units = paths::get().config("systemd/user");
+    let unit_path = format!("{}/{}", user_units, FIRST_LOGIN_UNIT);
+    
+    if config.dry_run {
+        let mut first_login = vec!["At the first login it runs `ass first-login`:".to_string()];
+        for (key, value) in &config.file.first_login.gsettings {
+            let (schema, name) = key.rsplit_once('.').expect("validated when loading the config");
+            first_login.push(format!("- gsettings set {} {} {}", schema, name, value));
+        }
+        first_login.push("- Start the secret service (gnome-keyring) if installed".to_string());
+        first_login.push("- Start xdg-desktop-portal if installed".to_string());
+        first_login.push(format!("then disables itself and records the results in {}", checkpoint::path()));
+        plan::add(plan::write(&unit_path, format!("Write {}", unit_path)));
+        plan::add(
+            plan::enable(format!("systemctl --user enable {}", FIRST_LOGIN_UNIT), FIRST_LOGIN_UNIT)
+                .effect(format!("{} runs at the first graphical login", FIRST_LOGIN_UNIT))
+                .details(first_login),
+        );
+        return;
+    }
+    
+    let exe = env::current_exe().expect("Failed to find the ass executable").to_string_lossy().to_string();
+    std::fs::create_dir_all(&user_units).expect("Failed to create systemd user unit directory");
+    std::fs::write(&unit_path, first_login_service(&exe)).expect("Failed to write first-login unit");
+    enable_user_unit(config, &user_units, FIRST_LOGIN_UNIT, &unit_path, "graphical-session.target");
+    
+    checkpoint::set_first_login(checkpoint::FirstLogin {
+        status: "pending".to_string(),
+        ..checkpoint::FirstLogin::default()
+    });
+    say!("✓ First-login actions run with your next graphical login ({})", FIRST_LOGIN_UNIT);
+}
+
+// A first-login action, Err says what went wrong
+type FirstLoginAction = fn(&Config) -> Result<(), String>;
+
+// gsettings set for every [first_login] gsettings entry
+fn apply_gsettings(config: &Config) -> Result<(), String> {
+    let mut failed = Vec::new();
+    for (key, value) in &config.file.first_login.gsettings {
+        let (schema, name) = key.rsplit_once('.').expect("validated when loading the config");
+        if run_command(config, Cmd::new("gsettings").args(["set", schema, name, value])).is_err() {
+            failed.push(key.as_str());
+        }
+    }
+    if failed.is_empty() { Ok(()) } else { Err(format!("gsettings set failed for {}", failed.join(", "))) }
+}
+
+// Activating the secret service makes gnome-keyring create and unlock the
+// login keyring now, instead of the first app asking for it
+fn init_keyring(config: &Config) -> Result<(), String> {
+    let start = Cmd::new("busctl").args([
+        "--user", "call",
+        "org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus",
+        "StartServiceByName", "su", "org.freedesktop.secrets", "0",
+    ]);
+    run_command(config, start).map_err(|_| "org.freedesktop.secrets could not be started".to_string())
+}
+
+fn check_portal(config: &Config) -> Result<(), String> {
+    run_command(config, Cmd::new("systemctl").args(["--user", "start", "xdg-desktop-portal.service"]))
+        .map_err(|_| "xdg-desktop-portal.service failed to start (systemctl --user status xdg-desktop-portal)".to_string())
+}
+
+// `ass first-login`, run by the unit from setup_first_login: do the deferred
+// actions, record how they went and remove the unit
+fn first_login(config: &Config) {
+    say!("Running first-login actions...");
+    
+    let mut actions: Vec<(&str, FirstLoginAction)> = Vec::new();
+    if !config.file.first_login.gsettings.is_empty() {
+        actions.push(("gsettings", apply_gsettings));
+    }
+    if package_installed("gnome-keyring") {
+        actions.push(("keyring", init_keyring));
+    }
+    if package_installed("xdg-desktop-portal") {
+        actions.push(("portal", check_portal));
+    }
+    
+    let mut results = BTreeMap::new();
+    for (name, action) in actions {
+        if config.dry_run {
+            say!("[DRY RUN] Would run the {} action", name);
+            continue;
+        }
+        match action(config) {
+            Ok(()) => {
+                say!("✓ {}", name);
+                results.insert(name.to_string(), "ok".to_string());
+            }
+            Err(e) => {
+                config.warnings.add(WarningKind::Failure, format!("First login: {}", e));
+                results.insert(name.to_string(), e);
+            }
+        }
+    }
+    if config.dry_run {
+        return;
+    }
+    checkpoint::set_first_login(checkpoint::FirstLogin {
+        status: "done".to_string(),
+        results,
+    });
+    
+    // One time only
+    run_or_warn(config, Cmd::new("systemctl").args(["--user", "disable", FIRST_LOGIN_UNIT]), &format!("Failed to disable {}", FIRST_LOGIN_UNIT));
+    let _ = std::fs::remove_file(paths::get().config(&format!("systemd/user/{}", FIRST_LOGIN_UNIT)));
+    say!("✓ First-login actions done, results in {}", checkpoint::path());
+}
+
+// Caches bigger than this get a line in the cleanliness report
+const LARGE_CACHE_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+// Apparent size of a directory in bytes, None if it doesn't exist
+fn dir_size(path: &str) -> Option<u64> {
+    if !Path::new(path).is_dir() {
+        return None;
+    }
+    let output = Command::new("du")
+        .args(["-sb", path])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout).split_whitespace().next()?.parse().ok()
+}
+
+// Non-empty output lines of a read-only query
+fn output_lines(program: &str, args: &[&str]) -> Vec<String> {
+    Command::new(program)
+        .args(args)
+        .stderr(std::process::Stdio::null())
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(|line| line.trim().to_string())
+                .filter(|line| !line.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Every package in the dotfiles package list, left out ones included. No
+// list (nothing cloned yet) is an empty one.
+fn read_package_list(config: &Config) -> Result<Vec<pkglist::Package>, String> {
+    let path = config.file.dotfiles.package_list_path(&paths::get().dotfiles());
+    match std::fs::read_to_string(&path) {
+        Ok(content) => pkglist::parse(&content).map_err(|e| format!("{}: {}", path, e)),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+// `ass packages`: the package list as documentation, with what each package
+// is for and whether this machine has it
+fn print_package_list(config: &Config) {
+    let path = config.file.dotfiles.package_list_path(&paths::get().dotfiles());
+    let packages = read_package_list(config).unwrap_or_else(|e| report::fatal(format!("Invalid package list {}", e)));
+    if packages.is_empty() {
+        say!("No packages in {}", path);
+        return;
+    }
+    
+    let host = facts::get("hostname");
+    let session = session_type(config);
+    let installed = pkg::backend().installed_packages();
+    let width = packages.iter().map(|package| package.name.len()).max().unwrap_or(0);
+    say!("{} (profile {}, host {}, sessions {}):", path, config.profile.name, host, session);
+    let mut counts = [0; 3];
+    let mut group = None;
+    for package in &packages {
+        if package.group != group {
+            group = package.group.clone();
+            say!();
+            say!("[{}]", group.as_deref().unwrap_or("always"));
+        }
+        
+        let (symbol, status) = match package.left_out(&config.profile.exclude_groups, host, &session) {
+            Some(pkglist::LeftOut::Group(group)) => ("⏭", format!("left out, the {} profile excludes {}", config.profile.name, group)),
+            Some(pkglist::LeftOut::Host) => ("⏭", format!("left out, hosts={}", package.hosts.join(","))),
+            Some(pkglist::LeftOut::Session(wanted)) => ("⏭", format!("left out, {} only", wanted)),
+            Some(pkglist::LeftOut::Facts(condition)) => ("⏭", format!("left out, when={}", condition)),
+            None if installed.contains(&package.name) => ("✓", String::new()),
+            None => ("✗", "not installed".to_string()),
+        };
+        counts[["✓", "✗", "⏭"].iter().position(|counted| *counted == symbol).unwrap_or(0)] += 1;
+        let notes: Vec<String> = [
+            package.why.clone(),
+            package.optional.then(|| "optional".to_string()),
+            (!status.is_empty()).then_some(status),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        say!("  {} {:<width$}  {}", symbol, package.name, notes.join("; "), width = width);
+    }
+    say!();
+    say!("{} installed, {} missing, {} left out on this machine", counts[0], counts[1], counts[2]);
+}
+
+// `ass facts`: what when = "..." conditions are checked against
+fn print_facts() {
+    let facts = facts::all();
+    let width = facts.keys().map(|name| name.len()).max().unwrap_or(0);
+    for (name, value) in facts {
+        say!("{:<width$}  {}", name, value, width = width);
+    }
+}
+
+// Point out what a fresh setup tends to leave behind. Only the safe fixes
+// (orphans, package caches) are offered, one y/N each.
+fn report_cleanliness(config: &Config) {
+    say!("Checking for leftover cruft...");
+    
+    if config.dry_run {
+        plan::add(plan::ask(match pkg::backend() {
+            Backend::Pacman => "Report orphaned packages (pacman -Qdtq) not in the package list, offer sudo pacman -Rns",
+            Backend::Apt => "Report orphaned packages (apt-get autoremove) not in the package list, offer sudo apt-get purge --autoremove",
+            Backend::Dnf => "Report orphaned packages (dnf repoquery --unneeded) not in the package list, offer sudo dnf remove",
+        }));
+        plan::add(plan::check("Report .pacnew/.pacsave files under /etc"));
+        plan::add(plan::check("Report failed system and user units (systemctl --failed)"));
+        plan::add(plan::ask(format!("Report package caches over {}, offer to clean them", usage::format_bytes(LARGE_CACHE_BYTES))));
+        return;
+    }
+    
+    // Nobody answers prompts in a pipeline, report only
+    let offer = |question: &str| !config.ci && ask_yes_no(question, false);
+    let mut clean = true;
+    
+    // Packages the list asks for stay, even if only a dependency got them in
+    let listed: BTreeSet<String> = read_package_list(config).unwrap_or_default().into_iter().map(|package| package.name).collect();
+    let (kept, orphans): (Vec<String>, Vec<String>) = pkg::backend().orphans().into_iter().partition(|orphan| listed.contains(orphan));
+    if !kept.is_empty() && config.verbose {
+        say!("  Keeping {} orphaned packages the package list has: {}", kept.len(), kept.join(" "));
+    }
+    if !orphans.is_empty() {
+        clean = false;
+        say!("⚠ {} orphaned packages: {}", orphans.len(), orphans.join(" "));
+        if offer(&format!("  {}", tr!("Remove them?"))) {
+            run_or_warn(
+                config,
+                package_manager(config).remove(&orphans, true),
+                "Failed to remove orphaned packages",
+            );
+        }
+    }
+    
+    let leftovers = pacnew_files();
+    if !leftovers.is_empty() {
+        clean = false;
+        say!("⚠ {} config files awaiting merge (run: ass pacdiff):", leftovers.len());
+        for path in &leftovers {
+            say!("    {}", path);
+        }
+    }
+    
+    let mut failed = output_lines("systemctl", &["--failed", "--plain", "--no-legend"]);
+    failed.extend(output_lines("systemctl", &["--user", "--failed", "--plain", "--no-legend"]));
+    if !failed.is_empty() {
+        clean = false;
+        say!("⚠ {} failed units:", failed.len());
+        for line in &failed {
+            let unit = line.split_whitespace().next().unwrap_or(line);
+            say!("    {} (journalctl -b -u {})", unit, unit);
+        }
+    }
+    
+    let backend = pkg::backend();
+    if let Some(size) = dir_size(backend.cache_dir()).filter(|size| *size > LARGE_CACHE_BYTES) {
+        clean = false;
+        say!("⚠ {} package cache is {}", backend.name(), usage::format_bytes(size));
+        if offer(&format!("  {}", tr!("Remove cached packages that aren't installed anymore?"))) {
+            run_or_warn(config, package_manager(config).clean_cache(), &format!("Failed to clean the {} cache", backend.name()));
+        }
+    }
+    
+    let helper_cache = format!("{}/{}", paths::get().cache_home, config.aur_helper());
+    if let Some(size) = dir_size(&helper_cache).filter(|size| *size > LARGE_CACHE_BYTES) {
+        clean = false;
+        say!("⚠ {} build cache ({}) is {}", config.aur_helper(), helper_cache, usage::format_bytes(size));
+        if offer(&format!("  {}", tr!("Delete it? Packages are rebuilt from scratch next time"))) {
+            run_or_warn(config, Cmd::new("rm").args(["-rf", &helper_cache]), "Failed to remove the AUR build cache");
+        }
+    }
+    
+    if clean {
+        say!("✓ No leftover cruft found!");
+    }
+}
+
+// AUR helper clones older versions built in the home instead of the work dir
+const LEFTOVER_BUILD_DIRS: [&str; 3] = ["paru", "yay", "pikaur"];
+
+// pacman, its keyrings and the AUR helper
+fn doctor_pacman(config: &Config, findings: &mut Vec<(String, String)>) {
+    if Path::new("/var/lib/pacman/db.lck").exists() && output_lines("pgrep", &["-x", "pacman"]).is_empty() {
+        findings.push((
+            "pacman's database is locked but pacman isn't running (stale lock from a crash)".to_string(),
+            "sudo rm /var/lib/pacman/db.lck".to_string(),
+        ));
+    }
+    let check = Command::new("pacman").args(["-Dk"]).stdout(std::process::Stdio::null()).stderr(std::process::Stdio::null()).status();
+    if check.map(|s| !s.success()).unwrap_or(false) {
+        findings.push((
+            "pacman's local database has missing dependencies or conflicts".to_string(),
+            "pacman -Dk to see them, then reinstall or remove the packages named".to_string(),
+        ));
+    }
+    match pacnew_files_unprompted() {
+        Some(leftovers) if !leftovers.is_empty() => {
+            findings.push((format!("{} .pacnew/.pacsave files waiting to be merged", leftovers.len()), "ass pacdiff".to_string()));
+        }
+        Some(_) => {}
+        None => findings.push(("Couldn't check for .pacnew/.pacsave files (needs sudo)".to_string(), "ass pacdiff".to_string())),
+    }
+    
+    // Keyrings
+    if !Path::new("/etc/pacman.d/gnupg/trustdb.gpg").exists() {
+        findings.push((
+            "pacman's keyring isn't initialized, signed packages can't be verified".to_string(),
+            "sudo pacman-key --init && sudo pacman-key --populate archlinux".to_string(),
+        ));
+    }
+    if !package_installed("archlinux-keyring") {
+        findings.push(("archlinux-keyring is not installed".to_string(), "sudo pacman -S archlinux-keyring".to_string()));
+    }
+    let pacman_conf = std::fs::read_to_string("/etc/pacman.conf").unwrap_or_default();
+    if pacman_conf.lines().any(|line| line.trim() == "[chaotic-aur]") && !package_installed("chaotic-keyring") {
+        findings.push((
+            "[chaotic-aur] is enabled but chaotic-keyring is not installed".to_string(),
+            "ass --only setup_chaotic_aur".to_string(),
+        ));
+    }
+    
+    if which(config.aur_helper()).is_none() {
+        findings.push((format!("{} (the configured AUR helper) is not installed", config.aur_helper()), "ass --only install_aur_helper".to_string()));
+    }
+}
+
+// Half-configured packages from an interrupted dpkg run
+fn doctor_apt(findings: &mut Vec<(String, String)>) {
+    if !output_lines("dpkg", &["--audit"]).iter().all(|line| line.is_empty()) {
+        findings.push((
+            "dpkg has packages that are only partly installed or configured".to_string(),
+            "sudo dpkg --configure -a && sudo apt-get -f install".to_string(),
+        ));
+    }
+}
+
+// Problems in the rpm database, and [copr] repos that aren't enabled
+fn doctor_dnf(config: &Config, findings: &mut Vec<(String, String)>) {
+    let check = Command::new("dnf").args(["check"]).stdout(std::process::Stdio::null()).stderr(std::process::Stdio::null()).status();
+    if check.map(|s| !s.success()).unwrap_or(false) {
+        findings.push((
+            "The rpm database has dependency problems or duplicate packages".to_string(),
+            "dnf check to see them, then sudo dnf distro-sync".to_string(),
+        ));
+    }
+    for repo in &config.file.copr.repos {
+        if !Path::new(&copr_repo_file(repo)).exists() {
+            findings.push((format!("COPR {} (in [copr]) is not enabled", repo), "ass --only setup_copr".to_string()));
+        }
+    }
+}
+
+// `ass doctor`: look the machine over and say what's wrong and how to fix
+// it, without changing anything. Each finding is (problem, fix).
+fn doctor(config: &Config) -> Vec<(String, String)> {
+    say!("Checking this machine...");
+    let mut findings: Vec<(String, String)> = Vec::new();
+    let paths = paths::get();
+    
+    let install = match pkg::backend() {
+        Backend::Pacman => "sudo pacman -S",
+        Backend::Apt => "sudo apt-get install",
+        Backend::Dnf => "sudo dnf install",
+    };
+    for tool in BOOTSTRAP_TOOLS.into_iter().chain(REQUIRED_TOOLS) {
+        if which(tool).is_none() {
+            findings.push((format!("{} is not installed", tool), format!("{} {}", install, tool)));
+        }
+    }
+    
+    match pkg::backend() {
+        Backend::Pacman => doctor_pacman(config, &mut findings),
+        Backend::Apt => doctor_apt(&mut findings),
+        Backend::Dnf => doctor_dnf(config, &mut findings),
+    }
+    
+    for dir in LEFTOVER_BUILD_DIRS {
+        let path = paths.home(dir);
+        if Path::new(&path).join("PKGBUILD").exists() {
+            findings.push((format!("Leftover {} build directory {}", dir, path), format!("rm -rf {}", path)));
+        }
+    }
+    
+    // Nix and Home Manager
+    if which("nix").is_some() {
+        if !nix_single_user() && !init::init().is_running("nix-daemon") {
+            findings.push(("nix-daemon is not running".to_string(), enable_command(init::init(), "nix-daemon")));
+        }
+        if which("home-manager").is_none() {
+            findings.push(("Home Manager is not installed".to_string(), "ass --only setup_home_manager".to_string()));
+        }
+    } else if Path::new("/nix").exists() {
+        findings.push((
+            "/nix exists but nix isn't on PATH (installed without logging in again?)".to_string(),
+            "log out and back in, or run: . /nix/var/nix/profiles/default/etc/profile.d/nix-daemon.sh".to_string(),
+        ));
+    }
+    
+    // Dotfiles
+    let dotfiles = Path::new(&paths.dotfiles()).to_path_buf();
+    let mut broken = Vec::new();
+    find_broken_dotfile_links(Path::new(&paths.home), &dotfiles, false, &mut broken);
+    find_broken_dotfile_links(Path::new(&paths.home(".config")), &dotfiles, true, &mut broken);
+    if !broken.is_empty() {
+        findings.push((format!("{} broken symlinks into ~/dotfiles", broken.len()), "ass --only clean_broken_stow_links".to_string()));
+    }
+    
+    // Earlier runs
+    if let Some(step) = checkpoint::load().and_then(|checkpoint| checkpoint.running.first().cloned()) {
+        findings.push((format!("The last setup run stopped at {}", step), "ass --resume".to_string()));
+    }
+    if reboot::read_paused().is_some() {
+        findings.push(("The setup is paused until a reboot".to_string(), "sudo systemctl reboot".to_string()));
+    }
+    let mut failed = output_lines("systemctl", &["--failed", "--plain", "--no-legend"]);
+    failed.extend(output_lines("systemctl", &["--user", "--failed", "--plain", "--no-legend"]));
+    for line in &failed {
+        let unit = line.split_whitespace().next().unwrap_or(line);
+        findings.push((format!("{} failed", unit), format!("journalctl -b -u {}", unit)));
+    }
+    
+    findings
+}
+
+// .pacnew/.pacsave files under /etc (some directories are root-only)
+const PACNEW_FIND: [&str; 9] = ["find", "/etc", "(", "-name", "*.pacnew", "-o", "-name", "*.pacsave", ")"];
+
+fn pacnew_files() -> Vec<String> {
+    let mut files = output_lines("sudo", &PACNEW_FIND);
+    files.sort();
+    files
+}
+
+// pacnew_files() for `ass doctor`, which doesn't ask for a password: None
+// when sudo would need one
+fn pacnew_files_unprompted() -> Option<Vec<String>> {
+    let output = Command::new("sudo").arg("-n").args(PACNEW_FIND).stderr(std::process::Stdio::null()).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let mut files: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect();
+    files.sort();
+    Some(files)
+}
+
+fn read_root_file(path: &str) -> Option<String> {
+    read_root_bytes(path).map(|content| String::from_utf8_lossy(&content).to_string())
+}
+
+fn read_root_bytes(path: &str) -> Option<Vec<u8>> {
+    std::fs::read(path).ok().or_else(|| {
+        let output = Command::new("sudo").args(["cat", path]).stderr(std::process::Stdio::null()).output().ok()?;
+        output.status.success().then_some(output.stdout)
+    })
+}
+
+// Whether a file exists, asking sudo when a directory on the way can't be
+// searched (/etc/sudoers.d is 750)
+fn root_file_exists(path: &str) -> bool {
+    match std::fs::symlink_metadata(path) {
+        Ok(_) => true,
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            Command::new("sudo").args(["test", "-e", path]).status().is_ok_and(|status| status.success())
+        }
+        Err(_) => false,
+    }
+}
+
+// Octal mode of a file, through sudo when it can't be looked at otherwise
+fn root_file_mode(path: &str) -> Option<String> {
+    if let Ok(meta) = std::fs::metadata(path) {
+        return Some(format!("{:o}", std::os::unix::fs::PermissionsExt::mode(&meta.permissions()) & 0o7777));
+    }
+    let output = Command::new("sudo").args(["stat", "-c", "%a", path]).stderr(std::process::Stdio::null()).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// Differences only in comments, blank lines or trailing whitespace
+fn trivially_different(current: &str, packaged: &str) -> bool {
+    let significant = |content: &str| -> Vec<String> {
+        content
+            .lines()
+            .map(|line| line.trim_end().to_string())
+            .filter(|line| !line.is_empty() && !line.trim_start().starts_with('#'))
+            .collect()
+    };
+    significant(current) == significant(packaged)
+}
+
+// Let the user merge the two versions with $DIFFPROG (vim -d by default) on
+// copies in the work dir. Returns the merged copy if they want it installed.
+fn merge_pacnew(config: &Config, live: &str, pacnew: &str) -> Option<String> {
+    let name = Path::new(live).file_name()?.to_string_lossy().to_string();
+    let merged = config.work_dir.path(&format!("{}.merged", name));
+    let packaged = config.work_dir.path(&format!("{}.pacnew", name));
+    // .pacnew files of /etc/shadow and the like are as secret as the originals
+    workdir::write_private(&merged, read_root_file(live)?).ok()?;
+    workdir::write_private(&packaged, read_root_file(pacnew)?).ok()?;
+    
+    let diffprog = env::var("DIFFPROG").unwrap_or_else(|_| "vim -d".to_string());
+    let mut words = diffprog.split_whitespace();
+    let status = Command::new(words.next()?).args(words).args([&merged, &packaged]).status();
+    if !status.map(|s| s.success()).unwrap_or(false) {
+        esay!("  {} exited with an error, leaving {} alone", diffprog, live);
+        return None;
+    }
+    
+    confirm(&format!("  {}", tr!("Install the merged version of {}?", live))).then_some(merged)
+}
+
+// Walk through the .pacnew/.pacsave files left by upgrades: trivial ones are
+// handled automatically, the rest are shown as a diff with a choice each.
+fn pacdiff(config: &Config) {
+    let files = pacnew_files();
+    if files.is_empty() {
+        say!("✓ No .pacnew or .pacsave files found");
+        return;
+    }
+    
+    say!("Found {} files awaiting merge", files.len());
+    
+    for file in &files {
+        let (live, is_pacnew) = match file.strip_suffix(".pacnew") {
+            Some(live) => (live, true),
+            None => (file.trim_end_matches(".pacsave"), false),
+        };
+        say!("\n{}", file);
+        
+        let current = read_root_file(live);
+        let contents = read_root_file(file).unwrap_or_default();
+        
+        if is_pacnew && current.as_deref().is_some_and(|current| trivially_different(current, &contents)) {
+            say!("  Only comments or whitespace differ, keeping {}", live);
+            run_or_warn(config, Cmd::sudo("rm").arg(file), &format!("Failed to remove {}", file));
+            continue;
+        }
+        
+        if current.is_some() {
+            let _ = Command::new("sudo").args(["diff", "-u", "--color=auto", live, file]).status();
+        } else if config.verbose {
+            say!("  {} doesn't exist anymore", live);
+        }
+        
+        if config.ci {
+            say!("  ⏭ {}", tr!("Needs a decision, skipping (non-interactive)"));
+            continue;
+        }
+        
+        let choices = if is_pacnew {
+            tr!("[r]eplace with .pacnew, [k]eep current, [m]erge, [s]kip")
+        } else if current.is_none() {
+            tr!("[r]estore, [d]elete .pacsave, [s]kip")
+        } else {
+            tr!("[d]elete .pacsave, [s]kip")
+        };
+        let backup = format!("{}.bak", live);
+        
+        match prompt(&format!("  {}", choices), "s").to_lowercase().as_str() {
+            "r" if is_pacnew => {
+                if run_or_warn(config, Cmd::sudo("cp").args(["-a", live, &backup]), &format!("Failed to back up {}", live)) {
+                    run_or_warn(config, Cmd::sudo("mv").args([file, live]), &format!("Failed to replace {}", live));
+                }
+            }
+            "r" if current.is_none() => {
+                run_or_warn(config, Cmd::sudo("mv").args([file, live]), &format!("Failed to restore {}", live));
+            }
+            "k" if is_pacnew => {
+                run_or_warn(config, Cmd::sudo("rm").arg(file), &format!("Failed to remove {}", file));
+            }
+            "d" if !is_pacnew => {
+                run_or_warn(config, Cmd::sudo("rm").arg(file), &format!("Failed to remove {}", file));
+            }
+            "m" if is_pacnew && config.dry_run => {
+                say!("[DRY RUN] Would open $DIFFPROG on copies of {} and {}", live, file);
+            }
+            "m" if is_pacnew => {
+                // cp onto the existing file keeps its owner and mode
+                if let Some(merged) = merge_pacnew(config, live, file)
+                    && run_or_warn(config, Cmd::sudo("cp").args(["-a", live, &backup]), &format!("Failed to back up {}", live))
+                    && run_or_warn(config, Cmd::sudo("cp").args([&merged, live]), &format!("Failed to install {}", live))
+                {
+                    run_or_warn(config, Cmd::sudo("rm").arg(file), &format!("Failed to remove {}", file));
+                }
+            }
+            _ => say!("  ⏭ Skipped"),
+        }
+    }
+}
+
+// Copy a file into a root-owned location, showing a diff and keeping a .bak of
+// what was there before. Returns whether anything changed.
+fn install_system_file(config: &Config, source: &str, target: &str, mode: &str) -> bool {
+    let new_content = std::fs::read(source).unwrap_or_else(|e| {
+        esay!("Failed to read {}: {}", source, e);
+        std::process::exit(1);
+    });
+    
+    let target_exists = root_file_exists(target);
+    if target_exists && read_root_bytes(target).is_some_and(|current| current == new_content) {
+        if config.verbose {
+            say!("  ✓ {} is up to date", target);
+        }
+        return false;
+    }
+    
+    if target_exists {
+        say!("  Changes to {}:", target);
+        let _ = Command::new("diff")
+            .args(["-u", "--color=auto", target, source])
+            .status();
+        
+        let backup = format!("{}.bak", target);
+        journal::file_changing(target);
+        run_or_exit(config, Cmd::sudo("cp").args(["-a", target, &backup]), &format!("Failed to back up {}", target));
+        if config.verbose {
+            say!("  Backed up old file to {}", backup);
+        }
+    } else {
+        journal::file_changing(target);
+    }
+    
+    run_or_exit(
+        config,
+        Cmd::sudo("install")
+            .args(["-D", "-m", mode, source, target]),
+        &format!("Failed to install {}", target),
+    );
+    
+    say!("  ✓ Installed {}", target);
+    true
+}
+
+// Same as install_system_file, for content generated by the tool
+fn install_system_content(config: &Config, content: &str, target: &str, mode: &str) -> bool {
+    let file_name = Path::new(target).file_name().unwrap().to_string_lossy();
+    let source = config.work_dir.path(&file_name);
+    std::fs::write(&source, content).expect("Failed to write temporary file");
+    
+    install_system_file(config, &source, target, mode)
+}
+
+// Every file under dir, in subdirectories too
+fn collect_files(dir: &Path, files: &mut Vec<std::path::PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+// Copy each [system_dotfiles] package from ~/dotfiles into its target dir
+// (greetd, keyd, pacman hooks...). Copies rather than stow symlinks, since
+// services reading /etc usually can't follow links into a 700 home.
+fn deploy_system_dotfiles(config: &Config) {
+    if config.file.system_dotfiles.is_empty() {
+        return;
+    }
+    
+    say!("Deploying system dotfiles...");
+    let dotfiles = paths::get().dotfiles();
+    
+    if config.dry_run {
+        for (package, target) in &config.file.system_dotfiles {
+            plan::add(
+                plan::write(target, format!("Copy ~/dotfiles/{}/** to {}/ (diff, .bak backup, sudo install)", package, target))
+                    .effect(format!("changes the files under {} that differ from ~/dotfiles/{}", target, package)),
+            );
+        }
+        return;
+    }
+    
+    for (package, target_dir) in &config.file.system_dotfiles {
+        let package_dir = Path::new(&dotfiles).join(package);
+        if !package_dir.is_dir() {
+            config.warnings.add(WarningKind::Skipped, format!("System dotfiles package {} not found in ~/dotfiles", package));
+            continue;
+        }
+        
+        let mut files = Vec::new();
+        collect_files(&package_dir, &mut files);
+        files.sort();
+        
+        for file in &files {
+            let relative = file.strip_prefix(&package_dir).unwrap();
+            let target = Path::new(target_dir).join(relative).to_string_lossy().to_string();
+            let mode = match std::fs::metadata(file) {
+                Ok(meta) if std::os::unix::fs::PermissionsExt::mode(&meta.permissions()) & 0o111 != 0 => "755",
+                _ => "644",
+            };
+            
+            // The journal keeps what was there before for undo-system-dotfiles
+            install_system_file(config, &file.to_string_lossy(), &target, mode);
+        }
+    }
+    
+    say!("✓ System dotfiles deployed!");
+}
+
+// `ass undo`: take back what the last run changed, newest first, with the
+// packages it installed removed last in one transaction
+fn undo(config: &Config) {
+    let Some(run) = journal::last_run() else {
+        say!("Nothing to undo, no changes recorded in {}", journal::path());
+        return;
+    };
+    
+    let packages: Vec<String> = run
+        .entries
+        .iter()
+        .filter_map(|entry| match entry {
+            journal::Entry::Package(name) if pkg::backend().is_installed(name) => Some(name.clone()),
+            _ => None,
+        })
+        .collect();
+    
+    say!("The last run changed:");
+    for entry in run.entries.iter().rev() {
+        match entry {
+            journal::Entry::Stow { package, .. } => say!("  - Stowed {}", package),
+            journal::Entry::Clone(path) => say!("  - Cloned {}", path),
+            journal::Entry::File { path, backup: Some(_) } => say!("  - Changed {}", path),
+            journal::Entry::File { path, backup: None } => say!("  - Created {}", path),
+            journal::Entry::Package(_) => {}
+        }
+    }
+    if !packages.is_empty() {
+        say!("  - Installed {} packages: {}", packages.len(), packages.join(" "));
+    }
+    
+    if config.dry_run {
+        say!("[DRY RUN] Would undo all of the above");
+        return;
+    }
+    if !confirm(tr!("Undo these changes?")) {
+        return;
+    }
+    
+    let mut failed = false;
+    for entry in run.entries.iter().rev() {
+        let undone = match entry {
+            journal::Entry::Stow { dir, package, target } => {
+                let target = target.iter().flat_map(|target| ["--target", target.as_str()]);
+                run_or_warn(config, Cmd::new("stow").args(target).args(["-D", package]).current_dir(dir), &format!("Failed to unstow {}", package))
+            }
+            journal::Entry::Clone(path) => {
+                // Work done in the clone since then isn't ours to throw away
+                if !Path::new(path).exists() {
+                    true
+                } else if git_output(path, &["status", "--porcelain"]).is_none_or(|changes| !changes.is_empty()) {
+                    config.warnings.add(WarningKind::Skipped, format!("{} has local changes, not removing it", path));
+                    true
+                } else {
+                    run_or_warn(config, Cmd::new("rm").args(["-rf", path]), &format!("Failed to remove {}", path))
+                }
+            }
+            journal::Entry::File { path, backup: Some(backup) } => {
+                // cp onto the existing file keeps its owner and mode
+                run_or_warn(config, Cmd::sudo("cp").args([backup, path]), &format!("Failed to restore {}", path))
+            }
+            journal::Entry::File { path, backup: None } => {
+                run_or_warn(config, Cmd::sudo("rm").args(["-f", path]), &format!("Failed to remove {}", path))
+            }
+            journal::Entry::Package(_) => true,
+        };
+        failed |= !undone;
+    }
+    
+    if !packages.is_empty() {
+        failed |= !run_or_warn(
+            config,
+            package_manager(config).remove(&packages, false),
+            "Failed to remove the installed packages",
+        );
+    }
+    
+    if failed {
+        esay!("Some changes could not be undone, keeping them in {}", journal::path());
+        std::process::exit(1);
+    }
+    journal::forget(&run.id);
+    say!("✓ Last run undone!");
+}
+
+// Where deploy_system_dotfiles puts the files of the [system_dotfiles]
+// packages
+fn system_dotfiles_targets(config: &Config) -> BTreeSet<String> {
+    let dotfiles = paths::get().dotfiles();
+    let mut targets = BTreeSet::new();
+    for (package, target_dir) in &config.file.system_dotfiles {
+        let package_dir = Path::new(&dotfiles).join(package);
+        let mut files = Vec::new();
+        collect_files(&package_dir, &mut files);
+        for file in &files {
+            let relative = file.strip_prefix(&package_dir).unwrap();
+            targets.insert(Path::new(target_dir).join(relative).to_string_lossy().to_string());
+        }
+    }
+    targets
+}
+
+// `ass undo-system-dotfiles`: the journal's changes to the files of
+// [system_dotfiles], whichever runs made them, taken back to what was there
+// before the first one, newest first
+fn undo_system_dotfiles(config: &Config) {
+    let targets = system_dotfiles_targets(config);
+    let files: Vec<(String, Option<String>)> = journal::files().into_iter().filter(|(path, _)| targets.contains(path)).collect();
+    if files.is_empty() {
+        say!("Nothing to undo, no system dotfiles recorded in {}", journal::path());
+        return;
+    }
+    
+    say!("Restoring system files replaced by system dotfiles...");
+    let mut restored = BTreeSet::new();
+    
+    for (path, backup) in files.iter().rev() {
+        let (description, cmd) = match backup {
+            // cp onto the existing file keeps its owner and mode
+            Some(backup) => (format!("Restore {}", path), Cmd::sudo("cp").args([backup, path])),
+            None => (format!("Remove {}", path), Cmd::sudo("rm").args(["-f", path])),
+        };
+        
+        if config.dry_run {
+            say!("[DRY RUN] Would {}", description.to_lowercase());
+            continue;
+        }
+        
+        if run_command(config, cmd).is_ok() {
+            say!("  ✓ {}", description);
+            restored.insert(path.clone());
+        } else {
+            esay!("  ✗ {} failed", description);
+        }
+    }
+    
+    if config.dry_run {
+        return;
+    }
+    // `ass undo` of a later run mustn't put our copies back
+    journal::forget_files(&restored);
+    if restored.len() < files.len() {
+        esay!("Some files could not be restored, keeping them in {}", journal::path());
+        std::process::exit(1);
+    }
+    
+    say!("✓ System dotfiles undone!");
+}
+
+// Desired content of a [files] entry given what's on disk now
+// content and line have {{ fact }} filled in
+fn asserted_content(file: &config::FileAssertion, current: &str) -> String {
+    if let Some(content) = &file.content {
+        return facts::render(content);
+    }
+    
+    let line = facts::render(file.line.as_deref().unwrap_or_default());
+    let line = line.as_str();
+    if current.lines().any(|l| l == line) {
+        return current.to_string();
+    }
+    
+    let mut replaced = false;
+    let mut lines: Vec<&str> = Vec::new();
+    for l in current.lines() {
+        if !replaced && file.matches.as_deref().is_some_and(|prefix| l.starts_with(prefix)) {
+            lines.push(line);
+            replaced = true;
+        } else {
+            lines.push(l);
+        }
+    }
+    if !replaced {
+        lines.push(line);
+    }
+    
+    let mut updated = lines.join("\n");
+    updated.push('\n');
+    updated
+}
+
+// Generic step for the [files] long tail: small drops and line-in-file
+// assertions, with the same diff/backup treatment as the dedicated steps
+fn apply_file_assertions(config: &Config) {
+    if config.file.files.is_empty() {
+        return;
+    }
+    
+    say!("Applying [files] assertions...");
+    let paths = paths::get();
+    
+    for (name, file) in &config.file.files {
+        if let Some(condition) = &file.when
+            && !facts::matches(condition)
+        {
+            if config.dry_run {
+                plan::add(plan::check(format!("{}: when = \"{}\" for {}", name, condition, file.path)).effect("nothing, it doesn't hold here"));
+            } else if config.verbose {
+                say!("  ⏭ {} (when = \"{}\" doesn't hold here)", file.path, condition);
+            }
+            continue;
+        }
+        let path = match file.path.strip_prefix("~/") {
+            Some(rest) => paths.home(rest),
+            None => file.path.clone(),
+        };
+        let sudo = file.sudo.unwrap_or(!path.starts_with(&paths.home("")));
+        let exists = if sudo { root_file_exists(&path) } else { Path::new(&path).exists() };
+        // Keep the current mode of existing files unless one is asked for
+        let mode = match &file.mode {
+            Some(mode) => mode.clone(),
+            None if exists && sudo => root_file_mode(&path).unwrap_or_else(|| "644".to_string()),
+            None => std::fs::metadata(&path)
+                .map(|meta| format!("{:o}", std::os::unix::fs::PermissionsExt::mode(&meta.permissions()) & 0o7777))
+                .unwrap_or_else(|_| "644".to_string()),
+        };
+        let mode = mode.as_str();
+        
+        // A file that's there but can't be read is not an empty one, asserting
+        // a line in it would replace everything else
+        let current = match (exists, sudo) {
+            (false, _) => Some(String::new()),
+            (true, true) => read_root_file(&path),
+            (true, false) => std::fs::read_to_string(&path).ok(),
+        };
+        
+        if config.dry_run {
+            let action = match &file.line {
+                Some(line) => format!("ensure line {:?}", facts::render(line)),
+                None => "write content".to_string(),
+            };
+            let owner = file.owner.as_ref().map(|o| format!(", owner {}", o)).unwrap_or_default();
+            let mut action = plan::write(
+                &path,
+                format!("{}: {} in {} (mode {}{}{})", name, action, path, mode, owner, if sudo { ", sudo" } else { "" }),
+            );
+            match &current {
+                Some(current) if exists && asserted_content(file, current) == *current => action = action.effect("nothing, already in place"),
+                None => action = action.effect(format!("nothing, {} can't be read", path)),
+                _ => {}
+            }
+            plan::add(action);
+            continue;
+        }
+        
+        let Some(current) = current else {
+            config.warnings.add(WarningKind::Failure, format!("Failed to read {} ([files.{}]), left it alone", path, name));
+            continue;
+        };
+        let desired = asserted_content(file, &current);
+        
+        if sudo {
+            install_system_content(config, &desired, &path, mode);
+        } else if exists && current == desired {
+            if config.verbose {
+                say!("  ✓ {} is up to date", path);
+            }
+        } else {
+            let staged = config.work_dir.path(&format!("files-{}", name));
+            std::fs::write(&staged, &desired).expect("Failed to write temporary file");
+            
+            if exists {
+                say!("  Changes to {}:", path);
+                let _ = Command::new("diff").args(["-u", "--color=auto", &path, &staged]).status();
+                // The first .bak is the file as it was before ass touched it
+                let backup = format!("{}.bak", path);
+                if !Path::new(&backup).exists()
+                    && let Err(e) = std::fs::copy(&path, &backup)
+                {
+                    config.warnings.add(WarningKind::Failure, format!("Failed to back up {} to {}: {}", path, backup, e));
+                }
+            }
+            journal::file_changing(&path);
+            
+            let install = Cmd::new("install").args(["-D", "-m", mode, &staged, &path]);
+            if !run_or_warn(config, install, &format!("Failed to write {} ([files.{}])", path, name)) {
+                continue;
+            }
+            say!("  ✓ Installed {}", path);
+        }
+        
+        if let Some(owner) = &file.owner {
+            let chown = if sudo { Cmd::sudo("chown") } else { Cmd::new("chown") };
+            run_or_warn(config, chown.args([owner, &path]), &format!("Failed to chown {} to {}", path, owner));
+        }
+    
+    }
+    
+    say!("✓ File assertions applied!");
+}
+
+// Install udev rules shipped in the dotfiles repo's udev/ directory
+fn deploy_udev_rules(config: &Config) {
+    let udev_dir = format!("{}/udev", paths::get().dotfiles());
+    
+    if config.dry_run {
+        say!("Deploying udev rules...");
+        plan::add(
+            plan::write("/etc/udev/rules.d", "For each ~/dotfiles/udev/*.rules: diff against /etc/udev/rules.d, back up and install changed files")
+                .effect("changes the rules in /etc/udev/rules.d that differ from ~/dotfiles/udev"),
+        );
+        plan::add(plan::run(Cmd::sudo("udevadm").args(["control", "--reload-rules"])));
+        plan::add(plan::run(Cmd::sudo("udevadm").arg("trigger")));
+        return;
+    }
+    
+    let mut rules: Vec<std::path::PathBuf> = match std::fs::read_dir(&udev_dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("rules"))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    rules.sort();
+    
+    if rules.is_empty() {
+        if config.verbose {
+            say!("⏭ No udev rules in {}, skipping", udev_dir);
+        }
+        return;
+    }
+    
+    say!("Deploying udev rules...");
+    
+    let mut changed = false;
+    for rule in &rules {
+        let file_name = rule.file_name().unwrap().to_string_lossy();
+        let target = format!("/etc/udev/rules.d/{}", file_name);
+        changed |= install_system_file(config, &rule.to_string_lossy(), &target, "644");
+    }
+    
+    if !changed {
+        say!("✓ udev rules already up to date");
+        return;
+    }
+    
+    if config.verbose {
+        say!("Reloading udev rules...");
+    }
+    run_or_exit(config, Cmd::sudo("udevadm").args(["control", "--reload-rules"]), "Failed to reload udev rules");
+    
+    run_or_exit(config, Cmd::sudo("udevadm").args(["trigger"]), "Failed to trigger udev");
+    
+    say!("✓ udev rules deployed!");
+}
+
+// kanata doesn't ship a system unit, so we provide one
+const KANATA_SERVICE: &str = "[Unit]
+Description=Kanata keyboard remapper
+Documentation=https://github.com/jtroo/kanata
+
+[Service]
+Type=simple
+ExecStart=/usr/bin/kanata --cfg /etc/kanata/config.kbd
+Restart=on-failure
+
+[Install]
+WantedBy=multi-user.target
+";
+
+// Install keyd or kanata, deploy its config from the dotfiles and enable it
+fn setup_keyboard_remapper(config: &Config) {
+    let Some(remapper) = config.file.keyboard.remapper.as_deref() else {
+        if config.verbose {
+            say!("⏭ No keyboard remapper configured, skipping");
+        }
+        return;
+    };
+    
+    let (extension, target_dir) = match remapper {
+        "keyd" => ("conf", "/etc/keyd"),
+        "kanata" => ("kbd", "/etc/kanata"),
+        _ => unreachable!("validated when loading the config"),
+    };
+    
+    say!("Setting up {}...", remapper);
+    
+    let config_dir = config.file.keyboard.config_dir.as_deref().unwrap_or(remapper);
+    let source_dir = format!("{}/{}", paths::get().dotfiles(), config_dir);
+    
+    if config.dry_run {
+        plan::add(planned_aur_install(config, &[remapper]));
+        plan::add(plan::write(target_dir, format!("Install ~/dotfiles/{}/*.{} into {} (diff + backup)", config_dir, extension, target_dir)));
+        if remapper == "kanata" {
+            plan::add(plan::write("/etc/systemd/system/kanata.service", "Write /etc/systemd/system/kanata.service"));
+        }
+        plan::add(plan::enable(format!("sudo systemctl enable --now {}", remapper), remapper));
+        plan::add(plan::check(format!("Verify {} picked up a keyboard", remapper)));
+        return;
+    }
+    
+    let mut config_files: Vec<std::path::PathBuf> = std::fs::read_dir(&source_dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|e| e.path())
+                .filter(|p| p.extension().and_then(|e| e.to_str()) == Some(extension))
+                .collect()
+        })
+        .unwrap_or_default();
+    config_files.sort();
+    
+    if config_files.is_empty() {
+        report::fatal(format!("No *.{} files found in {}", extension, source_dir));
+    }
+    
+    if config.verbose {
+        say!("Installing {}...", remapper);
+    }
+    install_aur_packages(config, &[remapper], &format!("Failed to install {}", remapper));
+    
+    let mut changed = false;
+    for file in &config_files {
+        let target = format!("{}/{}", target_dir, file.file_name().unwrap().to_string_lossy());
+        changed |= install_system_file(config, &file.to_string_lossy(), &target, "644");
+    }
+    
+    if remapper == "kanata" {
+        changed |= install_system_content(config, KANATA_SERVICE, "/etc/systemd/system/kanata.service", "644");
+        
+        run_or_warn(config, Cmd::sudo("systemctl").arg("daemon-reload"), "Failed to reload systemd");
+    }
+    
+    if config.verbose {
+        say!("Enabling {} service...", remapper);
+    }
+    run_or_exit(
+        config,
+        Cmd::sudo("systemctl")
+            .args(["enable", "--now", remapper]),
+        &format!("Failed to enable {} service", remapper),
+    );
+    
+    // Pick up config changes if the service was already running
+    if changed {
+        run_or_warn(config, Cmd::sudo("systemctl").args(["restart", remapper]), &format!("Failed to restart {}", remapper));
+        std::thread::sleep(std::time::Duration::from_secs(2));
+    }
+    
+    // keyd logs "DEVICE: match" for every keyboard it grabs
+    let output = Command::new("journalctl")
+        .args(["-u", remapper, "-b", "--no-pager", "-o", "cat"])
+        .output()
+        .expect("Failed to execute journalctl");
+    let log = String::from_utf8_lossy(&output.stdout).to_lowercase();
+    
+    let active = Command::new("systemctl")
+        .args(["is-active", "--quiet", remapper])
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false);
+    
+    let grabbed_keyboard = match remapper {
+        "keyd" => log.contains("device: match"),
+        _ => active && !log.contains("no keyboard devices"),
+    };
+    
+    if !active {
+        config.warnings.add(WarningKind::Failure, format!("{} service is not running, check: journalctl -u {}", remapper, remapper));
+    } else if !grabbed_keyboard {
+        config.warnings.add(WarningKind::Failure, format!("{} is running but didn't pick up a keyboard, check: journalctl -u {}", remapper, remapper));
+    } else {
+        say!("✓ {} is running and remapping your keyboard!", remapper);
+    }
+}
+
+// Guest agent / clipboard / display resize helpers for the detected hypervisor
+fn install_guest_tools(config: &Config) {
+    let (packages, services) = config.context.guest_tools();
+    if packages.is_empty() {
+        if config.verbose && matches!(config.context, RunContext::Vm(_)) {
+            say!("⏭ No guest tools known for {}", config.context.describe());
+        }
+        return;
+    }
+    
+    say!("Installing guest tools for {}...", config.context.describe());
+    
+    let pending = pending_packages(config, packages);
+    let native = pkg::backend().native_names(&pending);
+    if !native.is_empty()
+        && !run_or_warn(
+            config,
+            package_manager(config).install(&native),
+            &format!("Failed to install guest tools ({})", pending.join(", ")),
+        )
+    {
+        return;
+    }
+    
+    for service in services {
+        run_or_warn(
+            config,
+            Cmd::sudo("systemctl").args(["enable", "--now", service]),
+            &format!("Failed to enable {}", service),
+        );
+    }
+    
+    // Their kernel modules (shared folders, clipboard, display resizing) load at boot
+    if !pending.is_empty() {
+        config.reboots.add(reboot::Need::Recommended, format!("{} load kernel modules at boot", pending.join(", ")));
+    }
+    
+    if !config.dry_run {
+        say!("✓ Guest tools installed!");
+    }
+}
+
+// What the gaming profile adds on top of a desktop, the lib32 packages come
+// from [multilib]
+const GAMING_PACKAGES: [&str; 5] = ["steam", "gamemode", "lib32-gamemode", "mangohud", "lib32-mangohud"];
+
+// 32-bit Vulkan driver for the GPU, Steam's Proton needs it
+fn lib32_vulkan_driver() -> Option<&'static str> {
+    let gpus = output_lines("lspci", &[]).into_iter().filter(|line| line.contains("VGA") || line.contains("3D controller"));
+    let gpus: Vec<String> = gpus.map(|line| line.to_lowercase()).collect();
+    if gpus.iter().any(|gpu| gpu.contains("nvidia")) {
+        Some("lib32-nvidia-utils")
+    } else if gpus.iter().any(|gpu| gpu.contains("amd") || gpu.contains("ati ")) {
+        Some("lib32-vulkan-radeon")
+    } else if gpus.iter().any(|gpu| gpu.contains("intel")) {
+        Some("lib32-vulkan-intel")
+    } else {
+        None
+    }
+}
+
+// pacman.conf with the commented out [multilib] section enabled, or added
+fn enable_multilib(pacman_conf: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut in_multilib = false;
+    for line in pacman_conf.lines() {
+        let trimmed = line.trim();
+        if trimmed == "#[multilib]" {
+            in_multilib = true;
+            lines.push("[multilib]".to_string());
+            continue;
+        }
+        if in_multilib && let Some(include) = trimmed.strip_prefix('#').filter(|rest| rest.trim_start().starts_with("Include")) {
+            lines.push(include.trim_start().to_string());
+            in_multilib = false;
+            continue;
+        }
+        in_multilib = false;
+        lines.push(line.to_string());
+    }
+    if !lines.iter().any(|line| line.trim() == "[multilib]") {
+        lines.extend(["".to_string(), "[multilib]".to_string(), "Include = /etc/pacman.d/mirrorlist".to_string()]);
+    }
+    lines.join("\n") + "\n"
+}
+
+// gaming profile: [multilib], Steam, GameMode and MangoHud
+fn setup_gaming(config: &Config) {
+    say!("Setting up gaming...");
+    
+    let pacman_conf = std::fs::read_to_string("/etc/pacman.conf").unwrap_or_default();
+    if !pacman_conf.lines().any(|line| line.trim() == "[multilib]") {
+        if config.dry_run {
+            plan::add(plan::write("/etc/pacman.conf", "Enable [multilib] in /etc/pacman.conf"));
+        } else {
+            install_system_content(config, &enable_multilib(&pacman_conf), "/etc/pacman.conf", "644");
+        }
+        run_or_exit(config, package_manager(config).refresh(), "Failed to sync the [multilib] database");
+    }
+    
+    let mut packages = GAMING_PACKAGES.to_vec();
+    packages.extend(lib32_vulkan_driver());
+    install_packages(config, &packages, "Failed to install the gaming packages");
+    
+    // GameMode's daemon only renices processes of users in its group
+    let user = env::var("USER").expect("USER environment variable not set");
+    run_or_warn(config, Cmd::sudo("usermod").args(["-aG", "gamemode", &user]), &format!("Failed to add {} to the gamemode group", user));
+    
+    if !config.dry_run {
+        say!("✓ Gaming setup complete!");
+    }
+}
+
+// Check whether a package (by its Arch name) is installed
+fn package_installed(package: &str) -> bool {
+    let backend = pkg::backend();
+    backend.native_name(package).is_some_and(|native| backend.is_installed(&native))
+}
+
+// (compositor package, session file, portal backend package)
+const WAYLAND_COMPOSITORS: [(&str, &str, &str); 5] = [
+    ("hyprland", "/usr/share/wayland-sessions/hyprland.desktop", "xdg-desktop-portal-hyprland"),
+    ("sway", "/usr/share/wayland-sessions/sway.desktop", "xdg-desktop-portal-wlr"),
+    ("river", "/usr/share/wayland-sessions/river.desktop", "xdg-desktop-portal-wlr"),
+    ("niri", "/usr/share/wayland-sessions/niri.desktop", "xdg-desktop-portal-gnome"),
+    ("wayfire", "/usr/share/wayland-sessions/wayfire.desktop", "xdg-desktop-portal-wlr"),
+];
+
+// Packages providing a polkit authentication agent
+const POLKIT_AGENTS: [&str; 7] = [
+    "hyprpolkitagent",
+    "polkit-gnome",
+    "polkit-kde-agent",
+    "lxqt-policykit",
+    "mate-polkit",
+    "xfce-polkit",
+    "soteria",
+];
+
+// Which graphical sessions this machine runs: wayland, x11 or both. The
+// config says, or the session ass runs in, or the kinds of session files
+// installed. With none of those (a fresh install) it's both.
+fn session_type(config: &Config) -> String {
+    if let Some(session_type) = &config.file.session_type {
+        return session_type.clone();
+    }
+    if let Ok(current) = env::var("XDG_SESSION_TYPE")
+        && (current == "wayland" || current == "x11")
+    {
+        return current;
+    }
+    let has_sessions = |dir: &str| std::fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_some());
+    match (has_sessions("/usr/share/wayland-sessions"), has_sessions("/usr/share/xsessions")) {
+        (true, false) => "wayland".to_string(),
+        (false, true) => "x11".to_string(),
+        _ => "both".to_string(),
+    }
+}
+
+// Check that installed Wayland compositors have what they need for a working first login
+fn verify_wayland_session(config: &Config) {
+    say!("Verifying Wayland session setup...");
+    
+    if config.dry_run {
+        plan::add(plan::check("Check each installed compositor (hyprland, sway, river, niri, wayfire) for:").details([
+            "1. A session file in /usr/share/wayland-sessions",
+            "2. xdg-desktop-portal and the matching portal backend",
+            "3. A polkit authentication agent",
+            "4. qt5-wayland and qt6-wayland",
+        ]));
+        return;
+    }
+    
+    let compositors: Vec<&(&str, &str, &str)> = WAYLAND_COMPOSITORS
+        .iter()
+        .filter(|(package, _, _)| package_installed(package))
+        .collect();
+    
+    if compositors.is_empty() {
+        if config.verbose {
+            say!("⏭ No Wayland compositor installed, nothing to verify");
+        }
+        return;
+    }
+    
+    let mut problems = Vec::new();
+    
+    for (compositor, session_file, portal) in &compositors {
+        if config.verbose {
+            say!("Checking {}...", compositor);
+        }
+        if !Path::new(session_file).exists() {
+            problems.push(format!("{}: session file {} is missing (display managers won't list it)", compositor, session_file));
+        }
+        if !package_installed(portal) {
+            problems.push(format!("{}: portal backend {} is not installed (screen sharing/file pickers will fail)", compositor, portal));
+        }
+    }
+    
+    if !package_installed("xdg-desktop-portal") {
+        problems.push("xdg-desktop-portal is not installed".to_string());
+    }
+    
+    if !POLKIT_AGENTS.iter().any(|agent| package_installed(agent)) {
+        problems.push(format!("No polkit agent installed (privilege prompts will silently fail), e.g. {}", POLKIT_AGENTS[0]));
+    }
+    
+    for qt in ["qt5-wayland", "qt6-wayland"] {
+        if !package_installed(qt) {
+            problems.push(format!("{} is not installed (Qt apps will fall back to XWayland or fail)", qt));
+        }
+    }
+    
+    if problems.is_empty() {
+        say!("✓ Wayland session looks ready!");
+        return;
+    }
+    
+    for problem in problems {
+        config.warnings.add(WarningKind::Failure, format!("Wayland session incomplete: {}", problem));
+    }
+}
+
+// Portal backend to use for a compositor package
+fn compositor_portal_desktop(package: &str) -> &'static str {
+    match package {
+        "hyprland" => "hyprland",
+        "niri" => "gnome",
+        _ => "wlr",
+    }
+}
+
+// Backend packages and portals.conf preference for a [portal] desktop.
+// Backends without a file chooser fall back to the gtk one.
+fn portal_backend(desktop: &str) -> Option<(&'static [&'static str], &'static str)> {
+    match desktop {
+        "hyprland" => Some((&["xdg-desktop-portal-hyprland", "xdg-desktop-portal-gtk"], "hyprland;gtk")),
+        "wlr" => Some((&["xdg-desktop-portal-wlr", "xdg-desktop-portal-gtk"], "wlr;gtk")),
+        "gnome" => Some((&["xdg-desktop-portal-gnome", "xdg-desktop-portal-gtk"], "gnome;gtk")),
+        "kde" => Some((&["xdg-desktop-portal-kde"], "kde")),
+        "gtk" => Some((&["xdg-desktop-portal-gtk"], "gtk")),
+        _ => None,
+    }
+}
+
+// Install the portal backend for the desktop and point xdg-desktop-portal at it
+fn setup_desktop_portal(config: &Config) {
+    let desktop = match config.file.portal.desktop.as_deref() {
+        Some(desktop) => desktop.to_string(),
+        None if config.dry_run => "<detected>".to_string(),
+        None => match WAYLAND_COMPOSITORS.iter().find(|(package, _, _)| package_installed(package)) {
+            Some((package, _, _)) => compositor_portal_desktop(package).to_string(),
+            None => {
+                if config.verbose {
+                    say!("⏭ No compositor detected and no [portal] desktop set, skipping portal setup");
+                }
+                return;
+            }
+        },
+    };
+    
+    let (packages, preferred) = match desktop.as_str() {
+        "<detected>" => (&["xdg-desktop-portal-<backend>"][..], "<backend>"),
+        _ => portal_backend(&desktop).unwrap_or_else(|| {
+            report::fatal(format!("Unknown portal desktop '{}' (expected hyprland, wlr, gnome, kde or gtk)", desktop));
+        }),
+    };
+    
+    let mut portals_conf = format!("[preferred]\ndefault={}\n", preferred);
+    if preferred.ends_with(";gtk") {
+        portals_conf.push_str("org.freedesktop.impl.portal.FileChooser=gtk\n");
+    }
+    
+    say!("Setting up xdg-desktop-portal ({})...", desktop);
+    
+    let portals_dir = paths::get().config("xdg-desktop-portal");
+    let portals_path = format!("{}/portals.conf", portals_dir);
+    
+    if config.dry_run {
+        plan::add(planned_install(config, &[&["xdg-desktop-portal"], packages].concat()));
+        let mut write = plan::write(&portals_path, format!("Write {}:", portals_path)).details(portals_conf.lines());
+        if std::fs::read_to_string(&portals_path).is_ok_and(|current| current == portals_conf) {
+            write = write.effect("nothing, already up to date");
+        }
+        plan::add(write);
+        plan::add(plan::run("systemctl --user restart xdg-desktop-portal.service"));
+        return;
+    }
+    
+    if config.verbose {
+        say!("Installing portal packages...");
+    }
+    install_packages(config, &[&["xdg-desktop-portal"], packages].concat(), "Failed to install portal packages");
+    
+    if std::fs::symlink_metadata(&portals_path).map(|m| m.file_type().is_symlink()).unwrap_or(false) {
+        config.warnings.add(WarningKind::Skipped, format!("{} is a symlink (managed elsewhere?), not touching it", portals_path));
+    } else if std::fs::read_to_string(&portals_path).unwrap_or_default() != portals_conf {
+        std::fs::create_dir_all(&portals_dir).expect("Failed to create xdg-desktop-portal config directory");
+        std::fs::write(&portals_path, &portals_conf).expect("Failed to write portals.conf");
+        if config.verbose {
+            say!("✓ Wrote {}", portals_path);
+        }
+    } else if config.verbose {
+        say!("✓ portals.conf already up to date");
+    }
+    
+    // Only works inside a user session, fine to miss on a fresh TTY install
+    let restart = Cmd::new("systemctl").args(["--user", "restart", "xdg-desktop-portal.service"]).quiet();
+    if run_command(config, restart).is_err() && config.verbose {
+        say!("Portal service not restarted (no user session), it will start at next login");
+    }
+    
+    say!("✓ xdg-desktop-portal configured!");
+}
+
+// Enable a user unit wanted by `target`. Without a session bus (a bare TTY
+// before anyone logged in) the user manager is brought up or reached as
+// [user_units] fallback says, or the unit is linked into the target by hand
+// and deferred to the first login.
+fn enable_user_unit(config: &Config, user_units: &str, unit: &str, unit_path: &str, target: &str) {
+    let settings = &config.file.user_units;
+    let wait = std::time::Duration::from_secs(settings.wait);
+    let bus = match settings.fallback.as_str() {
+        _ if userbus::available() => true,
+        "linger" => userbus::linger(wait),
+        "machinectl" if userbus::machinectl_systemctl(&["enable", unit]) => return,
+        // logind may still be setting up the session this runs in
+        _ => userbus::wait(std::time::Duration::from_secs(settings.wait.min(2))),
+    };
+    
+    let enable = userbus::env()
+        .into_iter()
+        .fold(Cmd::new("systemctl").args(["--user", "enable", unit]), |cmd, (key, value)| cmd.env(key, value))
+        .quiet();
+    if bus && run_command(config, enable).is_ok() {
+        return;
+    }
+    
+    let wants_dir = format!("{}/{}.wants", user_units, target);
+    let link = format!("{}/{}", wants_dir, unit);
+    std::fs::create_dir_all(&wants_dir).unwrap_or_else(|e| panic!("Failed to create {}: {}", wants_dir, e));
+    if !Path::new(&link).exists() {
+        std::os::unix::fs::symlink(unit_path, &link).unwrap_or_else(|e| panic!("Failed to enable {}: {}", unit, e));
+    }
+    config.warnings.add_with_hints(
+        WarningKind::Skipped,
+        format!("{} linked into {} by hand, no user session bus to enable it through; it starts at the first login", unit, target),
+        vec!["Set [user_units] fallback = \"linger\" or \"machinectl\" to have user units enabled right away"],
+    );
+}
+
+// User unit for polkit-gnome, which only ships an XDG autostart entry
+const POLKIT_GNOME_SERVICE: &str = "[Unit]
+Description=polkit-gnome authentication agent
+PartOf=graphical-session.target
+After=graphical-session.target
+
+[Service]
+ExecStart=/usr/lib/polkit-gnome/polkit-gnome-authentication-agent-1
+Restart=on-failure
+
+[Install]
+WantedBy=graphical-session.target
+";
+
+// Make sure a polkit agent matching the session is installed and autostarted
+fn setup_polkit_agent(config: &Config) {
+    // Full desktops bring their own agent
+    if package_installed("plasma-workspace") || package_installed("gnome-shell") {
+        if config.verbose {
+            say!("⏭ Desktop environment provides its own polkit agent, skipping");
+        }
+        return;
+    }
+    
+    let agent = match config.file.polkit.agent.as_deref() {
+        Some(agent) => agent.to_string(),
+        None => match WAYLAND_COMPOSITORS.iter().find(|(package, _, _)| package_installed(package)) {
+            Some(("hyprland", _, _)) => "hyprpolkitagent".to_string(),
+            Some(_) => "polkit-gnome".to_string(),
+            // The compositor may be among the packages this run installs
+            None if config.dry_run => {
+                say!("Setting up polkit agent...");
+                plan::add(plan::check(
+                    "Pick the polkit agent for the Wayland compositor installed by then (hyprpolkitagent for Hyprland, polkit-gnome otherwise), install it and enable its user unit",
+                ));
+                return;
+            }
+            None => {
+                if config.verbose {
+                    say!("⏭ No Wayland session detected, skipping polkit agent setup");
+                }
+                return;
+            }
+        },
+    };
+    
+    let unit = match agent.as_str() {
+        "hyprpolkitagent" => "hyprpolkitagent.service",
+        "polkit-gnome" => "polkit-gnome-agent.service",
+        _ => unreachable!("validated when loading the config"),
+    };
+    
+    say!("Setting up polkit agent ({})...", agent);
+    
+    let user_units = paths::get().config("systemd/user");
+    
+    if config.dry_run {
+        plan::add(planned_install(config, &[&agent]));
+        if agent == "polkit-gnome" {
+            plan::add(plan::write(&format!("{}/{}", user_units, unit), format!("Write {}/{}", user_units, unit)));
+        }
+        plan::add(plan::enable(format!("systemctl --user enable {}", unit), unit));
+        return;
+    }
+    
+    install_packages(config, &[&agent], &format!("Failed to install {}", agent));
+    
+    let unit_path = if agent == "polkit-gnome" {
+        let path = format!("{}/{}", user_units, unit);
+        std::fs::create_dir_all(&user_units).expect("Failed to create systemd user unit directory");
+        if std::fs::read_to_string(&path).unwrap_or_default() != POLKIT_GNOME_SERVICE {
+            std::fs::write(&path, POLKIT_GNOME_SERVICE).expect("Failed to write polkit agent unit");
+        }
+        path
+    } else {
+        format!("/usr/lib/systemd/user/{}", unit)
+    };
+    
+    enable_user_unit(config, &user_units, unit, &unit_path, "graphical-session.target");
+    
+    if config.verbose {
+        say!("The agent starts with graphical-session.target (e.g. via uwsm or your compositor's systemd integration)");
+    }
+    say!("✓ Polkit agent {} installed and enabled!", agent);
+}
+
+// udiskie has no unit of its own
+const UDISKIE_SERVICE: &str = "[Unit]
+Description=udiskie removable media automounter
+PartOf=graphical-session.target
+After=graphical-session.target
+
+[Service]
+ExecStart=/usr/bin/udiskie --automount --notify
+Restart=on-failure
+
+[Install]
+WantedBy=graphical-session.target
+";
+
+// Install udisks2 plus udiskie or gvfs so removable drives mount automatically
+fn setup_removable_media(config: &Config) {
+    let automount = &config.file.automount;
+    
+    if !automount.enable {
+        if config.verbose {
+            say!("⏭ [automount] not enabled, skipping removable media setup");
+        }
+        return;
+    }
+    
+    // GTK file managers and full desktops mount through gvfs already
+    let helper = match automount.helper.as_deref() {
+        Some(helper) => helper.to_string(),
+        None if config.dry_run => "<udiskie or gvfs>".to_string(),
+        None => {
+            let gvfs_desktop = ["gnome-shell", "nautilus", "thunar", "nemo", "pcmanfm-gtk3", "xfce4-session"]
+                .iter()
+                .any(|p| package_installed(p));
+            if gvfs_desktop { "gvfs".to_string() } else { "udiskie".to_string() }
+        }
+    };
+    
+    if !matches!(helper.as_str(), "udiskie" | "gvfs" | "<udiskie or gvfs>") {
+        report::fatal(format!("Unknown automount helper '{}' (expected udiskie or gvfs)", helper));
+    }
+    
+    say!("Setting up removable media automounting ({})...", helper);
+    
+    let user_units = paths::get().config("systemd/user");
+    
+    if config.dry_run {
+        plan::add(planned_install(config, &["udisks2", &helper]));
+        plan::add(
+            plan::write(&format!("{}/udiskie.service", user_units), format!("udiskie: write {}/udiskie.service and enable it", user_units))
+                .detail("gvfs: nothing to enable, the volume monitor is D-Bus activated"),
+        );
+        return;
+    }
+    
+    install_packages(config, &["udisks2", &helper], &format!("Failed to install udisks2 and {}", helper));
+    
+    if helper == "udiskie" {
+        let unit_path = format!("{}/udiskie.service", user_units);
+        std::fs::create_dir_all(&user_units).expect("Failed to create systemd user unit directory");
+        if std::fs::read_to_string(&unit_path).unwrap_or_default() != UDISKIE_SERVICE {
+            std::fs::write(&unit_path, UDISKIE_SERVICE).expect("Failed to write udiskie unit");
+        }
+        enable_user_unit(config, &user_units, "udiskie.service", &unit_path, "graphical-session.target");
+    }
+    
+    say!("✓ Removable media will be mounted automatically!");
+}
+
+// Session command to launch for each compositor package
+fn compositor_session_command(package: &str) -> &'static str {
+    match package {
+        "hyprland" => "Hyprland",
+        "sway" => "sway",
+        "river" => "river",
+        "niri" => "niri-session",
+        "wayfire" => "wayfire",
+        _ => "",
+    }
+}
+
+fn autologin_getty_dropin(user: &str) -> String {
+    format!(
+        "[Service]\nExecStart=\nExecStart=-/sbin/agetty -o '-p -f -- \\\\u' --noclear --autologin {} %I $TERM\n",
+        user
+    )
+}
+
+const AUTOLOGIN_BLOCK_START: &str = "# >>> ass autologin >>>";
+const AUTOLOGIN_BLOCK_END: &str = "# <<< ass autologin <<<";
+
+// Replace our block in a shell profile, or append it
+fn with_autologin_block(content: &str, block: &str) -> String {
+    let mut kept = String::new();
+    let mut inside = false;
+    for line in content.lines() {
+        match line.trim() {
+            AUTOLOGIN_BLOCK_START => inside = true,
+            AUTOLOGIN_BLOCK_END => inside = false,
+            _ if !inside => {
+                kept.push_str(line);
+                kept.push('\n');
+            }
+            _ => {}
+        }
+    }
+    if !kept.is_empty() && !kept.ends_with("\n\n") {
+        kept.push('\n');
+    }
+    kept + block
+}
+
+// User unit starting the session once the autologin brings up the user manager
+fn autologin_session_service(session: &str) -> String {
+    format!(
+        "[Unit]\nDescription=Auto-started session (ass)\n\n[Service]\nExecStart=/bin/sh -lc 'exec {}'\nRestart=on-failure\n\n[Install]\nWantedBy=default.target\n",
+        session
+    )
+}
+
+// Log the user in on a tty at boot and start the session right away, for
+// kiosk/HTPC setups without a display manager
+fn setup_autologin(config: &Config) {
+    let autologin = &config.file.autologin;
+    
+    if !autologin.enable {
+        if config.verbose {
+            say!("⏭ [autologin] not enabled, skipping");
+        }
+        return;
+    }
+    
+    let user = autologin
+        .user
+        .clone()
+        .unwrap_or_else(|| env::var("USER").expect("USER environment variable not set"));
+    let session = match autologin.session.clone() {
+        Some(session) => session,
+        None if config.dry_run => "<detected compositor>".to_string(),
+        None => match WAYLAND_COMPOSITORS.iter().find(|(package, _, _)| package_installed(package)) {
+            Some((package, _, _)) => compositor_session_command(package).to_string(),
+            None => {
+                config.warnings.add(WarningKind::Skipped, "No compositor found for autologin, set session in [autologin]");
+                return;
+            }
+        },
+    };
+    
+    say!("Setting up autologin for {} on {} ({})...", user, autologin.tty, session);
+    
+    let paths = paths::get();
+    let dropin_path = format!("/etc/systemd/system/getty@{}.service.d/autologin.conf", autologin.tty);
+    let profile_path = if env::var("SHELL").unwrap_or_default().ends_with("zsh") {
+        paths.home(".zprofile")
+    } else {
+        paths.home(".bash_profile")
+    };
+    let user_units = paths.config("systemd/user");
+    
+    if config.dry_run {
+        let getty = format!("getty@{}.service", autologin.tty);
+        plan::add(plan::write(&dropin_path, format!("Write {} (agetty --autologin {})", dropin_path, user)));
+        plan::add(plan::enable(format!("sudo systemctl enable {}", getty), &getty));
+        if autologin.start == "profile" {
+            plan::add(plan::write(&profile_path, format!("Add a block to {} that runs exec {} on /dev/{}", profile_path, session, autologin.tty)));
+        } else {
+            plan::add(plan::write(
+                &format!("{}/ass-session.service", user_units),
+                format!("Write {}/ass-session.service (exec {}) and enable it", user_units, session),
+            ));
+        }
+        return;
+    }
+    
+    install_system_content(config, &autologin_getty_dropin(&user), &dropin_path, "644");
+    run_or_exit(config, Cmd::sudo("systemctl").arg("daemon-reload"), "Failed to reload systemd");
+    run_or_exit(
+        config,
+        Cmd::sudo("systemctl").args(["enable", &format!("getty@{}.service", autologin.tty)]),
+        &format!("Failed to enable getty@{}.service", autologin.tty),
+    );
+    
+    if autologin.start == "profile" {
+        let block = format!(
+            "{}\nif [ -z \"$WAYLAND_DISPLAY\" ] && [ \"$(tty)\" = \"/dev/{}\" ]; then\n    exec {}\nfi\n{}\n",
+            AUTOLOGIN_BLOCK_START, autologin.tty, session, AUTOLOGIN_BLOCK_END
+        );
+        
+        if std::fs::symlink_metadata(&profile_path).map(|m| m.file_type().is_symlink()).unwrap_or(false) {
+            config.warnings.add(
+                WarningKind::Skipped,
+                format!("{} is a symlink (managed by your dotfiles?), add this yourself:\n{}", profile_path, block),
+            );
+        } else {
+            let current = std::fs::read_to_string(&profile_path).unwrap_or_default();
+            let updated = with_autologin_block(&current, &block);
+            if updated != current {
+                std::fs::write(&profile_path, updated).unwrap_or_else(|e| {
+                    esay!("Failed to write {}: {}", profile_path, e);
+                    std::process::exit(1);
+                });
+            }
+            if config.verbose {
+                say!("  ✓ {} starts {} on /dev/{}", profile_path, session, autologin.tty);
+            }
+        }
+    } else {
+        let unit_path = format!("{}/ass-session.service", user_units);
+        std::fs::create_dir_all(&user_units).expect("Failed to create systemd user unit directory");
+        std::fs::write(&unit_path, autologin_session_service(&session)).expect("Failed to write session unit");
+        
+        enable_user_unit(config, &user_units, "ass-session.service", &unit_path, "default.target");
+    }
+    
+    say!("✓ Autologin configured, takes effect on the next boot!");
+}
+
+// Config file for the display manager, either from the dotfiles or generated
+fn display_manager_config(config: &Config, name: &str, dotfiles: &str) -> (String, String) {
+    let dm = &config.file.display_manager;
+    
+    match name {
+        "greetd" => {
+            let target = "/etc/greetd/config.toml".to_string();
+            let dotfiles_source = format!("{}/greetd/config.toml", dotfiles);
+            if Path::new(&dotfiles_source).exists() {
+                return (dotfiles_source, target);
+            }
+            
+            let session = dm.session.clone().unwrap_or_else(|| {
+                WAYLAND_COMPOSITORS
+                    .iter()
+                    .find(|(package, _, _)| config.dry_run || package_installed(package))
+                    .map(|(package, _, _)| compositor_session_command(package).to_string())
+                    .unwrap_or_else(|| {
+                        report::fatal("No compositor found for greetd, set session in [display_manager]");
+                    })
+            });
+            
+            let generated = format!(
+                "[terminal]\nvt = 1\n\n[default_session]\ncommand = \"tuigreet --time --remember --cmd {}\"\nuser = \"greeter\"\n",
+                session
+            );
+            let source = if config.dry_run {
+                config.work_dir.planned_path("greetd-config.toml")
+            } else {
+                config.work_dir.path("greetd-config.toml")
+            };
+            if !config.dry_run {
+                std::fs::write(&source, generated).expect("Failed to write temporary greetd config");
+            }
+            (source, target)
+        }
+        _ => {
+            let target = "/etc/sddm.conf.d/10-ass.conf".to_string();
+            let dotfiles_source = format!("{}/sddm/sddm.conf", dotfiles);
+            if Path::new(&dotfiles_source).exists() {
+                return (dotfiles_source, target);
+            }
+            
+            let mut generated = String::new();
+            if dm.wayland {
+                generated.push_str("[General]\nDisplayServer=wayland\n\n");
+            }
+            if let Some(theme) = &dm.theme {
+                generated.push_str(&format!("[Theme]\nCurrent={}\n", theme));
+            }
+            let source = if config.dry_run {
+                config.work_dir.planned_path("sddm.conf")
+            } else {
+                config.work_dir.path("sddm.conf")
+            };
+            if !config.dry_run {
+                std::fs::write(&source, generated).expect("Failed to write temporary sddm config");
+            }
+            (source, target)
+        }
+    }
+}
+
+fn display_manager_packages(name: &str) -> Option<&'static [&'static str]> {
+    match name {
+        "greetd" => Some(&["greetd", "greetd-tuigreet"]),
+        "sddm" => Some(&["sddm", "qt6-wayland"]),
+        _ => None,
+    }
+}
+
+// Install and enable the display manager along with a working configuration
+fn setup_display_manager(config: &Config) {
+    let Some(name) = config.file.display_manager.name.as_deref() else {
+        if config.verbose {
+            say!("⏭ No display manager configured, skipping");
+        }
+        return;
+    };
+    
+    let packages = display_manager_packages(name).unwrap_or_else(|| {
+        report::fatal(format!("Unknown display manager '{}' (expected greetd or sddm)", name));
+    });
+    
+    say!("Setting up {}...", name);
+    
+    let (source, target) = display_manager_config(config, name, &paths::get().dotfiles());
+    
+    if config.dry_run {
+        let unit = format!("{}.service", name);
+        plan::add(planned_install(config, packages));
+        plan::add(plan::write(&target, format!("Install {} to {} (diff + backup)", source, target)));
+        plan::add(plan::enable(format!("sudo systemctl enable {}", unit), &unit));
+        return;
+    }
+    
+    if config.verbose {
+        say!("Installing {}...", packages.join(", "));
+    }
+    install_packages(config, packages, &format!("Failed to install {}", name));
+    
+    install_system_file(config, &source, &target, "644");
+    
+    // Not --now: starting a greeter mid-run would grab the VT
+    if config.verbose {
+        say!("Enabling {}.service...", name);
+    }
+    run_or_exit(
+        config,
+        Cmd::sudo("systemctl")
+            .args(["enable", &format!("{}.service", name)]),
+        &format!("Failed to enable {}.service", name),
+    );
+    
+    say!("✓ {} configured and enabled (starts on next boot)!", name);
+}
+
+// Enables numlock on every TTY before getty starts
+const NUMLOCK_GETTY_DROPIN: &str = "[Service]
+ExecStartPre=/bin/sh -c 'setleds -D +num < /dev/%I'
+";
+
+// Applies the console key repeat settings at boot
+fn kbdrate_service(delay: u32, rate: u32) -> String {
+    format!(
+        "[Unit]
+Description=Set console keyboard repeat rate
+
+[Service]
+Type=oneshot
+StandardInput=tty
+TTYPath=/dev/tty1
+ExecStart=/usr/bin/kbdrate --silent --delay {} --rate {}
+
+[Install]
+WantedBy=multi-user.target
+",
+        delay, rate
+    )
+}
+
+// Numlock, console font and key repeat from the [system_tweaks] config
+fn apply_system_tweaks(config: &Config) {
+    let tweaks = &config.file.system_tweaks;
+    let repeat = tweaks.repeat_delay.is_some() || tweaks.repeat_rate.is_some();
+    
+    if !tweaks.numlock && tweaks.console_font.is_none() && !repeat {
+        if config.verbose {
+            say!("⏭ No [system_tweaks] configured, skipping");
+        }
+        return;
+    }
+    
+    say!("Applying system tweaks...");
+    
+    let delay = tweaks.repeat_delay.unwrap_or(250);
+    // kbdrate only accepts up to 30 cps
+    let rate = tweaks.repeat_rate.unwrap_or(30).min(30);
+    
+    if config.dry_run {
+        if tweaks.numlock {
+            plan::add(plan::write(
+                "/etc/systemd/system/getty@.service.d/numlock.conf",
+                "Write /etc/systemd/system/getty@.service.d/numlock.conf (setleds +num)",
+            ));
+            if config.file.display_manager.name.as_deref() == Some("sddm") {
+                plan::add(plan::write("/etc/sddm.conf.d/numlock.conf", "Write /etc/sddm.conf.d/numlock.conf (Numlock=on)"));
+            }
+        }
+        if let Some(font) = &tweaks.console_font {
+            plan::add(plan::write("/etc/vconsole.conf", format!("Set FONT={} in /etc/vconsole.conf", font)));
+        }
+        if repeat {
+            plan::add(plan::write(
+                "/etc/systemd/system/kbdrate.service",
+                format!("Write /etc/systemd/system/kbdrate.service (delay {}ms, rate {}cps) and enable it", delay, rate),
+            ));
+        }
+        return;
+    }
+    
+    if tweaks.numlock {
+        if config.verbose {
+            say!("Enabling numlock on boot...");
+        }
+        install_system_content(config, NUMLOCK_GETTY_DROPIN, "/etc/systemd/system/getty@.service.d/numlock.conf", "644");
+        
+        if config.file.display_manager.name.as_deref() == Some("sddm") {
+            install_system_content(config, "[General]\nNumlock=on\n", "/etc/sddm.conf.d/numlock.conf", "644");
+        }
+    }
+    
+    if let Some(font) = &tweaks.console_font {
+        // Terminus fonts aren't part of the base install
+        if font.starts_with("ter-") {
+            install_packages(config, &["terminus-font"], "Failed to install terminus-font");
+        }
+        
+        // Keep KEYMAP and anything else already in vconsole.conf
+        let current = std::fs::read_to_string("/etc/vconsole.conf").unwrap_or_default();
+        let mut lines: Vec<String> = current
+            .lines()
+            .filter(|line| !line.trim_start().starts_with("FONT="))
+            .map(|line| line.to_string())
+            .collect();
+        lines.push(format!("FONT={}", font));
+        
+        if install_system_content(config, &(lines.join("\n") + "\n"), "/etc/vconsole.conf", "644") {
+            run_or_warn(
+                config,
+                Cmd::sudo("systemctl").args(["restart", "systemd-vconsole-setup.service"]),
+                "Failed to restart systemd-vconsole-setup.service",
+            );
+        }
+    }
+    
+    if repeat {
+        if config.verbose {
+            say!("Setting console key repeat to {}ms delay, {}cps...", delay, rate);
+        }
+        install_system_content(config, &kbdrate_service(delay, rate), "/etc/systemd/system/kbdrate.service", "644");
+        
+        run_or_exit(
+            config,
+            Cmd::sudo("systemctl")
+                .args(["enable", "kbdrate.service"]),
+            "Failed to enable kbdrate.service",
+        );
+    }
+    
+    say!("✓ System tweaks applied!");
+}
+
+// Ask a yes/no question, Enter means yes
+fn confirm(question: &str) -> bool {
+    ask_yes_no(question, true)
+}
+
+fn ask_yes_no(question: &str, default: bool) -> bool {
+    print!("{} {} ", question, if default { tr!("[Y/n]") } else { tr!("[y/N]") });
+    let _ = std::io::stdout().flush();
+    
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    // English answers always work, whatever the language
+    let answer = answer.trim().to_lowercase();
+    if answer.is_empty() {
+        return default;
+    }
+    ["y", "yes", tr!("y"), tr!("yes")].contains(&answer.as_str())
+}
+
+// Ask for a value, Enter keeps the default
+fn prompt(question: &str, default: &str) -> String {
+    print!("{} [{}]: ", question, default);
+    let _ = std::io::stdout().flush();
+    
+    let mut answer = String::new();
+    let _ = std::io::stdin().read_line(&mut answer);
+    let answer = answer.trim();
+    if answer.is_empty() { default.to_string() } else { answer.to_string() }
+}
+
+// Set a KEY=value line in a shell-style config, uncommenting it if needed
+fn set_shell_var(content: &str, key: &str, value: &str) -> String {
+    let mut found = false;
+    let mut lines: Vec<String> = Vec::new();
+    
+    for line in content.lines() {
+        let stripped = line.trim_start().trim_start_matches('#').trim_start();
+        if !found && stripped.starts_with(&format!("{}=", key)) {
+            lines.push(format!("{}={}", key, value));
+            found = true;
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+    
+    if !found {
+        lines.push(format!("{}={}", key, value));
+    }
+    lines.join("\n") + "\n"
+}
+
+// Look for a Windows install: an NTFS partition or a Microsoft EFI boot entry
+fn windows_detected() -> bool {
+    let efi_dirs = ["/boot/efi/EFI/Microsoft", "/boot/EFI/Microsoft", "/efi/EFI/Microsoft"];
+    if efi_dirs.iter().any(|dir| Path::new(dir).exists()) {
+        return true;
+    }
+    
+    Command::new("lsblk")
+        .args(["-rno", "FSTYPE"])
+        .output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).lines().any(|fs| fs == "ntfs"))
+        .unwrap_or(false)
+}
+
+// Install a GRUB theme, enable os-prober for dual boot and regenerate grub.cfg
+fn configure_grub(config: &Config) {
+    let grub = &config.file.grub;
+    
+    if grub.theme.is_none() && grub.os_prober.is_none() {
+        if config.verbose {
+            say!("⏭ No [grub] settings configured, skipping");
+        }
+        return;
+    }
+    
+    say!("Configuring GRUB...");
+    
+    if config.dry_run {
+        if let Some(theme) = &grub.theme {
+            plan::add(plan::write("/boot/grub/themes", format!("Copy theme {} to /boot/grub/themes and set GRUB_THEME", theme)));
+        }
+        match grub.os_prober {
+            Some(true) => plan::add(planned_install(config, &["os-prober"]).detail("and set GRUB_DISABLE_OS_PROBER=false")),
+            Some(false) => {}
+            None => plan::add(
+                plan::check("If Windows is detected: install os-prober and set GRUB_DISABLE_OS_PROBER=false").target("/etc/default/grub"),
+            ),
+        }
+        plan::add(plan::ask("Preview the /etc/default/grub diff and ask for confirmation").target("/etc/default/grub"));
+        plan::add(plan::run(Cmd::sudo("grub-mkconfig").args(["-o", "/boot/grub/grub.cfg"])).target("/boot/grub/grub.cfg"));
+        return;
+    }
+    
+    if !Path::new("/etc/default/grub").exists() {
+        say!("⏭ GRUB is not installed (/etc/default/grub missing), skipping");
+        return;
+    }
+    
+    let current = std::fs::read_to_string("/etc/default/grub").expect("Failed to read /etc/default/grub");
+    let mut updated = current.clone();
+    let mut theme_installed = false;
+    
+    if let Some(theme) = &grub.theme {
+        let source = if theme.starts_with("https://") || theme.ends_with(".git") {
+            let clone_path = config.work_dir.path("grub-theme");
+            let _ = std::fs::remove_dir_all(&clone_path);
+            if config.verbose {
+                say!("Cloning GRUB theme {}...", theme);
+            }
+            
+            // Check out a release tag for GitHub themes when a version is configured
+            let mut clone_args = vec!["clone".to_string(), "--depth=1".to_string()];
+            if let (Some(version), Some(repo)) = (&grub.theme_version, releases::github_repo(theme)) {
+                let tag = releases::resolve(&repo, version, config.refresh_pins).unwrap_or_else(|e| {
+                    esay!("Failed to resolve GRUB theme release: {}", e);
+                    std::process::exit(1);
+                });
+                say!("Using GRUB theme {} {}", repo, tag);
+                clone_args.push(format!("--branch={}", tag));
+            }
+            
+            run_or_exit(
+                config,
+                Cmd::new("git")
+                    .args(&clone_args)
+                    .args([theme, &clone_path])
+                    .network(),
+                "Failed to clone GRUB theme",
+            );
+            clone_path
+        } else if theme.starts_with('/') {
+            theme.clone()
+        } else {
+            format!("{}/{}", paths::get().dotfiles(), theme)
+        };
+        
+        // Theme repos often keep the actual theme in a subdirectory
+        let theme_dir = if Path::new(&source).join("theme.txt").exists() {
+            Path::new(&source).to_path_buf()
+        } else {
+            std::fs::read_dir(&source)
+                .ok()
+                .and_then(|entries| {
+                    entries
+                        .flatten()
+                        .map(|e| e.path())
+                        .find(|p| p.join("theme.txt").exists())
+                })
+                .unwrap_or_else(|| {
+                    report::fatal(format!("No theme.txt found in GRUB theme {}", source));
+                })
+        };
+        
+        // Name after the configured theme unless it lives in a subdirectory
+        let theme_name = if theme_dir == Path::new(&source) {
+            theme.trim_end_matches('/').rsplit('/').next().unwrap_or(theme).trim_end_matches(".git").to_string()
+        } else {
+            theme_dir.file_name().unwrap().to_string_lossy().to_string()
+        };
+        let target = format!("/boot/grub/themes/{}", theme_name);
+        
+        if config.verbose {
+            say!("Installing GRUB theme to {}...", target);
+        }
+        run_or_exit(config, Cmd::sudo("mkdir").args(["-p", "/boot/grub/themes"]), "Failed to create /boot/grub/themes");
+        run_or_exit(config, Cmd::sudo("rm").args(["-rf", &target]), &format!("Failed to remove the old {}", target));
+        run_or_exit(
+            config,
+            Cmd::sudo("cp")
+                .args(["-r", &theme_dir.to_string_lossy(), &target]),
+            "Failed to install GRUB theme",
+        );
+        
+        theme_installed = true;
+        updated = set_shell_var(&updated, "GRUB_THEME", &format!("\"{}/theme.txt\"", target));
+    }
+    
+    let os_prober = grub.os_prober.unwrap_or_else(|| {
+        let detected = windows_detected();
+        if detected {
+            say!("Windows installation detected, enabling os-prober");
+        }
+        detected
+    });
+    
+    if os_prober {
+        install_packages(config, &["os-prober", "ntfs-3g"], "Failed to install os-prober");
+        updated = set_shell_var(&updated, "GRUB_DISABLE_OS_PROBER", "false");
+    }
+    
+    if updated != current {
+        // Preview before touching a file that can make the machine unbootable
+        let preview = config.work_dir.path("default-grub");
+        std::fs::write(&preview, &updated).expect("Failed to write temporary grub config");
+        say!("Proposed changes to /etc/default/grub:");
+        let _ = Command::new("diff")
+            .args(["-u", "--color=auto", "/etc/default/grub", &preview])
+            .status();
+        
+        if !confirm(tr!("Apply these changes?")) {
+            config.warnings.add(WarningKind::Skipped, "GRUB changes declined, /etc/default/grub left unchanged");
+            return;
+        }
+        
+        journal::file_changing("/etc/default/grub");
+        run_or_exit(
+            config,
+            Cmd::sudo("cp").args(["-a", "/etc/default/grub", "/etc/default/grub.bak"]),
+            "Failed to back up /etc/default/grub",
+        );
+        run_or_exit(
+            config,
+            Cmd::sudo("install")
+                .args(["-m", "644", &preview, "/etc/default/grub"]),
+            "Failed to update /etc/default/grub",
+        );
+    } else if !theme_installed {
+        say!("✓ GRUB already configured");
+        return;
+    }
+    
+    if config.verbose {
+        say!("Regenerating grub.cfg...");
+    }
+    run_or_exit(
+        config,
+        Cmd::sudo("grub-mkconfig")
+            .args(["-o", "/boot/grub/grub.cfg"]),
+        "Failed to regenerate grub.cfg (previous config saved as /etc/default/grub.bak)",
+    );
+    
+    say!("✓ GRUB configured!");
+}
+
+// Parse one line of `lsblk -P` output (KEY="value" pairs)
+fn parse_lsblk_pairs(line: &str) -> std::collections::HashMap<String, String> {
+    let mut fields = std::collections::HashMap::new();
+    let mut rest = line.trim();
+    
+    while let Some(eq) = rest.find("=\"") {
+        let key = rest[..eq].trim().to_string();
+        let after = &rest[eq + 2..];
+        let Some(end) = after.find('"') else {
+            break;
+        };
+        fields.insert(key, after[..end].to_string());
+        rest = &after[end + 1..];
+    }
+    
+    fields
+}
+
+// Find NTFS/exFAT partitions and make them mountable with the right drivers
+fn setup_shared_drives(config: &Config) {
+    let shared = &config.file.shared_drives;
+    
+    if !shared.enable {
+        if config.verbose {
+            say!("⏭ [shared_drives] not enabled, skipping");
+        }
+        return;
+    }
+    
+    if shared.mount_method != "fstab" && shared.mount_method != "udisks" {
+        report::fatal(format!("Unknown mount_method '{}' in [shared_drives] (expected fstab or udisks)", shared.mount_method));
+    }
+    
+    say!("Looking for shared NTFS/exFAT drives...");
+    
+    if config.dry_run {
+        plan::add(plan::check("lsblk -P -o NAME,FSTYPE,UUID,LABEL,MOUNTPOINT to find ntfs/exfat partitions"));
+        plan::add(plan::ask("Ask which partitions to set up"));
+        plan::add(planned_install(config, &["ntfs-3g", "exfatprogs"]));
+        if shared.mount_method == "fstab" {
+            plan::add(plan::write(
+                "/etc/fstab",
+                format!("Add nofail automount entries under {} to /etc/fstab (diff + backup)", shared.mount_root),
+            ));
+        } else {
+            plan::add(plan::write(
+                "/etc/udev/rules.d/99-ass-shared-drives.rules",
+                "Install udisks2 and write /etc/udev/rules.d/99-ass-shared-drives.rules",
+            ));
+        }
+        return;
+    }
+    
+    let output = Command::new("lsblk")
+        .args(["-P", "-o", "NAME,FSTYPE,UUID,LABEL,MOUNTPOINT"])
+        .output()
+        .expect("Failed to execute lsblk");
+    
+    let fstab = std::fs::read_to_string("/etc/fstab").unwrap_or_default();
+    
+    let partitions: Vec<_> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(parse_lsblk_pairs)
+        .filter(|p| matches!(p.get("FSTYPE").map(|s| s.as_str()), Some("ntfs" | "exfat")))
+        .filter(|p| p.get("UUID").is_some_and(|u| !u.is_empty()))
+        .collect();
+    
+    if partitions.is_empty() {
+        say!("✓ No NTFS/exFAT partitions found");
+        return;
+    }
+    
+    let mut selected = Vec::new();
+    for partition in &partitions {
+        let uuid = &partition["UUID"];
+        if fstab.contains(uuid.as_str()) {
+            if config.verbose {
+                say!("✓ {} is already in /etc/fstab", partition["NAME"]);
+            }
+            continue;
+        }
+        
+        let label = partition.get("LABEL").filter(|l| !l.is_empty()).cloned().unwrap_or_default();
+        let question = tr!(
+            "Set up /dev/{} ({}{})?",
+            partition["NAME"],
+            partition["FSTYPE"],
+            if label.is_empty() { String::new() } else { format!(", \"{}\"", label) }
+        );
+        if confirm(&question) {
+            selected.push(partition);
+        }
+    }
+    
+    if selected.is_empty() {
+        say!("✓ No shared drives to set up");
+        return;
+    }
+    
+    let mut packages = vec!["ntfs-3g", "exfatprogs"];
+    if shared.mount_method == "udisks" {
+        packages.push("udisks2");
+    }
+    install_packages(config, &packages, "Failed to install filesystem tools");
+    
+    if shared.mount_method == "udisks" {
+        let rules: String = selected
+            .iter()
+            .map(|p| format!("ENV{{ID_FS_UUID}}==\"{}\", ENV{{UDISKS_AUTO}}=\"1\", ENV{{UDISKS_FILESYSTEM_SHARED}}=\"1\"\n", p["UUID"]))
+            .collect();
+        
+        if install_system_content(config, &rules, "/etc/udev/rules.d/99-ass-shared-drives.rules", "644") {
+            run_or_warn(config, Cmd::sudo("udevadm").args(["control", "--reload-rules"]), "Failed to reload udev rules");
+            run_or_warn(config, Cmd::sudo("udevadm").arg("trigger"), "Failed to trigger udev");
+        }
+        say!("✓ Shared drives will be mounted under /media by udisks!");
+        return;
+    }
+    
+    // Mount as the invoking user since neither filesystem has unix permissions
+    let id = |flag: &str| {
+        Command::new("id")
+            .arg(flag)
+            .output()
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+            .unwrap_or_else(|_| "1000".to_string())
+    };
+    let (uid, gid) = (id("-u"), id("-g"));
+    
+    let mut new_fstab = fstab.clone();
+    if !new_fstab.ends_with('\n') && !new_fstab.is_empty() {
+        new_fstab.push('\n');
+    }
+    
+    for partition in &selected {
+        let name = partition
+            .get("LABEL")
+            .filter(|l| !l.is_empty())
+            .map(|l| l.replace(' ', "_"))
+            .unwrap_or_else(|| partition["UUID"].clone());
+        let mount_point = format!("{}/{}", shared.mount_root, name);
+        let (fs_type, options) = match partition["FSTYPE"].as_str() {
+            "ntfs" => ("ntfs3", format!("uid={},gid={},windows_names", uid, gid)),
+            _ => ("exfat", format!("uid={},gid={},umask=022", uid, gid)),
+        };
+        
+        run_or_warn(config, Cmd::sudo("mkdir").args(["-p", &mount_point]), &format!("Failed to create {}", mount_point));
+        new_fstab.push_str(&format!(
+            "UUID={} {} {} {},nofail,x-systemd.automount 0 0\n",
+            partition["UUID"], mount_point, fs_type, options
+        ));
+        say!("  {} -> {}", partition["NAME"], mount_point);
+    }
+    
+    install_system_content(config, &new_fstab, "/etc/fstab", "644");
+    run_or_warn(config, Cmd::sudo("systemctl").arg("daemon-reload"), "Failed to reload systemd");
+    
+    say!("✓ Shared drives added to /etc/fstab!");
+}
+
+// Read a line from the terminal without echoing it
+fn prompt_secret(prompt: &str) -> String {
+    print!("{}", prompt);
+    let _ = std::io::stdout().flush();
+    
+    let _ = Command::new("stty").arg("-echo").stdin(std::process::Stdio::inherit()).status();
+    let mut secret = String::new();
+    let _ = std::io::stdin().read_line(&mut secret);
+    let _ = Command::new("stty").arg("echo").stdin(std::process::Stdio::inherit()).status();
+    say!();
+    
+    secret.trim_end_matches(['\r', '\n']).to_string()
+}
+
+// Unit name systemd expects for a mount point, e.g. /mnt/nas -> mnt-nas
+fn systemd_escape_path(path: &str) -> String {
+    let output = Command::new("systemd-escape")
+        .args(["--path", path])
+        .output()
+        .expect("Failed to execute systemd-escape");
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+// Write the credentials file for a CIFS share, readable by root only
+fn write_cifs_credentials(config: &Config, name: &str, mount: &MountConfig) -> String {
+    let path = format!("/etc/ass/credentials/{}", name);
+    let username = mount.username.clone().unwrap_or_else(|| {
+        report::fatal(format!("[mounts.{}] is a cifs share but has no username", name));
+    });
+    
+    // Don't re-prompt on every run once the credentials are stored
+    let exists = Command::new("sudo")
+        .args(["test", "-f", &path])
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false);
+    if exists && mount.password.is_none() {
+        if config.verbose {
+            say!("  ✓ Credentials for {} already stored in {}", name, path);
+        }
+        return path;
+    }
+    
+    let password = mount
+        .password
+        .clone()
+        .unwrap_or_else(|| prompt_secret(&tr!("Password for {} on {}: ", username, mount.source)));
+    
+    let mut content = format!("username={}\npassword={}\n", username, password);
+    if let Some(domain) = &mount.domain {
+        content.push_str(&format!("domain={}\n", domain));
+    }
+    
+    run_or_exit(config, Cmd::sudo("install").args(["-d", "-m", "700", "/etc/ass/credentials"]), "Failed to create /etc/ass/credentials");
+    
+    let mut child = Command::new("sudo")
+        .args(["install", "-m", "600", "/dev/stdin", &path])
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .expect("Failed to write credentials file");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(content.as_bytes())
+        .expect("Failed to write credentials file");
+    
+    if !child.wait().map(|s| s.success()).unwrap_or(false) {
+        esay!("Failed to write credentials file {}", path);
+        std::process::exit(1);
+    }
+    
+    path
+}
+
+// The client packages for the kinds of share in [mounts]
+fn mount_packages(mounts: &BTreeMap<String, MountConfig>) -> Vec<&'static str> {
+    let mut packages: Vec<&str> = mounts
+        .values()
+        .map(|m| if m.fs_type == "nfs" { "nfs-utils" } else { "cifs-utils" })
+        .collect();
+    packages.sort();
+    packages.dedup();
+    packages
+}
+
+// Create systemd mount/automount units for the network shares in [mounts]
+fn setup_network_mounts(config: &Config) {
+    let mounts = &config.file.mounts;
+    
+    if mounts.is_empty() {
+        if config.verbose {
+            say!("⏭ No [mounts] configured, skipping network shares");
+        }
+        return;
+    }
+    
+    for (name, mount) in mounts {
+        if mount.fs_type != "nfs" && mount.fs_type != "cifs" {
+            report::fatal(format!("[mounts.{}] has unknown type '{}' (expected nfs or cifs)", name, mount.fs_type));
+        }
+        // The name of a cifs share is its credentials file's name
+        let file_name = !name.is_empty() && !name.starts_with('.') && name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+        if mount.fs_type == "cifs" && !file_name {
+            report::fatal(format!("[mounts.{}] can only be named with letters, digits, -, _ and . (not leading)", name));
+        }
+    }
+    
+    say!("Setting up network shares...");
+    
+    let packages = mount_packages(mounts);
+    
+    if config.dry_run {
+        plan::add(planned_install(config, &packages));
+        for (name, mount) in mounts {
+            let kind = if mount.automount { "automount" } else { "mount" };
+            let mut action = plan::write(
+                "/etc/systemd/system",
+                format!("{}: {} {} -> {} ({} unit)", name, mount.fs_type, mount.source, mount.target, kind),
+            )
+            .effect(format!("{} is mounted on {}", mount.source, mount.target));
+            if mount.fs_type == "cifs" {
+                action = action.detail(format!("credentials in /etc/ass/credentials/{} (mode 600)", name));
+            }
+            plan::add(action);
+        }
+        plan::add(plan::run("sudo systemctl daemon-reload && enable the units"));
+        return;
+    }
+    
+    install_packages(config, &packages, "Failed to install network filesystem tools");
+    
+    let mut units = Vec::new();
+    
+    for (name, mount) in mounts {
+        if config.verbose {
+            say!("Configuring {} ({} -> {})...", name, mount.source, mount.target);
+        }
+        
+        let mut options: Vec<String> = mount.options.iter().cloned().collect();
+        if mount.fs_type == "cifs" {
+            let credentials = write_cifs_credentials(config, name, mount);
+            options.push(format!("credentials={}", credentials));
+        }
+        options.push("_netdev".to_string());
+        
+        let unit_name = systemd_escape_path(&mount.target);
+        let mount_unit = format!(
+            "[Unit]
+Description={} share {}
+After=network-online.target
+Wants=network-online.target
+
+[Mount]
+What={}
+Where={}
+Type={}
+Options={}
+TimeoutSec=30
+{}",
+            mount.fs_type,
+            mount.source,
+            mount.source,
+            mount.target,
+            mount.fs_type,
+            options.join(","),
+            if mount.automount { "" } else { "\n[Install]\nWantedBy=remote-fs.target\n" }
+        );
+        
+        run_or_warn(config, Cmd::sudo("mkdir").args(["-p", &mount.target]), &format!("Failed to create {}", mount.target));
+        install_system_content(config, &mount_unit, &format!("/etc/systemd/system/{}.mount", unit_name), "644");
+        
+        if mount.automount {
+            let automount_unit = format!(
+                "[Unit]
+Description=Automount {}
+
+[Automount]
+Where={}
+TimeoutIdleSec=600
+
+[Install]
+WantedBy=remote-fs.target
+",
+                mount.target, mount.target
+            );
+            install_system_content(config, &automount_unit, &format!("/etc/systemd/system/{}.automount", unit_name), "644");
+            units.push(format!("{}.automount", unit_name));
+        } else {
+            units.push(format!("{}.mount", unit_name));
+        }
+    }
+    
+    run_or_warn(config, Cmd::sudo("systemctl").arg("daemon-reload"), "Failed to reload systemd");
+    
+    // An unreachable server shouldn't abort the whole setup
+    for unit in &units {
+        run_or_warn(config, Cmd::sudo("systemctl").args(["enable", "--now", unit]), &format!("Failed to enable {}", unit));
+    }
+    
+    say!("✓ Network shares configured!");
+}
+
+// smartd-runner hook sending a desktop notification to every graphical session
+const SMARTD_NOTIFY_SCRIPT: &str = r#"#!/bin/sh
+for bus in /run/user/*/bus; do
+    uid=$(basename "$(dirname "$bus")")
+    user=$(id -nu "$uid" 2>/dev/null) || continue
+    sudo -u "$user" DBUS_SESSION_BUS_ADDRESS="unix:path=$bus" \
+        notify-send --urgency=critical --icon=dialog-warning \
+        "S.M.A.R.T. error ($SMARTD_FAILTYPE)" "$SMARTD_MESSAGE"
+done
+"#;
+
+// smartd-runner hook mailing the full report
+const SMARTD_MAIL_SCRIPT: &str = r#"#!/bin/sh
+mail -s "$SMARTD_SUBJECT" "$SMARTD_ADDRESS" < "$SMARTD_FULLMSGFILE"
+"#;
+
+fn smartd_packages(smartd: &SmartdConfig) -> Vec<&'static str> {
+    let mut packages = vec!["smartmontools"];
+    if smartd.notify {
+        packages.push("libnotify");
+    }
+    if smartd.email.is_some() {
+        packages.push("s-nail");
+    }
+    packages
+}
+
+// Install smartmontools and monitor every disk with smartd
+fn setup_smartd(config: &Config) {
+    let smartd = &config.file.smartd;
+    
+    if !smartd.enable {
+        if config.verbose {
+            say!("⏭ [smartd] not enabled, skipping disk health monitoring");
+        }
+        return;
+    }
+    
+    say!("Setting up disk health monitoring (smartd)...");
+    
+    // Scan all disks, short self-test daily at 2am, long test Saturdays at 3am,
+    // and warn on temperature changes/limits
+    let mut directive = String::from("DEVICESCAN -a -o on -S on -n standby,q -s (S/../.././02|L/../../6/03) -W 4,45,55");
+    directive.push_str(&format!(" -m {}", smartd.email.as_deref().unwrap_or("root")));
+    if smartd.notify {
+        directive.push_str(" -M exec /usr/share/smartmontools/smartd-runner");
+    }
+    let smartd_conf = format!("# Generated by ass\n{}\n", directive);
+    
+    let packages = smartd_packages(smartd);
+    
+    if config.dry_run {
+        plan::add(planned_install(config, &packages));
+        plan::add(plan::write("/etc/smartd.conf", format!("Write /etc/smartd.conf: {}", directive)));
+        if smartd.notify {
+            plan::add(plan::write("/etc/smartmontools/run.d", "Install notification hooks in /etc/smartmontools/run.d"));
+        }
+        plan::add(plan::enable("sudo systemctl enable --now smartd.service", "smartd.service"));
+        return;
+    }
+    
+    install_packages(config, &packages, "Failed to install smartmontools");
+    
+    let mut changed = install_system_content(config, &smartd_conf, "/etc/smartd.conf", "644");
+    
+    // With -M exec the runner replaces the default mail, so mail from a hook too
+    if smartd.notify {
+        changed |= install_system_content(config, SMARTD_NOTIFY_SCRIPT, "/etc/smartmontools/run.d/10-notify", "755");
+        if smartd.email.is_some() {
+            changed |= install_system_content(config, SMARTD_MAIL_SCRIPT, "/etc/smartmontools/run.d/20-mail", "755");
+        }
+    }
+    
+    run_or_exit(
+        config,
+        Cmd::sudo("systemctl")
+            .args(["enable", "--now", "smartd.service"]),
+        "Failed to enable smartd.service",
+    );
+    
+    if changed {
+        run_or_warn(config, Cmd::sudo("systemctl").args(["restart", "smartd.service"]), "Failed to restart smartd.service");
+    }
+    
+    say!("✓ smartd is monitoring your disks!");
+}
+
+// Set up systemd-oomd or earlyoom so a runaway browser can't freeze the desktop
+fn setup_oom_protection(config: &Config) {
+    let oom = &config.file.oom;
+    
+    let Some(method) = oom.method.as_deref() else {
+        if config.verbose {
+            say!("⏭ No [oom] method configured, skipping OOM protection");
+        }
+        return;
+    };
+    
+    let other = match method {
+        "systemd-oomd" => "earlyoom",
+        "earlyoom" => "systemd-oomd",
+        _ => unreachable!("validated when loading the config"),
+    };
+    
+    say!("Setting up OOM protection ({})...", method);
+    
+    let user_dropin = format!(
+        "[Service]\nManagedOOMMemoryPressure=kill\nManagedOOMMemoryPressureLimit={}\n",
+        oom.memory_pressure_limit
+    );
+    let root_dropin = "[Slice]\nManagedOOMSwap=kill\n";
+    let earlyoom_args = format!(
+        "EARLYOOM_ARGS=\"-r 3600 -m {} -n --avoid '(^|/)(init|systemd|Xorg|Hyprland|sway|sshd)$'\"\n",
+        oom.min_free_percent
+    );
+    
+    if config.dry_run {
+        if method == "systemd-oomd" {
+            plan::add(plan::write(
+                "/etc/systemd/system/user@.service.d/10-oomd.conf",
+                format!(
+                    "Write /etc/systemd/system/user@.service.d/10-oomd.conf (ManagedOOMMemoryPressureLimit={})",
+                    oom.memory_pressure_limit
+                ),
+            ));
+            plan::add(plan::write(
+                "/etc/systemd/system/-.slice.d/10-oomd.conf",
+                "Write /etc/systemd/system/-.slice.d/10-oomd.conf (ManagedOOMSwap=kill)",
+            ));
+            plan::add(plan::enable("sudo systemctl enable --now systemd-oomd.service", "systemd-oomd.service"));
+        } else {
+            plan::add(planned_install(config, &["earlyoom", "libnotify"]));
+            plan::add(plan::write("/etc/default/earlyoom", format!("Write /etc/default/earlyoom ({}% minimum free)", oom.min_free_percent)));
+            plan::add(plan::enable("sudo systemctl enable --now earlyoom.service", "earlyoom.service"));
+        }
+        plan::add(plan::other(format!("Disable {} if it is enabled", other)));
+        return;
+    }
+    
+    // Two OOM killers fighting each other is worse than none. Failing is
+    // fine, it usually just isn't installed.
+    let _ = run_command(config, Cmd::sudo("systemctl").args(["disable", "--now", &format!("{}.service", other)]).quiet());
+    
+    if method == "systemd-oomd" {
+        install_system_content(config, &user_dropin, "/etc/systemd/system/user@.service.d/10-oomd.conf", "644");
+        install_system_content(config, root_dropin, "/etc/systemd/system/-.slice.d/10-oomd.conf", "644");
+        run_or_warn(config, Cmd::sudo("systemctl").arg("daemon-reload"), "Failed to reload systemd");
+    } else {
+        install_packages(config, &["earlyoom", "libnotify"], "Failed to install earlyoom");
+        install_system_content(config, &earlyoom_args, "/etc/default/earlyoom", "644");
+    }
+    
+    let service = format!("{}.service", method);
+    run_or_exit(
+        config,
+        Cmd::sudo("systemctl")
+            .args(["enable", "--now", &service]),
+        &format!("Failed to enable {}", service),
+    );
+    
+    run_or_warn(config, Cmd::sudo("systemctl").args(["restart", &service]), &format!("Failed to restart {}", service));
+    
+    say!("✓ OOM protection enabled with {}!", method);
+}
+
+// Enable periodic TRIM, check LUKS discards and report/set I/O schedulers
+fn setup_ssd_maintenance(config: &Config) {
+    let ssd = &config.file.ssd;
+    
+    if !ssd.enable {
+        if config.verbose {
+            say!("⏭ [ssd] not enabled, skipping SSD maintenance");
+        }
+        return;
+    }
+    
+    say!("Setting up SSD maintenance...");
+    
+    let mut rules = String::new();
+    if let Some(scheduler) = &ssd.ssd_scheduler {
+        rules.push_str(&format!(
+            "ACTION==\"add|change\", KERNEL==\"sd[a-z]*|mmcblk[0-9]*|nvme[0-9]*n[0-9]*\", ATTR{{queue/rotational}}==\"0\", ATTR{{queue/scheduler}}=\"{}\"\n",
+            scheduler
+        ));
+    }
+    if let Some(scheduler) = &ssd.hdd_scheduler {
+        rules.push_str(&format!(
+            "ACTION==\"add|change\", KERNEL==\"sd[a-z]*\", ATTR{{queue/rotational}}==\"1\", ATTR{{queue/scheduler}}=\"{}\"\n",
+            scheduler
+        ));
+    }
+    
+    if config.dry_run {
+        plan::add(plan::enable("sudo systemctl enable --now fstrim.timer", "fstrim.timer"));
+        plan::add(plan::check("Check that LUKS mappings allow discards"));
+        plan::add(plan::check("Report the active I/O scheduler for each disk"));
+        if !rules.is_empty() {
+            plan::add(
+                plan::write("/etc/udev/rules.d/60-ioschedulers.rules", "Write /etc/udev/rules.d/60-ioschedulers.rules:")
+                    .details(rules.lines()),
+            );
+        }
+        return;
+    }
+    
+    run_or_exit(
+        config,
+        Cmd::sudo("systemctl")
+            .args(["enable", "--now", "fstrim.timer"]),
+        "Failed to enable fstrim.timer",
+    );
+    say!("  ✓ fstrim.timer enabled (weekly TRIM)");
+    
+    // TRIM does nothing on encrypted volumes unless the mapping passes discards through
+    let output = Command::new("lsblk")
+        .args(["-P", "-o", "NAME,TYPE"])
+        .output()
+        .expect("Failed to execute lsblk");
+    let crypt_devices: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(parse_lsblk_pairs)
+        .filter(|p| p.get("TYPE").map(|t| t.as_str()) == Some("crypt"))
+        .filter_map(|p| p.get("NAME").cloned())
+        .collect();
+    
+    for name in &crypt_devices {
+        let output = Command::new("sudo")
+            .args(["dmsetup", "table", name])
+            .output()
+            .expect("Failed to execute dmsetup");
+        let table = String::from_utf8_lossy(&output.stdout);
+        
+        if table.contains("allow_discards") {
+            say!("  ✓ LUKS mapping {} allows discards", name);
+        } else {
+            config.warnings.add(
+                WarningKind::Failure,
+                format!(
+                    "LUKS mapping {} does not allow discards, TRIM won't reach the SSD (fix: sudo cryptsetup --allow-discards --persistent refresh {})",
+                    name, name
+                ),
+            );
+        }
+    }
+    
+    // Active scheduler is the bracketed entry, e.g. "[none] mq-deadline kyber bfq"
+    let mut disks: Vec<_> = std::fs::read_dir("/sys/block")
+        .map(|entries| entries.flatten().map(|e| e.file_name().to_string_lossy().to_string()).collect())
+        .unwrap_or_else(|_| Vec::new());
+    disks.retain(|d| !d.starts_with("loop") && !d.starts_with("ram") && !d.starts_with("zram") && !d.starts_with("dm-"));
+    disks.sort();
+    
+    for disk in &disks {
+        let scheduler = std::fs::read_to_string(format!("/sys/block/{}/queue/scheduler", disk)).unwrap_or_default();
+        let rotational = std::fs::read_to_string(format!("/sys/block/{}/queue/rotational", disk)).unwrap_or_default();
+        let active = scheduler
+            .split_whitespace()
+            .find(|s| s.starts_with('['))
+            .map(|s| s.trim_matches(|c| c == '[' || c == ']').to_string())
+            .unwrap_or_else(|| scheduler.trim().to_string());
+        let kind = if rotational.trim() == "1" { "HDD" } else { "SSD" };
+        say!("  {} ({}): scheduler {}", disk, kind, active);
+    }
+    
+    if !rules.is_empty() && install_system_content(config, &rules, "/etc/udev/rules.d/60-ioschedulers.rules", "644") {
+        run_or_warn(config, Cmd::sudo("udevadm").args(["control", "--reload-rules"]), "Failed to reload udev rules");
+        run_or_warn(config, Cmd::sudo("udevadm").args(["trigger", "--subsystem-match=block"]), "Failed to trigger udev");
+    }
+    
+    say!("✓ SSD maintenance configured!");
+}
+
+// Setup Chaotic AUR repository
+const CHAOTIC_KEY: &str = "3056513887B78AEB";
+
+// Receive a key into the pacman keyring, going down [keys] keyservers and
+// finally the key's HTTPS URL instead of failing on one flaky keyserver. All
+// of them failing is retried as the network being down for a moment.
+fn receive_pacman_key(config: &Config, key: &str) -> bool {
+    run_with_retry(&format!("Receiving key {}", key), || {
+        if try_receive_pacman_key(config, key) { Ok(()) } else { Err("no keyserver or URL had it") }
+    })
+    .is_ok()
+}
+
+fn try_receive_pacman_key(config: &Config, key: &str) -> bool {
+    let timeout = config.file.keys.timeout.to_string();
+    
+    for keyserver in &config.file.keys.keyservers {
+        if config.verbose {
+            say!("  Trying {}...", keyserver);
+        }
+        let receive = Cmd::sudo("timeout").args([&timeout, "pacman-key", "--recv-keys", key, "--keyserver", keyserver]);
+        if run_command(config, receive).is_ok() {
+            return true;
+        }
+        say!("  ⚠ Couldn't get key {} from {}, trying next keyserver", key, keyserver);
+    }
+    
+    let Some(url) = config.file.keys.urls.get(key) else {
+        return false;
+    };
+    
+    if config.verbose {
+        say!("  Downloading key from {}...", url);
+    }
+    let key_path = config.work_dir.path(&format!("{}.asc", key));
+    let download = Cmd::new("curl")
+        .args(["--proto", "=https", "--tlsv1.2", "-sSfL", "--max-time", &timeout, url, "-o", &key_path])
+        .args(curl_rate_args(config));
+    
+    run_command(config, download).is_ok() && run_command(config, Cmd::sudo("pacman-key").args(["--add", &key_path])).is_ok()
+}
+
+// Fetch <mirror>/<package>.pkg.tar.zst and its .sig from the first mirror
+// that serves both and whose signature checks out against the chaotic key,
+// going through the mirrors again with backoff when none of them does
+fn download_chaotic_package(config: &Config, package: &str) -> Option<String> {
+    run_with_retry(&format!("Downloading {}", package), || {
+        try_download_chaotic_package(config, package).ok_or("no mirror served it")
+    })
+    .ok()
+}
+
+fn try_download_chaotic_package(config: &Config, package: &str) -> Option<String> {
+    let file_name = format!("{}.pkg.tar.zst", package);
+    let path = config.work_dir.path(&file_name);
+    let sig_path = format!("{}.sig", path);
+    
+    for mirror in &config.file.chaotic_aur.mirrors {
+        let url = format!("{}/{}", mirror.trim_end_matches('/'), file_name);
+        if config.verbose {
+            say!("  Trying {}...", url);
+        }
+        
+        let downloaded = [(url.clone(), &path), (format!("{}.sig", url), &sig_path)]
+            .iter()
+            .all(|(url, target)| {
+                let download = Cmd::new("curl")
+                    .args(["--proto", "=https", "--tlsv1.2", "-sSfL", "--connect-timeout", "15", url, "-o", target])
+                    .args(curl_rate_args(config));
+                run_command(config, download).is_ok()
+            });
+        
+        if !downloaded {
+            say!("  ⚠ {} unavailable from {}, trying next mirror", package, mirror);
+            continue;
+        }
+        
+        let verified = Command::new("sudo")
+            .args(["pacman-key", "--verify", &sig_path, &path])
+            .output()
+            .map(|output| output.status.success())
+            .unwrap_or(false);
+        
+        if verified {
+            return Some(path);
+        }
+        config.warnings.add(WarningKind::Failure, format!("Signature check failed for {} from {}", package, mirror));
+    }
+    
+    None
+}
+
+const OFFICIAL_REPOS: [&str; 3] = ["core", "extra", "multilib"];
+
+// pacman takes a package from the first repository that has it, so
+// [chaotic-aur] above an official repository would replace official
+// packages with its rebuilds. Returns pacman.conf with it moved to the end.
+fn chaotic_repo_last(pacman_conf: &str) -> Option<String> {
+    let lines: Vec<&str> = pacman_conf.lines().collect();
+    let section = |line: &str| line.trim().strip_prefix('[').and_then(|rest| rest.strip_suffix(']')).map(str::to_string);
+    
+    let start = lines.iter().position(|line| section(line).as_deref() == Some("chaotic-aur"))?;
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| section(line).is_some())
+        .map(|offset| start + 1 + offset)
+        .unwrap_or(lines.len());
+    let official_after = lines[end..]
+        .iter()
+        .any(|line| section(line).is_some_and(|name| OFFICIAL_REPOS.contains(&name.as_str())));
+    if !official_after {
+        return None;
+    }
+    
+    // Comments in between usually describe the next section, they stay put
+    let (block, comments): (Vec<&str>, Vec<&str>) = lines[start..end]
+        .iter()
+        .partition(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'));
+    let comments = comments.into_iter().filter(|line| !line.trim().is_empty());
+    let mut reordered: Vec<&str> = lines[..start].iter().copied().chain(comments).chain(lines[end..].iter().copied()).collect();
+    while reordered.last().is_some_and(|line| line.trim().is_empty()) {
+        reordered.pop();
+    }
+    reordered.push("");
+    reordered.extend(block);
+    Some(reordered.join("\n") + "\n")
+}
+
+fn setup_chaotic_aur(config: &Config) {
+    say!("Setting up Chaotic AUR...");
+    
+    if config.dry_run {
+        plan::add(plan::check("Check if Chaotic AUR is already configured (and below the official repositories)"));
+        let mut receive = plan::run(format!("sudo pacman-key --recv-keys {}", CHAOTIC_KEY))
+            .detail("from the first working keyserver:")
+            .details(config.file.keys.keyservers.iter().cloned());
+        if let Some(url) = config.file.keys.urls.get(CHAOTIC_KEY) {
+            receive = receive.detail(format!("falling back to sudo pacman-key --add <{}>", url));
+        }
+        plan::add(receive);
+        plan::add(plan::run(format!("sudo pacman-key --lsign-key {}", CHAOTIC_KEY)));
+        plan::add(
+            plan::other("Download chaotic-keyring and chaotic-mirrorlist (+ .sig) from the first working mirror:")
+                .details(config.file.chaotic_aur.mirrors.iter().cloned()),
+        );
+        plan::add(plan::install(
+            "pacman-key --verify each package, then sudo pacman -U --noconfirm <package>",
+            &["chaotic-keyring", "chaotic-mirrorlist"],
+        ));
+        plan::add(plan::write("/etc/pacman.conf", "Append chaotic-aur config to /etc/pacman.conf"));
+        plan::add(plan::run("sudo pacman -Syu --noconfirm").effect("upgrades the system"));
+        return;
+    }
+    
+    // Check if Chaotic AUR is already configured
+    let pacman_conf = std::fs::read_to_string("/etc/pacman.conf")
+        .unwrap_or_default();
+    
+    if pacman_conf.contains("[chaotic-aur]") {
+        if let Some(reordered) = chaotic_repo_last(&pacman_conf) {
+            say!("Moving [chaotic-aur] below the official repositories...");
+            install_system_content(config, &reordered, "/etc/pacman.conf", "644");
+        }
+        if config.verbose {
+            say!("✓ Chaotic AUR already configured");
+        } else {
+            say!("✓ Chaotic AUR already configured, skipping setup");
+        }
+        return;
+    }
+    
+    // Receive GPG key
+    if config.verbose {
+        say!("Receiving Chaotic AUR GPG key...");
+    }
+    if !receive_pacman_key(config, CHAOTIC_KEY) {
+        esay!("Failed to receive Chaotic AUR GPG key");
+        std::process::exit(1);
+    }
+    
+    // Locally sign the key
+    if config.verbose {
+        say!("Signing Chaotic AUR GPG key...");
+    }
+    run_or_exit(
+        config,
+        Cmd::sudo("pacman-key")
+            .args(["--lsign-key", CHAOTIC_KEY]),
+        "Failed to sign Chaotic AUR GPG key",
+    );
+    
+    // Install chaotic-keyring and chaotic-mirrorlist
+    for package in ["chaotic-keyring", "chaotic-mirrorlist"] {
+        if config.verbose {
+            say!("Installing {}...", package);
+        }
+        
+        let Some(path) = download_chaotic_package(config, package) else {
+            esay!("Failed to download a verified {} from any chaotic mirror", package);
+            std::process::exit(1);
+        };
+        
+        run_or_exit(
+            config,
+            pacman_cmd(config)
+                .args(["-U", "--noconfirm", &path]),
+            &format!("Failed to install {}", package),
+        );
+    }
+    
+    // Append to /etc/pacman.conf
+    if config.verbose {
+        say!("Adding Chaotic AUR to pacman.conf...");
+    }
+    
+    let chaotic_conf = config.work_dir.path("chaotic-aur.conf");
+    
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&chaotic_conf)
+        .expect("Failed to create temp file");
+    
+    writeln!(file, "\n[chaotic-aur]").expect("Failed to write");
+    writeln!(file, "Include = /etc/pacman.d/chaotic-mirrorlist").expect("Failed to write");
+    
+    journal::file_changing("/etc/pacman.conf");
+    run_or_exit(
+        config,
+        Cmd::sudo("tee").args(["-a", "/etc/pacman.conf"]).stdin_file(&chaotic_conf).quiet(),
+        "Failed to update pacman.conf",
+    );
+    
+    // Update system
+    if config.verbose {
+        say!("Updating system with Chaotic AUR...");
+    }
+    run_or_exit(config, package_manager(config).refresh(), "Failed to update system");
+    
+    say!("✓ Chaotic AUR setup complete!");
+}
+
+// Provides `dnf copr`, dnf 5 has its own plugins package
+fn copr_plugin() -> &'static str {
+    if Path::new("/usr/bin/dnf5").exists() { "dnf5-plugins" } else { "dnf-plugins-core" }
+}
+
+// Where `dnf copr enable` puts the repository, groups are spelled group_<name>
+fn copr_repo_file(repo: &str) -> String {
+    let repo = repo.strip_prefix('@').map_or(repo.to_string(), |group| format!("group_{}", group));
+    format!("/etc/yum.repos.d/_copr:copr.fedorainfracloud.org:{}.repo", repo.replace('/', ":"))
+}
+
+// COPR is to Fedora what the AUR is to Arch. Enabled before the package list
+// is installed so it can name their packages.
+fn setup_copr(config: &Config) {
+    say!("Enabling COPR repositories...");
+    install_packages(config, &[copr_plugin()], "Failed to install the dnf copr plugin");
+    
+    for repo in &config.file.copr.repos {
+        let repo_file = copr_repo_file(repo);
+        if Path::new(&repo_file).exists() {
+            if config.verbose {
+                say!("✓ {} already enabled", repo);
+            }
+            continue;
+        }
+        journal::file_changing(&repo_file);
+        let Some(enable) = package_manager(config).add_repo(repo) else {
+            continue;
+        };
+        if run_or_warn(config, enable, &format!("Failed to enable COPR {}", repo))
+            && !config.dry_run
+        {
+            say!("✓ Enabled {}", repo);
+        }
+    }
+}
+
+// Configure pacman.conf with performance optimizations
+fn configure_pacman(config: &Config) {
+    say!("Configuring pacman.conf...");
+    
+    if config.dry_run {
+        plan::add(plan::write("/etc/pacman.conf", "Uncomment 'Color' in /etc/pacman.conf"));
+        plan::add(plan::write("/etc/pacman.conf", "Comment out 'NoProgressBar' in /etc/pacman.conf"));
+        plan::add(plan::write("/etc/pacman.conf", "Set 'ParallelDownloads = 25' in /etc/pacman.conf"));
+        plan::add(plan::write("/etc/pacman.conf", "Add 'ILoveCandy' to /etc/pacman.conf"));
+        return;
+    }
+    
+    // Read pacman.conf
+    let pacman_conf_content = std::fs::read_to_string("/etc/pacman.conf")
+        .expect("Failed to read /etc/pacman.conf");
+    
+    let mut modified_content = String::new();
+    let mut in_options_section = false;
+    let mut ilovecandy_added = false;
+    
+    for line in pacman_conf_content.lines() {
+        // Detect [options] section
+        if line.trim() == "[options]" {
+            in_options_section = true;
+            modified_content.push_str(line);
+            modified_content.push('\n');
+            continue;
+        }
+        
+        // Detect end of [options] section
+        if in_options_section && line.trim().starts_with('[') && line.trim() != "[options]" {
+            // Add ILoveCandy before leaving options section if not already added
+            if !ilovecandy_added {
+                modified_content.push_str("ILoveCandy\n");
+                ilovecandy_added = true;
+            }
+            in_options_section = false;
+        }
+        
+        if in_options_section {
+            // Uncomment Color
+            if line.trim() == "#Color" {
+                modified_content.push_str("Color\n");
+                if config.verbose {
+                    say!("  ✓ Enabled Color");
+                }
+                continue;
+            }
+            
+            // Keep Color if already uncommented
+            if line.trim() == "Color" {
+                modified_content.push_str(line);
+                modified_content.push('\n');
+                continue;
+            }
+            
+            // Comment out NoProgressBar if it's uncommented
+            if line.trim() == "NoProgressBar" {
+                modified_content.push_str("#NoProgressBar\n");
+                if config.verbose {
+                    say!("  ✓ Disabled NoProgressBar");
+                }
+                continue;
+            }
+            
+            // Keep NoProgressBar if already commented
+            if line.trim() == "#NoProgressBar" {
+                modified_content.push_str(line);
+                modified_content.push('\n');
+                continue;
+            }
+            
+            // Update ParallelDownloads
+            if line.trim().starts_with("ParallelDownloads") || line.trim().starts_with("#ParallelDownloads") {
+                modified_content.push_str("ParallelDownloads = 25\n");
+                if config.verbose {
+                    say!("  ✓ Set ParallelDownloads = 25");
+                }
+                continue;
+            }
+            
+            // Skip ILoveCandy if it already exists
+            if line.trim() == "ILoveCandy" {
+                ilovecandy_added = true;
+                modified_content.push_str(line);
+                modified_content.push('\n');
+                continue;
+            }
+        }
+        
+        modified_content.push_str(line);
+        modified_content.push('\n');
+    }
+    
+    // Write to temporary file
+    let temp_file = config.work_dir.path("pacman.conf");
+    std::fs::write(&temp_file, modified_content)
+        .expect("Failed to write temporary pacman.conf");
+    
+    // Copy to /etc/pacman.conf using sudo
+    journal::file_changing("/etc/pacman.conf");
+    run_or_exit(config, Cmd::sudo("cp").args([&temp_file, "/etc/pacman.conf"]), "Failed to update /etc/pacman.conf");
+    
+    say!("✓ Pacman.conf configured successfully!");
+}
+
+// Converge an already set up machine toward the config again: upgrade, pull
+// the dotfiles, install what the package list gained and redeploy. Nothing
+// in here asks questions, so `ass schedule` can run it from a timer.
+fn run_update(config: &Config) {
+    if checkpoint::phase() == "post-nix" {
+        report::fatal("Setup is still waiting for its second phase, run ass to finish it first");
+    }
+    
+    // Nobody is around to type a sudo password when a timer starts this
+    let unattended = !std::io::stdin().is_terminal();
+    if unattended && !config.dry_run && !passwordless_sudo() {
+        report::fatal("ass update needs passwordless sudo when run unattended");
+    }
+    
+    let dotfiles_path = paths::get().dotfiles();
+    if !config.dry_run && !Path::new(&dotfiles_path).exists() {
+        report::fatal(format!("{} doesn't exist, run ass to set this machine up first", dotfiles_path));
+    }
+    
+    say!("Updating towards the configured state...");
+    if !config.dry_run {
+        journal::begin(&history::begin("update", &config.profile.name, Vec::new(), false));
+    }
+    
+    // A scheduled run that fails still sends its report
+    let reporting = unattended && !config.dry_run && notify::enabled(&config.file.schedule);
+    if reporting {
+        let schedule = config.file.schedule.clone();
+        report::on_fatal(move |error| {
+            for failure in notify::send(&schedule, "failed", Some(error), pacnew_files) {
+                esay!("⚠ {}", tr!("Warning: {}", failure));
+            }
+        });
+    }
+    
+    // On Arch the AUR helper upgrades the AUR packages along with the rest
+    run_step(config, "update_system", || {
+        let upgrade = match pkg::backend() {
+            Backend::Pacman => aur_helper(config).upgrade(),
+            _ => package_manager(config).upgrade(),
+        };
+        for cmd in upgrade {
+            run_or_exit(config, cmd, "Failed to upgrade the system");
+        }
+    });
+    run_step(config, "pull_dotfiles", || {
+        run_or_warn(
+            config,
+            Cmd::new("git").args(["-C", &dotfiles_path, "pull", "--ff-only"]).network(),
+            "Failed to pull ~/dotfiles",
+        );
+    });
+    run_step(config, "setup_dotfiles", || setup_dotfiles(config));
+    if !config.file.repos.is_empty() {
+        run_step(config, "clone_repos", || clone_repos(config));
+    }
+    run_step(config, "deploy_system_dotfiles", || deploy_system_dotfiles(config));
+    run_step(config, "apply_file_assertions", || apply_file_assertions(config));
+    
+    if config.dry_run || which("home-manager").is_some() {
+        run_step(config, "restow_dotfiles", || {
+            for package in ["home-manager", "nix"] {
+                let args = match stow_target(config, &dotfiles_path, package) {
+                    Ok(target) => stow_args(&dotfiles_path, package, target.as_deref()),
+                    Err(e) => {
+                        config.warnings.add(WarningKind::Skipped, format!("Not restowing {}: {}", package, e));
+                        continue;
+                    }
+                };
+                run_or_warn(
+                    config,
+                    Cmd::new("stow").args(args).args(["--restow", package]).current_dir(&dotfiles_path),
+                    &format!("Failed to restow the {} config", package),
+                );
+            }
+        });
+        run_step(config, "rebuild_home_manager", || rebuild_home_manager(config));
+        if without_nix_packages(config).is_none() {
+            run_step(config, "install_nix_packages", || install_nix_packages(config));
+        }
+    } else {
+        config.warnings.add(WarningKind::Skipped, "home-manager isn't installed, not rebuilding");
+    }
+    
+    // run_step keeps its bookkeeping in the checkpoint, which shouldn't
+    // outlive an update
+    if !config.dry_run {
+        checkpoint::clear_steps();
+    }
+    
+    if config.dry_run {
+        say!("\n{}", tr!("=== DRY RUN COMPLETE ==="));
+        plan::finish("update", &config.profile.name);
+    } else {
+        say!("\n✓ Update complete!");
+    }
+    let outcome = if config.warnings.is_empty() { "success" } else { "warnings" };
+    history::finish(outcome);
+    if reporting {
+        for failure in notify::send(&config.file.schedule, outcome, None, pacnew_files) {
+            config.warnings.add(WarningKind::Failure, failure);
+        }
+    }
+    print_hm_news(config);
+}
+
+const SCHEDULE_SERVICE_PATH: &str = "/etc/systemd/system/ass-update.service";
+const SCHEDULE_TIMER_PATH: &str = "/etc/systemd/system/ass-update.timer";
+
+// ass update with the --config and --profile this run was given, so the
+// timer converges toward the same ones
+fn schedule_command(exe: &str) -> String {
+    let mut words = vec![exe.to_string(), "update".to_string()];
+    if early_option("--config").is_some() {
+        let path = config::config_path();
+        let path = std::path::absolute(&path).map(|absolute| absolute.to_string_lossy().to_string()).unwrap_or(path);
+        words.extend(["--config".to_string(), path]);
+    }
+    if let Some(profile) = early_option("--profile") {
+        words.extend(["--profile".to_string(), profile]);
+    }
+    words.iter().map(|word| reboot::systemd_quote(word)).collect::<Vec<_>>().join(" ")
+}
+
+fn schedule_service(command: &str, user: &str, home: &str, max_runtime: &str) -> String {
+    format!(
+        "[Unit]
+Description=Converge this machine toward its ass config
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=oneshot
+User={}
+Environment=HOME={} NO_COLOR=1
+ExecStart={}
+TimeoutStartSec={}
+Nice=10
+IOSchedulingClass=idle
+",
+        user, home, command, max_runtime
+    )
+}
+
+fn schedule_timer(at: &str) -> String {
+    format!(
+        "[Unit]
+Description=Run ass update on a schedule
+
+[Timer]
+OnCalendar={}
+Persistent=true
+RandomizedDelaySec=30m
+
+[Install]
+WantedBy=timers.target
+",
+        at
+    )
+}
+
+// Install a oneshot unit running `ass update` as this user, plus a timer
+// unless [schedule] at is empty
+fn schedule_update(config: &Config) {
+    let schedule = &config.file.schedule;
+    let exe = env::current_exe().expect("Failed to find the ass executable").to_string_lossy().to_string();
+    let user = env::var("USER").expect("USER environment variable not set");
+    let home = &paths::get().home;
+    let command = schedule_command(&exe);
+    
+    if exe.contains("/target/debug/") || exe.contains("/target/release/") {
+        config.warnings.add(
+            WarningKind::Skipped,
+            format!("{} looks like a build directory, the unit will break if it moves (install ass first)", exe),
+        );
+    }
+    
+    say!("Scheduling ass update...");
+    
+    if config.dry_run {
+        plan::add(plan::write(
+            SCHEDULE_SERVICE_PATH,
+            format!("Write {} (User={}, ExecStart={}, TimeoutStartSec={})", SCHEDULE_SERVICE_PATH, user, command, schedule.max_runtime),
+        ));
+        if schedule.at.is_empty() {
+            plan::add(plan::run(Cmd::sudo("systemctl").arg("daemon-reload")));
+        } else {
+            plan::add(plan::write(SCHEDULE_TIMER_PATH, format!("Write {} (OnCalendar={})", SCHEDULE_TIMER_PATH, schedule.at)));
+            plan::add(plan::enable("sudo systemctl daemon-reload && sudo systemctl enable --now ass-update.timer", "ass-update.timer"));
+        }
+        return;
+    }
+    
+    install_system_content(config, &schedule_service(&command, &user, home, &schedule.max_runtime), SCHEDULE_SERVICE_PATH, "644");
+    if !schedule.at.is_empty() {
+        install_system_content(config, &schedule_timer(&schedule.at), SCHEDULE_TIMER_PATH, "644");
+    }
+    run_or_exit(config, Cmd::sudo("systemctl").arg("daemon-reload"), "Failed to reload systemd");
+    
+    if schedule.at.is_empty() {
+        say!("✓ Installed ass-update.service, start it with: sudo systemctl start ass-update.service");
+    } else {
+        run_or_exit(
+            config,
+            Cmd::sudo("systemctl").args(["enable", "--now", "ass-update.timer"]),
+            "Failed to enable ass-update.timer",
+        );
+        say!("✓ ass update will run at {} (systemctl list-timers ass-update.timer)", schedule.at);
+        say!("  journalctl -t ass shows what the runs did, step by step");
+    }
+    
+    let destinations: Vec<&str> = [&schedule.report_email, &schedule.report_webhook].into_iter().flatten().map(String::as_str).collect();
+    if !destinations.is_empty() {
+        say!("  Run reports ({}) go to {}", schedule.report, destinations.join(" and "));
+    }
+}
+
+fn remove_schedule(config: &Config) {
+    say!("Removing the ass update schedule...");
+    
+    if Path::new(SCHEDULE_TIMER_PATH).exists() {
+        run_or_warn(
+            config,
+            Cmd::sudo("systemctl").args(["disable", "--now", "ass-update.timer"]),
+            "Failed to disable ass-update.timer",
+        );
+    }
+    run_or_exit(
+        config,
+        Cmd::sudo("rm").args(["-f", SCHEDULE_SERVICE_PATH, SCHEDULE_TIMER_PATH]),
+        "Failed to remove the ass-update units",
+    );
+    run_or_exit(config, Cmd::sudo("systemctl").arg("daemon-reload"), "Failed to reload systemd");
+    
+    if !config.dry_run {
+        say!("✓ ass update is no longer scheduled");
+    }
+}
+
+// A step of the setup as main() runs it
+struct StepSpec {
+    name: &'static str,
+    // Install state it runs in: "start", or "post-nix" after logging back in
+    phase: &'static str,
+    // Why the step doesn't apply to this machine or config, None to run it
+    skip: fn(&Config) -> Option<String>,
+    run: fn(&Config),
+    // Runs side by side with the neighbouring parallel steps
+    parallel: bool,
+}
+
+const fn step(name: &'static str, phase: &'static str, run: fn(&Config)) -> StepSpec {
+    StepSpec { name, phase, skip: |_| None, run, parallel: false }
+}
+
+const fn step_unless(name: &'static str, phase: &'static str, skip: fn(&Config) -> Option<String>, run: fn(&Config)) -> StepSpec {
+    StepSpec { name, phase, skip, run, parallel: false }
+}
+
+fn unless_desktop(config: &Config) -> Option<String> {
+    (!config.is_desktop()).then(|| format!("{} profile", config.profile.name))
+}
+
+// Steps about pacman, the AUR or Arch repositories
+fn unless_pacman(_config: &Config) -> Option<String> {
+    (pkg::backend() != Backend::Pacman).then(|| format!("{} system", pkg::backend().name()))
+}
+
+// Steps only some profiles run
+fn opt_in(config: &Config, step: &str) -> Option<String> {
+    (!config.profile.steps.iter().any(|wanted| wanted == step)).then(|| format!("not part of the {} profile", config.profile.name))
+}
+
+// No console, bootloader, udev, getty or block devices of our own in a container
+fn in_container(config: &Config) -> Option<String> {
+    config.context.is_container().then(|| format!("running in {}", config.context.describe()))
+}
+
+// SMART and SSD tuning mean nothing on virtual disks
+fn without_hardware(config: &Config) -> Option<String> {
+    (!config.context.has_hardware()).then(|| format!("running in {}", config.context.describe()))
+}
+
+// Steps built around systemd units (mount units, timers, getty drop-ins)
+fn unless_systemd() -> Option<String> {
+    (init::init() != Init::Systemd).then(|| format!("{} instead of systemd", init::init().name()))
+}
+
+// Nothing listed and nothing installed by an earlier run to take out again
+fn without_nix_packages(config: &Config) -> Option<String> {
+    (config.file.nix.packages.is_empty() && nixprofile::recorded().is_empty()).then(|| "no [nix] packages".to_string())
+}
+
+// Every step in the order a full run goes through them
+const STEPS: [StepSpec; 44] = [
+    step("check_deps", "start", check_deps),
+    // Configure pacman before installing anything
+    step_unless("configure_pacman", "start", unless_pacman, configure_pacman),
+    step("install_packages", "start", |config| {
+        *config.batched.lock().unwrap_or_else(|e| e.into_inner()) = install_declared_packages(config);
+    }),
+    step_unless("install_aur_helper", "start", unless_pacman, install_aur_helper),
+    step_unless(
+        "setup_chaotic_aur",
+        "start",
+        |config| unless_pacman(config).or_else(|| (!config.file.chaotic_aur.enable).then(|| "disabled in config".to_string())),
+        setup_chaotic_aur,
+    ),
+    step_unless(
+        "setup_copr",
+        "start",
+        |config| {
+            (pkg::backend() != Backend::Dnf)
+                .then(|| format!("{} system", pkg::backend().name()))
+                .or_else(|| config.file.copr.repos.is_empty().then(|| "no [copr] repos".to_string()))
+        },
+        setup_copr,
+    ),
+    step("setup_dotfiles", "start", setup_dotfiles),
+    step_unless("setup_desktop_portal", "start", unless_desktop, setup_desktop_portal),
+    step_unless("setup_polkit_agent", "start", unless_desktop, setup_polkit_agent),
+    step_unless("setup_removable_media", "start", unless_desktop, setup_removable_media),
+    step_unless("verify_wayland_session", "start", unless_desktop, verify_wayland_session),
+    step_unless("setup_display_manager", "start", |config| unless_desktop(config).or_else(unless_systemd), setup_display_manager),
+    step_unless(
+        "setup_autologin",
+        "start",
+        |config| unless_desktop(config).or_else(|| in_container(config)).or_else(unless_systemd),
+        setup_autologin,
+    ),
+    step_unless("install_guest_tools", "start", |_| unless_systemd(), install_guest_tools),
+    step_unless("setup_gaming", "start", |config| opt_in(config, "setup_gaming").or_else(|| unless_pacman(config)), setup_gaming),
+    step_unless("apply_system_tweaks", "start", in_container, apply_system_tweaks),
+    step_unless("setup_shared_drives", "start", in_container, setup_shared_drives),
+    step_unless("setup_network_mounts", "start", |_| unless_systemd(), setup_network_mounts),
+    step_unless("setup_smartd", "start", |config| without_hardware(config).or_else(unless_systemd), setup_smartd),
+    step_unless("setup_oom_protection", "start", |_| unless_systemd(), setup_oom_protection),
+    step_unless("setup_ssd_maintenance", "start", |config| without_hardware(config).or_else(unless_systemd), setup_ssd_maintenance),
+    step_unless("configure_grub", "start", in_container, configure_grub),
+    step("deploy_dotfiles", "start", deploy_dotfiles),
+    step_unless("deploy_udev_rules", "start", in_container, deploy_udev_rules),
+    step("deploy_system_dotfiles", "start", deploy_system_dotfiles),
+    step("apply_file_assertions", "start", apply_file_assertions),
+    step_unless("setup_keyboard_remapper", "start", |config| in_container(config).or_else(unless_systemd), setup_keyboard_remapper),
+    step("install_toolchains", "start", install_toolchains),
+    step("install_rust_tools", "start", install_rust_tools),
+    step("setup_distrobox", "start", setup_distrobox),
+    // Ends the program when it installs Nix, the rest runs after logging back in
+    step("install_nix", "start", install_nix),
+    step("setup_home_manager", "post-nix", setup_home_manager),
+    step("clean_broken_stow_links", "post-nix", clean_broken_stow_links),
+    step("stow_custom_configs", "post-nix", stow_custom_configs),
+    step("setup_xdg_user_dirs", "post-nix", setup_xdg_user_dirs),
+    step_unless(
+        "clone_repos",
+        "post-nix",
+        |config| {
+            if !config.file.repos.is_empty() {
+                None
+            } else if config.skip_wallpapers {
+                Some("--skip-wallpapers and no [repos]".to_string())
+            } else if !config.file.wallpapers.enable || !config.is_desktop() {
+                Some("no [repos] and wallpapers disabled by config/profile".to_string())
+            } else {
+                None
+            }
+        },
+        clone_repos,
+    ),
+    step("rebuild_home_manager", "post-nix", |config| {
+        *config.hm_changes.lock().unwrap_or_else(|e| e.into_inner()) = rebuild_home_manager(config);
+    }),
+    step_unless("install_nix_packages", "post-nix", without_nix_packages, install_nix_packages),
+    // Plugin bootstraps don't depend on each other, run them side by side
+    StepSpec {
+        parallel: true,
+        ..step_unless(
+            "bootstrap_nvim_plugins",
+            "post-nix",
+            |config| config.skip_nvim_bootstrap.then(|| "--skip-nvim-bootstrap".to_string()),
+            bootstrap_nvim_plugins,
+        )
+    },
+    StepSpec {
+        parallel: true,
+        ..step_unless(
+            "bootstrap_tmux_plugins",
+            "post-nix",
+            |config| config.skip_tmux_bootstrap.then(|| "--skip-tmux-bootstrap".to_string()),
+            bootstrap_tmux_plugins,
+        )
+    },
+    step_unless("configure_default_apps", "post-nix", unless_desktop, configure_default_apps),
+    step_unless(
+        "setup_first_login",
+        "post-nix",
+        |config| unless_desktop(config).or_else(|| (!config.file.first_login.enable).then(|| "disabled in config".to_string())),
+        setup_first_login,
+    ),
+    step("provision_users", "post-nix", provision_users),
+    step_unless(
+        "report_cleanliness",
+        "post-nix",
+        |config| config.skip_report.then(|| "--skip-report".to_string()),
+        report_cleanliness,
+    ),
+];
+
+// Steps that set up a home rather than the machine, run again for each of
+// the [users]
+const PER_USER_STEPS: [&str; 11] = [
+    "setup_dotfiles",
+    "deploy_dotfiles",
+    "setup_home_manager",
+    "clean_broken_stow_links",
+    "stow_custom_configs",
+    "setup_xdg_user_dirs",
+    "clone_repos",
+    "rebuild_home_manager",
+    "install_nix_packages",
+    "bootstrap_nvim_plugins",
+    "bootstrap_tmux_plugins",
+];
+
+// --user-steps: the per-user steps of both phases in one go, Nix is
+// already there from the main run
+fn run_user_steps(config: &Config) {
+    for step in STEPS.iter().filter(|step| PER_USER_STEPS.contains(&step.name)) {
+        if wanted(config, step) {
+            run_step(config, step.name, || (step.run)(config));
+        }
+    }
+}
+
+// Home directory of a local user, None if there's no such user
+fn user_home(user: &str) -> Option<String> {
+    let entry = output_lines("getent", &["passwd", user]).into_iter().next()?;
+    entry.split(':').nth(5).filter(|home| home.starts_with('/')).map(str::to_string)
+}
+
+// Run ass --user-steps as another user, the way switch_user says
+fn as_user(config: &Config, user: &str, exe: &str, args: &[String]) -> Cmd {
+    match config.file.switch_user() {
+        "machinectl" => Cmd::sudo("machinectl").args(["shell", &format!("{}@.host", user), exe]).args(args).live(),
+        _ => Cmd::new("sudo").args(["-u", user, "-H", exe]).args(args).live(),
+    }
+}
+
+// Set up the homes of the [users]: their dotfiles get cloned as them, their
+// package list installed by us, then the rest of the per-user steps run as them
+fn provision_users(config: &Config) {
+    if config.file.users.is_empty() {
+        return;
     }
     
-    // Read pacman.conf
-    let pacman_conf_content = std::fs::read_to_string("/etc/pacman.conf")
-        .expect("Failed to read /etc/pacman.conf");
-    
-    let mut modified_content = String::new();
-    let mut in_options_section = false;
-    let mut ilovecandy_added = false;
+    // Our binary may sit in a home the others can't read. The copy goes in a
+    // fresh directory of ours they can only pass through (711), so nobody
+    // can swap it for something else before it runs as them.
+    let dir = if config.dry_run {
+        PathBuf::from("/tmp/ass-provision-XXXXXX")
+    } else {
+        copy_for_other_users().unwrap_or_else(|e| report::fatal(format!("Failed to copy ass for the other users: {}", e)))
+    };
+    let exe = dir.join("ass").to_string_lossy().to_string();
     
-    for line in pacman_conf_content.lines() {
-        // Detect [options] section
-        if line.trim() == "[options]" {
-            in_options_section = true;
-            modified_content.push_str(line);
-            modified_content.push('\n');
+    for (user, settings) in &config.file.users {
+        say!("Provisioning {} (via {})...", user, config.file.switch_user());
+        
+        let Some(home) = user_home(user).or_else(|| config.dry_run.then(|| format!("/home/{}", user))) else {
+            config.warnings.add(WarningKind::Failure, format!("User {} doesn't exist, create it first (sudo useradd -m {})", user, user));
             continue;
-        }
+        };
         
-        // Detect end of [options] section
-        if in_options_section && line.trim().starts_with('[') && line.trim() != "[options]" {
-            // Add ILoveCandy before leaving options section if not already added
-            if !ilovecandy_added {
-                modified_content.push_str("ILoveCandy\n");
-                ilovecandy_added = true;
+        let dotfiles = &config.file.dotfiles;
+        let mut args = vec![
+            "--user-steps".to_string(),
+            "--home".to_string(),
+            home.clone(),
+            "--dotfiles-repo".to_string(),
+            settings.dotfiles_repo.clone().unwrap_or_else(|| dotfiles.repo.clone()),
+            "--stow".to_string(),
+            settings.stow.as_ref().unwrap_or(&dotfiles.stow).join(","),
+        ];
+        if let Some(branch) = settings.dotfiles_branch.as_ref().or(dotfiles.branch.as_ref()) {
+            args.extend(["--dotfiles-branch".to_string(), branch.clone()]);
+        }
+        if let Some(configuration) = &settings.home_manager {
+            args.extend(["--hm-config".to_string(), configuration.clone()]);
+        }
+        // Their config doesn't have our [profiles], the built-in it's based on will do
+        args.extend(["--profile".to_string(), config.profile.base.clone()]);
+        for (set, flag) in [
+            (config.verbose, "--verbose"),
+            (config.skip_wallpapers, "--skip-wallpapers"),
+            (config.skip_nvim_bootstrap, "--skip-nvim-bootstrap"),
+            (config.skip_tmux_bootstrap, "--skip-tmux-bootstrap"),
+        ] {
+            if set {
+                args.push(flag.to_string());
             }
-            in_options_section = false;
+        }
+        args.extend(["--tail".to_string(), config.tail.to_string()]);
+        for step in &config.file.skip {
+            args.extend(["--skip".to_string(), step.clone()]);
         }
         
-        if in_options_section {
-            // Uncomment Color
-            if line.trim() == "#Color" {
-                modified_content.push_str("Color\n");
-                if config.verbose {
-                    println!("  ✓ Enabled Color");
-                }
-                continue;
-            }
-            
-            // Keep Color if already uncommented
-            if line.trim() == "Color" {
-                modified_content.push_str(line);
-                modified_content.push('\n');
-                continue;
-            }
-            
-            // Comment out NoProgressBar if it's uncommented
-            if line.trim() == "NoProgressBar" {
-                modified_content.push_str("#NoProgressBar\n");
-                if config.verbose {
-                    println!("  ✓ Disabled NoProgressBar");
-                }
-                continue;
-            }
-            
-            // Keep NoProgressBar if already commented
-            if line.trim() == "#NoProgressBar" {
-                modified_content.push_str(line);
-                modified_content.push('\n');
-                continue;
-            }
-            
-            // Update ParallelDownloads
-            if line.trim().starts_with("ParallelDownloads") || line.trim().starts_with("#ParallelDownloads") {
-                modified_content.push_str("ParallelDownloads = 25\n");
-                if config.verbose {
-                    println!("  ✓ Set ParallelDownloads = 25");
+        // Clone first, so their packages are there before anything uses them
+        let clone = [args.clone(), vec!["--only".to_string(), "setup_dotfiles".to_string()]].concat();
+        if !run_or_warn(config, as_user(config, user, &exe, &clone), &format!("Failed to clone the dotfiles of {}", user)) {
+            continue;
+        }
+        
+        let list = dotfiles.package_list_path(&format!("{}/dotfiles", home));
+        if config.dry_run {
+            plan::add(
+                plan::Action::new(plan::Kind::Install, format!("Install the packages from {}", list))
+                    .target(list.clone())
+                    .effect(format!("installs the packages in {} unless already installed", list)),
+            );
+        } else {
+            // Their home is usually closed to us
+            let content = Command::new("sudo")
+                .args(["cat", &list])
+                .output()
+                .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+                .unwrap_or_default();
+            let packages = match parse_package_list(config, &content) {
+                Ok(packages) => packages,
+                Err(e) => {
+                    config.warnings.add(WarningKind::Failure, format!("Not installing the packages of {}, invalid {}: {}", user, list, e));
+                    Vec::new()
                 }
-                continue;
-            }
-            
-            // Skip ILoveCandy if it already exists
-            if line.trim() == "ILoveCandy" {
-                ilovecandy_added = true;
-                modified_content.push_str(line);
-                modified_content.push('\n');
-                continue;
+            };
+            if !packages.is_empty() {
+                say!("Installing {} packages from {}...", packages.len(), list);
+                install_package_list(config, packages, &paths::get().home, &[]);
             }
         }
         
-        modified_content.push_str(line);
-        modified_content.push('\n');
+        let rest = [args, vec!["--from".to_string(), "deploy_dotfiles".to_string()]].concat();
+        if run_or_warn(config, as_user(config, user, &exe, &rest), &format!("Failed to set up the home of {}", user)) && !config.dry_run {
+            say!("✓ {} provisioned!", user);
+        }
     }
     
-    // Write to temporary file
-    let temp_file = "/tmp/ass-pacman.conf";
-    std::fs::write(temp_file, modified_content)
-        .expect("Failed to write temporary pacman.conf");
+    if !config.dry_run {
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
+
+// The directory with the copy of ourselves provision_users runs as the users
+fn copy_for_other_users() -> std::io::Result<PathBuf> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    let dir = workdir::private_temp_dir("ass-provision")?;
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o711))?;
+    let mut copy = std::fs::OpenOptions::new().write(true).create_new(true).mode(0o755).open(dir.join("ass"))?;
+    // Whatever the umask took off
+    copy.set_permissions(std::fs::Permissions::from_mode(0o755))?;
+    std::io::copy(&mut std::fs::File::open(env::current_exe()?)?, &mut copy)?;
+    Ok(dir)
+}
+
+// Settle the profile once the config and --profile are known
+fn resolve_profile(config: &mut Config) {
+    let Some(profile) = config.file.profile_settings() else {
+        report::fatal(format!("Unknown profile '{}' (expected {} or one of [profiles])", config.file.profile(), config::PROFILES.join(", ")));
+    };
+    for name in profile.skip.iter().chain(&profile.steps) {
+        if step_index(name).is_none() {
+            report::fatal(format!("Unknown step '{}' in the {} profile (see ass --list-steps)", name, profile.name));
+        }
+    }
+    config.profile = profile;
+}
+
+fn list_steps() {
+    for step in &STEPS {
+        say!("{:<26}{}", step.name, step.phase);
+    }
+}
+
+// --only, --from and --until, by step name. Empty means the whole run.
+#[derive(Default)]
+struct StepSelection {
+    only: Vec<String>,
+    from: Option<String>,
+    until: Option<String>,
+}
+
+fn step_index(name: &str) -> Option<usize> {
+    STEPS.iter().position(|step| step.name == name)
+}
+
+impl StepSelection {
+    fn validate(&self) {
+        for name in self.only.iter().chain(&self.from).chain(&self.until) {
+            if step_index(name).is_none() {
+                report::fatal(format!("Unknown step '{}' (see ass --list-steps)", name));
+            }
+        }
+        if let (Some(from), Some(until)) = (&self.from, &self.until)
+            && step_index(from) > step_index(until)
+        {
+            report::fatal(format!("--from {} comes after --until {}", from, until));
+        }
+    }
     
-    // Copy to /etc/pacman.conf using sudo
-    let status = Command::new("sudo")
-        .args(&["cp", temp_file, "/etc/pacman.conf"])
-        .status()
-        .expect("Failed to copy pacman.conf");
+    fn is_everything(&self) -> bool {
+        self.only.is_empty() && self.from.is_none() && self.until.is_none()
+    }
     
-    if !status.success() {
-        eprintln!("Failed to update /etc/pacman.conf");
-        std::process::exit(1);
+    fn includes(&self, name: &str) -> bool {
+        let index = step_index(name);
+        (self.only.is_empty() || self.only.iter().any(|only| only == name))
+            && self.from.as_deref().is_none_or(|from| index >= step_index(from))
+            && self.until.as_deref().is_none_or(|until| index <= step_index(until))
     }
     
-    // Clean up temp file
-    let _ = std::fs::remove_file(temp_file);
+    // The phase to run: the one we're in, unless nothing selected is part of
+    // it, then the first one that has a selected step
+    fn phase<'a>(&self, state: &'a str) -> &'a str {
+        let selected = |phase: &str| STEPS.iter().any(|step| step.phase == phase && self.includes(step.name));
+        if self.is_everything() || selected(state) {
+            return state;
+        }
+        STEPS.iter().find(|step| self.includes(step.name)).map_or(state, |step| step.phase)
+    }
+}
+
+// Why a selected step won't run, None when it will
+fn skip_reason(config: &Config, step: &StepSpec) -> Option<String> {
+    if config.file.skip.iter().any(|skipped| skipped == step.name) {
+        return Some(format!("Skipping {} (skip in config or --skip)", step.name));
+    }
+    if config.profile.skip.iter().any(|skipped| skipped == step.name) {
+        return Some(format!("Skipping {} (left out by the {} profile)", step.name, config.profile.name));
+    }
+    if config.resuming && checkpoint::is_completed(step.name) {
+        return Some(format!("{} already done", step.name));
+    }
+    (step.skip)(config).map(|reason| format!("Skipping {} ({})", step.name, reason))
+}
+
+// Whether a step of the phase should run, saying why not when it won't
+fn wanted(config: &Config, step: &StepSpec) -> bool {
+    // In CI the skip line belongs to the step, not the one before it
+    ci::mark_step(step.name);
+    if !config.selection.includes(step.name) {
+        if config.verbose {
+            say!("⏭ Skipping {} (not selected)", step.name);
+        }
+        return false;
+    }
+    if let Some(reason) = skip_reason(config, step) {
+        say!("⏭ {}", reason);
+        return false;
+    }
+    true
+}
+
+// wanted() for a step that hasn't come up yet, without saying anything
+fn will_run(config: &Config, name: &str) -> bool {
+    STEPS
+        .iter()
+        .find(|step| step.name == name)
+        .is_some_and(|step| config.selection.includes(step.name) && skip_reason(config, step).is_none())
+}
+
+// run_step, keeping track of it in the checkpoint for --resume
+fn run_tracked(config: &Config, step: &StepSpec) {
+    if !config.dry_run {
+        checkpoint::started(step.name);
+    }
+    run_step(config, step.name, || (step.run)(config));
+    if !config.dry_run {
+        checkpoint::completed(step.name);
+    }
+}
+
+fn run_phase(config: &Config, phase: &str) {
+    let mut steps = STEPS.iter().filter(|step| step.phase == phase).peekable();
+    while let Some(step) = steps.next() {
+        let mut group = vec![step];
+        while step.parallel
+            && let Some(next) = steps.next_if(|next| next.parallel)
+        {
+            group.push(next);
+        }
+        let group: Vec<&StepSpec> = group.into_iter().filter(|step| wanted(config, step)).collect();
+        if group.is_empty() {
+            continue;
+        }
+        
+        output::parallel(
+            group
+                .iter()
+                .map(|step| -> output::Step { (step.name, Box::new(move || run_tracked(config, step))) })
+                .collect(),
+        );
+        if !config.dry_run {
+            pause_if_reboot_needed(config, group[group.len() - 1].name);
+        }
+    }
+}
+
+// --resume: carry on in the phase the checkpoint was in
+fn resume_from_checkpoint() {
+    let Some(checkpoint) = checkpoint::load().filter(|checkpoint| !checkpoint.phase.is_empty()) else {
+        report::fatal(format!("Nothing to resume ({} doesn't exist)", checkpoint::path()));
+    };
     
-    println!("✓ Pacman.conf configured successfully!");
+    match checkpoint.running.first() {
+        Some(step) => say!("↻ Resuming at {} ({} steps already done)", step, checkpoint.completed.len()),
+        None => say!("↻ Resuming after {} finished steps", checkpoint.completed.len()),
+    }
 }
 
 fn main() {
-    let config = parse_args();
+    let mut config = parse_args();
+    
+    // The supervisor's prefixes would break the JSON lines
+    if config.ci && !ci::is_child() && !report::is_json() {
+        ci::supervise();
+    }
+    
+    let read_only = matches!(config.subcommand.as_deref(), Some("history" | "packages" | "facts")) || config.shown_run.is_some();
+    if !config.dry_run && !read_only {
+        runlog::start();
+        journald::start();
+    }
+    
+    if config.subcommand.as_deref() == Some("undo-system-dotfiles") {
+        undo_system_dotfiles(&config);
+        return;
+    }
+    
+    if config.subcommand.as_deref() == Some("history") {
+        history::print_history();
+        return;
+    }
+    
+    if config.subcommand.as_deref() == Some("packages") {
+        print_package_list(&config);
+        return;
+    }
+    
+    if config.subcommand.as_deref() == Some("facts") {
+        print_facts();
+        return;
+    }
+    
+    if let Some(run) = config.shown_run.as_deref() {
+        history::show(run);
+        return;
+    }
+    
+    if config.subcommand.as_deref() == Some("undo") {
+        undo(&config);
+        config.work_dir.finish(config.keep_work_dir);
+        config.warnings.print_recap();
+        return;
+    }
+    
+    if config.subcommand.as_deref() == Some("state export") {
+        let archive = config.state_archive.clone().unwrap_or_else(state::default_archive_name);
+        state::export(&archive, config.dry_run);
+        return;
+    }
+    
+    if config.subcommand.as_deref() == Some("state import") {
+        let Some(archive) = &config.state_archive else {
+            esay!("Usage: ass state import <FILE>");
+            std::process::exit(1);
+        };
+        state::import(archive, config.dry_run);
+        return;
+    }
+    
+    if config.subcommand.as_deref() == Some("update") {
+        run_update(&config);
+        config.work_dir.finish(config.keep_work_dir);
+        config.warnings.print_recap();
+        return;
+    }
+    
+    if config.subcommand.as_deref() == Some("schedule") {
+        schedule_update(&config);
+        config.warnings.print_recap();
+        return;
+    }
+    
+    if config.subcommand.as_deref() == Some("schedule remove") {
+        remove_schedule(&config);
+        return;
+    }
+    
+    if config.subcommand.as_deref() == Some("pacdiff") {
+        pacdiff(&config);
+        config.work_dir.finish(config.keep_work_dir);
+        config.warnings.print_recap();
+        return;
+    }
+    
+    if config.subcommand.as_deref() == Some("doctor") {
+        let findings = doctor(&config);
+        if findings.is_empty() {
+            say!("✓ No problems found");
+            return;
+        }
+        say!();
+        for (problem, fix) in &findings {
+            say!("⚠ {}", problem);
+            say!("    fix: {}", fix);
+        }
+        say!("\n{} problem(s) found", findings.len());
+        std::process::exit(1);
+    }
+    
+    if config.subcommand.as_deref() == Some("first-login") {
+        first_login(&config);
+        config.warnings.print_recap();
+        return;
+    }
+    
+    if config.subcommand.as_deref() == Some("hm migrate-flakes") {
+        migrate_hm_to_flakes(&config);
+        return;
+    }
+    
+    if config.subcommand.as_deref() == Some("dotfiles init") {
+        if !scaffold::init(config.dry_run) {
+            return;
+        }
+        config.file = config::load_file_config(&config::config_path());
+        resolve_profile(&mut config);
+    }
+    
+    if config.subcommand.as_deref() == Some("wizard") {
+        if !wizard::run_wizard() {
+            return;
+        }
+        // Continue straight into the run with the freshly written config
+        config.file = config::load_file_config(&config::config_path());
+        resolve_profile(&mut config);
+    }
     
     if config.dry_run {
-        println!("=== DRY RUN MODE ===");
-        println!("No actual changes will be made\n");
+        say!("{}", tr!("=== DRY RUN MODE ==="));
+        say!("{}\n", tr!("No actual changes will be made"));
+    }
+    
+    say!("A.S.S. - Arch Setup Script");
+    if config.verbose || config.context != RunContext::BareMetal {
+        say!("Running in {}", config.context.describe());
     }
     
-    println!("A.S.S. - Arch Setup Script");
+    if config.other_user {
+        run_user_steps(&config);
+        config.work_dir.finish(config.keep_work_dir);
+        config.warnings.print_recap();
+        return;
+    }
     
-    let state = get_install_state();
+    if config.resuming {
+        resume_from_checkpoint();
+    } else if let Some(step) = checkpoint::load().and_then(|checkpoint| checkpoint.running.first().cloned()) {
+        say!("⚠ The last run stopped at {}, ass --resume picks up from there instead of starting over\n", step);
+    }
+    resume_paused_run(&mut config);
+    let state = checkpoint::phase();
+    if !config.dry_run {
+        checkpoint::set_phase(&state);
+    }
+    // Everything selected, before the steps' own checks leave any out
+    let plan: Vec<String> = STEPS
+        .iter()
+        .filter(|step| config.selection.includes(step.name))
+        .filter(|step| !config.file.skip.iter().chain(&config.profile.skip).any(|skipped| skipped == step.name))
+        .map(|step| step.name.to_string())
+        .collect();
+    progress::set_plan(plan.clone());
+    if !config.dry_run {
+        let run = history::begin("setup", &config.profile.name, plan, state.trim() == "post-nix" || config.resuming);
+        journal::begin(&run);
+    }
     
-    match state.trim() {
+    match config.selection.phase(state.trim()) {
         "start" => {
-            check_deps(&config);
-            configure_pacman(&config);  // Configure pacman before installing anything
-            install_paru(&config);
-            setup_chaotic_aur(&config);
-            setup_dotfiles(&config);
-            deploy_dotfiles(&config);
-            install_nix(&config);
-            // Program exits here after nix installation
+            resolve_aur_helper(&mut config);
+            run_phase(&config, "start");
+            // install_nix ends the program unless it could load Nix into this
+            // one, then the second phase follows right away
+            if checkpoint::phase() != "post-nix" {
+                // A dry run stops here too, it can't tell what Nix would leave
+                plan::finish("setup", &config.profile.name);
+                history::finish("paused for a new login after installing Nix");
+                config.work_dir.finish(config.keep_work_dir);
+                config.warnings.print_recap();
+                return;
+            }
+            say!();
+            finish_setup(&config);
         }
         "post-nix" => {
-            println!("⏩ Resuming installation after Nix setup...\n");
-            setup_home_manager(&config);
-            stow_custom_configs(&config);
-            
-            if !config.skip_wallpapers {
-                clone_wallpapers(&config);
-            } else {
-                println!("⏭ Skipping wallpaper repositories (--skip-wallpapers)");
-            }
-            
-            rebuild_home_manager(&config);
-            
-            // Clear state file on successful completion
-            clear_install_state();
-            
-            if config.dry_run {
-                println!("\n=== DRY RUN COMPLETE ===");
-            } else {
-                println!("\n✓ Setup complete! Your system is ready to use!");
+            say!("⏩ Resuming installation after Nix setup...\n");
+            // Run again from a shell that was open before Nix was installed
+            if !config.dry_run && which("nix").is_none() && environment::load_nix_profile().is_some() {
+                say!("✓ {}", tr!("Loaded the Nix environment, continuing without logging out"));
             }
+            finish_setup(&config);
         }
         _ => {
-            eprintln!("Unknown installation state: {}", state);
-            eprintln!("To start fresh, run: rm /tmp/ass-install-state");
+            esay!("Unknown installation state: {}", state);
+            esay!("To start fresh, run: rm {}", checkpoint::path());
             std::process::exit(1);
         }
     }
-}
\ No newline at end of file
+}
+
+// The post-nix phase and the summary at the end of the setup
+fn finish_setup(config: &Config) {
+    run_phase(config, "post-nix");
+    
+    // Read before the checkpoint goes away, it holds the first phase too
+    let step_usage = checkpoint::load().map(|checkpoint| checkpoint.usage).unwrap_or_default();
+    
+    // Clear the checkpoint on successful completion, a partial run leaves it
+    if config.selection.is_everything() && !config.dry_run {
+        checkpoint::clear();
+    }
+    config.work_dir.finish(config.keep_work_dir);
+    
+    if config.dry_run {
+        say!("\n{}", tr!("=== DRY RUN COMPLETE ==="));
+        plan::finish("setup", &config.profile.name);
+    } else if !config.selection.is_everything() {
+        say!("\n✓ {}", tr!("Selected steps done"));
+    } else if !config.warnings.is_empty() {
+        say!("\n✓ {}", tr!("Setup complete, but some things need attention (see below)"));
+    } else {
+        say!("\n✓ {}", tr!("Setup complete! Your system is ready to use!"));
+    }
+    
+    match config.hm_changes.lock().unwrap_or_else(|e| e.into_inner()).as_deref() {
+        Some("") => say!("\n{}", tr!("Home Manager: no package changes")),
+        Some(changes) => {
+            say!("\n{}", tr!("Home Manager package changes:"));
+            for line in changes.lines() {
+                say!("  {}", line);
+            }
+        }
+        None => {}
+    }
+    print_hm_news(config);
+    
+    history::finish(if config.warnings.is_empty() { "success" } else { "warnings" });
+    print_usage_summary(&step_usage);
+    config.warnings.print_recap();
+}
+#[cfg(test)]
+mod tests {
+    use super::chaotic_repo_last;
+    
+    #[test]
+    fn chaotic_already_last_is_left_alone() {
+        let conf = "[options]\nArchitecture = auto\n\n[core]\nInclude = /etc/pacman.d/mirrorlist\n\n[chaotic-aur]\nInclude = /etc/pacman.d/chaotic-mirrorlist\n";
+        assert_eq!(chaotic_repo_last(conf), None);
+    }
+    
+    #[test]
+    fn chaotic_above_core_moves_to_the_end() {
+        let conf = "[options]\nArchitecture = auto\n\n[chaotic-aur]\nInclude = /etc/pacman.d/chaotic-mirrorlist\n\n[core]\nInclude = /etc/pacman.d/mirrorlist\n";
+        let moved = chaotic_repo_last(conf).expect("chaotic-aur is above core");
+        assert_eq!(
+            moved,
+            "[options]\nArchitecture = auto\n\n[core]\nInclude = /etc/pacman.d/mirrorlist\n\n[chaotic-aur]\nInclude = /etc/pacman.d/chaotic-mirrorlist\n"
+        );
+        assert_eq!(chaotic_repo_last(&moved), None);
+    }
+    
+    #[test]
+    fn chaotic_leaves_comments_between_sections() {
+        let conf = "[options]\nArchitecture = auto\n\n[chaotic-aur]\nInclude = /etc/pacman.d/chaotic-mirrorlist\n\n# The official repositories\n[core]\nInclude = /etc/pacman.d/mirrorlist\n\n[extra]\nInclude = /etc/pacman.d/mirrorlist\n";
+        assert_eq!(
+            chaotic_repo_last(conf).as_deref(),
+            Some("[options]\nArchitecture = auto\n\n# The official repositories\n[core]\nInclude = /etc/pacman.d/mirrorlist\n\n[extra]\nInclude = /etc/pacman.d/mirrorlist\n\n[chaotic-aur]\nInclude = /etc/pacman.d/chaotic-mirrorlist\n")
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use crate::pkg::{self, Backend};
+use std::net::ToSocketAddrs;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+
+// Where the packages come from, plus GitHub for the dotfiles, the AUR
+// helper's sources and release downloads
+pub fn default_hosts() -> Vec<String> {
+    let mirror = match pkg::backend() {
+        Backend::Pacman => "archlinux.org",
+        Backend::Apt => "deb.debian.org",
+        Backend::Dnf => "fedoraproject.org",
+    };
+    vec![mirror.to_string(), "github.com".to_string()]
+}
+
+// Results for one host, Err holds what went wrong
+pub struct HostReport {
+    pub host: String,
+    pub dns: Result<(), String>,
+    pub ping: Result<(), String>,
+    pub https: Result<(), String>,
+}
+
+impl HostReport {
+    pub fn reachable(&self) -> bool {
+        self.https.is_ok()
+    }
+    
+    // "DNS: ..., ping: ..." for the probes that failed
+    pub fn failures(&self) -> Vec<String> {
+        [("DNS", &self.dns), ("ping", &self.ping), ("HTTPS", &self.https)]
+            .iter()
+            .filter_map(|(probe, result)| result.as_ref().err().map(|e| format!("{}: {}", probe, e)))
+            .collect()
+    }
+}
+
+// getaddrinfo has no timeout of its own, so it runs on a thread that's
+// abandoned when it takes too long
+fn resolve(host: &str, timeout: Duration) -> Result<(), String> {
+    let (sender, receiver) = mpsc::channel();
+    let target = format!("{}:443", host);
+    std::thread::spawn(move || {
+        let _ = sender.send(target.to_socket_addrs().map(|mut addrs| addrs.next().is_some()));
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(Ok(true)) => Ok(()),
+        Ok(Ok(false)) => Err("no addresses".to_string()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("no answer within {}s", timeout.as_secs())),
+    }
+}
+
+fn ping(host: &str, timeout: Duration) -> Result<(), String> {
+    let status = Command::new("ping")
+        .args(["-c", "1", "-W", &timeout.as_secs().max(1).to_string(), host])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|_| "ping isn't installed".to_string())?;
+    if status.success() { Ok(()) } else { Err("no reply (ICMP may be blocked)".to_string()) }
+}
+
+// A HEAD request, any HTTP status means the host is reachable
+fn head(host: &str, timeout: Duration) -> Result<(), String> {
+    let output = Command::new("curl")
+        .args(["--proto", "=https", "--tlsv1.2", "-sS", "-I", "-o", "/dev/null", "--max-time"])
+        .arg(timeout.as_secs().max(1).to_string())
+        .arg(format!("https://{}/", host))
+        .output()
+        .map_err(|e| format!("failed to execute curl: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let error = String::from_utf8_lossy(&output.stderr);
+        Err(error.trim().trim_start_matches("curl: ").to_string())
+    }
+}
+
+// Every probe against every host, one host per thread
+pub fn check(hosts: &[String], timeout: Duration) -> Vec<HostReport> {
+    std::thread::scope(|scope| {
+        let probes: Vec<_> = hosts
+            .iter()
+            .map(|host| {
+                scope.spawn(move || HostReport {
+                    host: host.clone(),
+                    dns: resolve(host, timeout),
+                    ping: ping(host, timeout),
+                    https: head(host, timeout),
+                })
+            })
+            .collect();
+        probes.into_iter().filter_map(|probe| probe.join().ok()).collect()
+    })
+}
@@ -0,0 +1,51 @@
+use crate::config;
+use std::process::Command;
+
+// Derivations a nix build says failed: "builder for '/nix/store/…-name.drv'
+// failed with exit code 1" (older Nix: "build of ‘…’ failed"). The parent
+// that merely had failed dependencies has no log of its own, so it's left out.
+pub fn failed_derivations(output: &str) -> Vec<String> {
+    let mut found: Vec<String> = Vec::new();
+    for line in output.lines() {
+        if !line.contains("failed") || line.contains("dependencies of derivation") {
+            continue;
+        }
+        for word in line.split(|c: char| c.is_whitespace() || "'‘’;,".contains(c)) {
+            if word.starts_with("/nix/store/") && word.ends_with(".drv") && !found.iter().any(|drv| drv == word) {
+                found.push(word.to_string());
+            }
+        }
+    }
+    found
+}
+
+fn log_dir() -> String {
+    config::state_path("nix-logs")
+}
+
+// Keep the full build log of every derivation that failed, since Nix only
+// prints its last lines. What the build printed stands in when Nix kept no
+// log. Returns the files written.
+pub fn save_failed_logs(output: &str) -> Vec<String> {
+    let derivations = failed_derivations(output);
+    let dir = log_dir();
+    if derivations.is_empty() || std::fs::create_dir_all(&dir).is_err() {
+        return Vec::new();
+    }
+    
+    derivations
+        .iter()
+        .filter_map(|drv| {
+            let name = drv.trim_start_matches("/nix/store/").trim_end_matches(".drv");
+            let path = format!("{}/{}.log", dir, name);
+            let log = Command::new("nix-store")
+                .args(["--read-log", drv])
+                .output()
+                .ok()
+                .filter(|output| output.status.success() && !output.stdout.is_empty())
+                .map_or_else(|| output.as_bytes().to_vec(), |output| output.stdout);
+            std::fs::write(&path, log).ok()?;
+            Some(path)
+        })
+        .collect()
+}
@@ -0,0 +1,68 @@
+use crate::config;
+use std::collections::BTreeMap;
+use std::process::Command;
+
+pub const NIX_FEATURES: [&str; 2] = ["--extra-experimental-features", "nix-command flakes"];
+
+// What `nix profile install` gets for a [nix] packages entry: nixpkgs
+// attributes as they are, flake installables (flake#attr) untouched
+pub fn installable(package: &str) -> String {
+    if package.contains('#') { package.to_string() } else { format!("nixpkgs#{}", package) }
+}
+
+// The name the profile lists it under, the last part of the attribute path
+pub fn element_name(package: &str) -> &str {
+    let attr = package.rsplit_once('#').map_or(package, |(_, attr)| attr);
+    attr.rsplit('.').next().unwrap_or(attr)
+}
+
+// What ass installed into the profile, so packages dropped from the config
+// can be removed again without touching ones installed by hand
+fn record_path() -> String {
+    config::state_path("nix-packages.txt")
+}
+
+pub fn recorded() -> Vec<String> {
+    std::fs::read_to_string(record_path())
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+pub fn record(packages: &[String]) -> std::io::Result<()> {
+    let path = record_path();
+    if let Some(dir) = std::path::Path::new(&path).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut content = packages.join("\n");
+    content.push('\n');
+    std::fs::write(path, content)
+}
+
+// Profile elements by name, each with what `nix profile remove` takes for
+// it. Nix before 2.20 lists them without names, to be removed by index.
+// None when there's no usable profile (Nix missing, a nix-env profile).
+pub fn installed() -> Option<BTreeMap<String, String>> {
+    let output = Command::new("nix").args(NIX_FEATURES).args(["profile", "list", "--json"]).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let list: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+    
+    let elements = match &list["elements"] {
+        serde_json::Value::Object(elements) => elements.keys().map(|name| (name.clone(), name.clone())).collect(),
+        serde_json::Value::Array(elements) => elements
+            .iter()
+            .enumerate()
+            .filter_map(|(index, element)| {
+                let attr = element["attrPath"].as_str()?;
+                Some((element_name(attr).to_string(), index.to_string()))
+            })
+            .collect(),
+        _ => BTreeMap::new(),
+    };
+    Some(elements)
+}
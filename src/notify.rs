@@ -0,0 +1,179 @@
+use crate::config::{self, ScheduleConfig};
+use crate::history::{self, Summary};
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const WEEK: u64 = 7 * 86400;
+
+// What a scheduled `ass update` sends, so an unattended machine that drifts
+// or breaks doesn't go unnoticed. It covers the runs since the last report.
+#[derive(Serialize)]
+struct Report<'a> {
+    host: String,
+    // How the run that sent it ended
+    outcome: &'a str,
+    // The fatal error it ended with, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+    runs: Vec<Summary>,
+    // .pacnew/.pacsave files waiting for ass pacdiff
+    pacnew: &'a [String],
+}
+
+impl Report<'_> {
+    fn subject(&self) -> String {
+        format!("[ass] {}: update {}", self.host, self.outcome)
+    }
+    
+    fn text(&self) -> String {
+        let mut text = format!("ass update on {}: {}\n", self.host, self.outcome);
+        if let Some(error) = self.error {
+            text.push_str(&format!("ERROR: {}\n", error));
+        }
+        for run in &self.runs {
+            text.push_str(&format!("\nRun {} (started {} UTC): {}\n", run.id, run.started, run.outcome));
+            for log in &run.logs {
+                text.push_str(&format!("  Log: {}\n", log));
+            }
+            if !run.changes.is_empty() {
+                text.push_str("  Changes:\n");
+                for change in &run.changes {
+                    text.push_str(&format!("    - {}\n", change));
+                }
+            }
+            if !run.warnings.is_empty() {
+                text.push_str("  Warnings:\n");
+                for warning in &run.warnings {
+                    text.push_str(&format!("    - {}\n", warning));
+                }
+            }
+        }
+        if !self.pacnew.is_empty() {
+            text.push_str(&format!("\n{} .pacnew/.pacsave file(s) waiting for ass pacdiff:\n", self.pacnew.len()));
+            for file in self.pacnew {
+                text.push_str(&format!("  - {}\n", file));
+            }
+        }
+        text
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn sent_path() -> String {
+    config::state_path("report-sent")
+}
+
+fn last_sent() -> u64 {
+    std::fs::read_to_string(sent_path()).ok().and_then(|content| content.trim().parse().ok()).unwrap_or(0)
+}
+
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname").map(|name| name.trim().to_string()).unwrap_or_else(|_| "localhost".to_string())
+}
+
+pub fn enabled(schedule: &ScheduleConfig) -> bool {
+    schedule.report_email.is_some() || schedule.report_webhook.is_some()
+}
+
+// Send the report for the run that just ended, if [schedule] report says
+// it's due. `pacnew` is only called when it is. Returns what failed to send.
+pub fn send(schedule: &ScheduleConfig, outcome: &str, error: Option<&str>, pacnew: impl FnOnce() -> Vec<String>) -> Vec<String> {
+    let last = last_sent();
+    let problems = error.is_some() || outcome != "success";
+    let due = match schedule.report.as_str() {
+        "always" => true,
+        "problems" => problems,
+        _ => problems || now().saturating_sub(last) >= WEEK,
+    };
+    if !enabled(schedule) || !due {
+        return Vec::new();
+    }
+    
+    // Runs since the last report, a week back at most
+    let pacnew = pacnew();
+    let report = Report {
+        host: hostname(),
+        outcome,
+        error,
+        runs: history::summaries("update", last.max(now().saturating_sub(WEEK))),
+        pacnew: &pacnew,
+    };
+    
+    let mut failed = Vec::new();
+    if let Some(email) = &schedule.report_email
+        && let Err(e) = mail(email, &report.subject(), &report.text())
+    {
+        failed.push(format!("Failed to mail the run report to {}: {}", email, e));
+    }
+    if let Some(webhook) = &schedule.report_webhook {
+        let posted = serde_json::to_string(&report).map_err(|e| e.to_string()).and_then(|json| post(webhook, &json));
+        if let Err(e) = posted {
+            failed.push(format!("Failed to send the run report to {}: {}", webhook, e));
+        }
+    }
+    
+    // Nothing got through, the next run tries again
+    let channels = usize::from(schedule.report_email.is_some()) + usize::from(schedule.report_webhook.is_some());
+    if failed.len() < channels {
+        let _ = std::fs::write(sent_path(), format!("{}\n", now()));
+    }
+    failed
+}
+
+// Feed the program its input on stdin and wait for it
+fn pipe(command: &mut Command, input: &str) -> Result<(), String> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes()).map_err(|e| e.to_string())?;
+    }
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+// Through whatever provides sendmail (msmtp, postfix, opensmtpd, ...),
+// which usually lives in sbin and may not be on the PATH of a user
+fn mail(to: &str, subject: &str, body: &str) -> Result<(), String> {
+    let sendmail = ["/usr/sbin/sendmail", "/usr/bin/sendmail"]
+        .into_iter()
+        .find(|path| Path::new(path).exists())
+        .ok_or("sendmail isn't installed (msmtp-mta, postfix or opensmtpd provide it)")?;
+    let message = format!("To: {}\nSubject: {}\nContent-Type: text/plain; charset=utf-8\n\n{}", to, subject, body);
+    pipe(Command::new(sendmail).args(["-t", "-i"]), &message)
+}
+
+fn post(url: &str, json: &str) -> Result<(), String> {
+    pipe(
+        Command::new("curl").args([
+            "-sS",
+            "-f",
+            "--max-time",
+            "30",
+            "--retry",
+            "2",
+            "-H",
+            "Content-Type: application/json",
+            "--data-binary",
+            "@-",
+            "-o",
+            "/dev/null",
+            url,
+        ]),
+        json,
+    )
+    .map_err(|e| e.trim_start_matches("curl: ").to_string())
+}
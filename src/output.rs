@@ -0,0 +1,407 @@
+use crate::progress;
+use crate::runlog;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, IsTerminal, Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Mutex, OnceLock, mpsc};
+use std::thread;
+use std::time::Instant;
+
+// Output of one step running in parallel with others, shown in one piece
+// (prefixed with the step name) once the step is done
+struct StepBuffer {
+    step: String,
+    // (is stderr, line)
+    lines: Vec<(bool, String)>,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<StepBuffer>> = const { RefCell::new(None) };
+}
+
+// Only one step may talk to the terminal at a time (sudo password prompts)
+static INTERACTIVE: Mutex<()> = Mutex::new(());
+
+// Height of the pane commands' output is shown in with --verbose on a
+// terminal, unset to let it scroll by as it comes
+static TAIL_LINES: OnceLock<usize> = OnceLock::new();
+
+// The step commands run for, named in the pane's title
+static STEP: Mutex<String> = Mutex::new(String::new());
+
+// Set with --verbose: commands' output is shown as it comes, not only when
+// they fail
+static VERBOSE: OnceLock<()> = OnceLock::new();
+
+// Lines of a failed command's output shown without --verbose
+const FAILURE_LINES: usize = 20;
+
+pub fn enable_tail(lines: usize) {
+    let _ = TAIL_LINES.set(lines);
+}
+
+pub fn enable_verbose() {
+    let _ = VERBOSE.set(());
+}
+
+pub fn set_step(step: &str) {
+    *STEP.lock().unwrap_or_else(|e| e.into_inner()) = step.to_string();
+}
+
+// println!/eprintln! for everything ass prints, so it goes through one place:
+// held back while the step runs in parallel with others, above the progress
+// bars, and to stderr with --output json (see report.rs)
+macro_rules! say {
+    () => { $crate::output::line(false, String::new()) };
+    ($($arg:tt)*) => { $crate::output::line(false, format!($($arg)*)) };
+}
+macro_rules! esay {
+    () => { $crate::output::line(true, String::new()) };
+    ($($arg:tt)*) => { $crate::output::line(true, format!($($arg)*)) };
+}
+pub(crate) use {esay, say};
+
+pub fn line(stderr: bool, text: String) {
+    print(stderr, text, true);
+}
+
+// line() without the run log entry, for command output (which the log has
+// with the command)
+fn print(stderr: bool, text: String, log: bool) {
+    let buffered = CURRENT.with(|current| match current.borrow_mut().as_mut() {
+        Some(buffer) => {
+            let prefixed = format!("[{}] {}", buffer.step, text);
+            if log {
+                runlog::message(&prefixed);
+            }
+            buffer.lines.push((stderr, prefixed));
+            true
+        }
+        None => false,
+    });
+    
+    if !buffered {
+        if log {
+            runlog::message(&text);
+        }
+        progress::suspend(|| {
+            if stderr {
+                eprintln!("{}", text);
+            } else {
+                println!("{}", text);
+            }
+        });
+    }
+}
+
+fn current_step() -> Option<String> {
+    CURRENT.with(|current| current.borrow().as_ref().map(|buffer| buffer.step.clone()))
+}
+
+// The step this thread is running, the parallel one if any
+pub fn step() -> String {
+    current_step().unwrap_or_else(|| STEP.lock().unwrap_or_else(|e| e.into_inner()).clone())
+}
+
+// Command::status() that plays nice with parallel steps: output is captured
+// into the step's buffer instead of the terminal, and sudo credentials are
+// refreshed up front while holding the terminal so prompts don't interleave.
+// Without --verbose the output isn't shown at all unless the command fails,
+// then its last lines are (see capture()).
+pub fn status(cmd: &mut Command) -> std::io::Result<ExitStatus> {
+    run(cmd, false, false, false).map(|(status, _)| status)
+}
+
+// Same as status(), also handing back what the command wrote to stderr
+pub fn status_with_stderr(cmd: &mut Command) -> std::io::Result<(ExitStatus, String)> {
+    run(cmd, true, false, false)
+}
+
+// status() for a command whose output is shown as it comes with or without
+// --verbose, such as a whole run of ass as another user
+pub fn status_live(cmd: &mut Command, keep_stderr: bool) -> std::io::Result<(ExitStatus, String)> {
+    run(cmd, keep_stderr, true, false)
+}
+
+// status() for a command that calls sudo itself, which gets its password
+// up front like one started through sudo
+pub fn status_escalating(cmd: &mut Command, keep_stderr: bool) -> std::io::Result<(ExitStatus, String)> {
+    run(cmd, keep_stderr, false, true)
+}
+
+// spawn() that notes a program that couldn't be started in the run log
+fn spawn(cmd: &mut Command) -> std::io::Result<Child> {
+    cmd.spawn().inspect_err(|e| runlog::not_started(cmd, e))
+}
+
+fn run(cmd: &mut Command, keep_stderr: bool, live: bool, escalates: bool) -> std::io::Result<(ExitStatus, String)> {
+    let show = live || VERBOSE.get().is_some();
+    let alone = current_step().is_none() && !progress::is_active();
+    // Off a terminal nothing is lost by catching stdout for the run log
+    // too, on one the command keeps it for its colors and progress bars
+    let catch_stdout = TAIL_LINES.get().is_some() || (runlog::is_open() && !std::io::stdout().is_terminal());
+    if alone && show && !catch_stdout {
+        if !keep_stderr && !runlog::is_open() {
+            return cmd.status().map(|status| (status, String::new()));
+        }
+        let started = Instant::now();
+        let mut child = spawn(cmd.stderr(Stdio::piped()))?;
+        let mut kept = String::new();
+        if let Some(stderr) = child.stderr.take() {
+            for text in BufReader::new(stderr).lines().map_while(Result::ok) {
+                eprintln!("{}", text);
+                kept.push_str(&text);
+                kept.push('\n');
+            }
+        }
+        let status = child.wait()?;
+        runlog::command(cmd, started, status, None, &kept);
+        return Ok((status, if keep_stderr { kept } else { String::new() }));
+    }
+    
+    // Steps running side by side can't share the terminal's input
+    if !alone {
+        cmd.stdin(Stdio::null());
+    }
+    let needs_sudo = escalates || cmd.get_program() == "sudo";
+    let (status, _, stderr) = capture(cmd, needs_sudo, show, None)?;
+    Ok((status, if keep_stderr { stderr } else { String::new() }))
+}
+
+// Columns of the terminal, for cutting pane lines short instead of wrapping
+fn terminal_width() -> usize {
+    // SAFETY: TIOCGWINSZ only writes into the struct we hand it
+    let columns = unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0 { size.ws_col as usize } else { 0 }
+    };
+    if columns == 0 { 80 } else { columns }
+}
+
+// The last lines of a running command, redrawn in place under a title
+struct Pane {
+    title: String,
+    lines: VecDeque<String>,
+    height: usize,
+    width: usize,
+    // Lines on screen from the last draw, to move back over
+    drawn: usize,
+}
+
+impl Pane {
+    fn new(title: String, height: usize) -> Pane {
+        Pane { title, lines: VecDeque::new(), height, width: terminal_width(), drawn: 0 }
+    }
+    
+    fn clear(&mut self) {
+        let mut out = std::io::stdout().lock();
+        if self.drawn > 0 {
+            let _ = write!(out, "\x1b[{}F\x1b[J", self.drawn);
+        }
+        let _ = out.flush();
+        self.drawn = 0;
+    }
+    
+    fn push(&mut self, line: &str) {
+        if self.lines.len() == self.height {
+            self.lines.pop_front();
+        }
+        // Progress bars redraw with \r, only what's after the last one shows
+        let line = line.rsplit('\r').next().unwrap_or(line);
+        self.lines.push_back(line.chars().filter(|c| !c.is_control()).collect());
+        self.draw();
+    }
+    
+    fn draw(&mut self) {
+        self.clear();
+        let cut = |text: &str| text.chars().take(self.width.saturating_sub(4)).collect::<String>();
+        let mut out = std::io::stdout().lock();
+        let _ = writeln!(out, "\x1b[2m┌ {}\x1b[0m", cut(&self.title));
+        for line in &self.lines {
+            let _ = writeln!(out, "\x1b[2m│\x1b[0m {}", cut(line));
+        }
+        let _ = out.flush();
+        self.drawn = self.lines.len() + 1;
+    }
+}
+
+// Send a child's lines to the pane's thread, tagged with whether they're stderr
+fn forward<R: Read + Send + 'static>(stream: R, is_stderr: bool, sender: mpsc::Sender<(bool, String)>) {
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            if sender.send((is_stderr, line)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+// Run a command with its output shown as it comes (with --verbose) and also
+// handed back as (stdout, stderr). sudo asks for the password before
+// anything is drawn, not in the middle of it.
+pub fn watch(cmd: &mut Command, needs_sudo: bool) -> std::io::Result<(ExitStatus, String, String)> {
+    capture(cmd, needs_sudo, VERBOSE.get().is_some(), None)
+}
+
+// watch() with `input` fed to the command's stdin
+pub fn watch_with_input(cmd: &mut Command, needs_sudo: bool, input: String) -> std::io::Result<(ExitStatus, String, String)> {
+    capture(cmd, needs_sudo, VERBOSE.get().is_some(), Some(input))
+}
+
+// Short enough for a spinner line
+fn describe(cmd: &Command) -> String {
+    let mut words = vec![cmd.get_program().to_string_lossy().to_string()];
+    words.extend(cmd.get_args().map(|arg| arg.to_string_lossy().to_string()));
+    let text = words.join(" ");
+    match text.char_indices().nth(60) {
+        Some((cut, _)) => format!("{}...", &text[..cut]),
+        None => text,
+    }
+}
+
+// Run a command with its output captured. Shown, it goes to the pane (with
+// --verbose on a terminal, cleared when the command succeeds and left for a
+// failure) or as it comes; not shown, a spinner stands in for it and only
+// the last lines come out, when it fails.
+fn capture(cmd: &mut Command, needs_sudo: bool, show: bool, input: Option<String>) -> std::io::Result<(ExitStatus, String, String)> {
+    let step = current_step();
+    let alone = step.is_none() && !progress::is_active();
+    let tail = TAIL_LINES.get().copied().filter(|_| show && alone);
+    if needs_sudo && (tail.is_some() || !show || !alone) {
+        let _terminal = INTERACTIVE.lock().unwrap_or_else(|e| e.into_inner());
+        progress::suspend(|| {
+            if let Some(step) = &step {
+                println!("[{}] needs sudo", step);
+                let _ = std::io::stdout().flush();
+            }
+            Command::new("sudo").arg("-v").status()
+        })?;
+    }
+    let spinner = (!show && alone).then(|| progress::spinner(format!("$ {}", describe(cmd))));
+    let started = Instant::now();
+    if input.is_some() {
+        cmd.stdin(Stdio::piped());
+    }
+    let mut child = spawn(cmd.stdout(Stdio::piped()).stderr(Stdio::piped()))?;
+    // From a thread of its own, a command that fills its output pipe before
+    // reading all its input would wait on us otherwise. Dropping stdin at the
+    // end is the EOF.
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        thread::spawn(move || {
+            let _ = stdin.write_all(input.as_bytes());
+        });
+    }
+    
+    let (sender, receiver) = mpsc::channel();
+    forward(child.stdout.take().expect("stdout is piped"), false, sender.clone());
+    forward(child.stderr.take().expect("stderr is piped"), true, sender);
+    
+    let title = {
+        let current = STEP.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        format!("{}$ {}", if current.is_empty() { String::new() } else { format!("[{}] ", current) }, describe(cmd))
+    };
+    let mut pane = tail.map(|height| Pane::new(title.clone(), height));
+    
+    let (mut stdout, mut stderr) = (String::new(), String::new());
+    let mut last = VecDeque::new();
+    for (is_stderr, line) in receiver {
+        match pane.as_mut() {
+            Some(pane) => pane.push(&line),
+            None if show => print(is_stderr, line.clone(), false),
+            None => {
+                if last.len() == FAILURE_LINES {
+                    last.pop_front();
+                }
+                last.push_back(line.clone());
+            }
+        }
+        let captured = if is_stderr { &mut stderr } else { &mut stdout };
+        captured.push_str(&line);
+        captured.push('\n');
+    }
+    let status = child.wait()?;
+    drop(spinner);
+    runlog::command(cmd, started, status, Some(&stdout), &stderr);
+    
+    if let Some(pane) = pane.as_mut()
+        && status.success()
+    {
+        pane.clear();
+    }
+    if !show && !status.success() && !last.is_empty() {
+        print(true, format!("┌ {} (last {} lines)", title, last.len()), false);
+        for line in last {
+            print(true, format!("│ {}", line), false);
+        }
+    }
+    let _ = std::io::stdout().flush();
+    Ok((status, stdout, stderr))
+}
+
+pub type Step<'a> = (&'a str, Box<dyn FnOnce() + Send + 'a>);
+
+// Run independent steps at the same time, printing each one's output as a
+// block when it finishes
+pub fn parallel(steps: Vec<Step<'_>>) {
+    pool(steps.len(), steps);
+}
+
+// Same as parallel(), but with at most `jobs` of the steps running at once,
+// the rest waiting for one of them to finish
+pub fn pool(jobs: usize, steps: Vec<Step<'_>>) {
+    if steps.len() <= 1 || jobs <= 1 {
+        for (_, step) in steps {
+            step();
+        }
+        return;
+    }
+    
+    let workers = jobs.min(steps.len());
+    let queue = Mutex::new(steps.into_iter());
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                loop {
+                    let next = queue.lock().unwrap_or_else(|e| e.into_inner()).next();
+                    let Some((name, step)) = next else {
+                        break;
+                    };
+                    buffered(name, step);
+                }
+            });
+        }
+    });
+}
+
+// Run a step on this thread with its output held back and printed in one block
+fn buffered(name: &str, step: Box<dyn FnOnce() + Send + '_>) {
+    CURRENT.with(|current| {
+        *current.borrow_mut() = Some(StepBuffer {
+            step: name.to_string(),
+            lines: Vec::new(),
+        })
+    });
+    
+    step();
+    
+    let buffer = CURRENT.with(|current| current.borrow_mut().take());
+    let Some(buffer) = buffer else {
+        return;
+    };
+    
+    // Hold the terminal so a pending sudo prompt can't land mid-block
+    let _terminal = INTERACTIVE.lock().unwrap_or_else(|e| e.into_inner());
+    progress::suspend(|| {
+        let stdout = std::io::stdout();
+        let mut out = stdout.lock();
+        for (stderr, text) in buffer.lines {
+            if stderr {
+                eprintln!("{}", text);
+            } else {
+                let _ = writeln!(out, "{}", text);
+            }
+        }
+        let _ = out.flush();
+    });
+}
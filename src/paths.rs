@@ -0,0 +1,80 @@
+use crate::report;
+use std::env;
+use std::path::Path;
+use std::sync::OnceLock;
+
+// Where things live in the home being set up. Resolved once, from --home or
+// $HOME, so every step agrees on it and a fake or another user's home can be
+// provisioned.
+pub struct Paths {
+    pub home: String,
+    // $XDG_CONFIG_HOME, ~/.config by default
+    pub config_home: String,
+    // $XDG_CACHE_HOME, ~/.cache by default
+    pub cache_home: String,
+    // $XDG_STATE_HOME, ~/.local/state by default
+    pub state_home: String,
+}
+
+static PATHS: OnceLock<Paths> = OnceLock::new();
+
+impl Paths {
+    // The XDG variables describe the invoking user's home, so they're only
+    // honoured when no other home was asked for
+    fn resolve(home_override: Option<String>) -> Self {
+        let use_xdg = home_override.is_none();
+        let home = home_override
+            .or_else(|| env::var("HOME").ok().filter(|home| !home.is_empty()))
+            .expect("HOME environment variable not set");
+        let home = home.trim_end_matches('/').to_string();
+        
+        let xdg = |var: &str, default: &str| match env::var(var) {
+            Ok(dir) if use_xdg && dir.starts_with('/') => dir,
+            _ => format!("{}/{}", home, default),
+        };
+        
+        Paths {
+            config_home: xdg("XDG_CONFIG_HOME", ".config"),
+            cache_home: xdg("XDG_CACHE_HOME", ".cache"),
+            state_home: xdg("XDG_STATE_HOME", ".local/state"),
+            home,
+        }
+    }
+    
+    // ~/<relative>
+    pub fn home(&self, relative: &str) -> String {
+        format!("{}/{}", self.home, relative)
+    }
+    
+    // $XDG_CONFIG_HOME/<relative>
+    pub fn config(&self, relative: &str) -> String {
+        format!("{}/{}", self.config_home, relative)
+    }
+    
+    pub fn dotfiles(&self) -> String {
+        self.home("dotfiles")
+    }
+}
+
+// Settle on the home before anything reads a path. With --home, HOME is
+// pointed there and the XDG variables dropped so the programs we run (stow,
+// git, home-manager, ...) use the same home as we do.
+pub fn init(home_override: Option<String>) {
+    if let Some(home) = &home_override {
+        if !home.starts_with('/') || !Path::new(home).is_dir() {
+            report::fatal(format!("--home {} is not an absolute path to an existing directory", home));
+        }
+        // SAFETY: called from main before any other thread is started
+        unsafe {
+            env::set_var("HOME", home);
+            for var in ["XDG_CONFIG_HOME", "XDG_CACHE_HOME", "XDG_STATE_HOME", "XDG_DATA_HOME"] {
+                env::remove_var(var);
+            }
+        }
+    }
+    let _ = PATHS.set(Paths::resolve(home_override));
+}
+
+pub fn get() -> &'static Paths {
+    PATHS.get_or_init(|| Paths::resolve(None))
+}
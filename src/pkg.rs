@@ -0,0 +1,484 @@
+use crate::aur::{self, FailureCategory, InstallOutcome, PackageFailure};
+use crate::command::Cmd;
+use crate::{diagnosis, output};
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+// The distribution's package manager. Steps name packages the Arch way,
+// other backends translate them.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Backend {
+    // Arch and derivatives: pacman plus an AUR helper
+    Pacman,
+    // Debian, Ubuntu and derivatives
+    Apt,
+    // Fedora and derivatives, COPR instead of the AUR
+    Dnf,
+}
+
+static BACKEND: OnceLock<Backend> = OnceLock::new();
+
+// From /etc/os-release, once
+pub fn backend() -> Backend {
+    *BACKEND.get_or_init(Backend::detect)
+}
+
+// Arch package names that are called something else on Debian/Ubuntu, and
+// "" for the ones there's no equivalent of
+const APT_NAMES: [(&str, &str); 12] = [
+    ("terminus-font", "console-terminus"),
+    ("libnotify", "libnotify-bin"),
+    ("nfs-utils", "nfs-common"),
+    ("hyperv", "hyperv-daemons"),
+    ("openssh", "openssh-client"),
+    ("base-devel", "build-essential"),
+    ("python-pip", "python3-pip"),
+    ("rust", "rustc"),
+    ("go", "golang"),
+    ("devtools", ""),
+    ("os-prober", "os-prober"),
+    ("xdg-user-dirs", "xdg-user-dirs"),
+];
+
+// The same for Fedora
+const DNF_NAMES: [(&str, &str); 9] = [
+    ("terminus-font", "terminus-fonts-console"),
+    ("hyperv", "hyperv-daemons"),
+    ("openssh", "openssh-clients"),
+    ("base-devel", "@development-tools"),
+    ("python-pip", "python3-pip"),
+    ("go", "golang"),
+    ("devtools", ""),
+    ("exfatprogs", "exfatprogs"),
+    ("nfs-utils", "nfs-utils"),
+];
+
+fn os_release() -> Vec<(String, String)> {
+    std::fs::read_to_string("/etc/os-release")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.trim_matches('"').to_string()))
+        .collect()
+}
+
+impl Backend {
+    fn detect() -> Backend {
+        let release = os_release();
+        let field = |key: &str| release.iter().find(|(name, _)| name == key).map(|(_, value)| value.clone()).unwrap_or_default();
+        let ids = format!("{} {}", field("ID"), field("ID_LIKE"));
+        let ids: Vec<&str> = ids.split_whitespace().collect();
+        
+        if ids.contains(&"arch") {
+            Backend::Pacman
+        } else if ids.iter().any(|id| *id == "debian" || *id == "ubuntu") {
+            Backend::Apt
+        } else if ids.contains(&"fedora") {
+            Backend::Dnf
+        } else if Command::new("pacman").arg("--version").output().is_ok() {
+            Backend::Pacman
+        } else if Command::new("apt-get").arg("--version").output().is_ok() {
+            // Unknown distribution, go by what's there
+            Backend::Apt
+        } else if Command::new("dnf").arg("--version").output().is_ok() {
+            Backend::Dnf
+        } else {
+            Backend::Pacman
+        }
+    }
+    
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Pacman => "pacman",
+            Backend::Apt => "apt",
+            Backend::Dnf => "dnf",
+        }
+    }
+    
+    // Default package list in the dotfiles repo
+    pub fn package_list(self) -> &'static str {
+        match self {
+            Backend::Pacman => "archpkglist.txt",
+            Backend::Apt => "aptpkglist.txt",
+            Backend::Dnf => "dnfpkglist.txt",
+        }
+    }
+    
+    // This backend's name for an Arch package, None when it has none
+    pub fn native_name(self, package: &str) -> Option<String> {
+        let names: &[(&str, &str)] = match self {
+            Backend::Pacman => &[],
+            Backend::Apt => &APT_NAMES,
+            Backend::Dnf => &DNF_NAMES,
+        };
+        match names.iter().find(|(arch, _)| *arch == package) {
+            Some((_, "")) => None,
+            Some((_, native)) => Some(native.to_string()),
+            None => Some(package.to_string()),
+        }
+    }
+    
+    pub fn native_names<S: AsRef<str>>(self, packages: &[S]) -> Vec<String> {
+        packages.iter().filter_map(|package| self.native_name(package.as_ref())).collect()
+    }
+    
+    // By this backend's name
+    pub fn is_installed(self, package: &str) -> bool {
+        self.manager(Vec::new()).is_installed(package)
+    }
+    
+    // The package manager, `options` go on every command it runs (rate
+    // limits, an alternative pacman.conf)
+    pub fn manager(self, options: Vec<String>) -> Box<dyn PackageManager> {
+        match self {
+            Backend::Pacman => Box::new(Pacman { options }),
+            Backend::Apt => Box::new(Apt { options }),
+            Backend::Dnf => Box::new(Dnf { options }),
+        }
+    }
+    
+    pub fn installed_packages(self) -> BTreeSet<String> {
+        let output = match self {
+            Backend::Pacman => Command::new("pacman").arg("-Qq").output(),
+            Backend::Apt => Command::new("dpkg-query").args(["-W", "-f=${db:Status-Abbrev} ${Package}\\n"]).output(),
+            Backend::Dnf => Command::new("rpm").args(["-qa", "--qf", "%{NAME}\\n"]).output(),
+        };
+        let stdout = output.map(|output| String::from_utf8_lossy(&output.stdout).to_string()).unwrap_or_default();
+        match self {
+            Backend::Pacman | Backend::Dnf => stdout.lines().map(str::to_string).collect(),
+            // "ii  name" for installed packages
+            Backend::Apt => stdout
+                .lines()
+                .filter(|line| line.starts_with("ii"))
+                .filter_map(|line| line.split_whitespace().nth(1))
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+    
+    // The command listing the packages installed on purpose rather than as
+    // dependencies, one name per line
+    pub fn explicit_query(self) -> &'static [&'static str] {
+        match self {
+            Backend::Pacman => &["pacman", "-Qqe"],
+            Backend::Apt => &["apt-mark", "showmanual"],
+            // dnf 4 ends each entry with a newline of its own, dnf 5 doesn't
+            Backend::Dnf => &["dnf", "repoquery", "--userinstalled", "-q", "--qf", "%{name}\\n"],
+        }
+    }
+    
+    // Dependencies nothing needs anymore
+    pub fn orphans(self) -> Vec<String> {
+        match self {
+            Backend::Pacman => lines("pacman", &["-Qdtq"]),
+            // "Remv foo [1.0-1]" for each package autoremove would take
+            Backend::Apt => lines("apt-get", &["--dry-run", "autoremove"])
+                .iter()
+                .filter_map(|line| line.strip_prefix("Remv "))
+                .filter_map(|rest| rest.split_whitespace().next())
+                .map(str::to_string)
+                .collect(),
+            // name-version-release.arch, the same in dnf 4 and 5
+            Backend::Dnf => lines("dnf", &["repoquery", "--unneeded", "-q"])
+                .iter()
+                .filter_map(|nevra| nevra.rsplitn(3, '-').nth(2))
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+    
+    pub fn cache_dir(self) -> &'static str {
+        match self {
+            Backend::Pacman => "/var/cache/pacman/pkg",
+            Backend::Apt => "/var/cache/apt/archives",
+            // dnf 5 moved it
+            Backend::Dnf if Path::new("/var/cache/libdnf5").is_dir() => "/var/cache/libdnf5",
+            Backend::Dnf => "/var/cache/dnf",
+        }
+    }
+}
+
+// What the steps need from a package manager, as commands to run through
+// command::run. Packages are named the way this package manager names them,
+// see Backend::native_names.
+pub trait PackageManager {
+    fn is_installed(&self, package: &str) -> bool;
+    // Sync the package databases. On Arch that's a full upgrade too, since
+    // partial upgrades aren't supported.
+    fn refresh(&self) -> Cmd;
+    fn install(&self, packages: &[String]) -> Cmd;
+    // install() with the databases synced first
+    fn install_refreshed(&self, packages: &[String]) -> Vec<Cmd> {
+        vec![self.refresh(), self.install(packages)]
+    }
+    // With the dependencies nothing else needs when `dependencies` is set
+    fn remove(&self, packages: &[String], dependencies: bool) -> Cmd;
+    fn upgrade(&self) -> Vec<Cmd>;
+    fn clean_cache(&self) -> Cmd;
+    // Enable an extra repository (a COPR, a PPA). None where repositories
+    // are set up some other way (pacman.conf).
+    fn add_repo(&self, repo: &str) -> Option<Cmd>;
+}
+
+fn pacman_installed(package: &str) -> bool {
+    Command::new("pacman")
+        .args(["-Q", package])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+pub struct Pacman {
+    pub options: Vec<String>,
+}
+
+impl Pacman {
+    fn pacman(&self) -> Cmd {
+        Cmd::sudo("pacman").args(&self.options).diagnose()
+    }
+}
+
+impl PackageManager for Pacman {
+    fn is_installed(&self, package: &str) -> bool {
+        pacman_installed(package)
+    }
+    
+    fn refresh(&self) -> Cmd {
+        self.pacman().args(["-Syu", "--noconfirm"]).network()
+    }
+    
+    fn install(&self, packages: &[String]) -> Cmd {
+        self.pacman().args(["-S", "--needed", "--noconfirm"]).args(packages)
+    }
+    
+    // One transaction
+    fn install_refreshed(&self, packages: &[String]) -> Vec<Cmd> {
+        vec![self.pacman().args(["-Syu", "--needed", "--noconfirm"]).args(packages)]
+    }
+    
+    fn remove(&self, packages: &[String], dependencies: bool) -> Cmd {
+        self.pacman().args([if dependencies { "-Rns" } else { "-Rn" }, "--noconfirm"]).args(packages)
+    }
+    
+    fn upgrade(&self) -> Vec<Cmd> {
+        vec![self.refresh()]
+    }
+    
+    fn clean_cache(&self) -> Cmd {
+        self.pacman().args(["-Sc", "--noconfirm"])
+    }
+    
+    fn add_repo(&self, _repo: &str) -> Option<Cmd> {
+        None
+    }
+}
+
+// paru, yay or pikaur on top of pacman. They run sudo themselves and pass
+// repository packages through to pacman.
+pub struct AurHelper {
+    pub helper: String,
+    pub flags: Vec<String>,
+}
+
+impl AurHelper {
+    fn helper(&self) -> Cmd {
+        Cmd::new(&self.helper).args(&self.flags).diagnose().escalates()
+    }
+}
+
+impl PackageManager for AurHelper {
+    fn is_installed(&self, package: &str) -> bool {
+        pacman_installed(package)
+    }
+    
+    fn refresh(&self) -> Cmd {
+        self.helper().args(["-Syu", "--noconfirm"]).network()
+    }
+    
+    fn install(&self, packages: &[String]) -> Cmd {
+        self.helper().args(["-S", "--needed", "--noconfirm"]).args(packages)
+    }
+    
+    fn install_refreshed(&self, packages: &[String]) -> Vec<Cmd> {
+        vec![self.helper().args(["-Syu", "--needed", "--noconfirm"]).args(packages)]
+    }
+    
+    fn remove(&self, packages: &[String], dependencies: bool) -> Cmd {
+        self.helper().args([if dependencies { "-Rns" } else { "-Rn" }, "--noconfirm"]).args(packages)
+    }
+    
+    fn upgrade(&self) -> Vec<Cmd> {
+        vec![self.refresh()]
+    }
+    
+    fn clean_cache(&self) -> Cmd {
+        self.helper().args(["-Sc", "--noconfirm"])
+    }
+    
+    fn add_repo(&self, _repo: &str) -> Option<Cmd> {
+        None
+    }
+}
+
+pub struct Apt {
+    pub options: Vec<String>,
+}
+
+impl Apt {
+    fn apt_get(&self) -> Cmd {
+        Cmd::sudo("env").args(["DEBIAN_FRONTEND=noninteractive", "apt-get", "-y"]).args(&self.options).diagnose()
+    }
+}
+
+impl PackageManager for Apt {
+    fn is_installed(&self, package: &str) -> bool {
+        Command::new("dpkg-query")
+            .args(["-W", "-f=${Status}", package])
+            .stderr(Stdio::null())
+            .output()
+            .is_ok_and(|output| output.stdout == b"install ok installed")
+    }
+    
+    fn refresh(&self) -> Cmd {
+        self.apt_get().arg("update").network()
+    }
+    
+    fn install(&self, packages: &[String]) -> Cmd {
+        self.apt_get().args(["install", "--no-install-recommends"]).args(packages)
+    }
+    
+    fn remove(&self, packages: &[String], dependencies: bool) -> Cmd {
+        self.apt_get().arg("purge").args(dependencies.then_some("--autoremove")).args(packages)
+    }
+    
+    fn upgrade(&self) -> Vec<Cmd> {
+        vec![self.refresh(), self.apt_get().arg("full-upgrade").network()]
+    }
+    
+    fn clean_cache(&self) -> Cmd {
+        self.apt_get().arg("autoclean")
+    }
+    
+    // A PPA (ppa:owner/name) or a full sources.list line
+    fn add_repo(&self, repo: &str) -> Option<Cmd> {
+        Some(Cmd::sudo("add-apt-repository").args(["-y", repo]))
+    }
+}
+
+pub struct Dnf {
+    pub options: Vec<String>,
+}
+
+impl Dnf {
+    fn dnf(&self) -> Cmd {
+        Cmd::sudo("dnf").arg("-y").args(&self.options).diagnose()
+    }
+}
+
+impl PackageManager for Dnf {
+    fn is_installed(&self, package: &str) -> bool {
+        Command::new("rpm")
+            .args(["-q", package])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+    
+    fn refresh(&self) -> Cmd {
+        self.dnf().arg("makecache").network()
+    }
+    
+    fn install(&self, packages: &[String]) -> Cmd {
+        self.dnf().arg("install").args(packages)
+    }
+    
+    fn install_refreshed(&self, packages: &[String]) -> Vec<Cmd> {
+        vec![self.dnf().args(["install", "--refresh"]).args(packages)]
+    }
+    
+    // dnf takes the dependencies nothing else needs along either way
+    fn remove(&self, packages: &[String], _dependencies: bool) -> Cmd {
+        self.dnf().arg("remove").args(packages)
+    }
+    
+    fn upgrade(&self) -> Vec<Cmd> {
+        vec![self.dnf().args(["upgrade", "--refresh"]).network()]
+    }
+    
+    fn clean_cache(&self) -> Cmd {
+        self.dnf().args(["clean", "packages"])
+    }
+    
+    // A COPR, owner/project or @group/project
+    fn add_repo(&self, repo: &str) -> Option<Cmd> {
+        Some(self.dnf().args(["copr", "enable", repo]))
+    }
+}
+
+fn lines(program: &str, args: &[&str]) -> Vec<String> {
+    Command::new(program)
+        .args(args)
+        .stderr(Stdio::null())
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).lines().map(|line| line.trim().to_string()).collect())
+        .unwrap_or_default()
+}
+
+// Targets apt or dnf refused outright. Anything else fails the whole
+// transaction.
+fn parse_failures(output: &str, targets: &[String]) -> Vec<PackageFailure> {
+    let mut failures: Vec<PackageFailure> = Vec::new();
+    for line in output.lines() {
+        let trimmed = line.trim();
+        let name = trimmed
+            .strip_prefix("E: Unable to locate package ")
+            .or_else(|| trimmed.strip_prefix("E: Package '").and_then(|rest| rest.split('\'').next()))
+            .or_else(|| trimmed.strip_prefix("E: Couldn't find any package by glob '").and_then(|rest| rest.split('\'').next()))
+            .or_else(|| trimmed.strip_prefix("No match for argument: "));
+        if let Some(name) = name
+            && targets.iter().any(|target| target == name)
+            && !failures.iter().any(|failure| failure.package == name)
+        {
+            failures.push(PackageFailure {
+                package: name.to_string(),
+                category: FailureCategory::NotFound,
+                detail: trimmed.to_string(),
+            });
+        }
+    }
+    failures
+}
+
+// Bulk install of a package list, with the targets that failed identified
+// so they can be dropped. pacman's goes through the AUR helper, run from `cwd`.
+pub fn install(backend: Backend, helper: &str, flags: &[String], packages: &[String], cwd: &str) -> InstallOutcome {
+    let mut command = match backend {
+        Backend::Pacman => return aur::install(helper, flags, packages, cwd),
+        Backend::Apt => {
+            let mut command = Command::new("sudo");
+            command.args(["env", "DEBIAN_FRONTEND=noninteractive", "apt-get", "-y"]).args(flags).args(["install", "--no-install-recommends"]);
+            command
+        }
+        Backend::Dnf => {
+            let mut command = Command::new("sudo");
+            command.args(["dnf", "-y"]).args(flags).arg("install");
+            command
+        }
+    };
+    let (status, mut output, stderr) =
+        output::watch(command.args(packages), true).unwrap_or_else(|_| panic!("Failed to execute {}", backend.name()));
+    output.push_str(&stderr);
+    
+    if status.success() {
+        return InstallOutcome { success: true, failures: Vec::new(), diagnoses: Vec::new() };
+    }
+    InstallOutcome {
+        success: false,
+        failures: parse_failures(&output, packages),
+        diagnoses: diagnosis::classify(&output),
+    }
+}
@@ -0,0 +1,127 @@
+use crate::facts;
+use std::sync::Mutex;
+
+// One package of the package list, with what its comment says about it:
+//
+//   steam  # Games through Proton group=gaming optional=true hosts=tower,!laptop
+//   waybar # Status bar session=wayland
+//   nvidia-utils # Driver when=gpu=nvidia
+//
+// key=value words set the metadata, the rest of the comment says why it's
+// installed. hosts lists the machines it's for (by hostname), !name the
+// ones it isn't for. session=wayland or x11 keeps it to machines with that
+// kind of graphical session. when= is a condition on the machine's facts,
+// like chassis=laptop or gpu=nvidia,ram_gb>=16.
+#[derive(Clone, Default)]
+pub struct Package {
+    pub name: String,
+    // From group=, or the "[group]" section it's in
+    pub group: Option<String>,
+    pub why: Option<String>,
+    // A failed install is skipped over rather than counted as a failure
+    pub optional: bool,
+    pub hosts: Vec<String>,
+    pub session: Option<String>,
+    pub when: Option<String>,
+}
+
+// Why a package in the list isn't installed on this machine
+pub enum LeftOut {
+    Group(String),
+    Host,
+    Session(String),
+    Facts(String),
+}
+
+impl Package {
+    pub fn for_host(&self, host: &str) -> bool {
+        let (excluded, included): (Vec<&str>, Vec<&str>) = self.hosts.iter().map(String::as_str).partition(|entry| entry.starts_with('!'));
+        !excluded.iter().any(|entry| entry[1..] == *host) && (included.is_empty() || included.contains(&host))
+    }
+    
+    // `session` is wayland, x11 or both, as main's session_type() has it
+    pub fn left_out(&self, exclude_groups: &[String], host: &str, session: &str) -> Option<LeftOut> {
+        if let Some(group) = &self.group
+            && exclude_groups.contains(group)
+        {
+            return Some(LeftOut::Group(group.clone()));
+        }
+        if !self.for_host(host) {
+            return Some(LeftOut::Host);
+        }
+        if let Some(wanted) = &self.session
+            && session != "both"
+            && wanted != session
+        {
+            return Some(LeftOut::Session(wanted.clone()));
+        }
+        match &self.when {
+            Some(condition) if !facts::matches(condition) => Some(LeftOut::Facts(condition.clone())),
+            _ => None,
+        }
+    }
+}
+
+// Every package in the list, left out ones included. paru-debug shows up in
+// lists dumped from pacman -Qqe and is never wanted.
+pub fn parse(content: &str) -> Result<Vec<Package>, String> {
+    let mut section = None;
+    let mut packages = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if let Some(group) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+            section = Some(group.trim().to_string());
+            continue;
+        }
+        let (name, comment) = line.split_once('#').unwrap_or((line, ""));
+        let name = name.trim();
+        if name.is_empty() || name == "paru-debug" {
+            continue;
+        }
+        if name.contains(char::is_whitespace) {
+            return Err(format!("line {}: one package per line, '{}' has more", number + 1, name));
+        }
+        
+        let mut package = Package { name: name.to_string(), group: section.clone(), ..Package::default() };
+        let mut why = Vec::new();
+        for word in comment.split_whitespace() {
+            match word.split_once('=') {
+                Some(("group", group)) if !group.is_empty() => package.group = Some(group.to_string()),
+                Some(("optional", "true" | "yes")) => package.optional = true,
+                Some(("optional", "false" | "no")) => package.optional = false,
+                Some(("optional", other)) => {
+                    return Err(format!("line {}: optional={} for {} (expected true or false)", number + 1, other, name));
+                }
+                Some(("session", session @ ("wayland" | "x11"))) => package.session = Some(session.to_string()),
+                Some(("session", other)) => {
+                    return Err(format!("line {}: session={} for {} (expected wayland or x11)", number + 1, other, name));
+                }
+                Some(("when", condition)) => {
+                    if let Err(e) = facts::check(condition) {
+                        return Err(format!("line {}: when={} for {}: {}", number + 1, condition, name, e));
+                    }
+                    package.when = Some(condition.to_string());
+                }
+                Some(("hosts", hosts)) => package.hosts = hosts.split(',').filter(|host| !host.is_empty()).map(str::to_string).collect(),
+                _ => why.push(word),
+            }
+        }
+        if !why.is_empty() {
+            package.why = Some(why.join(" "));
+        }
+        packages.push(package);
+    }
+    Ok(packages)
+}
+
+// The packages of the lists read so far this run, for what happens to them
+// later (a failed install of an optional one)
+static KNOWN: Mutex<Vec<Package>> = Mutex::new(Vec::new());
+
+pub fn remember(packages: &[Package]) {
+    KNOWN.lock().unwrap_or_else(|e| e.into_inner()).extend(packages.iter().cloned());
+}
+
+pub fn find(name: &str) -> Option<Package> {
+    KNOWN.lock().unwrap_or_else(|e| e.into_inner()).iter().find(|package| package.name == name).cloned()
+}
@@ -0,0 +1,252 @@
+use crate::output::say;
+use crate::paths;
+use crate::report::{self, Event};
+use serde::Serialize;
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+pub const PLAN_FORMATS: [&str; 3] = ["text", "json", "yaml"];
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    // A command, as it would be run
+    Run,
+    Install,
+    Write,
+    Clone,
+    Enable,
+    // Looked at before deciding what to do, changes nothing
+    Check,
+    // Asked at the terminal, what happens next depends on the answer
+    Ask,
+    Other,
+}
+
+// One thing a dry run would do, in the order it would happen
+#[derive(Serialize, Clone)]
+pub struct Action {
+    pub step: String,
+    pub kind: Kind,
+    // What a person reads in the text plan
+    pub action: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    // File, directory, unit or package the action works on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    // What changes on the machine, as far as can be told without doing it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effect: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<String>,
+}
+
+impl Action {
+    pub fn new(kind: Kind, action: impl Into<String>) -> Self {
+        Action {
+            step: crate::output::step(),
+            kind,
+            action: action.into(),
+            command: None,
+            target: None,
+            effect: None,
+            details: Vec::new(),
+        }
+    }
+    
+    pub fn command(mut self, command: impl Display) -> Self {
+        self.command = Some(command.to_string());
+        self
+    }
+    
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+    
+    pub fn effect(mut self, effect: impl Into<String>) -> Self {
+        self.effect = Some(effect.into());
+        self
+    }
+    
+    // A line shown under the action, e.g. the content of a file to write
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.details.push(detail.into());
+        self
+    }
+    
+    pub fn details<I: IntoIterator<Item = S>, S: Into<String>>(mut self, details: I) -> Self {
+        self.details.extend(details.into_iter().map(Into::into));
+        self
+    }
+}
+
+// A command run as it is, the action reads as the command
+pub fn run(command: impl Display) -> Action {
+    let command = command.to_string();
+    Action::new(Kind::Run, command.clone()).command(command)
+}
+
+pub fn install(command: impl Display, packages: &[impl AsRef<str>]) -> Action {
+    let command = command.to_string();
+    let packages: Vec<&str> = packages.iter().map(AsRef::as_ref).collect();
+    Action::new(Kind::Install, command.clone())
+        .command(command)
+        .effect(format!("installs {} unless already installed", packages.join(", ")))
+}
+
+// Creating or changing a file, which of the two judged by whether it's there
+pub fn write(path: &str, action: impl Into<String>) -> Action {
+    let effect = if Path::new(&expand(path)).exists() { format!("changes {}", path) } else { format!("creates {}", path) };
+    Action::new(Kind::Write, action).target(path).effect(effect)
+}
+
+pub fn clone(command: impl Display, dest: &str) -> Action {
+    let command = command.to_string();
+    let effect = if Path::new(&expand(dest)).exists() { format!("updates {}", dest) } else { format!("creates {}", dest) };
+    Action::new(Kind::Clone, command.clone()).command(command).target(dest).effect(effect)
+}
+
+// Enabling a unit, started at boot (or login, for user units) from then on
+pub fn enable(command: impl Display, unit: &str) -> Action {
+    let command = command.to_string();
+    Action::new(Kind::Enable, command.clone()).command(command).target(unit).effect(format!("{} starts automatically", unit))
+}
+
+pub fn check(action: impl Into<String>) -> Action {
+    Action::new(Kind::Check, action)
+}
+
+pub fn ask(action: impl Into<String>) -> Action {
+    Action::new(Kind::Ask, action)
+}
+
+pub fn other(action: impl Into<String>) -> Action {
+    Action::new(Kind::Other, action)
+}
+
+fn expand(path: &str) -> String {
+    match path.strip_prefix("~/") {
+        Some(rest) => format!("{}/{}", paths::get().home, rest),
+        None => path.to_string(),
+    }
+}
+
+// text (default) prints the plan as it's made; json and yaml collect it and
+// print it on stdout at the end, with everything else on stderr
+static FORMAT: OnceLock<String> = OnceLock::new();
+
+static ACTIONS: Mutex<Vec<Action>> = Mutex::new(Vec::new());
+
+pub fn set_format(format: &str) {
+    if format != "text" {
+        report::claim_stdout();
+    }
+    let _ = FORMAT.set(format.to_string());
+}
+
+fn format() -> &'static str {
+    FORMAT.get().map(String::as_str).unwrap_or("text")
+}
+
+pub fn add(action: Action) {
+    let mut actions = ACTIONS.lock().unwrap_or_else(|e| e.into_inner());
+    if format() == "text" {
+        let number = actions.iter().filter(|earlier| earlier.step == action.step).count() + 1;
+        if number == 1 {
+            say!("[DRY RUN] Would execute:");
+        }
+        say!("  {}. {}", number, action.action);
+        for detail in &action.details {
+            say!("       {}", detail);
+        }
+    }
+    actions.push(action);
+}
+
+#[derive(Serialize)]
+struct Plan<'a> {
+    command: &'a str,
+    profile: &'a str,
+    actions: &'a [Action],
+}
+
+// Print the collected plan, for json and yaml
+pub fn finish(command: &str, profile: &str) {
+    let actions = ACTIONS.lock().unwrap_or_else(|e| e.into_inner());
+    if format() == "text" {
+        return;
+    }
+    if report::is_json() {
+        report::emit(Event::Plan { command, profile, actions: &actions });
+        return;
+    }
+    
+    let plan = Plan { command, profile, actions: &actions };
+    let text = match format() {
+        "yaml" => serde_json::to_value(&plan).map(|value| yaml(&value)),
+        _ => serde_json::to_string_pretty(&plan).map(|json| json + "\n"),
+    };
+    match text {
+        Ok(text) => report::write_stdout(&text),
+        Err(e) => report::fatal(format!("Failed to serialize the plan: {}", e)),
+    }
+}
+
+// YAML for the plan, which is only maps, lists and strings; the strings are
+// double-quoted the JSON way, which YAML reads the same
+fn yaml(value: &serde_json::Value) -> String {
+    let mut out = String::new();
+    yaml_value(value, 0, &mut out);
+    out
+}
+
+fn yaml_value(value: &serde_json::Value, indent: usize, out: &mut String) {
+    use serde_json::Value;
+    let pad = " ".repeat(indent);
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                match value {
+                    Value::Object(inner) if !inner.is_empty() => {
+                        out.push_str(&format!("{}{}:\n", pad, key));
+                        yaml_value(value, indent + 2, out);
+                    }
+                    Value::Array(items) if !items.is_empty() => {
+                        out.push_str(&format!("{}{}:\n", pad, key));
+                        yaml_value(value, indent, out);
+                    }
+                    _ => out.push_str(&format!("{}{}: {}\n", pad, key, yaml_scalar(value))),
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                match item {
+                    Value::Object(map) if !map.is_empty() => {
+                        // The first key goes on the dash line, the rest under it
+                        let mut nested = String::new();
+                        yaml_value(item, indent + 2, &mut nested);
+                        out.push_str(&format!("{}- {}", pad, &nested[indent + 2..]));
+                    }
+                    Value::Array(inner) if !inner.is_empty() => {
+                        out.push_str(&format!("{}-\n", pad));
+                        yaml_value(item, indent + 2, out);
+                    }
+                    _ => out.push_str(&format!("{}- {}\n", pad, yaml_scalar(item))),
+                }
+            }
+        }
+        _ => out.push_str(&format!("{}{}\n", pad, yaml_scalar(value))),
+    }
+}
+
+fn yaml_scalar(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Object(_) => "{}".to_string(),
+        serde_json::Value::Array(_) => "[]".to_string(),
+        other => other.to_string(),
+    }
+}
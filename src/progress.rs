@@ -0,0 +1,126 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+// Spinners and bars are drawn on a terminal only, and not with --verbose,
+// where the tail pane shows what's going on instead
+static ENABLED: OnceLock<()> = OnceLock::new();
+
+// The bars on screen right now; other output goes above them instead of
+// through them
+static ACTIVE: Mutex<Option<MultiProgress>> = Mutex::new(None);
+
+// Steps this run means to go through, numbered for "Step 4/10"
+static PLAN: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+const TICK: Duration = Duration::from_millis(100);
+
+pub fn enable() {
+    let _ = ENABLED.set(());
+}
+
+pub fn set_plan(steps: Vec<String>) {
+    *PLAN.lock().unwrap_or_else(|e| e.into_inner()) = steps;
+}
+
+// (number, total) of a planned step. Steps that turn out not to apply keep
+// their number, so the count can jump ahead.
+pub fn position(step: &str) -> Option<(usize, usize)> {
+    let plan = PLAN.lock().unwrap_or_else(|e| e.into_inner());
+    let index = plan.iter().position(|planned| planned == step)?;
+    Some((index + 1, plan.len()))
+}
+
+fn active() -> Option<MultiProgress> {
+    ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+pub fn is_active() -> bool {
+    active().is_some()
+}
+
+// Print with the bars out of the way, they're redrawn below afterwards
+pub fn suspend<R>(print: impl FnOnce() -> R) -> R {
+    match active() {
+        Some(multi) => multi.suspend(print),
+        None => print(),
+    }
+}
+
+// Claim the screen for a new set of bars, unless they're off or another
+// set is already drawn (a clone inside the parallel wallpaper fetches)
+fn claim() -> Option<MultiProgress> {
+    ENABLED.get()?;
+    let mut active = ACTIVE.lock().unwrap_or_else(|e| e.into_inner());
+    if active.is_some() {
+        return None;
+    }
+    let multi = MultiProgress::with_draw_target(ProgressDrawTarget::stdout());
+    *active = Some(multi.clone());
+    Some(multi)
+}
+
+fn release() {
+    *ACTIVE.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+fn spinner_bar(multi: &MultiProgress, message: String) -> ProgressBar {
+    let style = ProgressStyle::with_template("{spinner} {msg} ({elapsed})")
+        .expect("spinner template is valid")
+        .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏ ");
+    let bar = multi.add(ProgressBar::new_spinner().with_style(style).with_message(message));
+    bar.enable_steady_tick(TICK);
+    bar
+}
+
+// A spinner for one long operation, cleared again when dropped
+pub struct Spinner {
+    bar: Option<ProgressBar>,
+}
+
+pub fn spinner(message: String) -> Spinner {
+    Spinner { bar: claim().map(|multi| spinner_bar(&multi, message)) }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        if let Some(bar) = self.bar.take() {
+            bar.finish_and_clear();
+            release();
+        }
+    }
+}
+
+// A bar counting finished jobs, with a spinner under it for each running one
+pub struct Bars {
+    multi: MultiProgress,
+    total: ProgressBar,
+}
+
+pub fn bars(len: usize, message: &str) -> Option<Bars> {
+    let multi = claim()?;
+    let style = ProgressStyle::with_template("{msg} [{bar:30}] {pos}/{len}")
+        .expect("bar template is valid")
+        .progress_chars("=> ");
+    let total = multi.add(ProgressBar::new(len as u64).with_style(style).with_message(message.to_string()));
+    Some(Bars { multi, total })
+}
+
+impl Bars {
+    pub fn start(&self, name: &str) -> ProgressBar {
+        spinner_bar(&self.multi, name.to_string())
+    }
+    
+    pub fn done(&self, bar: ProgressBar) {
+        bar.finish_and_clear();
+        self.multi.remove(&bar);
+        self.total.inc(1);
+    }
+}
+
+impl Drop for Bars {
+    fn drop(&mut self) {
+        self.total.finish_and_clear();
+        release();
+    }
+}
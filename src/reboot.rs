@@ -0,0 +1,174 @@
+use crate::config;
+use std::path::Path;
+use std::sync::Mutex;
+
+// How badly a step wants a reboot before the steps after it run
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum Need {
+    Recommended,
+    Required,
+}
+
+impl Need {
+    pub fn name(self) -> &'static str {
+        match self {
+            Need::Recommended => "recommended",
+            Need::Required => "required",
+        }
+    }
+    
+    fn parse(name: &str) -> Option<Need> {
+        match name {
+            "recommended" => Some(Need::Recommended),
+            "required" => Some(Need::Required),
+            _ => None,
+        }
+    }
+}
+
+const MICROCODE_IMAGES: [&str; 2] = ["/boot/intel-ucode.img", "/boot/amd-ucode.img"];
+
+#[derive(Default)]
+struct Pending {
+    reasons: Vec<(Need, String)>,
+    kernel_noted: bool,
+    microcode_noted: bool,
+}
+
+// Reboots steps asked for since run_step last looked. Steps only get
+// &Config and may run in parallel, hence the Mutex.
+pub struct Requests {
+    pending: Mutex<Pending>,
+    // Whether a microcode image was already in /boot when we started
+    had_microcode: bool,
+}
+
+impl Requests {
+    pub fn new() -> Self {
+        Requests {
+            pending: Mutex::new(Pending::default()),
+            had_microcode: MICROCODE_IMAGES.iter().any(|image| Path::new(image).exists()),
+        }
+    }
+    
+    fn lock(&self) -> std::sync::MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    
+    // For steps that know the following steps need a reboot to see their work
+    pub fn add(&self, need: Need, reason: impl Into<String>) {
+        self.lock().reasons.push((need, reason.into()));
+    }
+    
+    // Notice package installs that only take effect on the next boot, whatever
+    // step they came from. Each is only reported once.
+    pub fn check_boot_changes(&self) {
+        let mut pending = self.lock();
+        
+        // Upgrading or replacing the running kernel's package removes its
+        // modules, so loading one (a new filesystem, wireguard, ...) fails
+        // until we boot the new kernel
+        if !pending.kernel_noted && kernel_replaced() {
+            pending.kernel_noted = true;
+            pending.reasons.push((Need::Required, "the running kernel was upgraded or replaced".to_string()));
+        }
+        
+        if !self.had_microcode && !pending.microcode_noted && MICROCODE_IMAGES.iter().any(|image| Path::new(image).exists()) {
+            pending.microcode_noted = true;
+            pending.reasons.push((Need::Recommended, "CPU microcode was installed and only loads at boot".to_string()));
+        }
+    }
+    
+    pub fn take(&self) -> Vec<(Need, String)> {
+        std::mem::take(&mut self.lock().reasons)
+    }
+}
+
+fn kernel_replaced() -> bool {
+    match std::fs::read_to_string("/proc/sys/kernel/osrelease") {
+        Ok(release) => !Path::new("/usr/lib/modules").join(release.trim()).exists(),
+        Err(_) => false,
+    }
+}
+
+fn boot_id() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/random/boot_id")
+        .map(|id| id.trim().to_string())
+        .unwrap_or_default()
+}
+
+// A paused run's progress itself is in the checkpoint
+pub fn paused_path() -> String {
+    config::state_path("paused-run")
+}
+
+// A run paused for a reboot: "boot <id>" and "need <need>" lines
+pub struct Paused {
+    pub need: Need,
+    boot_id: String,
+}
+
+impl Paused {
+    pub fn rebooted(&self) -> bool {
+        self.boot_id != boot_id()
+    }
+}
+
+pub fn read_paused() -> Option<Paused> {
+    let content = std::fs::read_to_string(paused_path()).ok()?;
+    let mut lines = content.lines();
+    let boot_id = lines.next()?.strip_prefix("boot ")?.to_string();
+    let need = Need::parse(lines.next()?.strip_prefix("need ")?)?;
+    Some(Paused { need, boot_id })
+}
+
+pub fn write_paused(need: Need) -> std::io::Result<()> {
+    let path = paused_path();
+    if let Some(dir) = Path::new(&path).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&path, format!("boot {}\nneed {}\n", boot_id(), need.name()))
+}
+
+pub fn clear_paused() {
+    let _ = std::fs::remove_file(paused_path());
+}
+
+pub const RESUME_UNIT_PATH: &str = "/etc/systemd/system/ass-resume.service";
+
+// ExecStart= quoting: plain words as they are, anything else in double
+// quotes, with % and $ doubled so systemd doesn't expand them
+pub fn systemd_quote(word: &str) -> String {
+    let escaped = word.replace('%', "%%").replace('$', "$$");
+    if !escaped.is_empty() && escaped.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=@+%$,~".contains(c)) {
+        escaped
+    } else {
+        format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+// Oneshot unit that runs ass again with the same arguments on the next boot.
+// The resumed run disables and removes it.
+pub fn resume_unit(exe: &str, args: &[String], user: &str, home: &str) -> String {
+    let command: Vec<String> = std::iter::once(exe).chain(args.iter().map(String::as_str)).map(systemd_quote).collect();
+    format!(
+        "[Unit]
+Description=Resume the ass run paused for a reboot
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=oneshot
+User={}
+Environment=HOME={} NO_COLOR=1
+ExecStart={}
+TimeoutStartSec=infinity
+
+[Install]
+WantedBy=multi-user.target
+",
+        user,
+        home,
+        command.join(" ")
+    )
+}
@@ -0,0 +1,89 @@
+use crate::{command, config};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+
+// Release tags "latest" resolved to on the first run, keyed by "owner/repo",
+// so later runs install exactly the same thing until asked to refresh
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct Pins {
+    releases: BTreeMap<String, String>,
+}
+
+pub fn pins_path() -> String {
+    config::state_path("pins.toml")
+}
+
+fn read_pins() -> Pins {
+    std::fs::read_to_string(pins_path())
+        .ok()
+        .and_then(|content| toml::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn write_pin(repo: &str, tag: &str) {
+    let path = pins_path();
+    let mut pins = read_pins();
+    pins.releases.insert(repo.to_string(), tag.to_string());
+    
+    if let Some(dir) = Path::new(&path).parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    if let Ok(content) = toml::to_string(&pins) {
+        let _ = std::fs::write(&path, content);
+    }
+}
+
+pub fn pinned(repo: &str) -> Option<String> {
+    read_pins().releases.remove(repo)
+}
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+}
+
+fn latest_release(repo: &str) -> Result<String, String> {
+    let url = format!("https://api.github.com/repos/{}/releases/latest", repo);
+    let output = command::run_with_retry(&format!("Looking up the latest release of {}", repo), || {
+        let output = Command::new("curl")
+            .args(["--proto", "=https", "--tlsv1.2", "-sSfL", "-H", "Accept: application/vnd.github+json", &url])
+            .output()
+            .map_err(|e| format!("failed to execute curl: {}", e))?;
+        if !output.status.success() {
+            return Err(format!("GitHub API request failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        Ok(output)
+    })?;
+    
+    serde_json::from_slice::<Release>(&output.stdout)
+        .map(|release| release.tag_name)
+        .map_err(|e| format!("unexpected GitHub API response: {}", e))
+}
+
+// Turn a requested version into a concrete tag. "latest" is looked up once
+// and pinned; later runs reuse the pin unless refresh is set.
+pub fn resolve(repo: &str, requested: &str, refresh: bool) -> Result<String, String> {
+    if requested != "latest" {
+        return Ok(requested.to_string());
+    }
+    
+    if !refresh && let Some(tag) = pinned(repo) {
+        return Ok(tag);
+    }
+    
+    let tag = latest_release(repo)?;
+    write_pin(repo, &tag);
+    Ok(tag)
+}
+
+// "https://github.com/owner/repo(.git)" -> "owner/repo"
+pub fn github_repo(url: &str) -> Option<String> {
+    let rest = url.strip_prefix("https://github.com/")?;
+    let mut parts = rest.trim_end_matches('/').trim_end_matches(".git").splitn(3, '/');
+    let owner = parts.next().filter(|s| !s.is_empty())?;
+    let repo = parts.next().filter(|s| !s.is_empty())?;
+    Some(format!("{}/{}", owner, repo))
+}
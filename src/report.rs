@@ -0,0 +1,164 @@
+use crate::ci;
+use crate::journald;
+use crate::plan::Action;
+use crate::runlog;
+use serde::Serialize;
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::FromRawFd;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// What a run reports with --output json, one object per line on stdout for
+// scripts and provisioning systems to follow along
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    RunStarted {
+        run: &'a str,
+        command: &'a str,
+        profile: &'a str,
+        plan: &'a [String],
+    },
+    StepStarted {
+        step: &'a str,
+    },
+    StepFinished {
+        step: &'a str,
+        secs: u64,
+    },
+    Command {
+        command: &'a str,
+        success: bool,
+        // None when it was killed by a signal or couldn't be started
+        code: Option<i32>,
+        error: Option<String>,
+    },
+    Warning {
+        kind: &'a str,
+        message: &'a str,
+        hints: &'a [&'a str],
+    },
+    Error {
+        message: &'a str,
+    },
+    RunFinished {
+        outcome: &'a str,
+    },
+    // The whole plan of a dry run, with --plan-format json or yaml
+    Plan {
+        command: &'a str,
+        profile: &'a str,
+        actions: &'a [Action],
+    },
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    time: String,
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+// The original stdout, once something machine-readable claimed it
+static STDOUT: OnceLock<Mutex<File>> = OnceLock::new();
+
+// Set with --output json
+static JSON: OnceLock<()> = OnceLock::new();
+
+type Hook = Box<dyn Fn(&str) + Send + Sync>;
+
+// Called with the message before a fatal error ends the run
+static ON_FATAL: OnceLock<Hook> = OnceLock::new();
+
+// Keep stdout for machine-readable output (--output json, --plan-format)
+// and point it at stderr for everything else, so the messages, prompts and
+// the output of the commands run (which inherit stdout) still reach a
+// person watching without getting mixed into it
+pub fn claim_stdout() {
+    if STDOUT.get().is_some() {
+        return;
+    }
+    // SAFETY: plain fd juggling, the new fd is owned by the File from here on
+    let kept = unsafe { libc::dup(libc::STDOUT_FILENO) };
+    if kept < 0 {
+        eprintln!("ERROR: Failed to set up machine-readable output: {}", std::io::Error::last_os_error());
+        std::process::exit(1);
+    }
+    let _ = std::io::stdout().flush();
+    // SAFETY: both fds are open
+    if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+        eprintln!("ERROR: Failed to set up machine-readable output: {}", std::io::Error::last_os_error());
+        std::process::exit(1);
+    }
+    // SAFETY: kept was just returned by dup and nothing else owns it
+    let _ = STDOUT.set(Mutex::new(unsafe { File::from_raw_fd(kept) }));
+}
+
+// Write to the claimed stdout in one piece, so output from parallel steps
+// can't interleave
+pub fn write_stdout(text: &str) {
+    if let Some(stdout) = STDOUT.get() {
+        let mut stdout = stdout.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = stdout.write_all(text.as_bytes());
+    }
+}
+
+pub fn enable_json() {
+    claim_stdout();
+    let _ = JSON.set(());
+    
+    // A panic ends the run as much as an ERROR does
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let message = match info.payload().downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => info.payload().downcast_ref::<String>().cloned().unwrap_or_else(|| "panic".to_string()),
+        };
+        emit(Event::Error { message: &message });
+        default_hook(info);
+    }));
+}
+
+pub fn is_json() -> bool {
+    JSON.get().is_some()
+}
+
+// To the journal (see journald) and, with --output json, stdout
+pub fn emit(event: Event<'_>) {
+    journald::send(&event);
+    if !is_json() {
+        return;
+    }
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    if let Ok(json) = serde_json::to_string(&Line { time: ci::utc(secs), event }) {
+        write_stdout(&format!("{}\n", json));
+    }
+}
+
+// Print an error the run is about to end with, for when more follows it
+pub fn error(message: impl AsRef<str>) {
+    let message = message.as_ref();
+    eprintln!("ERROR: {}", message);
+    runlog::message(&format!("ERROR: {}", message));
+    emit(Event::Error { message });
+}
+
+// Something that has to happen even when the run ends in an error, like the
+// report of a scheduled run. Only the first one set counts.
+pub fn on_fatal(hook: impl Fn(&str) + Send + Sync + 'static) {
+    let _ = ON_FATAL.set(Box::new(hook));
+}
+
+// Print an error and end the run
+pub fn fatal(message: impl AsRef<str>) -> ! {
+    let message = message.as_ref();
+    error(message);
+    if let Some(log) = runlog::path() {
+        eprintln!("  (everything this run did is in {})", log);
+    }
+    if let Some(hook) = ON_FATAL.get() {
+        hook(message);
+    }
+    std::process::exit(1);
+}
@@ -0,0 +1,130 @@
+use crate::ci;
+use crate::config;
+use std::fs::File;
+use std::io::Write;
+use std::process::{Command, ExitStatus};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// Logs kept in the logs directory, the oldest ones beyond this are dropped
+const KEPT_LOGS: usize = 50;
+
+// Everything a run printed and every command it ran with its exit code and
+// output, whatever --verbose says, for finding out afterwards why an
+// unattended run failed
+struct Log {
+    path: String,
+    file: Mutex<File>,
+}
+
+// Unset until start(), so dry runs and the read-only subcommands leave no log
+static LOG: OnceLock<Log> = OnceLock::new();
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn dir() -> String {
+    config::state_path("logs")
+}
+
+// Open run-<timestamp>.log for this run. A log that can't be written only
+// costs the log, not the run.
+pub fn start() {
+    let dir = dir();
+    if std::fs::create_dir_all(&dir).is_err() {
+        return;
+    }
+    let mut old: Vec<String> = std::fs::read_dir(&dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter(|name| name.starts_with("run-") && name.ends_with(".log"))
+                .collect()
+        })
+        .unwrap_or_default();
+    old.sort();
+    for name in old.iter().take(old.len().saturating_sub(KEPT_LOGS - 1)) {
+        let _ = std::fs::remove_file(format!("{}/{}", dir, name));
+    }
+    
+    // 2026-10-18T03:00:00Z becomes 20261018T030000Z. Runs started in the
+    // same second share a log rather than overwrite each other's.
+    let stamp: String = ci::utc(now()).chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+    let path = format!("{}/run-{}.log", dir, stamp);
+    let Ok(mut file) = std::fs::OpenOptions::new().create(true).append(true).open(&path) else {
+        return;
+    };
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let _ = writeln!(file, "# ass {} {}", env!("CARGO_PKG_VERSION"), args.join(" "));
+    let _ = writeln!(file, "# started {}", ci::utc(now()));
+    let _ = LOG.set(Log { path, file: Mutex::new(file) });
+}
+
+pub fn path() -> Option<&'static str> {
+    LOG.get().map(|log| log.path.as_str())
+}
+
+pub fn is_open() -> bool {
+    LOG.get().is_some()
+}
+
+// Appended in one piece, so parallel steps don't interleave inside an entry
+fn write(text: &str) {
+    if let Some(log) = LOG.get() {
+        let mut file = log.file.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = file.write_all(text.as_bytes());
+    }
+}
+
+fn time() -> String {
+    let stamp = ci::utc(now());
+    stamp[11..19].to_string()
+}
+
+// A line ass printed, as it was shown
+pub fn message(text: &str) {
+    if is_open() {
+        write(&format!("[{}] {}\n", time(), text));
+    }
+}
+
+// Shell-like rendering of a std Command, for the commands that don't come
+// from a Cmd
+fn render(command: &Command) -> String {
+    let mut words = vec![command.get_program().to_string_lossy().to_string()];
+    words.extend(command.get_args().map(|arg| arg.to_string_lossy().to_string()));
+    let line = words.join(" ");
+    match command.get_current_dir() {
+        Some(dir) => format!("cd {} && {}", dir.display(), line),
+        None => line,
+    }
+}
+
+// A finished command with what it wrote, stdout lines marked with | and
+// stderr lines with !. None for stdout means it went straight to the
+// terminal and wasn't captured.
+pub fn command(command: &Command, started: Instant, status: ExitStatus, stdout: Option<&str>, stderr: &str) {
+    if !is_open() {
+        return;
+    }
+    let mut entry = format!("[{}] $ {}\n", time(), render(command));
+    match stdout {
+        Some(stdout) => entry.extend(stdout.lines().map(|line| format!("  | {}\n", line))),
+        None => entry.push_str("  (stdout went to the terminal)\n"),
+    }
+    entry.extend(stderr.lines().map(|line| format!("  ! {}\n", line)));
+    let outcome = match status.code() {
+        Some(code) => format!("exit {}", code),
+        None => "killed by a signal".to_string(),
+    };
+    entry.push_str(&format!("[{}] {} after {}s\n", time(), outcome, started.elapsed().as_secs()));
+    write(&entry);
+}
+
+pub fn not_started(command: &Command, error: &std::io::Error) {
+    if is_open() {
+        write(&format!("[{}] $ {}\n[{}] could not start: {}\n", time(), render(command), time(), error));
+    }
+}
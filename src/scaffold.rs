@@ -0,0 +1,179 @@
+use crate::i18n::tr;
+use crate::output::{esay, say};
+use crate::{ask_yes_no, config, paths, prompt, report};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+const PACKAGE_LIST: &str = "\
+# One package per line. Packages below a [group] line can be left out by
+# profiles, the server profile leaves out [gui].
+git
+stow
+neovim
+tmux
+
+[gui]
+";
+
+const NIX_CONF: &str = "experimental-features = nix-command flakes\n";
+
+// Stowed into ~/.config/home-manager by the home-manager stow package
+fn home_nix(user: &str, home: &str) -> String {
+    format!(
+        r#"{{ config, pkgs, ... }}:
+
+{{
+  home.username = "{}";
+  home.homeDirectory = "{}";
+  home.stateVersion = "25.11";
+  
+  home.packages = with pkgs; [
+  ];
+  
+  programs.home-manager.enable = true;
+}}
+"#,
+        user, home
+    )
+}
+
+
+fn git(dir: &str, args: &[&str]) -> bool {
+    Command::new("git").arg("-C").arg(dir).args(args).status().is_ok_and(|status| status.success())
+}
+
+// `-c` options giving a commit its author and committer: [git] first, then
+// git's own settings, then the login name as a last resort so the commit
+// doesn't fail on a machine git was never set up on
+fn identity_options(dir: &str, git_config: &config::GitConfig, user: &str) -> Vec<String> {
+    let configured = |key: &str| {
+        Command::new("git")
+            .args(["-C", dir, "config", key])
+            .output()
+            .ok()
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    let name = git_config.name.clone().or_else(|| configured("user.name")).unwrap_or_else(|| user.to_string());
+    let email = git_config.email.clone().or_else(|| configured("user.email")).unwrap_or_else(|| format!("{}@localhost", user));
+    vec!["-c".to_string(), format!("user.name={}", name), "-c".to_string(), format!("user.email={}", email)]
+}
+
+// (relative path, content) of everything the new repo starts with
+fn layout(package_list: &str, user: &str, home: &str) -> Vec<(String, String)> {
+    vec![
+        (package_list.to_string(), PACKAGE_LIST.to_string()),
+        ("home-manager/.config/home-manager/home.nix".to_string(), home_nix(user, home)),
+        ("nix/.config/nix/nix.conf".to_string(), NIX_CONF.to_string()),
+    ]
+}
+
+// POST /user/repos, returning the clone URL. The token goes to curl on
+// stdin so it doesn't show up in the process list.
+fn create_github_repo(token: &str, name: &str, private: bool) -> Result<String, String> {
+    let body = serde_json::json!({ "name": name, "private": private }).to_string();
+    let mut child = Command::new("curl")
+        .args(["--proto", "=https", "--tlsv1.2", "-sSL", "-H", "@-", "-H", "Accept: application/vnd.github+json", "-d", &body])
+        .arg("https://api.github.com/user/repos")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to execute curl: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = writeln!(stdin, "Authorization: Bearer {}", token);
+    }
+    let output = child.wait_with_output().map_err(|e| format!("failed to execute curl: {}", e))?;
+    
+    let response: serde_json::Value =
+        serde_json::from_slice(&output.stdout).map_err(|e| format!("unexpected GitHub API response: {}", e))?;
+    match response["clone_url"].as_str() {
+        Some(url) => Ok(url.to_string()),
+        None => Err(response["message"].as_str().unwrap_or("no clone URL in the response").to_string()),
+    }
+}
+
+// Push with the token from the environment, without storing it anywhere
+fn push(dir: &str, token_var: &str) -> bool {
+    let helper = format!("!f() {{ echo username=x-access-token; echo \"password=${}\"; }}; f", token_var);
+    Command::new("git")
+        .args(["-C", dir, "-c", "credential.helper=", "-c", &format!("credential.helper={}", helper), "push", "-u", "origin", "main"])
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+// `ass dotfiles init`: start a dotfiles repo in ~/dotfiles with the layout
+// the setup expects, optionally on GitHub (with GITHUB_TOKEN or GH_TOKEN
+// set), and point the config at it. Returns whether to start the run.
+pub fn init(dry_run: bool) -> bool {
+    let dir = paths::get().dotfiles();
+    let file_config = config::load_file_config(&config::config_path());
+    let package_list = file_config.dotfiles.package_list().to_string();
+    if package_list.starts_with('/') || package_list.starts_with("~/") {
+        report::fatal(format!("dotfiles.package_list is {}, outside the repo; unset it to use ass dotfiles init", package_list));
+    }
+    let user = std::env::var("USER").unwrap_or_default();
+    let files = layout(&package_list, &user, &paths::get().home);
+    
+    if Path::new(&dir).read_dir().is_ok_and(|mut entries| entries.next().is_some()) {
+        report::fatal(format!("{} already exists and isn't empty", dir));
+    }
+    
+    if dry_run {
+        say!("[DRY RUN] Would create {} with:", dir);
+        for (path, _) in &files {
+            say!("  {}", path);
+        }
+        say!("  then git init, commit, optionally create and push a GitHub repo,");
+        say!("  and set dotfiles.repo in {}", config::config_path());
+        return false;
+    }
+    
+    say!("Creating a dotfiles repo in {}...", dir);
+    for (path, content) in &files {
+        let path = Path::new(&dir).join(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).unwrap_or_else(|e| report::fatal(format!("Failed to create {}: {}", parent.display(), e)));
+        }
+        std::fs::write(&path, content).unwrap_or_else(|e| report::fatal(format!("Failed to write {}: {}", path.display(), e)));
+        say!("  ✓ {}", path.display());
+    }
+    
+    if !git(&dir, &["init", "-q", "-b", "main"]) || !git(&dir, &["add", "-A"]) {
+        report::fatal(format!("Failed to create a git repo in {}", dir));
+    }
+    // setup_dotfiles takes a repo without a commit for a broken clone
+    let mut commit = identity_options(&dir, &file_config.git, &user);
+    commit.extend(["commit", "-q", "-m", "Initial dotfiles"].map(String::from));
+    if !git(&dir, &commit.iter().map(String::as_str).collect::<Vec<_>>()) {
+        report::fatal(format!("Failed to commit the new repo in {}", dir));
+    }
+    say!("✓ {}", tr!("Created {}", dir));
+    
+    let mut repo = dir.clone();
+    let token = ["GITHUB_TOKEN", "GH_TOKEN"].into_iter().find_map(|var| std::env::var(var).ok().map(|token| (var, token)));
+    match token {
+        Some((var, token)) if ask_yes_no(tr!("Create a GitHub repository for it and push?"), true) => {
+            let name = prompt(tr!("Repository name"), "dotfiles");
+            let private = ask_yes_no(tr!("Make it private?"), false);
+            match create_github_repo(&token, &name, private) {
+                Ok(url) if git(&dir, &["remote", "add", "origin", &url]) && push(&dir, var) => {
+                    say!("✓ {}", tr!("Pushed to {}", url));
+                    repo = url;
+                }
+                Ok(url) => esay!("⚠ Failed to push to {}, the config points at the local repo for now", url),
+                Err(e) => esay!("⚠ Failed to create the GitHub repository: {}", e),
+            }
+        }
+        Some(_) => {}
+        None => say!("  Set GITHUB_TOKEN to have it created on GitHub and pushed as well"),
+    }
+    
+    let path = config::config_path();
+    config::write_config_values(&path, &[("dotfiles", "repo", toml::Value::String(repo))]);
+    say!("✓ {}", tr!("Wrote {}", path));
+    say!("  Add your packages to {}/{} and your configs as stow packages", dir, package_list);
+    say!();
+    
+    ask_yes_no(tr!("Start the setup now?"), false)
+}
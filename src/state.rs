@@ -0,0 +1,145 @@
+use crate::output::{esay, say};
+use crate::{config, pkg, releases, report, timings, workdir};
+use std::path::Path;
+use std::process::Command;
+
+// Directory inside the archive holding the files
+const ARCHIVE_DIR: &str = "ass-state";
+
+// Explicitly installed packages of the exporting machine, kept on import so
+// setup_dotfiles installs them on top of the dotfiles package list
+pub fn imported_packages_path() -> String {
+    config::state_path("imported-packages.txt")
+}
+
+// (name in the archive, where it lives on this machine)
+fn state_files() -> [(&'static str, String); 3] {
+    [
+        ("config.toml", config::config_path()),
+        ("pins.toml", releases::pins_path()),
+        ("timings.toml", timings::default_timings_path()),
+    ]
+}
+
+// A fresh directory only we can get into: the config can hold [mounts]
+// passwords, and an unpacked one drives sudo commands
+fn staging_dir() -> String {
+    let dir = workdir::private_temp_dir("ass-state").unwrap_or_else(|e| report::fatal(format!("Failed to create a staging directory: {}", e)));
+    dir.to_string_lossy().to_string()
+}
+
+pub fn default_archive_name() -> String {
+    let host = std::fs::read_to_string("/etc/hostname").unwrap_or_default();
+    let host = host.trim();
+    format!("ass-state-{}.tar.gz", if host.is_empty() { "localhost" } else { host })
+}
+
+// Config, release pins, timing profile and a package snapshot in one tarball
+pub fn export(archive: &str, dry_run: bool) {
+    say!("Exporting state to {}...", archive);
+    
+    if dry_run {
+        say!("[DRY RUN] Would pack into {}/:", ARCHIVE_DIR);
+        for (name, path) in state_files() {
+            if Path::new(&path).exists() {
+                say!("  {} (from {})", name, path);
+            }
+        }
+        say!("  packages.txt ({})", pkg::backend().explicit_query().join(" "));
+        return;
+    }
+    
+    let staging = staging_dir();
+    let dir = format!("{}/{}", staging, ARCHIVE_DIR);
+    std::fs::create_dir_all(&dir).unwrap_or_else(|e| report::fatal(format!("Failed to create {}: {}", dir, e)));
+    
+    for (name, path) in state_files() {
+        if Path::new(&path).exists() {
+            std::fs::copy(&path, format!("{}/{}", dir, name))
+                .unwrap_or_else(|e| report::fatal(format!("Failed to copy {}: {}", path, e)));
+            say!("  ✓ {}", name);
+        }
+    }
+    
+    let query = pkg::backend().explicit_query();
+    let packages = Command::new(query[0]).args(&query[1..]).output().ok().filter(|output| output.status.success());
+    match packages {
+        Some(output) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let names: Vec<&str> = stdout.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+            std::fs::write(format!("{}/packages.txt", dir), format!("{}\n", names.join("\n")))
+                .unwrap_or_else(|e| report::fatal(format!("Failed to write the package snapshot: {}", e)));
+            say!("  ✓ packages.txt ({} packages)", names.len());
+        }
+        None => esay!("  ⚠ {} failed, exporting without a package snapshot", query.join(" ")),
+    }
+    
+    let status = Command::new("tar").args(["-czf", archive, "-C", &staging, ARCHIVE_DIR]).status();
+    let _ = std::fs::remove_dir_all(&staging);
+    if !status.map(|s| s.success()).unwrap_or(false) {
+        report::fatal(format!("Failed to create {}", archive));
+    }
+    
+    say!("✓ State exported to {}", archive);
+}
+
+// Put an exported state in place on this machine. Existing files are kept
+// as .bak next to the imported ones.
+pub fn import(archive: &str, dry_run: bool) {
+    if !Path::new(archive).exists() {
+        report::fatal(format!("{} doesn't exist", archive));
+    }
+    
+    say!("Importing state from {}...", archive);
+    
+    let staging = staging_dir();
+    let status = Command::new("tar").args(["-xzf", archive, "-C", &staging]).status();
+    if !status.map(|s| s.success()).unwrap_or(false) {
+        let _ = std::fs::remove_dir_all(&staging);
+        report::fatal(format!("Failed to unpack {}", archive));
+    }
+    
+    let dir = format!("{}/{}", staging, ARCHIVE_DIR);
+    let mut targets: Vec<(&str, String)> = state_files().into_iter().collect();
+    targets.push(("packages.txt", imported_packages_path()));
+    
+    for (name, target) in targets {
+        let source = format!("{}/{}", dir, name);
+        if !Path::new(&source).exists() {
+            continue;
+        }
+        if dry_run {
+            say!("[DRY RUN] Would install {} to {}", name, target);
+            continue;
+        }
+        
+        if let Some(parent) = Path::new(&target).parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        if Path::new(&target).exists() {
+            let _ = std::fs::copy(&target, format!("{}.bak", target));
+        }
+        std::fs::copy(&source, &target).unwrap_or_else(|e| report::fatal(format!("Failed to install {}: {}", target, e)));
+        say!("  ✓ {} -> {}", name, target);
+    }
+    
+    let _ = std::fs::remove_dir_all(&staging);
+    
+    // A config from another version of ass should fail here, not halfway through setup
+    config::load_file_config(&config::config_path());
+    
+    if !dry_run {
+        say!("✓ State imported, run ass to set this machine up the same way");
+    }
+}
+
+// Package names from an imported snapshot, empty when nothing was imported
+pub fn imported_packages() -> Vec<String> {
+    std::fs::read_to_string(imported_packages_path())
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
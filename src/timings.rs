@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use crate::config;
+use crate::output::say;
+use std::path::Path;
+use std::sync::Mutex;
+
+// How long each step took last time, in seconds. The file can be copied to
+// another machine and passed with --timings to get estimates on a first run.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct TimingProfile {
+    steps: BTreeMap<String, u64>,
+}
+
+pub fn default_timings_path() -> String {
+    config::state_path("timings.toml")
+}
+
+fn read_profile(path: &str) -> TimingProfile {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| toml::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+pub fn format_duration(secs: u64) -> String {
+    if secs >= 60 {
+        format!("{}m {:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+// Step estimates plus the durations measured so far in this run (including
+// the part before the Nix restart, which main() keeps in the state file)
+pub struct Timings {
+    estimates: BTreeMap<String, u64>,
+    done: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Timings {
+    pub fn load(profile: Option<&str>, done: BTreeMap<String, u64>) -> Self {
+        let path = profile.map(str::to_string).unwrap_or_else(default_timings_path);
+        Timings {
+            estimates: read_profile(&path).steps,
+            done: Mutex::new(done),
+        }
+    }
+    
+    // Estimate for a step and for everything not run yet, if we've seen them before
+    pub fn announce(&self, step: &str) {
+        let Some(estimate) = self.estimates.get(step) else {
+            return;
+        };
+        
+        let mut done = self.done.lock().unwrap_or_else(|e| e.into_inner());
+        let remaining: u64 = self
+            .estimates
+            .iter()
+            .filter(|(name, _)| !done.contains_key(*name))
+            .map(|(_, secs)| secs)
+            .sum();
+        // Started steps stop counting towards what's left, even in dry runs
+        done.insert(step.to_string(), *estimate);
+        
+        say!(
+            "⏱ {}: ~{} (about {} left overall)",
+            step,
+            format_duration(*estimate),
+            format_duration(remaining)
+        );
+    }
+    
+    // Remember a finished step and fold it into the timing profile right away,
+    // since install_nix ends the process before main() gets another chance
+    pub fn record(&self, step: &str, secs: u64) {
+        self.done.lock().unwrap_or_else(|e| e.into_inner()).insert(step.to_string(), secs);
+        
+        let path = default_timings_path();
+        let mut profile = read_profile(&path);
+        profile.steps.insert(step.to_string(), secs);
+        
+        if let Some(dir) = Path::new(&path).parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        if let Ok(content) = toml::to_string(&profile) {
+            let _ = std::fs::write(&path, content);
+        }
+    }
+}
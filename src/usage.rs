@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Resource counters at one point in time. CPU, peak memory and disk writes
+// cover ass and every child it has waited for; network is the whole machine,
+// Linux doesn't count bytes per process.
+#[derive(Clone, Copy)]
+pub struct Snapshot {
+    cpu_ms: u64,
+    children_peak_kb: u64,
+    written: u64,
+    received: u64,
+}
+
+// What one step cost
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct StepUsage {
+    pub cpu_ms: u64,
+    // Largest child RSS, None when no child outgrew an earlier step's peak
+    pub peak_kb: Option<u64>,
+    pub written: u64,
+    pub downloaded: u64,
+}
+
+fn rusage(who: libc::c_int) -> libc::rusage {
+    // SAFETY: getrusage only writes into the struct we hand it
+    unsafe {
+        let mut usage: libc::rusage = std::mem::zeroed();
+        libc::getrusage(who, &mut usage);
+        usage
+    }
+}
+
+fn cpu_ms(usage: &libc::rusage) -> u64 {
+    let ms = |t: libc::timeval| t.tv_sec as u64 * 1000 + t.tv_usec as u64 / 1000;
+    ms(usage.ru_utime) + ms(usage.ru_stime)
+}
+
+// write_bytes from /proc/self/io, which includes reaped children
+fn bytes_written() -> u64 {
+    std::fs::read_to_string("/proc/self/io")
+        .unwrap_or_default()
+        .lines()
+        .find_map(|line| line.strip_prefix("write_bytes:"))
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+// Received bytes over all interfaces but loopback
+fn bytes_received() -> u64 {
+    std::fs::read_to_string("/proc/net/dev")
+        .unwrap_or_default()
+        .lines()
+        .skip(2)
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(interface, _)| interface.trim() != "lo")
+        .filter_map(|(_, counters)| counters.split_whitespace().next()?.parse::<u64>().ok())
+        .sum()
+}
+
+pub fn snapshot() -> Snapshot {
+    let children = rusage(libc::RUSAGE_CHILDREN);
+    Snapshot {
+        cpu_ms: cpu_ms(&rusage(libc::RUSAGE_SELF)) + cpu_ms(&children),
+        children_peak_kb: children.ru_maxrss as u64,
+        written: bytes_written(),
+        received: bytes_received(),
+    }
+}
+
+impl Snapshot {
+    // Steps that run side by side share counters, so their numbers overlap
+    pub fn usage_since(&self, before: &Snapshot) -> StepUsage {
+        StepUsage {
+            cpu_ms: self.cpu_ms.saturating_sub(before.cpu_ms),
+            peak_kb: (self.children_peak_kb > before.children_peak_kb).then_some(self.children_peak_kb),
+            written: self.written.saturating_sub(before.written),
+            downloaded: self.received.saturating_sub(before.received),
+        }
+    }
+}
+
+impl StepUsage {
+    // Not worth a line in the summary
+    pub fn is_negligible(&self) -> bool {
+        self.cpu_ms < 1000 && self.written < 1024 * 1024 && self.downloaded < 1024 * 1024
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 { format!("{} B", bytes) } else { format!("{:.1} {}", value, UNITS[unit]) }
+}
+
+impl fmt::Display for StepUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let peak = self.peak_kb.map(|kb| format_bytes(kb * 1024)).unwrap_or_else(|| "-".to_string());
+        write!(
+            f,
+            "cpu {:.1}s, peak {}, wrote {}, downloaded {}",
+            self.cpu_ms as f64 / 1000.0,
+            peak,
+            format_bytes(self.written),
+            format_bytes(self.downloaded)
+        )
+    }
+}
@@ -0,0 +1,64 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+// The user's session bus socket, where systemctl --user finds the user manager
+fn runtime_dir() -> String {
+    std::env::var("XDG_RUNTIME_DIR").unwrap_or_else(|_| {
+        // SAFETY: getuid can't fail
+        format!("/run/user/{}", unsafe { libc::getuid() })
+    })
+}
+
+fn socket() -> String {
+    format!("{}/bus", runtime_dir())
+}
+
+pub fn available() -> bool {
+    Path::new(&socket()).exists()
+}
+
+// What systemctl --user needs to reach the bus when the environment doesn't
+// have it (run from a TTY before any login session, or through sudo)
+pub fn env() -> [(&'static str, String); 2] {
+    [("XDG_RUNTIME_DIR", runtime_dir()), ("DBUS_SESSION_BUS_ADDRESS", format!("unix:path={}", socket()))]
+}
+
+// Poll for the bus socket, the user manager takes a moment to come up
+pub fn wait(timeout: Duration) -> bool {
+    let started = Instant::now();
+    while !available() {
+        if started.elapsed() >= timeout {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    }
+    true
+}
+
+// Start the user manager without a login session by letting the user linger.
+// Tried once a run, it also keeps user services running after logout.
+pub fn linger(timeout: Duration) -> bool {
+    static LINGERING: OnceLock<bool> = OnceLock::new();
+    *LINGERING.get_or_init(|| {
+        let user = std::env::var("USER").unwrap_or_default();
+        let enabled = Command::new("sudo")
+            .args(["loginctl", "enable-linger", &user])
+            .status()
+            .is_ok_and(|status| status.success());
+        enabled && wait(timeout)
+    })
+}
+
+// systemctl --user <args> through a transient machinectl session, which
+// comes with a user manager of its own
+pub fn machinectl_systemctl(args: &[&str]) -> bool {
+    let user = std::env::var("USER").unwrap_or_default();
+    Command::new("sudo")
+        .args(["machinectl", "--quiet", "shell", &format!("{}@.host", user), "/usr/bin/systemctl", "--user"])
+        .args(args)
+        .stdout(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
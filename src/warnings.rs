@@ -0,0 +1,95 @@
+use crate::history;
+use crate::i18n::tr;
+use crate::output::{esay, say};
+use crate::report::{self, Event};
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum WarningKind {
+    // Something failed but the run carried on
+    Failure,
+    // Something was deliberately left undone
+    Skipped,
+    Deprecation,
+}
+
+impl WarningKind {
+    fn heading(self) -> &'static str {
+        match self {
+            WarningKind::Failure => tr!("Non-fatal failures"),
+            WarningKind::Skipped => tr!("Skipped"),
+            WarningKind::Deprecation => tr!("Deprecations"),
+        }
+    }
+    
+    // How the run history records it
+    fn name(self) -> &'static str {
+        match self {
+            WarningKind::Failure => "failure",
+            WarningKind::Skipped => "skipped",
+            WarningKind::Deprecation => "deprecation",
+        }
+    }
+}
+
+pub struct Warning {
+    pub kind: WarningKind,
+    pub message: String,
+    // What to do about it, shown in the recap
+    pub hints: Vec<&'static str>,
+}
+
+// Warnings collected over a run so they can be recapped at the end instead
+// of scrolling away. Steps only get &Config and may run in parallel, hence
+// the Mutex.
+#[derive(Default)]
+pub struct Warnings {
+    items: Mutex<Vec<Warning>>,
+}
+
+impl Warnings {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Warning>> {
+        self.items.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    
+    // Print the warning right away and remember it for the recap
+    pub fn add(&self, kind: WarningKind, message: impl Into<String>) {
+        self.add_with_hints(kind, message, Vec::new());
+    }
+    
+    pub fn add_with_hints(&self, kind: WarningKind, message: impl Into<String>, hints: Vec<&'static str>) {
+        let message = message.into();
+        esay!("⚠ {}", tr!("Warning: {}", message));
+        history::warning(kind.name(), &message);
+        report::emit(Event::Warning { kind: kind.name(), message: &message, hints: &hints });
+        self.lock().push(Warning { kind, message, hints });
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+    
+    pub fn print_recap(&self) {
+        let items = self.lock();
+        if items.is_empty() {
+            return;
+        }
+        
+        say!();
+        say!("⚠ {}", tr!("{} warning(s) during this run:", items.len()));
+        for kind in [WarningKind::Failure, WarningKind::Skipped, WarningKind::Deprecation] {
+            let group: Vec<&Warning> = items.iter().filter(|w| w.kind == kind).collect();
+            if group.is_empty() {
+                continue;
+            }
+            
+            say!("  {}:", kind.heading());
+            for warning in group {
+                say!("    - {}", warning.message);
+                for hint in &warning.hints {
+                    say!("      → {}", hint);
+                }
+            }
+        }
+    }
+}
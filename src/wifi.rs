@@ -0,0 +1,147 @@
+use crate::i18n::tr;
+use crate::output::{esay, say};
+use crate::{prompt, prompt_secret, which};
+use std::process::{Command, Stdio};
+
+// What connects to Wi-Fi on this machine. Arch's install image and a fresh
+// minimal install use iwd, desktops mostly NetworkManager.
+#[derive(Clone, Copy)]
+enum Tool {
+    NetworkManager,
+    Iwd,
+}
+
+impl Tool {
+    fn detect() -> Option<Tool> {
+        let nm_running = which("nmcli").is_some()
+            && Command::new("nmcli")
+                .args(["-t", "general", "status"])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success());
+        if nm_running {
+            Some(Tool::NetworkManager)
+        } else if which("iwctl").is_some() {
+            Some(Tool::Iwd)
+        } else {
+            None
+        }
+    }
+}
+
+// Wireless interfaces, the ones with a wireless directory in sysfs
+pub fn interfaces() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir("/sys/class/net") else {
+        return Vec::new();
+    };
+    let mut interfaces: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.path().join("wireless").is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    interfaces.sort();
+    interfaces
+}
+
+pub fn available() -> bool {
+    !interfaces().is_empty() && Tool::detect().is_some()
+}
+
+// nmcli -t escapes ':' inside fields as "\:"
+fn split_terse(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => fields.last_mut().unwrap().extend(chars.next()),
+            ':' => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+// Print the networks in range, strongest first where the tool says
+fn list_networks(tool: Tool, interface: &str) {
+    match tool {
+        Tool::NetworkManager => {
+            let output = Command::new("nmcli")
+                .args(["-t", "-f", "SSID,SIGNAL,SECURITY", "device", "wifi", "list", "--rescan", "yes", "ifname", interface])
+                .output();
+            let text = output.map(|output| String::from_utf8_lossy(&output.stdout).to_string()).unwrap_or_default();
+            let mut networks: Vec<Vec<String>> =
+                text.lines().map(split_terse).filter(|fields| fields.len() == 3 && !fields[0].is_empty()).collect();
+            networks.sort_by_key(|fields| std::cmp::Reverse(fields[1].parse::<u32>().unwrap_or(0)));
+            networks.dedup_by(|a, b| a[0] == b[0]);
+            for fields in &networks {
+                let security = if fields[2].is_empty() { "open" } else { fields[2].as_str() };
+                say!("  {:>3}%  {}  ({})", fields[1], fields[0], security);
+            }
+        }
+        Tool::Iwd => {
+            let _ = Command::new("sudo").args(["iwctl", "station", interface, "scan"]).status();
+            // iwd needs a moment to fill in the results
+            std::thread::sleep(std::time::Duration::from_secs(3));
+            let _ = Command::new("sudo").args(["iwctl", "station", interface, "get-networks"]).status();
+        }
+    }
+}
+
+// The passphrase goes on the command line of nmcli or iwctl, so these run
+// directly instead of through command::run, which would log it
+fn connect(tool: Tool, interface: &str, ssid: &str, passphrase: &str) -> bool {
+    let mut command = Command::new("sudo");
+    match tool {
+        Tool::NetworkManager => {
+            command.args(["nmcli", "device", "wifi", "connect", ssid, "ifname", interface]);
+            if !passphrase.is_empty() {
+                command.args(["password", passphrase]);
+            }
+        }
+        Tool::Iwd => {
+            command.arg("iwctl");
+            if !passphrase.is_empty() {
+                command.args(["--passphrase", passphrase]);
+            }
+            command.args(["station", interface, "connect", ssid]);
+        }
+    }
+    command.status().is_ok_and(|status| status.success())
+}
+
+// Ask for a network and connect to it. Returns whether the connection was
+// made; whether it reaches the internet is for the caller to check.
+pub fn onboard() -> bool {
+    let Some(tool) = Tool::detect() else {
+        return false;
+    };
+    let interfaces = interfaces();
+    let Some(first) = interfaces.first() else {
+        return false;
+    };
+    let interface = if interfaces.len() == 1 {
+        first.clone()
+    } else {
+        say!("Wireless interfaces: {}", interfaces.join(", "));
+        prompt(tr!("Interface"), first)
+    };
+    
+    say!("{}", tr!("Networks in range of {}:", interface));
+    list_networks(tool, &interface);
+    
+    let ssid = prompt(tr!("Network name (SSID)"), "");
+    if ssid.is_empty() {
+        return false;
+    }
+    let passphrase = prompt_secret(&format!("{} ", tr!("Passphrase (empty for an open network):")));
+    
+    if !connect(tool, &interface, &ssid, &passphrase) {
+        esay!("⚠ Failed to connect to {}", ssid);
+        return false;
+    }
+    say!("✓ {}", tr!("Connected to {}", ssid));
+    // DHCP and DNS take a few seconds after associating
+    std::thread::sleep(std::time::Duration::from_secs(5));
+    true
+}
@@ -0,0 +1,69 @@
+use crate::config::{self, AUR_HELPERS, DEFAULT_DOTFILES_REPO, PROFILES};
+use crate::i18n::tr;
+use crate::output::say;
+use crate::{ask_yes_no, prompt};
+
+// Ask for a directory until it's absolute or under ~/, or left empty
+fn prompt_dir(question: &str, default: &str) -> String {
+    loop {
+        let answer = prompt(question, default);
+        if answer.is_empty() || answer.starts_with('/') || answer.starts_with("~/") {
+            return answer;
+        }
+        say!("{}", tr!("Use an absolute path or one starting with ~/"));
+    }
+}
+
+// Ask a value until it's one of the allowed choices
+fn prompt_choice(question: &str, choices: &[&str], default: &str) -> String {
+    loop {
+        let answer = prompt(&format!("{} ({})", question, choices.join("/")), default);
+        if choices.contains(&answer.as_str()) {
+            return answer;
+        }
+        say!("{}", tr!("Please answer one of: {}", choices.join(", ")));
+    }
+}
+
+// `ass wizard`: ask the basics and write them to the config file. Other
+// sections already in the file are kept. Returns whether to start the run.
+pub fn run_wizard() -> bool {
+    let path = config::config_path();
+    let existing = config::load_file_config(&path);
+    
+    say!("{}", tr!("A.S.S. setup wizard"));
+    say!("{}", tr!("Press Enter to keep the value in brackets."));
+    say!();
+    
+    let repo = prompt(
+        tr!("Dotfiles repository URL"),
+        if existing.dotfiles.repo.is_empty() { DEFAULT_DOTFILES_REPO } else { &existing.dotfiles.repo },
+    );
+    let helper = prompt_choice(tr!("AUR helper"), &AUR_HELPERS, &existing.aur.helper);
+    let chaotic = ask_yes_no(tr!("Enable the Chaotic AUR binary repository?"), existing.chaotic_aur.enable);
+    let wallpapers = ask_yes_no(tr!("Clone the wallpaper repositories?"), existing.wallpapers.enable);
+    let wallpaper_dir = if wallpapers {
+        prompt_dir(tr!("Wallpaper directory (empty for the Pictures directory)"), existing.wallpapers.dir.as_deref().unwrap_or(""))
+    } else {
+        String::new()
+    };
+    let profile = prompt_choice(tr!("Profile"), &PROFILES, existing.profile());
+    
+    let mut values = vec![
+        ("dotfiles", "repo", toml::Value::String(repo)),
+        ("aur", "helper", toml::Value::String(helper)),
+        ("chaotic_aur", "enable", toml::Value::Boolean(chaotic)),
+        ("wallpapers", "enable", toml::Value::Boolean(wallpapers)),
+        ("", "profile", toml::Value::String(profile)),
+    ];
+    if !wallpaper_dir.is_empty() {
+        values.push(("wallpapers", "dir", toml::Value::String(wallpaper_dir)));
+    }
+    config::write_config_values(&path, &values);
+    
+    say!();
+    say!("✓ {}", tr!("Wrote {}", path));
+    say!();
+    
+    ask_yes_no(tr!("Start the setup now?"), false)
+}
@@ -0,0 +1,90 @@
+use crate::output::say;
+use crate::paths;
+use crate::report;
+use std::ffi::{CString, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Scratch directory for everything a run downloads or generates
+// ($XDG_CACHE_HOME/ass/<run-id>), removed again when the run succeeds
+pub struct WorkDir {
+    root: PathBuf,
+}
+
+impl WorkDir {
+    pub fn new() -> Self {
+        let cache = PathBuf::from(&paths::get().cache_home);
+        
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let run_id = format!("{}-{}", started, std::process::id());
+        
+        WorkDir {
+            root: cache.join("ass").join(run_id),
+        }
+    }
+    
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+    
+    // Path for an artifact inside the work dir, created on first use so
+    // dry runs don't leave empty directories behind. It's kept to us, some
+    // of what's staged there is root-only content.
+    pub fn path(&self, name: &str) -> String {
+        create_private_dir(&self.root.to_string_lossy()).unwrap_or_else(|e| {
+            report::fatal(format!("Failed to create work directory {}: {}", self.root.display(), e));
+        });
+        self.root.join(name).to_string_lossy().to_string()
+    }
+    
+    // Same as path() without creating anything, for dry-run output
+    pub fn planned_path(&self, name: &str) -> String {
+        self.root.join(name).to_string_lossy().to_string()
+    }
+    
+    // Remove the work dir unless asked to keep it for debugging
+    pub fn finish(&self, keep: bool) {
+        if !self.root.exists() {
+            return;
+        }
+        
+        if keep {
+            say!("Work directory kept at {}", self.root.display());
+            return;
+        }
+        
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+// A new directory in /tmp that only we can write to (mkdtemp(3), mode 700),
+// so nothing another user planted there beforehand gets picked up
+pub fn private_temp_dir(prefix: &str) -> std::io::Result<PathBuf> {
+    let template = std::env::temp_dir().join(format!("{}-XXXXXX", prefix));
+    let mut bytes = CString::new(template.as_os_str().as_bytes())?.into_bytes_with_nul();
+    // SAFETY: a writable NUL-terminated template ending in XXXXXX, as mkdtemp needs
+    if unsafe { libc::mkdtemp(bytes.as_mut_ptr().cast()) }.is_null() {
+        return Err(std::io::Error::last_os_error());
+    }
+    bytes.pop();
+    Ok(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+// A directory only we can get into, for copies of root-only files. An
+// existing one is closed up too.
+pub fn create_private_dir(dir: &str) -> std::io::Result<()> {
+    std::fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+}
+
+// Write a file only we can read, whatever the umask and whatever it was before
+pub fn write_private(path: &str, content: impl AsRef<[u8]>) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    std::io::Write::write_all(&mut file, content.as_ref())
+}